@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use criterion::{criterion_group, criterion_main, Criterion};
 
 use rlua::prelude::*;
@@ -51,6 +53,67 @@ fn create_string_table(c: &mut Criterion) {
     });
 }
 
+fn create_table_from_map(c: &mut Criterion) {
+    let map: HashMap<String, i64> = (0..10_000).map(|i| (format!("key{}", i), i)).collect();
+    let lua = Lua::new();
+    lua.context(|ctx| {
+        c.bench_function("create table from map 10000", |b| {
+            b.iter(|| {
+                ctx.create_table_from(map.iter().map(|(k, &v)| (k.as_str(), v)))
+                    .unwrap()
+            })
+        });
+    });
+}
+
+fn serialize_records(c: &mut Criterion) {
+    const FIELDS: [&str; 5] = ["id", "name", "position", "velocity", "health"];
+
+    fn serialize<'lua>(ctx: LuaContext<'lua>, key: impl Fn(&str) -> LuaResult<LuaString<'lua>>) {
+        for i in 0..100 {
+            let record = ctx.create_table().unwrap();
+            for &field in &FIELDS {
+                record.raw_set(key(field).unwrap(), i).unwrap();
+            }
+        }
+    }
+
+    let lua = Lua::new();
+    lua.context(|ctx| {
+        c.bench_function("serialize records create_string 100", |b| {
+            b.iter(|| serialize(ctx, |s| ctx.create_string(s)))
+        });
+        c.bench_function("serialize records intern_string 100", |b| {
+            b.iter(|| serialize(ctx, |s| ctx.intern_string(s)))
+        });
+    });
+}
+
+fn lookup_keys(c: &mut Criterion) {
+    let lua = Lua::new();
+    lua.context(|ctx| {
+        let table: LuaTable = ctx
+            .load("{ id = 1, name = 'x', position = 2, velocity = 3, health = 4 }")
+            .eval()
+            .unwrap();
+        c.bench_function("lookup string key 100", |b| {
+            b.iter(|| {
+                for _ in 0..100 {
+                    table.raw_get::<_, i64>("position").unwrap();
+                }
+            })
+        });
+        let position = ctx.intern_key("position").unwrap();
+        c.bench_function("lookup interned key 100", |b| {
+            b.iter(|| {
+                for _ in 0..100 {
+                    table.raw_get_k::<i64>(&position).unwrap();
+                }
+            })
+        });
+    });
+}
+
 fn call_add_function(c: &mut Criterion) {
     c.bench_function("call add function 3 10", |b| {
         b.iter_with_setup(
@@ -84,6 +147,65 @@ fn call_add_function(c: &mut Criterion) {
     });
 }
 
+fn call_function_one_arg(c: &mut Criterion) {
+    let lua = Lua::new();
+    lua.context(|ctx| {
+        let f: LuaFunction = ctx.load("function(x) return x + 1 end").eval().unwrap();
+        c.bench_function("call function 1 arg 100", |b| {
+            b.iter(|| {
+                for i in 0..100 {
+                    let _result: i64 = f.call(i).unwrap();
+                }
+            })
+        });
+        c.bench_function("call1 function 1 arg 100", |b| {
+            b.iter(|| {
+                for i in 0..100 {
+                    let _result: i64 = f.call1(i).unwrap();
+                }
+            })
+        });
+    });
+}
+
+fn resume_generator(c: &mut Criterion) {
+    let lua = Lua::new();
+    lua.context(|ctx| {
+        let generator: LuaFunction = ctx
+            .load(
+                r#"
+                    function(x)
+                        while true do
+                            x = coroutine.yield(x + 1)
+                        end
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        let mut group = c.benchmark_group("resume generator");
+        group.sample_size(10);
+        group.bench_function("resume 1000000", |b| {
+            let thread = ctx.create_thread(generator.clone()).unwrap();
+            b.iter(|| {
+                for i in 0..1_000_000 {
+                    let _result: i64 = thread.resume(i).unwrap();
+                }
+            })
+        });
+        group.bench_function("resume1 1000000", |b| {
+            let thread = ctx.create_thread(generator.clone()).unwrap();
+            b.iter(|| {
+                for i in 0..1_000_000 {
+                    let _result: i64 = thread.resume1(i).unwrap();
+                }
+            })
+        });
+        group.finish();
+    });
+}
+
 fn call_add_callback(c: &mut Criterion) {
     c.bench_function("call callback add 2 10", |b| {
         b.iter_with_setup(
@@ -197,6 +319,39 @@ fn create_userdata(c: &mut Criterion) {
     });
 }
 
+fn call_userdata_method(c: &mut Criterion) {
+    #[derive(Clone, Copy)]
+    struct Vec3(f64, f64, f64);
+    impl LuaUserData for Vec3 {
+        fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("dot", |_, a, b: Vec3| Ok(a.0 * b.0 + a.1 * b.1 + a.2 * b.2));
+            methods.add_method_fn("dot_fn", |_, a, b: Vec3| {
+                Ok(a.0 * b.0 + a.1 * b.1 + a.2 * b.2)
+            });
+        }
+    }
+
+    let lua = Lua::new();
+    lua.context(|ctx| {
+        ctx.globals().set("a", Vec3(1.0, 2.0, 3.0)).unwrap();
+        ctx.globals().set("b", Vec3(4.0, 5.0, 6.0)).unwrap();
+        let method: LuaFunction = ctx
+            .load("function() for i = 1, 100 do a:dot(b) end end")
+            .eval()
+            .unwrap();
+        let method_fn: LuaFunction = ctx
+            .load("function() for i = 1, 100 do a:dot_fn(b) end end")
+            .eval()
+            .unwrap();
+        c.bench_function("call userdata method 100", |b| {
+            b.iter(|| method.call::<_, ()>(()).unwrap())
+        });
+        c.bench_function("call userdata method_fn 100", |b| {
+            b.iter(|| method_fn.call::<_, ()>(()).unwrap())
+        });
+    });
+}
+
 criterion_group! {
     name = benches;
     config = Criterion::default()
@@ -206,11 +361,17 @@ criterion_group! {
         create_table,
         create_array,
         create_string_table,
+        create_table_from_map,
+        serialize_records,
+        lookup_keys,
         call_add_function,
+        call_function_one_arg,
+        resume_generator,
         call_add_callback,
         call_append_callback,
         create_registry_values,
-        create_userdata
+        create_userdata,
+        call_userdata_method
 }
 
 criterion_main!(benches);
@@ -0,0 +1,288 @@
+//! Derive macros for the `ToLua` and `FromLua` traits of [`rlua`], which are re-exported by
+//! `rlua` when its `derive` feature is enabled.
+//!
+//! Both traits can be derived for structs with named fields and for enums without fields:
+//!
+//! * A struct is converted to a table with a key for each field, named after the field.  When
+//!   converting from Lua, the value must be a table, and each field is converted from the value
+//!   with its name.  A missing field is an error naming the field, unless the field type accepts
+//!   `nil` (such as an `Option`).
+//! * An enum is converted to the name of its variant as a string.  With the `#[rlua(integer)]`
+//!   attribute on the enum, it is instead converted to the integer value of its discriminant, and
+//!   can be converted from either the integer or the variant name.
+//!
+//! Other types, such as tuple structs and enums with fields, are not supported.
+//!
+//! [`rlua`]: https://docs.rs/rlua
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, GenericParam, Generics,
+    Ident, Result, Type,
+};
+
+/// Derives `rlua::ToLua`, see the [crate documentation](index.html).
+#[proc_macro_derive(ToLua, attributes(rlua))]
+pub fn derive_to_lua(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    to_lua(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `rlua::FromLua`, see the [crate documentation](index.html).
+#[proc_macro_derive(FromLua, attributes(rlua))]
+pub fn derive_from_lua(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    from_lua(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+enum Shape<'a> {
+    // The name and type of each field.
+    Struct(Vec<(&'a Ident, &'a Type)>),
+    Enum {
+        variants: Vec<&'a Ident>,
+        integer: bool,
+    },
+}
+
+fn shape(input: &DeriveInput) -> Result<Shape<'_>> {
+    let mut integer = false;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("rlua")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("integer") {
+                integer = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported rlua attribute"))
+            }
+        })?;
+    }
+
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) if !integer => Ok(Shape::Struct(
+                fields
+                    .named
+                    .iter()
+                    .map(|f| (f.ident.as_ref().unwrap(), &f.ty))
+                    .collect(),
+            )),
+            Fields::Named(_) => Err(Error::new_spanned(
+                &input.ident,
+                "#[rlua(integer)] is only supported on enums",
+            )),
+            _ => Err(unsupported(input)),
+        },
+        Data::Enum(data) => {
+            let mut variants = Vec::new();
+            for variant in &data.variants {
+                match variant.fields {
+                    Fields::Unit => variants.push(&variant.ident),
+                    _ => return Err(unsupported(input)),
+                }
+            }
+            Ok(Shape::Enum { variants, integer })
+        }
+        Data::Union(_) => Err(unsupported(input)),
+    }
+}
+
+fn unsupported(input: &DeriveInput) -> Error {
+    Error::new_spanned(
+        &input.ident,
+        "rlua conversions can only be derived for structs with named fields and enums without fields",
+    )
+}
+
+// Adds the `'lua` lifetime to the generics of the type, unless it already has one, and requires
+// each of the given types to implement `bound`.
+fn lua_generics(input: &DeriveInput, types: &[&Type], bound: TokenStream2) -> Generics {
+    let mut generics = input.generics.clone();
+    let has_lua = generics
+        .lifetimes()
+        .any(|l| l.lifetime.ident.unraw() == "lua");
+    if !has_lua {
+        generics
+            .params
+            .insert(0, GenericParam::Lifetime(parse_quote!('lua)));
+    }
+    let where_clause = generics.make_where_clause();
+    for ty in types {
+        where_clause
+            .predicates
+            .push(parse_quote!(#ty: ::rlua::#bound));
+    }
+    generics
+}
+
+fn to_lua(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let (types, body) = match shape(input)? {
+        Shape::Struct(fields) => {
+            let idents = fields.iter().map(|&(ident, _)| ident);
+            let keys = fields.iter().map(|&(ident, _)| ident.unraw().to_string());
+            let body = quote! {
+                let table = lua.create_table()?;
+                #(table.raw_set(#keys, self.#idents)?;)*
+                ::std::result::Result::Ok(::rlua::Value::Table(table))
+            };
+            (fields.iter().map(|&(_, ty)| ty).collect(), body)
+        }
+        Shape::Enum {
+            variants,
+            integer: false,
+        } => {
+            let keys = variants.iter().map(|v| v.unraw().to_string());
+            let body = quote! {
+                let variant: &str = match self {
+                    #(#name::#variants => #keys,)*
+                };
+                ::std::result::Result::Ok(::rlua::Value::String(lua.create_string(variant)?))
+            };
+            (Vec::new(), body)
+        }
+        Shape::Enum { integer: true, .. } => {
+            let body = quote! {
+                let _ = lua;
+                ::std::result::Result::Ok(::rlua::Value::Integer(self as ::rlua::Integer))
+            };
+            (Vec::new(), body)
+        }
+    };
+
+    let generics = lua_generics(input, &types, quote!(ToLua<'lua>));
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rlua::ToLua<'lua> for #name #ty_generics #where_clause {
+            fn to_lua(
+                self,
+                lua: ::rlua::Context<'lua>,
+            ) -> ::rlua::Result<::rlua::Value<'lua>> {
+                #body
+            }
+        }
+    })
+}
+
+fn from_lua(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let name_str = name.unraw().to_string();
+    let (types, body) = match shape(input)? {
+        Shape::Struct(fields) => {
+            let idents = fields.iter().map(|&(ident, _)| ident);
+            let keys = fields.iter().map(|&(ident, _)| ident.unraw().to_string());
+            let body = quote! {
+                let table = match value {
+                    ::rlua::Value::Table(table) => table,
+                    value => {
+                        return ::std::result::Result::Err(::rlua::Error::FromLuaConversionError {
+                            from: value.type_name(),
+                            to: #name_str,
+                            message: ::std::option::Option::Some("expected a table".to_owned()),
+                        });
+                    }
+                };
+                ::std::result::Result::Ok(#name {
+                    #(#idents: {
+                        let value: ::rlua::Value<'lua> = table.get(#keys)?;
+                        let from = value.type_name();
+                        ::rlua::FromLua::from_lua(value, lua).map_err(|err| {
+                            ::rlua::Error::FromLuaConversionError {
+                                from,
+                                to: #name_str,
+                                message: ::std::option::Option::Some(if from == "nil" {
+                                    ::std::format!("missing field `{}`", #keys)
+                                } else {
+                                    ::std::format!("invalid field `{}`: {}", #keys, err)
+                                }),
+                            }
+                        })?
+                    },)*
+                })
+            };
+            (fields.iter().map(|&(_, ty)| ty).collect(), body)
+        }
+        Shape::Enum { variants, integer } => {
+            let keys: Vec<_> = variants.iter().map(|v| v.unraw().to_string()).collect();
+            let (from_integer, expected) = if integer {
+                let from_integer = quote! {
+                    let integer = match value {
+                        ::rlua::Value::Integer(i) => ::std::option::Option::Some(i),
+                        ::rlua::Value::Number(n) if n as ::rlua::Integer as ::rlua::Number == n => {
+                            ::std::option::Option::Some(n as ::rlua::Integer)
+                        }
+                        _ => ::std::option::Option::None,
+                    };
+                    if let ::std::option::Option::Some(integer) = integer {
+                        #(if integer == #name::#variants as ::rlua::Integer {
+                            return ::std::result::Result::Ok(#name::#variants);
+                        })*
+                    }
+                };
+                let expected = quote! {
+                    ::std::vec![#(::std::format!("{} ({})", #keys, #name::#variants as ::rlua::Integer)),*]
+                };
+                (from_integer, expected)
+            } else {
+                (quote!(), quote!(::std::vec![#(#keys),*]))
+            };
+            let body = quote! {
+                let _ = lua;
+                #from_integer
+                if let ::rlua::Value::String(s) = &value {
+                    match s.as_bytes() {
+                        #(key if key == #keys.as_bytes() => {
+                            return ::std::result::Result::Ok(#name::#variants);
+                        })*
+                        _ => {}
+                    }
+                }
+                let given = match &value {
+                    ::rlua::Value::Integer(i) => i.to_string(),
+                    ::rlua::Value::Number(n) => n.to_string(),
+                    ::rlua::Value::String(s) => {
+                        ::std::format!("{:?}", ::std::string::String::from_utf8_lossy(s.as_bytes()))
+                    }
+                    value => ::std::format!("a {}", value.type_name()),
+                };
+                let expected: ::std::vec::Vec<::std::string::String> = #expected
+                    .into_iter()
+                    .map(|e| e.to_string())
+                    .collect();
+                ::std::result::Result::Err(::rlua::Error::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: #name_str,
+                    message: ::std::option::Option::Some(::std::format!(
+                        "{} is not a valid variant, expected one of {}",
+                        given,
+                        expected.join(", ")
+                    )),
+                })
+            };
+            (Vec::new(), body)
+        }
+    };
+
+    let generics = lua_generics(input, &types, quote!(FromLua<'lua>));
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rlua::FromLua<'lua> for #name #ty_generics #where_clause {
+            fn from_lua(
+                value: ::rlua::Value<'lua>,
+                lua: ::rlua::Context<'lua>,
+            ) -> ::rlua::Result<Self> {
+                #body
+            }
+        }
+    })
+}
@@ -0,0 +1,70 @@
+use std::string::String as StdString;
+
+use crate::context::Context;
+use crate::error::Result;
+use crate::table::Table;
+use crate::value::FromLuaMulti;
+
+/// Options for [`Context::eval_batch`].
+///
+/// [`Context::eval_batch`]: struct.Context.html#method.eval_batch
+#[derive(Clone, Debug, Default)]
+pub struct BatchOptions<'lua> {
+    environment: Option<Table<'lua>>,
+}
+
+impl<'lua> BatchOptions<'lua> {
+    /// Creates the default options, which evaluate every chunk in the global environment.
+    pub fn new() -> BatchOptions<'lua> {
+        BatchOptions::default()
+    }
+
+    /// Evaluates each chunk in a fresh environment table which reads missing fields from
+    /// `template`, so that globals assigned by one chunk are not seen by the others or by the
+    /// template.
+    ///
+    /// The field `_G` of each environment is the environment itself.  Tables reached through the
+    /// template, such as `string`, are shared and can still be modified.
+    pub fn environment(mut self, template: Table<'lua>) -> BatchOptions<'lua> {
+        self.environment = Some(template);
+        self
+    }
+}
+
+pub(crate) fn eval_batch<'lua, R, I>(
+    lua: Context<'lua>,
+    chunks: I,
+    options: BatchOptions<'lua>,
+) -> Vec<(StdString, Result<R>)>
+where
+    R: FromLuaMulti<'lua>,
+    I: IntoIterator<Item = (StdString, StdString)>,
+{
+    chunks
+        .into_iter()
+        .map(|(name, source)| {
+            let result = eval_one(lua, &name, &source, options.environment.as_ref());
+            (name, result)
+        })
+        .collect()
+}
+
+fn eval_one<'lua, R: FromLuaMulti<'lua>>(
+    lua: Context<'lua>,
+    name: &str,
+    source: &str,
+    template: Option<&Table<'lua>>,
+) -> Result<R> {
+    let chunk = lua.load(source).set_name(name)?;
+    match template {
+        Some(template) => {
+            let env = lua.create_table()?;
+            let metatable = lua.create_table()?;
+            metatable.raw_set("__index", template.clone())?;
+            env.set_metatable(Some(metatable));
+            env.raw_set("_G", env.clone())?;
+            chunk.set_environment(env)?.eval()
+        }
+        None => chunk.eval(),
+    }
+}
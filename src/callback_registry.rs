@@ -0,0 +1,153 @@
+use std::string::String as StdString;
+
+use crate::context::Context;
+use crate::error::Result;
+use crate::function::Function;
+use crate::table::Table;
+use crate::types::{Integer, RegistryKey};
+use crate::value::{MultiValue, Nil, ToLuaMulti};
+
+/// Lua functions subscribed to named events, created by [`Context::create_callback_registry`].
+///
+/// The functions are kept in a table in the Lua registry, so they stay alive for as long as they
+/// are subscribed or until the `CallbackRegistry` is dropped.  Like any other [`RegistryKey`],
+/// dropping the `CallbackRegistry` only frees the functions once
+/// [`Context::expire_registry_values`] is called.
+///
+/// Every method takes the `Context` to operate in, and returns `Error::MismatchedRegistryKey` if
+/// it belongs to a different Lua state than the one the registry was created in.
+///
+/// [`Context::create_callback_registry`]: struct.Context.html#method.create_callback_registry
+/// [`Context::expire_registry_values`]: struct.Context.html#method.expire_registry_values
+/// [`RegistryKey`]: struct.RegistryKey.html
+#[derive(Debug)]
+pub struct CallbackRegistry {
+    // A table holding `next_id`, and two tables keyed by event name: `functions`, holding the
+    // subscribed functions in registration order, and `ids`, holding their subscription ids at
+    // the same indices.
+    state: RegistryKey,
+}
+
+/// Identifies a function subscribed to a [`CallbackRegistry`], returned by
+/// [`CallbackRegistry::add`].
+///
+/// [`CallbackRegistry`]: struct.CallbackRegistry.html
+/// [`CallbackRegistry::add`]: struct.CallbackRegistry.html#method.add
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId {
+    event: StdString,
+    id: Integer,
+}
+
+impl CallbackRegistry {
+    pub(crate) fn new(lua: Context) -> Result<CallbackRegistry> {
+        let state = lua.create_table()?;
+        state.raw_set("next_id", 1)?;
+        state.raw_set("functions", lua.create_table()?)?;
+        state.raw_set("ids", lua.create_table()?)?;
+        Ok(CallbackRegistry {
+            state: lua.create_registry_value(state)?,
+        })
+    }
+
+    /// Subscribes `function` to `event`, after any functions already subscribed to it.
+    pub fn add<'lua>(
+        &self,
+        lua: Context<'lua>,
+        event: &str,
+        function: Function<'lua>,
+    ) -> Result<SubscriptionId> {
+        let state: Table = lua.registry_value(&self.state)?;
+        let id: Integer = state.raw_get("next_id")?;
+        state.raw_set("next_id", id + 1)?;
+
+        let (functions, ids) = match self.event_tables(lua, event)? {
+            Some(tables) => tables,
+            None => {
+                let tables = (lua.create_table()?, lua.create_table()?);
+                state
+                    .raw_get::<_, Table>("functions")?
+                    .raw_set(event, tables.0.clone())?;
+                state
+                    .raw_get::<_, Table>("ids")?
+                    .raw_set(event, tables.1.clone())?;
+                tables
+            }
+        };
+        let index = functions.raw_len() + 1;
+        functions.raw_set(index, function)?;
+        ids.raw_set(index, id)?;
+
+        Ok(SubscriptionId {
+            event: event.to_owned(),
+            id,
+        })
+    }
+
+    /// Unsubscribes a function, returning false if it was not subscribed (because it was already
+    /// removed, or its event was cleared).
+    pub fn remove(&self, lua: Context, id: &SubscriptionId) -> Result<bool> {
+        if let Some((functions, ids)) = self.event_tables(lua, &id.event)? {
+            for index in 1..=ids.raw_len() {
+                if ids.raw_get::<_, Integer>(index)? == id.id {
+                    functions.raw_remove(index)?;
+                    ids.raw_remove(index)?;
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Unsubscribes every function subscribed to `event`.
+    pub fn clear(&self, lua: Context, event: &str) -> Result<()> {
+        let state: Table = lua.registry_value(&self.state)?;
+        state
+            .raw_get::<_, Table>("functions")?
+            .raw_set(event, Nil)?;
+        state.raw_get::<_, Table>("ids")?.raw_set(event, Nil)?;
+        Ok(())
+    }
+
+    /// Calls every function subscribed to `event` with `args`, in the order they were added.
+    ///
+    /// An error raised by one function does not prevent the others from being called: the result
+    /// of each call is returned in the same order as the calls.
+    ///
+    /// The functions to call are fixed when `emit` starts.  Functions added while the event is
+    /// being emitted are first called by the next `emit`, and functions removed while the event is
+    /// being emitted are still called by this one if they have not been called yet.
+    pub fn emit<'lua, A: ToLuaMulti<'lua>>(
+        &self,
+        lua: Context<'lua>,
+        event: &str,
+        args: A,
+    ) -> Result<Vec<Result<MultiValue<'lua>>>> {
+        let functions = match self.event_tables(lua, event)? {
+            Some((functions, _)) => functions
+                .sequence_values::<Function>()
+                .collect::<Result<Vec<_>>>()?,
+            None => return Ok(Vec::new()),
+        };
+        let args = args.to_lua_multi(lua)?;
+        Ok(functions
+            .into_iter()
+            .map(|function| function.call(args.clone()))
+            .collect())
+    }
+
+    fn event_tables<'lua>(
+        &self,
+        lua: Context<'lua>,
+        event: &str,
+    ) -> Result<Option<(Table<'lua>, Table<'lua>)>> {
+        let state: Table = lua.registry_value(&self.state)?;
+        let functions = state
+            .raw_get::<_, Table>("functions")?
+            .raw_get::<_, Option<Table>>(event)?;
+        let ids = state
+            .raw_get::<_, Table>("ids")?
+            .raw_get::<_, Option<Table>>(event)?;
+        Ok(functions.and_then(|functions| ids.map(|ids| (functions, ids))))
+    }
+}
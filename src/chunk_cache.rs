@@ -0,0 +1,141 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::os::raw::c_int;
+use std::string::String as StdString;
+
+/// Statistics of the cache of compiled chunks used by [`Context::load_cached`], returned by
+/// [`Lua::chunk_cache_stats`].
+///
+/// [`Context::load_cached`]: struct.Context.html#method.load_cached
+/// [`Lua::chunk_cache_stats`]: struct.Lua.html#method.chunk_cache_stats
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct ChunkCacheStats {
+    /// The number of loads which returned a cached function.
+    pub hits: usize,
+    /// The number of loads which compiled their source, either because no function was cached
+    /// for the key or because the source had changed.
+    pub misses: usize,
+    /// The number of functions removed to keep the cache within its capacity.
+    pub evictions: usize,
+    /// The number of functions currently cached.
+    pub len: usize,
+    /// The maximum number of functions cached.
+    pub capacity: usize,
+}
+
+pub(crate) const DEFAULT_CHUNK_CACHE_CAPACITY: usize = 64;
+
+struct Entry {
+    source_hash: u64,
+    // The source the function was compiled from, compared on every hit so that a hash collision
+    // can never return a function compiled from a different source.
+    source: Vec<u8>,
+    // The registry id of the compiled function.
+    id: c_int,
+    last_used: u64,
+}
+
+// A least recently used cache of the functions compiled by `Context::load_cached`, holding the
+// registry id of each function.  Registry ids returned by its methods are no longer cached, and
+// must be released by the caller.
+pub(crate) struct ChunkCache {
+    capacity: usize,
+    entries: HashMap<StdString, Entry>,
+    clock: u64,
+    hits: usize,
+    misses: usize,
+    evictions: usize,
+}
+
+impl ChunkCache {
+    pub fn new() -> ChunkCache {
+        ChunkCache {
+            capacity: DEFAULT_CHUNK_CACHE_CAPACITY,
+            entries: HashMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Returns the registry id of the function cached for `key`, if it was compiled from `source`.
+    pub fn get(&mut self, key: &str, source: &[u8]) -> Option<c_int> {
+        self.clock += 1;
+        let source_hash = hash_source(source);
+        match self.entries.get_mut(key) {
+            Some(entry) if entry.source_hash == source_hash && entry.source == source => {
+                entry.last_used = self.clock;
+                self.hits += 1;
+                Some(entry.id)
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    // Caches the function with the given registry id for `key`, replacing any function compiled
+    // from an older source.
+    pub fn insert(&mut self, key: &str, source: &[u8], id: c_int) -> Vec<c_int> {
+        let entry = Entry {
+            source_hash: hash_source(source),
+            source: source.to_vec(),
+            id,
+            last_used: self.clock,
+        };
+        let mut released = Vec::new();
+        if let Some(old) = self.entries.insert(key.to_owned(), entry) {
+            released.push(old.id);
+        }
+        released.extend(self.evict());
+        released
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) -> Vec<c_int> {
+        self.capacity = capacity;
+        self.evict()
+    }
+
+    pub fn clear(&mut self) -> Vec<c_int> {
+        self.entries.drain().map(|(_, entry)| entry.id).collect()
+    }
+
+    pub fn stats(&self) -> ChunkCacheStats {
+        ChunkCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            len: self.entries.len(),
+            capacity: self.capacity,
+        }
+    }
+
+    // Removes the least recently used functions until the cache is within its capacity.
+    fn evict(&mut self) -> Vec<c_int> {
+        let mut released = Vec::new();
+        while self.entries.len() > self.capacity {
+            let key = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            released.push(self.entries.remove(&key).unwrap().id);
+            self.evictions += 1;
+        }
+        released
+    }
+}
+
+fn hash_source(source: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(source);
+    hasher.finish()
+}
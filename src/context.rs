@@ -1,26 +1,52 @@
-use std::any::TypeId;
+use std::any::{type_name, TypeId};
 use std::cell::RefCell;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
+use std::fmt;
 use std::marker::PhantomData;
+use std::ops::Range;
 use std::os::raw::{c_char, c_int, c_void};
+use std::string::String as StdString;
 use std::sync::Arc;
-use std::{mem, ptr};
+use std::{mem, ptr, slice};
 
+use crate::batch::{self, BatchOptions};
+use crate::callback_registry::CallbackRegistry;
+use crate::coroutine::{self, CoroutineConfig};
+use crate::deprecation;
 use crate::error::{Error, Result};
 use crate::ffi;
 use crate::function::Function;
-use crate::lua::{extra_data, ExtraData, FUNCTION_METATABLE_REGISTRY_KEY};
+use crate::hook::{self, StackFrame};
+use crate::inspect::{self, InspectOptions};
+#[cfg(feature = "json")]
+use crate::json::{self, JsonOptions};
+use crate::lua::{
+    check_chunk_size, check_multivalue_limit, extra_data, ExtraData,
+    FUNCTION_METATABLE_REGISTRY_KEY, THREAD_BODIES_REGISTRY_KEY,
+};
+use crate::lua_enum::{self, LuaEnum};
 use crate::markers::{Invariant, NoUnwindSafe};
+use crate::number_format::format_number;
+use crate::sandbox::{self, SandboxOptions, SandboxReport};
 use crate::scope::Scope;
+use crate::snapshot::{self, GlobalsDiff, GlobalsSnapshot};
 use crate::string::String;
 use crate::table::Table;
+use crate::table_builder::TableBuilder;
 use crate::thread::Thread;
-use crate::types::{Callback, Integer, LightUserData, LuaRef, Number, RegistryKey};
-use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
+use crate::transfer::{Transfer, TransferOptions};
+use crate::types::{
+    Callback, Integer, LightUserData, LuaKey, LuaRef, Number, RegistryKey, TypeCategory,
+};
+use crate::userdata::{
+    check_tostring_helper, AnyUserData, MetaMethod, MethodDescriptor, TypeDescriptor, UserData,
+    UserDataHandle, UserDataMethods,
+};
 use crate::util::{
-    assert_stack, callback_error, check_stack, get_userdata, get_wrapped_error,
-    init_userdata_metatable, pop_error, protect_lua, protect_lua_closure, push_string,
-    push_userdata, push_wrapped_error, StackGuard,
+    apply_error_hook, assert_stack, callback_error, check_stack, expire_registry_values,
+    get_userdata, get_wrapped_error, init_tracked_userdata_metatable, pop_error_value, protect_lua,
+    protect_lua_closure, push_string, push_userdata, push_wrapped_error, to_string, track_userdata,
+    StackGuard,
 };
 use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
 
@@ -48,6 +74,126 @@ impl<'lua> Context<'lua> {
             source: source.as_ref(),
             name: None,
             env: None,
+            line_offset: 0,
+            resident: false,
+        }
+    }
+
+    /// Returns Lua source code as a `Chunk` builder type, like [`load`], but keeps a copy of the
+    /// source once it is loaded so that runtime errors can show the offending line.
+    ///
+    /// When a runtime error message refers to a line of this chunk, such as `script.lua:42: ...`,
+    /// the text of line 42 is added to the message after its first line.  Sources are looked up
+    /// by chunk name, so resident chunks should usually be given distinct names with
+    /// [`Chunk::set_name`].  A source is forgotten when another chunk with the same name is
+    /// loaded, whether resident or not, and only the sources of the 64 most recently loaded
+    /// resident chunks are kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Error, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let result = lua_context
+    ///     .load_resident("local x = 1\nlocal y = x + nil")
+    ///     .set_name("=script.lua")?
+    ///     .exec();
+    /// match result {
+    ///     Err(Error::RuntimeError(message)) => {
+    ///         assert!(message.starts_with("script.lua:2: attempt to perform arithmetic"));
+    ///         assert_eq!(message.lines().nth(1), Some("    local y = x + nil"));
+    ///     }
+    ///     r => panic!("unexpected result {:?}", r),
+    /// }
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`load`]: #method.load
+    /// [`Chunk::set_name`]: struct.Chunk.html#method.set_name
+    pub fn load_resident<'a, S>(self, source: &'a S) -> Chunk<'lua, 'a>
+    where
+        S: ?Sized + AsRef<[u8]>,
+    {
+        Chunk {
+            resident: true,
+            ..self.load(source)
+        }
+    }
+
+    /// Compiles `source` into a function, or returns the function compiled for `key` before if
+    /// its source has not changed since.
+    ///
+    /// This is meant for the same small chunks, such as templates, being loaded over and over.
+    /// Compiled functions are kept in the registry, in a least recently used cache keyed by `key`,
+    /// along with a copy of the source they were compiled from.  Loading a different source with
+    /// the same key compiles it again and replaces the cached function.  The capacity of the cache
+    /// is set by [`Lua::set_chunk_cache_capacity`], and it can be emptied with
+    /// [`Lua::clear_chunk_cache`].
+    /// `key` is also used as the name of the chunk, as by [`Chunk::set_name`].
+    ///
+    /// The same function is returned for every load of a key, so the function is not tied to any
+    /// environment: its `_ENV` upvalue is reset to the globals table each time it is returned.  To
+    /// run it with a custom environment, call [`Function::set_environment`] on the returned
+    /// function before each call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # let lua = Lua::new();
+    /// # lua.context(|lua_context| {
+    /// for name in &["left", "right"] {
+    ///     let greet = lua_context.load_cached("greet", "return 'hello ' .. ...")?;
+    ///     assert_eq!(greet.call::<_, String>(*name)?, format!("hello {}", name));
+    /// }
+    /// # Ok::<_, rlua::Error>(())
+    /// # })?;
+    /// let stats = lua.chunk_cache_stats();
+    /// assert_eq!((stats.hits, stats.misses), (1, 1));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Lua::set_chunk_cache_capacity`]: struct.Lua.html#method.set_chunk_cache_capacity
+    /// [`Lua::clear_chunk_cache`]: struct.Lua.html#method.clear_chunk_cache
+    /// [`Chunk::set_name`]: struct.Chunk.html#method.set_name
+    /// [`Function::set_environment`]: struct.Function.html#method.set_environment
+    pub fn load_cached<S>(self, key: &str, source: &S) -> Result<Function<'lua>>
+    where
+        S: ?Sized + AsRef<[u8]>,
+    {
+        let source = source.as_ref();
+        unsafe {
+            let extra = extra_data(self.state);
+            if let Some(id) = (*extra).chunk_cache.get(key, source) {
+                let function = {
+                    let _sg = StackGuard::new(self.state);
+                    assert_stack(self.state, 1);
+                    ffi::lua_rawgeti(self.state, ffi::LUA_REGISTRYINDEX, id as ffi::lua_Integer);
+                    Function(self.pop_ref())
+                };
+                function.set_environment(self.globals())?;
+                return Ok(function);
+            }
+
+            let function = self.load(source).set_name(key)?.into_function()?;
+            if (*extra).chunk_cache.capacity() > 0 {
+                let _sg = StackGuard::new(self.state);
+                assert_stack(self.state, 2);
+
+                self.push_ref(&function.0);
+                let id = protect_lua_closure(self.state, 1, 0, |state| {
+                    ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+                })?;
+                for id in (*extra).chunk_cache.insert(key, source, id) {
+                    ffi::luaL_unref(self.state, ffi::LUA_REGISTRYINDEX, id);
+                }
+            }
+            Ok(function)
         }
     }
 
@@ -66,6 +212,108 @@ impl<'lua> Context<'lua> {
         }
     }
 
+    /// Creates a Lua string, reusing a cached handle if the same bytes have been interned before.
+    ///
+    /// The first time a string is interned, it is created as with [`create_string`] and kept in
+    /// the registry.  Later calls with the same bytes fetch it from the registry instead, which
+    /// is cheaper than creating the string again.  Interned strings are never removed, so this is
+    /// meant for a small set of strings that are created over and over, such as the field names
+    /// used during serialization.  They are counted in [`Lua::registry_report`].
+    ///
+    /// [`create_string`]: #method.create_string
+    /// [`Lua::registry_report`]: struct.Lua.html#method.registry_report
+    pub fn intern_string<S>(self, s: &S) -> Result<String<'lua>>
+    where
+        S: ?Sized + AsRef<[u8]>,
+    {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 4);
+
+            let id = self.interned_string_id(s.as_ref())?;
+            ffi::lua_rawgeti(self.state, ffi::LUA_REGISTRYINDEX, id as ffi::lua_Integer);
+            Ok(String(self.pop_ref()))
+        }
+    }
+
+    /// Interns a string for use as a table key, returning a [`LuaKey`] handle to it.
+    ///
+    /// The string is interned as with [`intern_string`], and the returned key can be used with
+    /// [`Table::get_k`], [`Table::set_k`] and their raw counterparts, which look up the string in
+    /// the registry rather than creating it from the Rust bytes again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let position = lua_context.intern_key("position")?;
+    /// let entity = lua_context.create_table()?;
+    /// entity.set_k(&position, 10)?;
+    /// assert_eq!(entity.get::<_, i64>("position")?, 10);
+    /// assert_eq!(entity.get_k::<i64>(&position)?, 10);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`LuaKey`]: struct.LuaKey.html
+    /// [`intern_string`]: #method.intern_string
+    /// [`Table::get_k`]: struct.Table.html#method.get_k
+    /// [`Table::set_k`]: struct.Table.html#method.set_k
+    pub fn intern_key<S>(self, s: &S) -> Result<LuaKey>
+    where
+        S: ?Sized + AsRef<[u8]>,
+    {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 4);
+
+            let id = self.interned_string_id(s.as_ref())?;
+            Ok(LuaKey::new(
+                id,
+                (*extra_data(self.state)).registry_unref_list.clone(),
+            ))
+        }
+    }
+
+    /// Creates a Lua string from a sequence of byte chunks.
+    ///
+    /// The string is assembled inside Lua, so a large string built from many small chunks does
+    /// not need to be collected into a contiguous Rust buffer first.  Like [`create_string`], the
+    /// bytes are copied exactly, including any zero bytes or invalid UTF-8.
+    ///
+    /// [`create_string`]: #method.create_string
+    pub fn create_string_from_chunks<I, C>(self, chunks: I) -> Result<String<'lua>>
+    where
+        I: IntoIterator<Item = C>,
+        C: AsRef<[u8]>,
+    {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 5);
+
+            let mut buffer: ffi::luaL_Buffer = mem::zeroed();
+            let buffer = &mut buffer as *mut ffi::luaL_Buffer;
+            ffi::luaL_buffinit(self.state, buffer);
+
+            // Once the buffer outgrows its initial storage it moves into a userdata which
+            // `luaL_Buffer` expects at the top of the stack, so it is passed to and returned from
+            // each protected call.  Until then, nil takes its place.
+            ffi::lua_pushnil(self.state);
+            for chunk in chunks {
+                let chunk = chunk.as_ref();
+                protect_lua_closure(self.state, 1, 1, |_| {
+                    ffi::luaL_addlstring(buffer, chunk.as_ptr() as *const c_char, chunk.len());
+                })?;
+            }
+            protect_lua_closure(self.state, 1, 1, |_| ffi::luaL_pushresult(buffer))?;
+
+            Ok(String(self.pop_ref()))
+        }
+    }
+
     /// Creates and returns a new table.
     pub fn create_table(self) -> Result<Table<'lua>> {
         unsafe {
@@ -80,45 +328,142 @@ impl<'lua> Context<'lua> {
         }
     }
 
+    /// Creates and returns a new table with room for `narr` sequence elements and `nrec` other
+    /// fields, so that filling it up to that size does not need to grow it.
+    pub fn create_table_with_capacity(self, narr: usize, nrec: usize) -> Result<Table<'lua>> {
+        let narr = narr.min(c_int::MAX as usize) as c_int;
+        let nrec = nrec.min(c_int::MAX as usize) as c_int;
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 3);
+            protect_lua_closure(self.state, 0, 1, |state| {
+                ffi::lua_createtable(state, narr, nrec)
+            })?;
+            Ok(Table(self.pop_ref()))
+        }
+    }
+
     /// Creates a table and fills it with values from an iterator.
+    ///
+    /// The table is created with room for as many fields as the lower bound of the iterator's
+    /// size hint, which is the exact size of an `ExactSizeIterator` such as a `HashMap`.
     pub fn create_table_from<K, V, I>(self, cont: I) -> Result<Table<'lua>>
     where
         K: ToLua<'lua>,
         V: ToLua<'lua>,
         I: IntoIterator<Item = (K, V)>,
     {
-        unsafe {
-            let _sg = StackGuard::new(self.state);
-            // `Lua` instance assumes that on any callback, the Lua stack has at least LUA_MINSTACK
-            // slots available to avoid panics.
-            check_stack(self.state, 5 + ffi::LUA_MINSTACK)?;
-
-            unsafe extern "C" fn new_table(state: *mut ffi::lua_State) -> c_int {
-                ffi::lua_newtable(state);
-                1
-            }
-            protect_lua(self.state, 0, new_table)?;
-
-            for (k, v) in cont {
-                self.push_value(k.to_lua(self)?)?;
-                self.push_value(v.to_lua(self)?)?;
-                unsafe extern "C" fn raw_set(state: *mut ffi::lua_State) -> c_int {
-                    ffi::lua_rawset(state, -3);
-                    1
-                }
-                protect_lua(self.state, 3, raw_set)?;
-            }
-            Ok(Table(self.pop_ref()))
-        }
+        let cont = cont.into_iter();
+        let table = self.create_table_with_capacity(0, cont.size_hint().0)?;
+        self.fill_table(table, cont)
     }
 
     /// Creates a table from an iterator of values, using `1..` as the keys.
+    ///
+    /// Like [`create_table_from`], the table is created with room for as many values as the lower
+    /// bound of the iterator's size hint.
+    ///
+    /// [`create_table_from`]: #method.create_table_from
     pub fn create_sequence_from<T, I>(self, cont: I) -> Result<Table<'lua>>
     where
         T: ToLua<'lua>,
         I: IntoIterator<Item = T>,
     {
-        self.create_table_from(cont.into_iter().enumerate().map(|(k, v)| (k + 1, v)))
+        let cont = cont.into_iter();
+        let table = self.create_table_with_capacity(cont.size_hint().0, 0)?;
+        self.fill_table(table, cont.enumerate().map(|(k, v)| (k + 1, v)))
+    }
+
+    /// Returns a [`TableBuilder`] for creating a table of functions and constants.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let vec2 = lua_context
+    ///     .table_builder()
+    ///     .value("zero", lua_context.create_sequence_from(vec![0, 0])?)
+    ///     .function("dot", |_, (a, b): (Vec<i64>, Vec<i64>)| Ok(a[0] * b[0] + a[1] * b[1]))
+    ///     .build()?;
+    /// assert_eq!(vec2.get::<_, Function>("dot")?.call::<_, i64>((vec![1, 2], vec![3, 4]))?, 11);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`TableBuilder`]: struct.TableBuilder.html
+    pub fn table_builder(self) -> TableBuilder<'lua> {
+        TableBuilder::new(self, StdString::new())
+    }
+
+    /// Builds a table with a [`TableBuilder`] and sets it as the global `name`.
+    ///
+    /// If the `package` library is loaded, the table is also stored in `package.loaded`, so that
+    /// scripts can `require` it.  Errors from the builder hold the path of the failed entry
+    /// starting with `name`.
+    ///
+    /// [`TableBuilder`]: struct.TableBuilder.html
+    pub fn register_global_module<F>(self, name: &str, build: F) -> Result<Table<'lua>>
+    where
+        F: FnOnce(TableBuilder<'lua>) -> TableBuilder<'lua>,
+    {
+        let module = build(TableBuilder::new(self, name.to_owned())).build()?;
+        let globals = self.globals();
+        globals.set(name, module.clone())?;
+        if let Value::Table(package) = globals.raw_get("package")? {
+            if let Value::Table(loaded) = package.raw_get("loaded")? {
+                loaded.set(name, module.clone())?;
+            }
+        }
+        Ok(module)
+    }
+
+    /// Creates a read-only table of the variants of a [`LuaEnum`] and sets it as the global
+    /// `name`.
+    ///
+    /// Each variant is available as `name.VARIANT`, and `name.names` maps the integer values back
+    /// to the variant names.  Iterating over the table with `pairs` yields only the variants, and
+    /// assigning to it raises an error.
+    ///
+    /// Returns an error if one of the variants is itself called `names`.
+    ///
+    /// [`LuaEnum`]: trait.LuaEnum.html
+    pub fn register_enum<E: LuaEnum>(self, name: &str) -> Result<Table<'lua>> {
+        let table = lua_enum::create_enum_table::<E>(self)?;
+        self.globals().set(name, table.clone())?;
+        Ok(table)
+    }
+
+    /// Installs a `coroutine` library whose coroutines are managed according to `config`.
+    ///
+    /// This is meant for states created without the standard `coroutine` library, and replaces it
+    /// if it is loaded.  The library is set as the global `coroutine`, and stored in
+    /// `package.loaded` if the `package` library is loaded.
+    ///
+    /// `coroutine.create` and `coroutine.wrap` enforce the limit on live coroutines and attach the
+    /// instruction budget from `config`.  Like `pcall`, `coroutine.resume` does not catch Rust
+    /// panics raised inside the coroutine, they continue to propagate.  `coroutine.yield` and
+    /// `coroutine.isyieldable` are always available, and `coroutine.status` and
+    /// `coroutine.running` unless disabled in `config`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{CoroutineConfig, Lua, Result, StdLib};
+    /// # fn main() -> Result<()> {
+    /// Lua::new_with(StdLib::BASE).context(|lua_context| {
+    ///     lua_context.install_managed_coroutines(CoroutineConfig::new().budget(100_000))?;
+    ///     lua_context.load(r#"
+    ///         local spin = coroutine.wrap(function() while true do end end)
+    ///         assert(not pcall(spin))
+    ///     "#).exec()
+    /// })
+    /// # }
+    /// ```
+    pub fn install_managed_coroutines(self, config: CoroutineConfig) -> Result<Table<'lua>> {
+        coroutine::install(self, config)
     }
 
     /// Wraps a Rust function or closure, creating a callable Lua function handle to it.
@@ -179,6 +524,52 @@ impl<'lua> Context<'lua> {
         }))
     }
 
+    /// Wraps a Rust function or closure like [`create_function`], giving it a name which is shown
+    /// in tracebacks.
+    ///
+    /// A Rust function has no name of its own, so tracebacks can only name it after the variable
+    /// or field it was called through, and show `?` when it is called in other ways, such as
+    /// through `pcall` or a metamethod.  A function created with this method is always shown as
+    /// `function 'name'` in the tracebacks of [`Error::CallbackError`], and has the name in the
+    /// frames returned by [`backtrace`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Error, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let load_asset = lua_context.create_named_function("load_asset", |_, path: String| {
+    ///     Err::<(), _>(Error::RuntimeError(format!("no asset at {}", path)))
+    /// })?;
+    /// lua_context.globals().set("assets", vec![load_asset])?;
+    ///
+    /// match lua_context.load("assets[1]('missing.png')").exec() {
+    ///     Err(Error::CallbackError { traceback, .. }) => {
+    ///         assert!(traceback.contains("[C]: in function 'load_asset'"));
+    ///     }
+    ///     r => panic!("unexpected result {:?}", r),
+    /// }
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`create_function`]: #method.create_function
+    /// [`backtrace`]: #method.backtrace
+    /// [`Error::CallbackError`]: enum.Error.html#variant.CallbackError
+    pub fn create_named_function<A, R, F>(self, name: &str, func: F) -> Result<Function<'lua>>
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, A) -> Result<R>,
+    {
+        self.create_named_callback(
+            Some(name),
+            Box::new(move |lua, args| func(lua, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)),
+        )
+    }
+
     /// Wraps a Rust mutable closure, creating a callable Lua function handle to it.
     ///
     /// This is a version of [`create_function`] that accepts a FnMut argument.  Refer to
@@ -199,16 +590,80 @@ impl<'lua> Context<'lua> {
         })
     }
 
+    /// Wraps a Rust iterator into a Lua function which returns its next item on each call, for use
+    /// as the iterator of a generic `for` loop.
+    ///
+    /// Each item is converted to the values of one iteration, and once the iterator is exhausted
+    /// the function returns nothing, which ends the loop.  An `Err` item raises the error in Lua.
+    /// The iterator is held by the function itself, so it is dropped when the function is
+    /// collected.  Returning such a function from the `__pairs` metamethod, or from any method,
+    /// lets scripts loop over a userdata.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, MetaMethod, Result, UserData, UserDataMethods};
+    /// # fn main() -> Result<()> {
+    /// struct Inventory(Vec<(String, u32)>);
+    ///
+    /// impl UserData for Inventory {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_meta_method(MetaMethod::Pairs, |lua, inventory, ()| {
+    ///             lua.create_iterator(inventory.0.clone().into_iter().map(Ok))
+    ///         });
+    ///     }
+    /// }
+    ///
+    /// # Lua::new().context(|lua_context| {
+    /// let inventory = Inventory(vec![("arrows".to_owned(), 12), ("potions".to_owned(), 3)]);
+    /// lua_context.globals().set("inventory", inventory)?;
+    /// let total: u32 = lua_context.load(r#"
+    ///     local total = 0
+    ///     for name, count in pairs(inventory) do
+    ///         total = total + count
+    ///     end
+    ///     return total
+    /// "#).eval()?;
+    /// assert_eq!(total, 15);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn create_iterator<I, T>(self, iter: I) -> Result<Function<'lua>>
+    where
+        I: 'static + Send + Iterator<Item = Result<T>>,
+        T: ToLuaMulti<'lua>,
+    {
+        let mut iter = iter.fuse();
+        self.create_function_mut(move |lua, _: MultiValue| match iter.next() {
+            Some(item) => item?.to_lua_multi(lua),
+            None => Ok(MultiValue::new()),
+        })
+    }
+
     /// Wraps a Lua function into a new thread (or coroutine).
     ///
-    /// Equivalent to `coroutine.create`.
+    /// Equivalent to `coroutine.create`.  The thread remembers `func`, so that
+    /// [`Thread::body_matches`] can identify it even after it has finished.
+    ///
+    /// [`Thread::body_matches`]: struct.Thread.html#method.body_matches
     pub fn create_thread(self, func: Function<'lua>) -> Result<Thread<'lua>> {
         unsafe {
             let _sg = StackGuard::new(self.state);
-            assert_stack(self.state, 2);
+            assert_stack(self.state, 4);
 
             let thread_state =
                 protect_lua_closure(self.state, 0, 1, |state| ffi::lua_newthread(state))?;
+
+            ffi::lua_pushlightuserdata(
+                self.state,
+                &THREAD_BODIES_REGISTRY_KEY as *const u8 as *mut c_void,
+            );
+            ffi::lua_rawget(self.state, ffi::LUA_REGISTRYINDEX);
+            ffi::lua_pushvalue(self.state, -2);
+            self.push_ref(&func.0);
+            protect_lua_closure(self.state, 3, 0, |state| ffi::lua_rawset(state, -3))?;
+
             self.push_ref(&func.0);
             ffi::lua_xmove(self.state, thread_state, 1);
 
@@ -224,6 +679,42 @@ impl<'lua> Context<'lua> {
         unsafe { self.make_userdata(data) }
     }
 
+    /// Create a Lua userdata object from a custom userdata type, returning a handle which
+    /// remembers the type.
+    ///
+    /// This is like [`create_userdata`], but borrowing through the returned [`UserDataHandle`]
+    /// skips the type check done by [`AnyUserData::borrow`].
+    ///
+    /// [`create_userdata`]: #method.create_userdata
+    /// [`UserDataHandle`]: struct.UserDataHandle.html
+    /// [`AnyUserData::borrow`]: struct.AnyUserData.html#method.borrow
+    pub fn create_typed_userdata<T>(self, data: T) -> Result<UserDataHandle<'lua, T>>
+    where
+        T: 'static + Send + UserData,
+    {
+        unsafe { Ok(UserDataHandle::new(self.make_userdata(data)?)) }
+    }
+
+    /// Create a Lua userdata object from a boxed custom userdata type.
+    ///
+    /// The Lua userdata holds the `Box` itself, so the value is never moved out of its heap
+    /// allocation.  This avoids copying large values, which with [`create_userdata`] are moved
+    /// into memory allocated by Lua and may overflow the stack on the way.  The box is dropped
+    /// when the userdata is garbage collected.
+    ///
+    /// The userdata behaves exactly like one created by [`create_userdata`]: it has the same
+    /// methods, and [`AnyUserData::borrow`] and [`AnyUserData::is`] treat it as a `T`.
+    ///
+    /// [`create_userdata`]: #method.create_userdata
+    /// [`AnyUserData::borrow`]: struct.AnyUserData.html#method.borrow
+    /// [`AnyUserData::is`]: struct.AnyUserData.html#method.is
+    pub fn create_userdata_boxed<T>(self, data: Box<T>) -> Result<AnyUserData<'lua>>
+    where
+        T: 'static + Send + UserData,
+    {
+        unsafe { self.make_boxed_userdata(data) }
+    }
+
     /// Returns a handle to the global environment.
     pub fn globals(self) -> Table<'lua> {
         unsafe {
@@ -234,16 +725,153 @@ impl<'lua> Context<'lua> {
         }
     }
 
+    /// Returns a read-only view of the global environment, which can be handed to introspection
+    /// or debugging code that should not assign globals.
+    ///
+    /// Only the top level is read-only: the view returns the real values of the globals, so the
+    /// tables they hold can still be modified through it, and `view.string.format = nil` changes
+    /// the real `string` table.  To protect such a table as well, make it read-only with
+    /// [`Table::set_readonly`].
+    ///
+    /// The view is an empty table whose `__index` and `__pairs` metamethods read from the globals
+    /// table, so it always reflects the current globals, and whose `__newindex` metamethod raises
+    /// the error `attempt to modify readonly table`.  Its metatable is protected by a
+    /// `__metatable` field.  Like a table made read-only by [`Table::set_readonly`], methods which
+    /// do not invoke metamethods, such as [`Table::raw_get`] and [`Table::pairs`], see an empty
+    /// table.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// lua_context.globals().set("level", 3)?;
+    /// let view = lua_context.globals_readonly()?;
+    /// assert_eq!(view.get::<_, i64>("level")?, 3);
+    ///
+    /// let inspect: Function = lua_context.load("function(g) g.level = 4 end").eval()?;
+    /// assert!(inspect.call::<_, ()>(view).is_err());
+    /// assert_eq!(lua_context.globals().get::<_, i64>("level")?, 3);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Table::set_readonly`]: struct.Table.html#method.set_readonly
+    /// [`Table::raw_get`]: struct.Table.html#method.raw_get
+    /// [`Table::pairs`]: struct.Table.html#method.pairs
+    pub fn globals_readonly(self) -> Result<Table<'lua>> {
+        self.globals().readonly_view()
+    }
+
+    /// Marks a global as deprecated, so that accessing it calls the handler set with
+    /// [`Lua::set_deprecation_handler`].
+    ///
+    /// `path` is the name of a global, or a dotted path to a field of a global table such as
+    /// `"units.spawn"`.  The value is moved out of its containing table into a hidden table, and
+    /// the containing table is given an `__index` metamethod that reports the access and returns
+    /// the value unchanged, so a deprecated function still behaves exactly as before.  An existing
+    /// `__index` metamethod is still used for other keys.
+    ///
+    /// The value is hidden from anything which does not go through `__index`: it is not seen by
+    /// `pairs` or by `rawget`, so code which iterates over the globals, for example to copy them,
+    /// misses it.  Assigning a new value to the global replaces it without any report.
+    ///
+    /// Deprecating the same global again replaces its message, and it is reported again even if
+    /// it had already been reported.
+    ///
+    /// [`Lua::set_deprecation_handler`]: struct.Lua.html#method.set_deprecation_handler
+    pub fn deprecate_global(self, path: &str, message: &str) -> Result<()> {
+        deprecation::deprecate_global(self, path, message)
+    }
+
+    /// Calls `f` with `env` in place of the global environment, and restores the original global
+    /// environment afterwards, even if `f` returns an error or panics.
+    ///
+    /// While `f` runs, [`globals`] returns `env`, and chunks loaded with [`load`] use `env` as
+    /// their `_ENV`, including after `f` has returned.  Functions loaded before the call keep the
+    /// environment they were loaded with, since Lua functions capture `_ENV` when they are loaded,
+    /// so to sandbox a function, load it inside `f`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// lua_context.globals().set("x", 1)?;
+    ///
+    /// let env = lua_context.create_table()?;
+    /// env.set("x", 2)?;
+    /// let x: i64 = lua_context.with_temporary_globals(env, || lua_context.load("x").eval())?;
+    /// assert_eq!(x, 2);
+    ///
+    /// assert_eq!(lua_context.load("x").eval::<i64>()?, 1);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`globals`]: #method.globals
+    /// [`load`]: #method.load
+    pub fn with_temporary_globals<R, F>(self, env: Table<'lua>, f: F) -> Result<R>
+    where
+        F: FnOnce() -> Result<R>,
+    {
+        struct RestoreGlobals<'lua>(Table<'lua>);
+
+        impl<'lua> Drop for RestoreGlobals<'lua> {
+            fn drop(&mut self) {
+                let lua = (self.0).0.lua;
+                unsafe {
+                    let _sg = StackGuard::new(lua.state);
+                    assert_stack(lua.state, 1);
+                    lua.push_ref(&(self.0).0);
+                    // The globals slot already exists in the registry, so this cannot fail.
+                    ffi::lua_rawseti(lua.state, ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_GLOBALS);
+                }
+            }
+        }
+
+        let _restore = RestoreGlobals(self.globals());
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
+            self.push_ref(&env.0);
+            ffi::lua_rawseti(self.state, ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_GLOBALS);
+        }
+        f()
+    }
+
     /// Returns a handle to the active `Thread` for this `Context`.  For calls to `Lua::context`
     /// this will be the main Lua thread, for `Context` parameters given to a callback, this will be
     /// whatever Lua thread called the callback.
+    ///
+    /// The returned handle keeps the thread alive, to keep a thread past the end of a callback,
+    /// for example to resume it later from an event loop, place it in the registry with
+    /// [`create_registry_value`].
+    ///
+    /// [`create_registry_value`]: #method.create_registry_value
     pub fn current_thread(self) -> Thread<'lua> {
         unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
             ffi::lua_pushthread(self.state);
             Thread(self.pop_ref())
         }
     }
 
+    /// Returns true if this `Context` is running on the main Lua thread, and false if it is running
+    /// inside a coroutine.
+    pub fn is_main_thread(self) -> bool {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
+            ffi::lua_pushthread(self.state) != 0
+        }
+    }
+
     /// Calls the given function with a `Scope` parameter, giving the function the ability to create
     /// userdata and callbacks from rust types that are !Send or non-'static.
     ///
@@ -275,11 +903,20 @@ impl<'lua> Context<'lua> {
     /// behavior.
     ///
     /// To succeed, the value must be a string (in which case this is a no-op), an integer, or a
-    /// number.
+    /// number.  Numbers are formatted as set by [`Lua::set_number_format`].
+    ///
+    /// [`Lua::set_number_format`]: struct.Lua.html#method.set_number_format
     pub fn coerce_string(self, v: Value<'lua>) -> Result<Option<String<'lua>>> {
         Ok(match v {
             Value::String(s) => Some(s),
             v => unsafe {
+                if let Value::Number(n) = v {
+                    let number_format = (*extra_data(self.state)).number_format;
+                    if let Some(s) = format_number(number_format, n) {
+                        return Ok(Some(self.create_string(&s)?));
+                    }
+                }
+
                 let _sg = StackGuard::new(self.state);
                 assert_stack(self.state, 4);
 
@@ -344,6 +981,207 @@ impl<'lua> Context<'lua> {
         })
     }
 
+    /// Returns the field `name` of the metatable of a value, like `luaL_getmetafield`.
+    ///
+    /// Returns `None` if the value has no metatable or the field is nil.  The field is read with
+    /// a raw access from the actual metatable, so this works even when the metatable is protected
+    /// by a `__metatable` field.
+    pub fn get_metafield(self, value: &Value<'lua>, name: &str) -> Result<Option<Value<'lua>>> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 5);
+
+            self.push_value(value.clone())?;
+            if ffi::lua_getmetatable(self.state, -1) == 0 {
+                return Ok(None);
+            }
+            push_string(self.state, name)?;
+            ffi::lua_rawget(self.state, -2);
+            Ok(match self.pop_value() {
+                Value::Nil => None,
+                v => Some(v),
+            })
+        }
+    }
+
+    /// Returns the metatable shared by all values of the type `ty`.
+    ///
+    /// When the `string` library is loaded, strings have a metatable whose `__index` is the
+    /// `string` table, which is what allows method calls such as `("abc"):upper()`.  The other
+    /// types have no metatable unless one is set with [`set_metatable_of`].
+    ///
+    /// [`set_metatable_of`]: #method.set_metatable_of
+    pub fn metatable_of(self, ty: TypeCategory) -> Result<Option<Table<'lua>>> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 3);
+
+            push_type_exemplar(self.state, ty)?;
+            if ffi::lua_getmetatable(self.state, -1) == 0 {
+                return Ok(None);
+            }
+            Ok(Some(Table(self.pop_ref())))
+        }
+    }
+
+    /// Sets or removes the metatable shared by all values of the type `ty`, like
+    /// `debug.setmetatable` does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, TypeCategory};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let metatable = lua_context.create_table()?;
+    /// metatable.set("__index", lua_context.create_function(|_, (n, key): (f64, String)| {
+    ///     Ok(if key == "squared" { Some(n * n) } else { None })
+    /// })?)?;
+    /// lua_context.set_metatable_of(TypeCategory::Number, Some(metatable))?;
+    /// assert_eq!(lua_context.load("(3).squared").eval::<f64>()?, 9.0);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn set_metatable_of(self, ty: TypeCategory, metatable: Option<Table<'lua>>) -> Result<()> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 3);
+
+            push_type_exemplar(self.state, ty)?;
+            match metatable {
+                Some(metatable) => self.push_ref(&metatable.0),
+                None => ffi::lua_pushnil(self.state),
+            }
+            ffi::lua_setmetatable(self.state, -2);
+            Ok(())
+        }
+    }
+
+    /// Protects the string metatable from scripts by setting its `__metatable` field, so that
+    /// `getmetatable("")` returns `false`.  Method calls on strings keep working.
+    ///
+    /// If `copy_index` is true and the `__index` field is a table, usually the global `string`
+    /// table, it is replaced with a shallow copy, so that scripts modifying `string` cannot change
+    /// the methods seen by strings.  If strings have no metatable, an empty protected one is set.
+    pub fn seal_string_metatable(self, copy_index: bool) -> Result<()> {
+        let metatable = match self.metatable_of(TypeCategory::String)? {
+            Some(metatable) => metatable,
+            None => {
+                let metatable = self.create_table()?;
+                self.set_metatable_of(TypeCategory::String, Some(metatable.clone()))?;
+                metatable
+            }
+        };
+        if copy_index {
+            if let Value::Table(index) = metatable.raw_get::<_, Value>("__index")? {
+                let copy = self.create_table()?;
+                for pair in index.pairs::<Value, Value>() {
+                    let (k, v) = pair?;
+                    copy.raw_set(k, v)?;
+                }
+                metatable.raw_set("__index", copy)?;
+            }
+        }
+        metatable.raw_set("__metatable", false)
+    }
+
+    /// Locks down the global environment for running untrusted scripts, returning what was
+    /// removed and replaced.
+    ///
+    /// Unless kept with [`SandboxOptions::keep`], this:
+    ///
+    /// - removes `dofile`, `loadfile`, `collectgarbage` and `load`, or replaces `load` with a
+    ///   version that only loads text chunks if [`SandboxOptions::allow_load_text_chunks`] is set
+    /// - removes the `io`, `os` and `debug` libraries, from the globals and from `package.loaded`
+    /// - removes `string.dump` and `package.loadlib`, and leaves only the `package.preload`
+    ///   searcher in `package.searchers`, so that `require` cannot load files or C libraries
+    /// - replaces `string.rep` with a version that refuses to build very large strings
+    /// - replaces `print` with a version that passes its output to a handler, or discards it
+    /// - seals the string metatable, as by [`seal_string_metatable`] with `copy_index`
+    /// - sets the `__metatable` field of the number metatable, if numbers have one, so that
+    ///   scripts cannot get at it
+    ///
+    /// Parts of the standard library which are not loaded, such as in a state created by
+    /// [`Lua::new_with`], are skipped.  Calling this again changes nothing, except that the
+    /// handler of `print` is updated from the new options and the limit of `string.rep` is
+    /// lowered if the new options have a lower one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, SandboxOptions};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let report = lua_context.sandbox(SandboxOptions::new().keep("os.time"))?;
+    /// assert!(report.removed.contains(&"io".to_owned()));
+    /// assert!(report.replaced.contains(&"os".to_owned()));
+    ///
+    /// lua_context.load(r#"
+    ///     assert(io == nil and os.execute == nil and os.time() > 0)
+    ///     assert(not pcall(require, "io"))
+    /// "#).exec()?;
+    /// assert_eq!(lua_context.sandbox(SandboxOptions::new().keep("os.time"))?, Default::default());
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`SandboxOptions::keep`]: struct.SandboxOptions.html#method.keep
+    /// [`SandboxOptions::allow_load_text_chunks`]: struct.SandboxOptions.html#method.allow_load_text_chunks
+    /// [`seal_string_metatable`]: #method.seal_string_metatable
+    /// [`Lua::new_with`]: struct.Lua.html#method.new_with
+    pub fn sandbox(self, options: SandboxOptions) -> Result<SandboxReport> {
+        sandbox::sandbox(self, options)
+    }
+
+    /// Evaluates each of a batch of named chunks like [`Chunk::eval`], returning the name and the
+    /// result of every chunk in the order they were given.
+    ///
+    /// Every chunk is compiled and run independently, so a syntax or runtime error in one chunk is
+    /// returned as its result and does not stop the rest of the batch.  A panic in a Rust callback
+    /// is also returned as an error if the state was created with
+    /// [`LuaOptions::catch_rust_panics`] disabled, otherwise it propagates as usual.
+    ///
+    /// By default the chunks run in the global environment, so one chunk can see the globals
+    /// assigned by the chunks before it.  Use [`BatchOptions::environment`] to give each chunk an
+    /// environment of its own.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{BatchOptions, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let chunks = vec![
+    ///     ("width".to_owned(), "40 * 2".to_owned()),
+    ///     ("height".to_owned(), "20 +".to_owned()),
+    /// ];
+    /// let options = BatchOptions::new().environment(lua_context.globals());
+    /// let results = lua_context.eval_batch::<i64, _>(chunks, options);
+    /// assert_eq!(results[0].0, "width");
+    /// assert_eq!(*results[0].1.as_ref().unwrap(), 80);
+    /// assert!(results[1].1.is_err());
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Chunk::eval`]: struct.Chunk.html#method.eval
+    /// [`LuaOptions::catch_rust_panics`]: struct.LuaOptions.html#method.catch_rust_panics
+    /// [`BatchOptions::environment`]: struct.BatchOptions.html#method.environment
+    pub fn eval_batch<R, I>(
+        self,
+        chunks: I,
+        options: BatchOptions<'lua>,
+    ) -> Vec<(StdString, Result<R>)>
+    where
+        R: FromLuaMulti<'lua>,
+        I: IntoIterator<Item = (StdString, StdString)>,
+    {
+        batch::eval_batch(self, chunks, options)
+    }
+
     /// Converts a value that implements `ToLua` into a `Value` instance.
     pub fn pack<T: ToLua<'lua>>(self, t: T) -> Result<Value<'lua>> {
         t.to_lua(self)
@@ -361,9 +1199,323 @@ impl<'lua> Context<'lua> {
 
     /// Converts a `MultiValue` instance into a value that implements `FromLuaMulti`.
     pub fn unpack_multi<T: FromLuaMulti<'lua>>(self, value: MultiValue<'lua>) -> Result<T> {
+        unsafe {
+            check_multivalue_limit(self.state, value.len())?;
+        }
         T::from_lua_multi(value, self)
     }
 
+    /// Copies a value from this Lua state into the state of another `Context`.
+    ///
+    /// Plain data is deep copied: nil, booleans, numbers, strings, light userdata, errors and
+    /// tables of these.  Tables referenced multiple times (including through cycles) are copied
+    /// only once, so the shape of the data is preserved.  Table metatables are not copied.
+    ///
+    /// Functions, threads and userdata cannot be copied, and attempting to transfer them (or a
+    /// table containing them) results in an [`Error::NotTransferable`] naming where the value was
+    /// found.  Use [`transfer_with`] to transfer metatables or to allow specific userdata types.
+    /// Tables nested more than 200 levels deep are not transferred either, and also result in an
+    /// [`Error::NotTransferable`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, Table};
+    /// # fn main() -> Result<()> {
+    /// let worker = Lua::new();
+    /// let main = Lua::new();
+    /// worker.context(|worker_context| {
+    ///     main.context(|main_context| {
+    ///         let result = worker_context.load("{ total = 10, items = { 'a', 'b' } }").eval()?;
+    ///         let result = worker_context.transfer(result, main_context)?;
+    ///         let result: Table = main_context.unpack(result)?;
+    ///         assert_eq!(result.get::<_, i64>("total")?, 10);
+    ///         Ok(())
+    ///     })
+    /// })
+    /// # }
+    /// ```
+    ///
+    /// [`Error::NotTransferable`]: enum.Error.html#variant.NotTransferable
+    /// [`transfer_with`]: #method.transfer_with
+    pub fn transfer<'target>(
+        self,
+        value: Value<'lua>,
+        target: Context<'target>,
+    ) -> Result<Value<'target>> {
+        self.transfer_with(value, target, &TransferOptions::new())
+    }
+
+    /// Copies a value from this Lua state into the state of another `Context`, as configured by
+    /// the given [`TransferOptions`].
+    ///
+    /// See [`transfer`] for more details.
+    ///
+    /// [`TransferOptions`]: struct.TransferOptions.html
+    /// [`transfer`]: #method.transfer
+    pub fn transfer_with<'target>(
+        self,
+        value: Value<'lua>,
+        target: Context<'target>,
+        options: &TransferOptions,
+    ) -> Result<Value<'target>> {
+        Transfer::new(target, options).value(value, "")
+    }
+
+    /// Renders a value as a human readable string, for debugging.
+    ///
+    /// Tables are rendered with their contents, sequence values first and then the remaining
+    /// entries sorted by key.  Tables reached more than once are labelled as `<1>{ ... }` on
+    /// their first appearance and shown as `<table 1>` afterwards, so cyclic tables are safe to
+    /// inspect.  Nesting depth, the number of entries per table and the length of strings are
+    /// limited as configured by `options`.  No metamethods are invoked, except that userdata are
+    /// shown with the `__name` field of their metatable, if present.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{InspectOptions, Lua, Result, Value};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let value: Value = lua_context.load("{ 1, 2, name = 'point', nested = { x = 1 } }").eval()?;
+    /// assert_eq!(
+    ///     lua_context.inspect(&value, &InspectOptions::new().max_depth(1))?,
+    ///     r#"{ 1, 2, name = "point", nested = {...} }"#,
+    /// );
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn inspect(self, value: &Value<'lua>, options: &InspectOptions) -> Result<StdString> {
+        let number_format = unsafe { (*extra_data(self.state)).number_format };
+        inspect::inspect(value, options, number_format)
+    }
+
+    /// Returns the frames of the Lua call stack, starting with the innermost function.
+    ///
+    /// Unlike the string traceback attached to errors, this gives the source, current line, name
+    /// and kind of each function separately.  It is most useful from inside a Rust callback or a
+    /// hook, where the first frame is the callback itself.  Called outside of any Lua code, the
+    /// stack is empty.
+    pub fn backtrace(self) -> Vec<StackFrame> {
+        unsafe { hook::backtrace(self.state) }
+    }
+
+    /// Converts a Lua value to JSON, using the default [`JsonOptions`].
+    ///
+    /// The conversion follows these rules:
+    ///
+    /// * `nil` becomes `null`, and integers and floats stay integers and floats.
+    /// * NaN and infinite numbers cannot be represented in JSON and are an error.
+    /// * Strings must be valid UTF-8.
+    /// * A table whose keys are exactly `1..n` becomes an array.  Any other table, including an
+    ///   empty one, becomes an object: string keys are kept, number keys are converted to strings,
+    ///   and other keys are an error.  Tables mixing a sequence with other keys become objects
+    ///   unless disabled with [`JsonOptions::allow_mixed_tables`].
+    /// * Metamethods are not used, and functions, userdata and threads are an error.
+    /// * A table containing itself is an error, and so is nesting tables more deeply than
+    ///   [`JsonOptions::max_depth`] allows.
+    ///
+    /// Errors are `Error::FromLuaConversionError`, with a message giving the location of the
+    /// value which could not be converted, such as `items[2].name`.
+    ///
+    /// [`JsonOptions`]: struct.JsonOptions.html
+    /// [`JsonOptions::allow_mixed_tables`]: struct.JsonOptions.html#method.allow_mixed_tables
+    /// [`JsonOptions::max_depth`]: struct.JsonOptions.html#method.max_depth
+    #[cfg(feature = "json")]
+    pub fn to_json(self, value: Value<'lua>) -> Result<serde_json::Value> {
+        json::to_json(value, &JsonOptions::new())
+    }
+
+    /// Converts a Lua value to JSON, as [`to_json`] does, using the given options.
+    ///
+    /// [`to_json`]: #method.to_json
+    #[cfg(feature = "json")]
+    pub fn to_json_with(
+        self,
+        value: Value<'lua>,
+        options: &JsonOptions,
+    ) -> Result<serde_json::Value> {
+        json::to_json(value, options)
+    }
+
+    /// Converts JSON to a Lua value.
+    ///
+    /// Arrays become sequences and objects become tables with string keys.  `null` becomes `nil`,
+    /// so `null` members of objects are left out, and `null` elements of arrays leave holes in
+    /// the sequence.  Numbers which fit in a Lua integer become integers, and all others become
+    /// floats.
+    ///
+    /// Arrays and objects nested more deeply than the default [`JsonOptions::max_depth`] are an
+    /// `Error::ToLuaConversionError`.
+    ///
+    /// [`JsonOptions::max_depth`]: struct.JsonOptions.html#method.max_depth
+    #[cfg(feature = "json")]
+    pub fn from_json(self, json: &serde_json::Value) -> Result<Value<'lua>> {
+        json::from_json(self, json, &JsonOptions::new())
+    }
+
+    /// Converts JSON to a Lua value, as [`from_json`] does, using the given options.
+    ///
+    /// [`from_json`]: #method.from_json
+    #[cfg(feature = "json")]
+    pub fn from_json_with(
+        self,
+        json: &serde_json::Value,
+        options: &JsonOptions,
+    ) -> Result<Value<'lua>> {
+        json::from_json(self, json, options)
+    }
+
+    /// Parses JSON text and converts it to a Lua value, as [`from_json`] does.
+    ///
+    /// Text which is not valid JSON is an `Error::ToLuaConversionError` with the message of the
+    /// parser.  There is no null sentinel value, so `null` becomes `nil`.
+    ///
+    /// [`from_json`]: #method.from_json
+    #[cfg(feature = "json")]
+    pub fn create_table_from_json(self, json: &str) -> Result<Value<'lua>> {
+        let json = serde_json::from_str(json).map_err(|err| Error::ToLuaConversionError {
+            from: "JSON",
+            to: "table",
+            message: Some(err.to_string()),
+        })?;
+        self.from_json(&json)
+    }
+
+    /// Saves the current contents of the global environment, so that it can later be reset with
+    /// [`restore_globals`].
+    ///
+    /// Every table reachable from the globals table (through keys, values or metatables) has its
+    /// contents and metatable recorded.  Other values, such as functions and userdata, are saved
+    /// by reference, so their internal state (such as function upvalues) is not captured.  The
+    /// registry is not included, and `package.loaded` is only included if `include_loaded` is
+    /// true.
+    ///
+    /// [`restore_globals`]: #method.restore_globals
+    pub fn snapshot_globals(self, include_loaded: bool) -> Result<GlobalsSnapshot> {
+        snapshot::snapshot(self, include_loaded)
+    }
+
+    /// Resets the global environment to the state saved by [`snapshot_globals`].
+    ///
+    /// Tables present in the snapshot are restored in place, so references to them held elsewhere
+    /// remain valid: keys added since the snapshot are removed, and removed or changed keys and
+    /// metatables are put back.
+    ///
+    /// Returns `Error::MismatchedRegistryKey` if the snapshot was taken from an unrelated Lua
+    /// state.
+    ///
+    /// [`snapshot_globals`]: #method.snapshot_globals
+    pub fn restore_globals(self, snapshot: &GlobalsSnapshot) -> Result<()> {
+        snapshot::restore(self, snapshot)
+    }
+
+    /// Reports which globals were added, modified or removed since [`snapshot_globals`] was
+    /// called.
+    ///
+    /// By default only the values of the globals themselves are compared, as with `rawequal`, so
+    /// a global table whose contents were changed in place is not reported.  If `deep` is true,
+    /// tables reachable from a global are also compared with their contents in the snapshot, and
+    /// a global which now refers to a different table with the same contents is reported in
+    /// `replaced_with_equal` rather than as modified.
+    ///
+    /// Returns `Error::MismatchedRegistryKey` if the snapshot was taken from an unrelated Lua
+    /// state.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// lua_context.load("config = { verbose = false }").exec()?;
+    /// let snapshot = lua_context.snapshot_globals(false)?;
+    /// lua_context.load("answer = 42; config.verbose = true").exec()?;
+    ///
+    /// let diff = lua_context.diff_globals(&snapshot, false)?;
+    /// assert_eq!(diff.added, vec!["answer".to_owned()]);
+    /// assert!(diff.modified.is_empty());
+    /// let diff = lua_context.diff_globals(&snapshot, true)?;
+    /// assert_eq!(diff.modified, vec!["config".to_owned()]);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`snapshot_globals`]: #method.snapshot_globals
+    pub fn diff_globals(self, snapshot: &GlobalsSnapshot, deep: bool) -> Result<GlobalsDiff> {
+        snapshot::diff(self, snapshot, deep)
+    }
+
+    /// Creates an `Error` which raises the given value as the Lua error when returned from a Rust
+    /// callback.
+    ///
+    /// This allows callbacks to raise structured errors, such as tables, which scripts can catch
+    /// with `pcall` and inspect.  The value is kept in the registry until the returned error is
+    /// dropped, see [`Error::RuntimeErrorValue`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, Value};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let withdraw = lua_context.create_function(|lua, amount: i64| -> Result<()> {
+    ///     let err = lua.create_table()?;
+    ///     err.set("code", "insufficient_funds")?;
+    ///     err.set("requested", amount)?;
+    ///     Err(lua.create_error(Value::Table(err))?)
+    /// })?;
+    /// lua_context.globals().set("withdraw", withdraw)?;
+    /// lua_context.load(r#"
+    ///     local ok, err = pcall(withdraw, 100)
+    ///     assert(not ok and err.code == "insufficient_funds" and err.requested == 100)
+    /// "#).exec()
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Error::RuntimeErrorValue`]: enum.Error.html#variant.RuntimeErrorValue
+    pub fn create_error(self, value: Value<'lua>) -> Result<Error> {
+        let message = unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 2);
+            expire_registry_values(self.state);
+            self.push_value(value.clone())?;
+            to_string(self.state, -1).into_owned()
+        };
+        Ok(Error::RuntimeErrorValue {
+            message,
+            value: Arc::new(self.create_registry_value(value)?),
+        })
+    }
+
+    /// Creates a [`CallbackRegistry`], for keeping Lua functions subscribed to named events so
+    /// that Rust code can call them later.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let events = lua_context.create_callback_registry()?;
+    /// let on_save: Function = lua_context.load("function(path) return #path end").eval()?;
+    /// events.add(lua_context, "save", on_save)?;
+    ///
+    /// let results = events.emit(lua_context, "save", "notes.txt")?;
+    /// assert_eq!(results.len(), 1);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`CallbackRegistry`]: struct.CallbackRegistry.html
+    pub fn create_callback_registry(self) -> Result<CallbackRegistry> {
+        CallbackRegistry::new(self)
+    }
+
     /// Set a value in the Lua registry based on a string name.
     ///
     /// This value will be available to rust from all `Lua` instances which share the same main
@@ -434,13 +1586,24 @@ impl<'lua> Context<'lua> {
     /// maintaining ownership of a Lua value outside of a [`Lua::context`] call.
     ///
     /// Be warned, garbage collection of values held inside the registry is not automatic, see
-    /// [`RegistryKey`] for more details.
+    /// [`RegistryKey`] for more details.  [`Lua::set_registry_expiry_interval`] can be used to
+    /// expire dropped keys periodically from this method.
     ///
     /// [`RegistryKey`]: struct.RegistryKey.html
     /// [`Lua::context`]: struct.Lua.html#method.context
+    /// [`Lua::set_registry_expiry_interval`]: struct.Lua.html#method.set_registry_expiry_interval
     pub fn create_registry_value<T: ToLua<'lua>>(self, t: T) -> Result<RegistryKey> {
         let t = t.to_lua(self)?;
         unsafe {
+            let extra = extra_data(self.state);
+            if let Some(interval) = (*extra).registry_expiry_interval {
+                (*extra).registry_values_created += 1;
+                if (*extra).registry_values_created >= interval {
+                    (*extra).registry_values_created = 0;
+                    self.expire_registry_values();
+                }
+            }
+
             let _sg = StackGuard::new(self.state);
             assert_stack(self.state, 2);
 
@@ -449,10 +1612,10 @@ impl<'lua> Context<'lua> {
                 ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
             })?;
 
-            Ok(RegistryKey {
+            Ok(RegistryKey::new(
                 registry_id,
-                unref_list: (*extra_data(self.state)).registry_unref_list.clone(),
-            })
+                (*extra_data(self.state)).registry_unref_list.clone(),
+            ))
         }
     }
 
@@ -474,7 +1637,7 @@ impl<'lua> Context<'lua> {
             ffi::lua_rawgeti(
                 self.state,
                 ffi::LUA_REGISTRYINDEX,
-                key.registry_id as ffi::lua_Integer,
+                key.registry_id() as ffi::lua_Integer,
             );
             self.pop_value()
         };
@@ -488,6 +1651,9 @@ impl<'lua> Context<'lua> {
     /// [`expire_registry_values`] to automatically remove values from the registry whose
     /// `RegistryKey`s have been dropped.
     ///
+    /// If other clones of `key` still exist, this only drops `key`, and the value is removed once
+    /// the last clone is removed or dropped.
+    ///
     /// [`create_registry_value`]: #method.create_registry_value
     /// [`expire_registry_values`]: #method.expire_registry_values
     pub fn remove_registry_value(self, key: RegistryKey) -> Result<()> {
@@ -496,9 +1662,39 @@ impl<'lua> Context<'lua> {
                 return Err(Error::MismatchedRegistryKey);
             }
 
-            ffi::luaL_unref(self.state, ffi::LUA_REGISTRYINDEX, key.take());
-            Ok(())
+            if let Some(registry_id) = key.take() {
+                ffi::luaL_unref(self.state, ffi::LUA_REGISTRYINDEX, registry_id);
+            }
+            Ok(())
+        }
+    }
+
+    // Pushes the string interned by `key` onto the stack, checking that it belongs to this state.
+    pub(crate) unsafe fn push_key(self, key: &LuaKey) -> Result<()> {
+        if !Arc::ptr_eq(key.owner(), &(*extra_data(self.state)).registry_unref_list) {
+            return Err(Error::MismatchedRegistryKey);
+        }
+        ffi::lua_rawgeti(
+            self.state,
+            ffi::LUA_REGISTRYINDEX,
+            key.registry_id() as ffi::lua_Integer,
+        );
+        Ok(())
+    }
+
+    // Returns the registry id of the string interned with the bytes `s`, interning it first if
+    // needed.
+    unsafe fn interned_string_id(self, s: &[u8]) -> Result<c_int> {
+        let extra = extra_data(self.state);
+        if let Some(&id) = (*extra).interned_strings.get(s) {
+            return Ok(id);
         }
+        push_string(self.state, s)?;
+        let id = protect_lua_closure(self.state, 1, 0, |state| {
+            ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+        })?;
+        (*extra).interned_strings.insert(s.to_vec(), id);
+        Ok(id)
     }
 
     /// Returns true if the given `RegistryKey` was created by a `Lua` which shares the underlying
@@ -510,7 +1706,7 @@ impl<'lua> Context<'lua> {
     pub fn owns_registry_value(self, key: &RegistryKey) -> bool {
         unsafe {
             Arc::ptr_eq(
-                &key.unref_list,
+                key.unref_list(),
                 &(*extra_data(self.state)).registry_unref_list,
             )
         }
@@ -523,16 +1719,9 @@ impl<'lua> Context<'lua> {
     /// by `Lua::remove_registry_value`.
     pub fn expire_registry_values(self) {
         unsafe {
-            let unref_list = mem::replace(
-                &mut *rlua_expect!(
-                    (*extra_data(self.state)).registry_unref_list.lock(),
-                    "unref list poisoned"
-                ),
-                Some(Vec::new()),
-            );
-            for id in rlua_expect!(unref_list, "unref list not set") {
-                ffi::luaL_unref(self.state, ffi::LUA_REGISTRYINDEX, id);
-            }
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 2);
+            expire_registry_values(self.state);
         }
     }
 
@@ -686,10 +1875,25 @@ impl<'lua> Context<'lua> {
     }
 
     pub(crate) unsafe fn userdata_metatable<T: 'static + UserData>(self) -> Result<c_int> {
-        if let Some(table_id) = (*extra_data(self.state))
-            .registered_userdata
-            .get(&TypeId::of::<T>())
-        {
+        self.registered_metatable::<T, RefCell<T>>(false)
+    }
+
+    // The metatable of userdata created by `create_userdata_boxed`, which needs a destructor for
+    // the boxed value.
+    pub(crate) unsafe fn boxed_userdata_metatable<T: 'static + UserData>(self) -> Result<c_int> {
+        self.registered_metatable::<T, RefCell<Box<T>>>(true)
+    }
+
+    // Creates or returns the metatable for userdata of type `T`, stored as an `S`.
+    unsafe fn registered_metatable<T: 'static + UserData, S>(self, boxed: bool) -> Result<c_int> {
+        let registered = |extra: *mut ExtraData| {
+            if boxed {
+                &mut (*extra).registered_boxed_userdata
+            } else {
+                &mut (*extra).registered_userdata
+            }
+        };
+        if let Some(table_id) = registered(extra_data(self.state)).get(&TypeId::of::<T>()) {
             return Ok(*table_id);
         }
 
@@ -698,6 +1902,11 @@ impl<'lua> Context<'lua> {
 
         let mut methods = StaticUserDataMethods::default();
         T::add_methods(&mut methods);
+        check_tostring_helper(
+            type_name::<T>(),
+            methods.tostring_helper,
+            methods.meta_methods.iter().map(|&(meta, _)| meta),
+        )?;
 
         protect_lua_closure(self.state, 0, 1, |state| {
             ffi::lua_newtable(state);
@@ -712,29 +1921,39 @@ impl<'lua> Context<'lua> {
         }
 
         if methods.methods.is_empty() {
-            init_userdata_metatable::<RefCell<T>>(self.state, -1, None)?;
+            init_tracked_userdata_metatable::<S, T>(self.state, -1, None)?;
         } else {
             protect_lua_closure(self.state, 0, 1, |state| {
                 ffi::lua_newtable(state);
             })?;
             for (k, m) in methods.methods {
                 push_string(self.state, &k)?;
-                self.push_value(Value::Function(self.create_callback(m)?))?;
+                let function = match m {
+                    StaticMethod::Callback(callback) => self.create_callback(callback)?,
+                    StaticMethod::Direct(create) => create(self)?,
+                };
+                self.push_value(Value::Function(function))?;
                 protect_lua_closure(self.state, 3, 1, |state| {
                     ffi::lua_rawset(state, -3);
                 })?;
             }
 
-            init_userdata_metatable::<RefCell<T>>(self.state, -2, Some(-1))?;
+            init_tracked_userdata_metatable::<S, T>(self.state, -2, Some(-1))?;
             ffi::lua_pop(self.state, 1);
         }
 
+        let ptr = ffi::lua_topointer(self.state, -1);
         let id = protect_lua_closure(self.state, 1, 0, |state| {
             ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
         })?;
-        (*extra_data(self.state))
-            .registered_userdata
-            .insert(TypeId::of::<T>(), id);
+        let extra = extra_data(self.state);
+        registered(extra).insert(TypeId::of::<T>(), id);
+        (*extra)
+            .userdata_descriptors
+            .insert(TypeId::of::<T>(), describe_userdata::<T>);
+        (*extra)
+            .registered_userdata_types
+            .insert(ptr, TypeId::of::<T>());
         Ok(id)
     }
 
@@ -750,38 +1969,16 @@ impl<'lua> Context<'lua> {
     // changed to remove the lifetime parameter, which will enable using the correct callback type
     // and will reduce the number of hacks required in Context and Scope.
     pub(crate) fn create_callback(self, func: Callback<'lua, 'static>) -> Result<Function<'lua>> {
-        unsafe extern "C" fn call_callback(state: *mut ffi::lua_State) -> c_int {
-            callback_error(state, |nargs| {
-                if ffi::lua_type(state, ffi::lua_upvalueindex(1)) == ffi::LUA_TNIL {
-                    return Err(Error::CallbackDestructed);
-                }
-
-                if nargs < ffi::LUA_MINSTACK {
-                    check_stack(state, ffi::LUA_MINSTACK - nargs)?;
-                }
-
-                let context = Context::new(state);
-
-                let mut args = MultiValue::new();
-                args.reserve(nargs as usize);
-                for _ in 0..nargs {
-                    args.push_front(context.pop_value());
-                }
-
-                let func = get_userdata::<Callback>(state, ffi::lua_upvalueindex(1));
-
-                let results = (*func)(context, args)?;
-                let nresults = results.len() as c_int;
-
-                check_stack(state, nresults)?;
-                for r in results {
-                    context.push_value(r)?;
-                }
-
-                Ok(nresults)
-            })
-        }
+        self.create_named_callback(None, func)
+    }
 
+    // Like `create_callback`, but if `name` is given it is kept as the second upvalue of the
+    // closure, where `callback_name` finds it.
+    pub(crate) fn create_named_callback(
+        self,
+        name: Option<&str>,
+        func: Callback<'lua, 'static>,
+    ) -> Result<Function<'lua>> {
         unsafe {
             let _sg = StackGuard::new(self.state);
             assert_stack(self.state, 4);
@@ -795,8 +1992,14 @@ impl<'lua> Context<'lua> {
             ffi::lua_rawget(self.state, ffi::LUA_REGISTRYINDEX);
             ffi::lua_setmetatable(self.state, -2);
 
-            protect_lua_closure(self.state, 1, 1, |state| {
-                ffi::lua_pushcclosure(state, call_callback, 1);
+            let nupvalues = if let Some(name) = name {
+                push_string(self.state, name)?;
+                2
+            } else {
+                1
+            };
+            protect_lua_closure(self.state, nupvalues, 1, |state| {
+                ffi::lua_pushcclosure(state, call_callback, nupvalues);
             })?;
 
             Ok(Function(self.pop_ref()))
@@ -820,6 +2023,28 @@ impl<'lua> Context<'lua> {
             ud_index as ffi::lua_Integer,
         );
         ffi::lua_setmetatable(self.state, -2);
+        track_userdata(self.state, type_name::<T>());
+
+        Ok(AnyUserData(self.pop_ref()))
+    }
+
+    pub(crate) unsafe fn make_boxed_userdata<T>(self, data: Box<T>) -> Result<AnyUserData<'lua>>
+    where
+        T: 'static + UserData,
+    {
+        let _sg = StackGuard::new(self.state);
+        assert_stack(self.state, 4);
+
+        let ud_index = self.boxed_userdata_metatable::<T>()?;
+        push_userdata::<RefCell<Box<T>>>(self.state, RefCell::new(data))?;
+
+        ffi::lua_rawgeti(
+            self.state,
+            ffi::LUA_REGISTRYINDEX,
+            ud_index as ffi::lua_Integer,
+        );
+        ffi::lua_setmetatable(self.state, -2);
+        track_userdata(self.state, type_name::<T>());
 
         Ok(AnyUserData(self.pop_ref()))
     }
@@ -832,33 +2057,88 @@ impl<'lua> Context<'lua> {
         }
     }
 
+    // Sets every key and value from `cont` in `table`, which is returned.
+    fn fill_table<K, V, I>(self, table: Table<'lua>, cont: I) -> Result<Table<'lua>>
+    where
+        K: ToLua<'lua>,
+        V: ToLua<'lua>,
+        I: Iterator<Item = (K, V)>,
+    {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            // `Lua` instance assumes that on any callback, the Lua stack has at least LUA_MINSTACK
+            // slots available to avoid panics.
+            check_stack(self.state, 5 + ffi::LUA_MINSTACK)?;
+
+            self.push_ref(&table.0);
+            for (k, v) in cont {
+                self.push_value(k.to_lua(self)?)?;
+                self.push_value(v.to_lua(self)?)?;
+                unsafe extern "C" fn raw_set(state: *mut ffi::lua_State) -> c_int {
+                    ffi::lua_rawset(state, -3);
+                    1
+                }
+                protect_lua(self.state, 3, raw_set)?;
+            }
+        }
+        Ok(table)
+    }
+
+    // Loads `source` as if it were preceded by `line_offset` empty lines, so that Lua numbers its
+    // lines from `line_offset + 1`.  Errors are returned before the error hook is applied, so that
+    // `eval` can tell a syntax error apart, and syntax errors are not yet located with
+    // `locate_syntax_error`, which `eval` does not need for its attempt at an expression.
     fn load_chunk(
         &self,
         source: &[u8],
+        line_offset: usize,
         name: Option<&CString>,
         env: Option<Value<'lua>>,
     ) -> Result<Function<'lua>> {
+        // The empty lines are read from this buffer over and over, rather than being allocated
+        // along with the source.
+        const NEWLINES: [u8; 256] = [b'\n'; 256];
+
+        struct ChunkReader<'a> {
+            newlines: usize,
+            source: Option<&'a [u8]>,
+        }
+
+        unsafe extern "C" fn read_chunk(
+            _state: *mut ffi::lua_State,
+            data: *mut c_void,
+            size: *mut usize,
+        ) -> *const c_char {
+            let reader = &mut *(data as *mut ChunkReader);
+            if reader.newlines > 0 {
+                let n = reader.newlines.min(NEWLINES.len());
+                reader.newlines -= n;
+                *size = n;
+                NEWLINES.as_ptr() as *const c_char
+            } else if let Some(source) = reader.source.take() {
+                *size = source.len();
+                source.as_ptr() as *const c_char
+            } else {
+                *size = 0;
+                ptr::null()
+            }
+        }
+
         unsafe {
             let _sg = StackGuard::new(self.state);
             assert_stack(self.state, 1);
 
-            match if let Some(name) = name {
-                ffi::luaL_loadbufferx(
-                    self.state,
-                    source.as_ptr() as *const c_char,
-                    source.len(),
-                    name.as_ptr() as *const c_char,
-                    cstr!("t"),
-                )
-            } else {
-                ffi::luaL_loadbufferx(
-                    self.state,
-                    source.as_ptr() as *const c_char,
-                    source.len(),
-                    ptr::null(),
-                    cstr!("t"),
-                )
-            } {
+            let mut reader = ChunkReader {
+                newlines: line_offset,
+                source: Some(source),
+            };
+            match ffi::lua_load(
+                self.state,
+                read_chunk,
+                &mut reader as *mut ChunkReader as *mut c_void,
+                name.map_or(ptr::null(), |name| name.as_ptr()),
+                cstr!("t"),
+            ) {
                 ffi::LUA_OK => {
                     if let Some(env) = env {
                         self.push_value(env)?;
@@ -866,12 +2146,63 @@ impl<'lua> Context<'lua> {
                     }
                     Ok(Function(self.pop_ref()))
                 }
-                err => Err(pop_error(self.state, err)),
+                err => Err(pop_error_value(self.state, err)),
+            }
+        }
+    }
+
+    // Keeps the source of a chunk loaded with `load_resident`, so that `error_traceback` can add
+    // source lines to errors from it.  The source of the oldest resident chunk is dropped once
+    // there are more than `MAX_RESIDENT_SOURCES`.
+    fn retain_source(&self, function: &Function<'lua>, line_offset: usize, source: &[u8]) {
+        let chunk_name = self.chunk_name(function);
+        unsafe {
+            let sources = &mut (*extra_data(self.state)).resident_sources;
+            sources.retain(|(name, _, _)| *name != chunk_name);
+            if sources.len() >= MAX_RESIDENT_SOURCES {
+                sources.remove(0);
+            }
+            sources.push((chunk_name, line_offset, source.to_vec()));
+        }
+    }
+
+    // Forgets the source kept for a resident chunk with the same name as this function, which was
+    // loaded with `load`, so that its errors are not given lines from the other chunk.
+    fn forget_source(&self, function: &Function<'lua>) {
+        unsafe {
+            if (*extra_data(self.state)).resident_sources.is_empty() {
+                return;
             }
         }
+        let chunk_name = self.chunk_name(function);
+        unsafe {
+            (*extra_data(self.state))
+                .resident_sources
+                .retain(|(name, _, _)| *name != chunk_name);
+        }
+    }
+
+    // Returns the full name of the chunk a Lua function was loaded from, rather than the
+    // `short_src` which appears in error messages, which is truncated if the name is long.
+    fn chunk_name(&self, function: &Function<'lua>) -> Vec<u8> {
+        unsafe {
+            let _sg = StackGuard::new(self.state);
+            assert_stack(self.state, 1);
+
+            self.push_ref(&function.0);
+            let mut ar: ffi::lua_Debug = mem::zeroed();
+            rlua_assert!(
+                ffi::lua_getinfo(self.state, cstr!(">S"), &mut ar) != 0,
+                "lua_getinfo failed with `>S`"
+            );
+            CStr::from_ptr(ar.source).to_bytes().to_vec()
+        }
     }
 }
 
+// The number of resident chunks whose source is kept, see `Context::load_resident`.
+const MAX_RESIDENT_SOURCES: usize = 64;
+
 /// Returned from [`Context::load`] and is used to finalize loading and executing Lua main chunks.
 ///
 /// [`Context::load`]: struct.Context.html#method.load
@@ -881,6 +2212,8 @@ pub struct Chunk<'lua, 'a> {
     source: &'a [u8],
     name: Option<CString>,
     env: Option<Value<'lua>>,
+    line_offset: usize,
+    resident: bool,
 }
 
 impl<'lua, 'a> Chunk<'lua, 'a> {
@@ -912,6 +2245,16 @@ impl<'lua, 'a> Chunk<'lua, 'a> {
         Ok(self)
     }
 
+    /// Sets the number of lines preceding this chunk, so that line numbers in errors and
+    /// tracebacks refer to the file the chunk was taken from.
+    ///
+    /// With an offset of `n`, the first line of the chunk is reported as line `n + 1`.  This is
+    /// useful for Lua code embedded in a larger file, such as a template or a configuration file.
+    pub fn set_line_offset(mut self, offset: usize) -> Chunk<'lua, 'a> {
+        self.line_offset = offset;
+        self
+    }
+
     /// Execute this chunk of code.
     ///
     /// This is equivalent to calling the chunk function with no arguments and no return values.
@@ -922,22 +2265,37 @@ impl<'lua, 'a> Chunk<'lua, 'a> {
 
     /// Evaluate the chunk as either an expression or block.
     ///
-    /// If the chunk can be parsed as an expression, this loads and executes the chunk and returns
-    /// the value that it evaluates to.  Otherwise, the chunk is interpreted as a block as normal,
-    /// and this is equivalent to calling `exec`.
+    /// If the chunk can be parsed as an expression, or a list of expressions separated by commas,
+    /// this loads and executes the chunk and returns all of the values that it evaluates to.
+    /// Otherwise, the chunk is interpreted as a block as normal, and this is equivalent to
+    /// calling `call(())`, returning the values of any `return` statement in the block.
+    ///
+    /// Only a syntax error causes the chunk to be loaded as a block, other errors from loading the
+    /// chunk as an expression are returned.
     pub fn eval<R: FromLuaMulti<'lua>>(self) -> Result<R> {
+        unsafe { check_chunk_size(self.context.state, self.source.len())? };
         // First, try interpreting the lua as an expression by adding
         // "return", then as a statement.  This is the same thing the
         // actual lua repl does.
         let mut expression_source = b"return ".to_vec();
         expression_source.extend(self.source);
-        if let Ok(function) =
-            self.context
-                .load_chunk(&expression_source, self.name.as_ref(), self.env.clone())
-        {
-            function.call(())
-        } else {
-            self.call(())
+        match self.context.load_chunk(
+            &expression_source,
+            self.line_offset,
+            self.name.as_ref(),
+            self.env.clone(),
+        ) {
+            Ok(function) => {
+                if self.resident {
+                    self.context
+                        .retain_source(&function, self.line_offset, self.source);
+                } else {
+                    self.context.forget_source(&function);
+                }
+                function.call(())
+            }
+            Err(Error::SyntaxError { .. }) => self.call(()),
+            Err(err) => Err(unsafe { apply_error_hook(self.context.state, err) }),
         }
     }
 
@@ -952,8 +2310,27 @@ impl<'lua, 'a> Chunk<'lua, 'a> {
     ///
     /// This simply compiles the chunk without actually executing it.  
     pub fn into_function(self) -> Result<Function<'lua>> {
-        self.context
-            .load_chunk(self.source, self.name.as_ref(), self.env)
+        let state = self.context.state;
+        self.into_function_unhooked()
+            .map_err(|err| unsafe { apply_error_hook(state, err) })
+    }
+
+    // Like `into_function`, but returns errors before the error hook is applied, for code within
+    // the crate which needs to tell a syntax error apart, such as the sandbox `load`.
+    pub(crate) fn into_function_unhooked(self) -> Result<Function<'lua>> {
+        unsafe { check_chunk_size(self.context.state, self.source.len())? };
+        let (state, source, line_offset) = (self.context.state, self.source, self.line_offset);
+        let function = self
+            .context
+            .load_chunk(source, line_offset, self.name.as_ref(), self.env)
+            .map_err(|err| unsafe { locate_syntax_error(state, source, line_offset, err) })?;
+        if self.resident {
+            self.context
+                .retain_source(&function, self.line_offset, self.source);
+        } else {
+            self.context.forget_source(&function);
+        }
+        Ok(function)
     }
 }
 
@@ -976,16 +2353,37 @@ unsafe fn ref_stack_pop(extra: *mut ExtraData) -> c_int {
 }
 
 struct StaticUserDataMethods<'lua, T: 'static + UserData> {
-    methods: Vec<(Vec<u8>, Callback<'lua, 'static>)>,
+    methods: Vec<(Vec<u8>, StaticMethod<'lua>)>,
     meta_methods: Vec<(MetaMethod, Callback<'lua, 'static>)>,
+    // The documentation given to `add_method_with_doc`, keyed by the index of the method in
+    // `methods`.
+    docs: Vec<(usize, StdString)>,
+    // Whether `__tostring` was added by `add_tostring_from_display` or `add_tostring_from_debug`.
+    tostring_helper: bool,
     _type: PhantomData<T>,
 }
 
+// Describes the methods added by `T::add_methods`, without creating any functions for them.
+pub(crate) fn describe_userdata<T: 'static + UserData>() -> TypeDescriptor {
+    let mut methods = StaticUserDataMethods::default();
+    T::add_methods(&mut methods);
+    methods.describe()
+}
+
+// A regular method, either a boxed callback, or a method added with `add_method_fn`, which creates
+// a function calling it directly.
+enum StaticMethod<'lua> {
+    Callback(Callback<'lua, 'static>),
+    Direct(Box<dyn FnOnce(Context<'lua>) -> Result<Function<'lua>>>),
+}
+
 impl<'lua, T: 'static + UserData> Default for StaticUserDataMethods<'lua, T> {
     fn default() -> StaticUserDataMethods<'lua, T> {
         StaticUserDataMethods {
             methods: Vec::new(),
             meta_methods: Vec::new(),
+            docs: Vec::new(),
+            tostring_helper: false,
             _type: PhantomData,
         }
     }
@@ -999,8 +2397,21 @@ impl<'lua, T: 'static + UserData> UserDataMethods<'lua, T> for StaticUserDataMet
         R: ToLuaMulti<'lua>,
         M: 'static + Send + Fn(Context<'lua>, &T, A) -> Result<R>,
     {
-        self.methods
-            .push((name.as_ref().to_vec(), Self::box_method(method)));
+        self.methods.push((
+            name.as_ref().to_vec(),
+            StaticMethod::Callback(Self::box_method(method)),
+        ));
+    }
+
+    fn add_method_with_doc<S, A, R, M>(&mut self, name: &S, doc: &str, method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        M: 'static + Send + Fn(Context<'lua>, &T, A) -> Result<R>,
+    {
+        self.docs.push((self.methods.len(), doc.to_owned()));
+        self.add_method(name, method);
     }
 
     fn add_method_mut<S, A, R, M>(&mut self, name: &S, method: M)
@@ -1010,8 +2421,10 @@ impl<'lua, T: 'static + UserData> UserDataMethods<'lua, T> for StaticUserDataMet
         R: ToLuaMulti<'lua>,
         M: 'static + Send + FnMut(Context<'lua>, &mut T, A) -> Result<R>,
     {
-        self.methods
-            .push((name.as_ref().to_vec(), Self::box_method_mut(method)));
+        self.methods.push((
+            name.as_ref().to_vec(),
+            StaticMethod::Callback(Self::box_method_mut(method)),
+        ));
     }
 
     fn add_function<S, A, R, F>(&mut self, name: &S, function: F)
@@ -1021,8 +2434,10 @@ impl<'lua, T: 'static + UserData> UserDataMethods<'lua, T> for StaticUserDataMet
         R: ToLuaMulti<'lua>,
         F: 'static + Send + Fn(Context<'lua>, A) -> Result<R>,
     {
-        self.methods
-            .push((name.as_ref().to_vec(), Self::box_function(function)));
+        self.methods.push((
+            name.as_ref().to_vec(),
+            StaticMethod::Callback(Self::box_function(function)),
+        ));
     }
 
     fn add_function_mut<S, A, R, F>(&mut self, name: &S, function: F)
@@ -1031,9 +2446,22 @@ impl<'lua, T: 'static + UserData> UserDataMethods<'lua, T> for StaticUserDataMet
         A: FromLuaMulti<'lua>,
         R: ToLuaMulti<'lua>,
         F: 'static + Send + FnMut(Context<'lua>, A) -> Result<R>,
+    {
+        self.methods.push((
+            name.as_ref().to_vec(),
+            StaticMethod::Callback(Self::box_function_mut(function)),
+        ));
+    }
+
+    fn add_method_fn<S, A, R, M>(&mut self, name: &S, method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        M: 'static + Send + Copy + Fn(Context<'lua>, &T, A) -> Result<R>,
     {
         self.methods
-            .push((name.as_ref().to_vec(), Self::box_function_mut(function)));
+            .push((name.as_ref().to_vec(), Self::direct_method(method)));
     }
 
     fn add_meta_method<A, R, M>(&mut self, meta: MetaMethod, method: M)
@@ -1072,9 +2500,117 @@ impl<'lua, T: 'static + UserData> UserDataMethods<'lua, T> for StaticUserDataMet
         self.meta_methods
             .push((meta, Self::box_function_mut(function)));
     }
+
+    fn add_tostring_from_display(&mut self)
+    where
+        T: fmt::Display,
+    {
+        self.tostring_helper = true;
+        self.add_meta_method(MetaMethod::ToString, |_, data, ()| Ok(data.to_string()));
+    }
+
+    fn add_tostring_from_debug(&mut self)
+    where
+        T: fmt::Debug,
+    {
+        self.tostring_helper = true;
+        self.add_meta_method(MetaMethod::ToString, |_, data, ()| {
+            Ok(format!("{:?}", data))
+        });
+    }
 }
 
 impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
+    fn describe(&self) -> TypeDescriptor {
+        // A method or metamethod added again under the same name replaces the earlier one, as it
+        // does in the metatable.
+        let mut methods: Vec<MethodDescriptor> = Vec::new();
+        for (i, (name, _)) in self.methods.iter().enumerate() {
+            let name = StdString::from_utf8_lossy(name).into_owned();
+            methods.retain(|method| method.name != name);
+            let doc = self
+                .docs
+                .iter()
+                .find(|(index, _)| *index == i)
+                .map(|(_, doc)| doc.clone());
+            methods.push(MethodDescriptor { name, doc });
+        }
+        let mut metamethods: Vec<MetaMethod> = Vec::new();
+        for &(meta, _) in &self.meta_methods {
+            metamethods.retain(|&m| m != meta);
+            metamethods.push(meta);
+        }
+        TypeDescriptor {
+            name: type_name::<T>(),
+            methods,
+            metamethods,
+        }
+    }
+
+    // Creates a function which calls `method` directly, behaving like the callback from
+    // `box_method` without boxing `method`.  `method` is kept in a userdata upvalue, which needs
+    // no destructor as `method` is `Copy`.
+    fn direct_method<A, R, M>(method: M) -> StaticMethod<'lua>
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        M: 'static + Send + Copy + Fn(Context<'lua>, &T, A) -> Result<R>,
+    {
+        unsafe extern "C" fn call_method<'lua, T, A, R, M>(state: *mut ffi::lua_State) -> c_int
+        where
+            T: 'static + UserData,
+            A: FromLuaMulti<'lua>,
+            R: ToLuaMulti<'lua>,
+            M: Copy + Fn(Context<'lua>, &T, A) -> Result<R>,
+        {
+            callback_error(state, |nargs| {
+                check_multivalue_limit(state, nargs as usize)?;
+                if nargs == 0 {
+                    return Err(Error::FromLuaConversionError {
+                        from: "missing argument",
+                        to: "userdata",
+                        message: None,
+                    });
+                }
+                if nargs < ffi::LUA_MINSTACK {
+                    check_stack(state, ffi::LUA_MINSTACK - nargs)?;
+                }
+
+                let lua = Context::new(state);
+                let mut args = MultiValue::new();
+                args.reserve(nargs as usize - 1);
+                for _ in 1..nargs {
+                    args.push_front(lua.pop_value());
+                }
+                let userdata = AnyUserData::from_lua(lua.pop_value(), lua)?;
+                let userdata = userdata.borrow::<T>()?;
+
+                let method = *get_userdata::<M>(state, ffi::lua_upvalueindex(1));
+                let results =
+                    method(lua, &userdata, A::from_lua_multi(args, lua)?)?.to_lua_multi(lua)?;
+                check_multivalue_limit(state, results.len())?;
+                let nresults = results.len() as c_int;
+
+                check_stack(state, nresults)?;
+                for r in results {
+                    lua.push_value(r)?;
+                }
+                Ok(nresults)
+            })
+        }
+
+        StaticMethod::Direct(Box::new(move |lua| unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 4);
+
+            push_userdata::<M>(lua.state, method)?;
+            protect_lua_closure(lua.state, 1, 1, |state| {
+                ffi::lua_pushcclosure(state, call_method::<T, A, R, M>, 1);
+            })?;
+            Ok(Function(lua.pop_ref()))
+        }))
+    }
+
     fn box_method<A, R, M>(method: M) -> Callback<'lua, 'static>
     where
         A: FromLuaMulti<'lua>,
@@ -1145,3 +2681,225 @@ impl<'lua, T: 'static + UserData> StaticUserDataMethods<'lua, T> {
         })
     }
 }
+
+// Pushes a value of the type `ty`, for getting or setting the metatable shared by that type.
+unsafe fn push_type_exemplar(state: *mut ffi::lua_State, ty: TypeCategory) -> Result<()> {
+    unsafe extern "C" fn exemplar_function(_: *mut ffi::lua_State) -> c_int {
+        0
+    }
+
+    match ty {
+        TypeCategory::Nil => ffi::lua_pushnil(state),
+        TypeCategory::Boolean => ffi::lua_pushboolean(state, 0),
+        TypeCategory::LightUserData => ffi::lua_pushlightuserdata(state, ptr::null_mut()),
+        TypeCategory::Number => ffi::lua_pushinteger(state, 0),
+        TypeCategory::String => push_string(state, "")?,
+        TypeCategory::Function => ffi::lua_pushcfunction(state, exemplar_function),
+        TypeCategory::Thread => {
+            ffi::lua_pushthread(state);
+        }
+    }
+    Ok(())
+}
+
+unsafe extern "C" fn call_callback(state: *mut ffi::lua_State) -> c_int {
+    callback_error(state, |nargs| {
+        if ffi::lua_type(state, ffi::lua_upvalueindex(1)) == ffi::LUA_TNIL {
+            return Err(Error::CallbackDestructed);
+        }
+
+        check_multivalue_limit(state, nargs as usize)?;
+
+        if nargs < ffi::LUA_MINSTACK {
+            check_stack(state, ffi::LUA_MINSTACK - nargs)?;
+        }
+
+        let context = Context::new(state);
+
+        let mut args = MultiValue::new();
+        args.reserve(nargs as usize);
+        for _ in 0..nargs {
+            args.push_front(context.pop_value());
+        }
+
+        let func = get_userdata::<Callback>(state, ffi::lua_upvalueindex(1));
+
+        let results = (*func)(context, args)?;
+        check_multivalue_limit(state, results.len())?;
+        let nresults = results.len() as c_int;
+
+        check_stack(state, nresults)?;
+        for r in results {
+            context.push_value(r)?;
+        }
+
+        Ok(nresults)
+    })
+}
+
+// Returns the name given to `Context::create_named_function`, if the value at `index` is a Rust
+// callback created with a name.  Uses 1 stack space.
+pub(crate) unsafe fn callback_name(state: *mut ffi::lua_State, index: c_int) -> Option<Vec<u8>> {
+    match ffi::lua_tocfunction(state, index) {
+        Some(f) if f as usize == call_callback as ffi::lua_CFunction as usize => {}
+        _ => return None,
+    }
+    if ffi::lua_getupvalue(state, index, 2).is_null() {
+        return None;
+    }
+    let mut size = 0;
+    let data = ffi::lua_tolstring(state, -1, &mut size);
+    let name = slice::from_raw_parts(data as *const u8, size).to_vec();
+    ffi::lua_pop(state, 1);
+    Some(name)
+}
+
+// Fills in the position of a syntax error raised while loading `source` after `line_offset` empty
+// lines.
+//
+// Lua only reports the line of a syntax error, and the token it was found at.  To find the token
+// in the source, the chunk is loaded again one byte at a time, so that the number of bytes read by
+// the lexer gives the end of the token.  This only happens once the chunk has failed to load.
+unsafe fn locate_syntax_error(
+    state: *mut ffi::lua_State,
+    source: &[u8],
+    line_offset: usize,
+    err: Error,
+) -> Error {
+    let (message, incomplete_input) = match err {
+        Error::SyntaxError {
+            message,
+            incomplete_input,
+            ..
+        } => (message, incomplete_input),
+        err => return err,
+    };
+
+    let span = syntax_error_span(state, source, &message);
+    let (line, column) = match &span {
+        Some(span) => {
+            let (line, column) = line_and_column(source, span.start);
+            (Some(line + line_offset), Some(column))
+        }
+        None => (message_line(&message), None),
+    };
+    Error::SyntaxError {
+        message,
+        incomplete_input,
+        line,
+        column,
+        span,
+    }
+}
+
+unsafe fn syntax_error_span(
+    state: *mut ffi::lua_State,
+    source: &[u8],
+    message: &str,
+) -> Option<Range<usize>> {
+    // Syntax errors end with "near <eof>" or "near 'token'", except for a few semantic errors.
+    let token = if message.ends_with(" near <eof>") {
+        None
+    } else {
+        let start = message.rfind(" near '")? + " near '".len();
+        if !message.ends_with('\'') || start >= message.len() {
+            return None;
+        }
+        Some(&message.as_bytes()[start..message.len() - 1])
+    };
+
+    struct ByteReader<'a> {
+        source: &'a [u8],
+        read: usize,
+        eof: bool,
+    }
+
+    unsafe extern "C" fn read_byte(
+        _state: *mut ffi::lua_State,
+        data: *mut c_void,
+        size: *mut usize,
+    ) -> *const c_char {
+        let reader = &mut *(data as *mut ByteReader);
+        if reader.read < reader.source.len() {
+            let byte = reader.source.as_ptr().add(reader.read);
+            reader.read += 1;
+            *size = 1;
+            byte as *const c_char
+        } else {
+            reader.eof = true;
+            *size = 0;
+            ptr::null()
+        }
+    }
+
+    let mut reader = ByteReader {
+        source,
+        read: 0,
+        eof: false,
+    };
+    let _sg = StackGuard::new(state);
+    assert_stack(state, 1);
+    let status = ffi::lua_load(
+        state,
+        read_byte,
+        &mut reader as *mut ByteReader as *mut c_void,
+        ptr::null(),
+        cstr!("t"),
+    );
+    if status != ffi::LUA_ERRSYNTAX {
+        return None;
+    }
+
+    // The lexer reads one byte past the token it stopped at, unless the token ends the source.
+    let end = if reader.eof {
+        source.len()
+    } else {
+        reader.read.saturating_sub(1)
+    };
+    match token {
+        None if end == source.len() => Some(end..end),
+        None => None,
+        Some(token) => {
+            let start = end.checked_sub(token.len())?;
+            if &source[start..end] == token {
+                Some(start..end)
+            } else {
+                // The text of strings with escape sequences differs from their source.
+                None
+            }
+        }
+    }
+}
+
+// Returns the line and the byte column of `offset` in `source`, both counting from 1, breaking
+// lines like the Lua lexer does.
+fn line_and_column(source: &[u8], offset: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut i = 0;
+    while i < offset {
+        let c = source[i];
+        i += 1;
+        if c == b'\n' || c == b'\r' {
+            // "\n\r" and "\r\n" are single line breaks.
+            if i < source.len() && (source[i] == b'\n' || source[i] == b'\r') && source[i] != c {
+                i += 1;
+            }
+            line += 1;
+            line_start = i;
+        }
+    }
+    (line, offset - line_start + 1)
+}
+
+// Returns the line named by an error message of the form "chunk:line: message".
+fn message_line(message: &str) -> Option<usize> {
+    for (i, _) in message.match_indices(':') {
+        let rest = &message[i + 1..];
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        if digits > 0 && rest[digits..].starts_with(':') {
+            return rest[..digits].parse().ok();
+        }
+    }
+    None
+}
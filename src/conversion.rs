@@ -1,14 +1,21 @@
 use std::collections::{BTreeMap, HashMap};
 use std::ffi::{CStr, CString};
 use std::hash::{BuildHasher, Hash};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::num::{
+    NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU128,
+    NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize, Wrapping,
+};
 use std::string::String as StdString;
 
 use bstr::{BStr, BString};
+#[cfg(feature = "either")]
+use either::Either;
 use num_traits::cast;
 
 use crate::context::Context;
 use crate::error::{Error, Result};
-use crate::function::Function;
+use crate::function::{Callable, Function};
 use crate::string::String;
 use crate::table::Table;
 use crate::thread::Thread;
@@ -84,6 +91,29 @@ impl<'lua> FromLua<'lua> for Function<'lua> {
     }
 }
 
+impl<'lua> ToLua<'lua> for Callable<'lua> {
+    fn to_lua(self, _: Context<'lua>) -> Result<Value<'lua>> {
+        Ok(self.0)
+    }
+}
+
+impl<'lua> FromLua<'lua> for Callable<'lua> {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Callable<'lua>> {
+        match value {
+            Value::Function(_) => Ok(Callable(value)),
+            Value::Table(_) | Value::UserData(_) if value.is_callable(lua) => Ok(Callable(value)),
+            _ => Err(Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: "Callable",
+                message: Some(
+                    "expected a function, or a table or userdata with a `__call` metamethod"
+                        .to_owned(),
+                ),
+            }),
+        }
+    }
+}
+
 impl<'lua> ToLua<'lua> for Thread<'lua> {
     fn to_lua(self, _: Context<'lua>) -> Result<Value<'lua>> {
         Ok(Value::Thread(self))
@@ -284,6 +314,74 @@ impl<'lua, 'a> ToLua<'lua> for &BStr {
     }
 }
 
+impl<'lua> ToLua<'lua> for char {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        let mut buf = [0; 4];
+        Ok(Value::String(
+            lua.create_string(self.encode_utf8(&mut buf))?,
+        ))
+    }
+}
+
+impl<'lua> FromLua<'lua> for char {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+        let ty = value.type_name();
+        let string = lua
+            .coerce_string(value)?
+            .ok_or_else(|| Error::FromLuaConversionError {
+                from: ty,
+                to: "char",
+                message: Some("expected string or number".to_string()),
+            })?;
+
+        let mut chars = string.to_str()?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c),
+            _ => Err(Error::FromLuaConversionError {
+                from: ty,
+                to: "char",
+                message: Some("expected a string of exactly one character".to_string()),
+            }),
+        }
+    }
+}
+
+macro_rules! lua_convert_addr {
+    ($x:ty) => {
+        impl<'lua> ToLua<'lua> for $x {
+            fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+                Ok(Value::String(lua.create_string(&self.to_string())?))
+            }
+        }
+
+        impl<'lua> FromLua<'lua> for $x {
+            fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+                let ty = value.type_name();
+                let string =
+                    lua.coerce_string(value)?
+                        .ok_or_else(|| Error::FromLuaConversionError {
+                            from: ty,
+                            to: stringify!($x),
+                            message: Some("expected string".to_string()),
+                        })?;
+                let string = string.to_str()?;
+                string.parse().map_err(|err| Error::FromLuaConversionError {
+                    from: ty,
+                    to: stringify!($x),
+                    message: Some(format!("{}: {:?}", err, string)),
+                })
+            }
+        }
+    };
+}
+
+lua_convert_addr!(IpAddr);
+lua_convert_addr!(Ipv4Addr);
+lua_convert_addr!(Ipv6Addr);
+lua_convert_addr!(SocketAddr);
+lua_convert_addr!(SocketAddrV4);
+lua_convert_addr!(SocketAddrV6);
+
 macro_rules! lua_convert_int {
     ($x:ty) => {
         impl<'lua> ToLua<'lua> for $x {
@@ -341,6 +439,62 @@ lua_convert_int!(u128);
 lua_convert_int!(isize);
 lua_convert_int!(usize);
 
+macro_rules! lua_convert_nonzero {
+    ($x:ty, $prim:ty) => {
+        impl<'lua> ToLua<'lua> for $x {
+            fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+                self.get().to_lua(lua)
+            }
+        }
+
+        impl<'lua> FromLua<'lua> for $x {
+            fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+                let ty = value.type_name();
+                let i = <$prim>::from_lua(value, lua).map_err(|err| match err {
+                    Error::FromLuaConversionError { from, message, .. } => {
+                        Error::FromLuaConversionError {
+                            from,
+                            to: stringify!($x),
+                            message,
+                        }
+                    }
+                    err => err,
+                })?;
+                <$x>::new(i).ok_or_else(|| Error::FromLuaConversionError {
+                    from: ty,
+                    to: stringify!($x),
+                    message: Some("expected a non-zero integer".to_owned()),
+                })
+            }
+        }
+    };
+}
+
+lua_convert_nonzero!(NonZeroI8, i8);
+lua_convert_nonzero!(NonZeroU8, u8);
+lua_convert_nonzero!(NonZeroI16, i16);
+lua_convert_nonzero!(NonZeroU16, u16);
+lua_convert_nonzero!(NonZeroI32, i32);
+lua_convert_nonzero!(NonZeroU32, u32);
+lua_convert_nonzero!(NonZeroI64, i64);
+lua_convert_nonzero!(NonZeroU64, u64);
+lua_convert_nonzero!(NonZeroI128, i128);
+lua_convert_nonzero!(NonZeroU128, u128);
+lua_convert_nonzero!(NonZeroIsize, isize);
+lua_convert_nonzero!(NonZeroUsize, usize);
+
+impl<'lua, T: ToLua<'lua>> ToLua<'lua> for Wrapping<T> {
+    fn to_lua(self, lua: Context<'lua>) -> Result<Value<'lua>> {
+        self.0.to_lua(lua)
+    }
+}
+
+impl<'lua, T: FromLua<'lua>> FromLua<'lua> for Wrapping<T> {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+        T::from_lua(value, lua).map(Wrapping)
+    }
+}
+
 macro_rules! lua_convert_float {
     ($x:ty) => {
         impl<'lua> ToLua<'lua> for $x {
@@ -454,3 +608,112 @@ impl<'lua, T: FromLua<'lua>> FromLua<'lua> for Option<T> {
         }
     }
 }
+
+/// Converting from Lua tries `L` first and then `R`, so when a value converts to both, such as a
+/// number to `Either<String, Integer>`, the left alternative is chosen.  An alternative which fails
+/// with a `FromLuaConversionError`, a `UserDataTypeMismatch` or a `ForeignUserData` error is
+/// skipped, any other error is returned as it is.  If neither converts, the error lists the Lua
+/// types the alternatives expected along with the type of the value received, including the
+/// alternatives of nested `Either`s.
+///
+/// A union of more than two types is written as a nested `Either`, such as `Either<A, Either<B,
+/// C>>`.  There is no `ToLua` implementation, because it would overlap with the `ToLuaMulti`
+/// implementation for `Either`, which already lets callbacks return either alternative.
+///
+/// # Examples
+///
+/// ```
+/// # use either::Either;
+/// # use rlua::{Lua, Result, Table};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let describe = lua_context.create_function(|_, value: Either<String, Table>| {
+///     Ok(match value {
+///         Either::Left(name) => name,
+///         Either::Right(options) => options.get("name")?,
+///     })
+/// })?;
+/// lua_context.globals().set("describe", describe)?;
+/// assert_eq!(lua_context.load("describe('a')").eval::<String>()?, "a");
+/// assert_eq!(lua_context.load("describe({ name = 'b' })").eval::<String>()?, "b");
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+#[cfg(feature = "either")]
+impl<'lua, L: FromLua<'lua>, R: FromLua<'lua>> FromLua<'lua> for Either<L, R> {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Self> {
+        let mut expected = Vec::new();
+        match L::from_lua(value.clone(), lua) {
+            Ok(l) => return Ok(Either::Left(l)),
+            Err(err) => union_alternative_failed(err, &mut expected)?,
+        }
+        match R::from_lua(value.clone(), lua) {
+            Ok(r) => return Ok(Either::Right(r)),
+            Err(err) => union_alternative_failed(err, &mut expected)?,
+        }
+        Err(union_error(&value, &expected))
+    }
+}
+
+// Conversion failures move on to the next alternative, adding the Lua types the alternative
+// expected to `expected`.  Other errors are returned as they are.
+#[cfg(feature = "either")]
+fn union_alternative_failed(err: Error, expected: &mut Vec<StdString>) -> Result<()> {
+    let names = match err {
+        // A nested `Either` already listed the types of its own alternatives.
+        Error::FromLuaConversionError {
+            to: "Either",
+            message: Some(ref message),
+            ..
+        } if message.starts_with("expected ") => {
+            let list = &message["expected ".len()..];
+            let list = list.rfind(", got ").map_or(list, |end| &list[..end]);
+            list.split(" or ").map(|name| name.to_owned()).collect()
+        }
+        Error::FromLuaConversionError { to, .. } => vec![expected_lua_type(to).to_owned()],
+        Error::UserDataTypeMismatch | Error::ForeignUserData => vec!["userdata".to_owned()],
+        err => return Err(err),
+    };
+    for name in names {
+        if !expected.contains(&name) {
+            expected.push(name);
+        }
+    }
+    Ok(())
+}
+
+// The Lua type expected by a failed conversion to `to`.  The conversions in this crate name the
+// Rust type they convert to, other names are kept as they are.
+#[cfg(feature = "either")]
+fn expected_lua_type(to: &'static str) -> &'static str {
+    match to {
+        "i8" | "u8" | "i16" | "u16" | "i32" | "u32" | "i64" | "u64" | "i128" | "u128" | "isize"
+        | "usize" | "f32" | "f64" | "NonZeroI8" | "NonZeroU8" | "NonZeroI16" | "NonZeroU16"
+        | "NonZeroI32" | "NonZeroU32" | "NonZeroI64" | "NonZeroU64" | "NonZeroI128"
+        | "NonZeroU128" | "NonZeroIsize" | "NonZeroUsize" => "number",
+        "String" | "&str" | "CString" | "char" | "IpAddr" | "Ipv4Addr" | "Ipv6Addr"
+        | "SocketAddr" | "SocketAddrV4" | "SocketAddrV6" => "string",
+        "Vec" | "HashMap" | "BTreeMap" => "table",
+        "Callable" => "function",
+        to => to,
+    }
+}
+
+#[cfg(feature = "either")]
+fn union_error(value: &Value, expected: &[StdString]) -> Error {
+    let received = value.type_name();
+    Error::FromLuaConversionError {
+        from: received,
+        to: "Either",
+        message: Some(if expected.iter().any(|name| name == received) {
+            format!(
+                "expected {}, got {} which did not convert",
+                expected.join(" or "),
+                received
+            )
+        } else {
+            format!("expected {}, got {}", expected.join(" or "), received)
+        }),
+    }
+}
@@ -0,0 +1,310 @@
+use std::os::raw::c_int;
+use std::ptr;
+use std::sync::Arc;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::ffi;
+use crate::function::Function;
+use crate::hook::HookTriggers;
+use crate::table::Table;
+use crate::thread::{Thread, ThreadStatus};
+use crate::util::{assert_stack, is_wrapped_panic, protect_lua_closure, StackGuard};
+use crate::value::{Nil, Value};
+
+/// Controls the `coroutine` library installed by [`Context::install_managed_coroutines`].
+///
+/// [`Context::install_managed_coroutines`]: struct.Context.html#method.install_managed_coroutines
+#[derive(Clone, Debug)]
+pub struct CoroutineConfig {
+    max_live: Option<usize>,
+    budget: Option<u32>,
+    introspection: bool,
+}
+
+impl Default for CoroutineConfig {
+    fn default() -> CoroutineConfig {
+        CoroutineConfig {
+            max_live: None,
+            budget: None,
+            introspection: true,
+        }
+    }
+}
+
+impl CoroutineConfig {
+    /// Creates the default configuration, with no limit on live coroutines, no instruction
+    /// budget, and `coroutine.status` and `coroutine.running` available.
+    pub fn new() -> CoroutineConfig {
+        CoroutineConfig::default()
+    }
+
+    /// Sets the maximum number of coroutines created by `coroutine.create` or `coroutine.wrap`
+    /// which may be live at the same time.
+    ///
+    /// A coroutine is live until it finishes, raises an error, or is garbage collected.  Creating
+    /// a coroutine beyond the limit raises an error.
+    pub fn max_live(mut self, max: usize) -> CoroutineConfig {
+        self.max_live = Some(max);
+        self
+    }
+
+    /// Sets the number of VM instructions each coroutine may execute, over all of its resumes.
+    ///
+    /// The budget is enforced with a hook set on the coroutine's thread, which takes the place of
+    /// any hook set with [`Lua::set_hook`] for that coroutine.  A coroutine which exhausts its
+    /// budget raises the error `coroutine instruction budget exhausted`.
+    ///
+    /// [`Lua::set_hook`]: struct.Lua.html#method.set_hook
+    pub fn budget(mut self, instructions: u32) -> CoroutineConfig {
+        self.budget = Some(instructions);
+        self
+    }
+
+    /// Sets whether `coroutine.status` and `coroutine.running` are available to scripts.
+    pub fn expose_introspection(mut self, enabled: bool) -> CoroutineConfig {
+        self.introspection = enabled;
+        self
+    }
+}
+
+pub(crate) fn install<'lua>(lua: Context<'lua>, config: CoroutineConfig) -> Result<Table<'lua>> {
+    let std_lib = open_coroutine_lib(lua)?;
+
+    // Coroutines created here which have not finished yet, each mapped to a sentinel table.  The
+    // keys are weak, so an abandoned coroutine can still be collected, and the `__gc` metamethod
+    // of its sentinel then stops counting it.  The number of live coroutines is kept at index 1 of
+    // `count`.
+    let live = lua.create_table()?;
+    let live_metatable = lua.create_table()?;
+    live_metatable.raw_set("__mode", "k")?;
+    live.set_metatable(Some(live_metatable));
+    let count = lua.create_table()?;
+    count.raw_set(1, 0)?;
+    let sentinel_metatable = lua.create_table()?;
+    sentinel_metatable.raw_set(
+        "__gc",
+        c_closure(lua, collect_sentinel, &[Value::Table(count.clone())])?,
+    )?;
+    let live = Arc::new(lua.create_registry_value(live)?);
+    let count = Arc::new(lua.create_registry_value(count)?);
+    let sentinel_metatable = lua.create_registry_value(sentinel_metatable)?;
+
+    let create = {
+        let live = live.clone();
+        let count = count.clone();
+        let CoroutineConfig {
+            max_live, budget, ..
+        } = config;
+        lua.create_function(move |lua, function: Function| {
+            let live: Table = lua.registry_value(&live)?;
+            let count: Table = lua.registry_value(&count)?;
+            let live_count: usize = count.raw_get(1)?;
+            if let Some(max) = max_live {
+                if live_count >= max {
+                    return Err(Error::RuntimeError(format!(
+                        "too many live coroutines (the limit is {})",
+                        max
+                    )));
+                }
+            }
+            let thread = lua.create_thread(function)?;
+            if let Some(budget) = budget {
+                set_budget_hook(&thread, budget);
+            }
+            let sentinel = lua.create_table()?;
+            sentinel.set_metatable(Some(lua.registry_value(&sentinel_metatable)?));
+            live.raw_set(thread.clone(), sentinel)?;
+            count.raw_set(1, live_count + 1)?;
+            Ok(thread)
+        })?
+    };
+
+    let release = lua.create_function(move |lua, thread: Value| {
+        if let Value::Thread(thread) = thread {
+            let live: Table = lua.registry_value(&live)?;
+            if let Value::Table(sentinel) = live.raw_get(thread.clone())? {
+                if thread.status() != ThreadStatus::Resumable {
+                    // Without its metatable, the sentinel is collected without being counted
+                    // again.
+                    sentinel.set_metatable(None);
+                    live.raw_set(thread, Nil)?;
+                    let count: Table = lua.registry_value(&count)?;
+                    count.raw_set(1, count.raw_get::<_, usize>(1)? - 1)?;
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    let resume = c_closure(
+        lua,
+        managed_resume,
+        &[
+            std_lib.raw_get::<_, Value>("resume")?,
+            Value::Function(release),
+        ],
+    )?;
+    let wrap = c_closure(
+        lua,
+        managed_wrap,
+        &[
+            Value::Function(create.clone()),
+            Value::Function(resume.clone()),
+        ],
+    )?;
+
+    let mut names = vec!["yield", "isyieldable"];
+    if config.introspection {
+        names.extend(&["status", "running"]);
+    }
+    let std_functions = names
+        .into_iter()
+        .map(|name| Ok((name, std_lib.raw_get::<_, Value>(name)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    lua.register_global_module("coroutine", |builder| {
+        let builder = builder
+            .value("create", create)
+            .value("resume", resume)
+            .value("wrap", wrap);
+        std_functions
+            .into_iter()
+            .fold(builder, |builder, (name, function)| {
+                builder.value(name, function)
+            })
+    })
+}
+
+// Returns the table of the standard `coroutine` library, without registering it anywhere.
+fn open_coroutine_lib<'lua>(lua: Context<'lua>) -> Result<Table<'lua>> {
+    unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 3);
+        protect_lua_closure(lua.state, 0, 1, |state| {
+            ffi::luaopen_coroutine(state);
+        })?;
+        Ok(Table(lua.pop_ref()))
+    }
+}
+
+fn c_closure<'lua>(
+    lua: Context<'lua>,
+    function: ffi::lua_CFunction,
+    upvalues: &[Value<'lua>],
+) -> Result<Function<'lua>> {
+    unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, upvalues.len() as c_int + 3);
+        for upvalue in upvalues {
+            lua.push_value(upvalue.clone())?;
+        }
+        let nupvalues = upvalues.len() as c_int;
+        protect_lua_closure(lua.state, nupvalues, 1, |state| {
+            ffi::lua_pushcclosure(state, function, nupvalues);
+        })?;
+        Ok(Function(lua.pop_ref()))
+    }
+}
+
+// Sets a count hook on the thread which raises an error once it has executed `budget`
+// instructions.  Unlike `Thread::set_hook`, this does not keep the thread alive.
+fn set_budget_hook(thread: &Thread, budget: u32) {
+    let triggers = HookTriggers {
+        every_nth_instruction: Some(budget),
+        ..Default::default()
+    };
+    let lua = thread.0.lua;
+    unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 1);
+        lua.push_ref(&thread.0);
+        let thread_state = ffi::lua_tothread(lua.state, -1);
+        ffi::lua_sethook(
+            thread_state,
+            Some(budget_exhausted),
+            triggers.mask(),
+            triggers.count(),
+        );
+    }
+}
+
+unsafe extern "C" fn budget_exhausted(state: *mut ffi::lua_State, _ar: *mut ffi::lua_Debug) {
+    ffi::lua_pushstring(state, cstr!("coroutine instruction budget exhausted"));
+    ffi::lua_error(state);
+}
+
+// The `__gc` metamethod of the sentinel of a coroutine collected while it was still live, which
+// decrements the count given as the first upvalue.
+unsafe extern "C" fn collect_sentinel(state: *mut ffi::lua_State) -> c_int {
+    ffi::lua_rawgeti(state, ffi::lua_upvalueindex(1), 1);
+    let count = ffi::lua_tointeger(state, -1);
+    ffi::lua_pushinteger(state, count - 1);
+    ffi::lua_rawseti(state, ffi::lua_upvalueindex(1), 1);
+    0
+}
+
+// Calls the standard `coroutine.resume` given as the first upvalue, then the release function
+// given as the second upvalue with the coroutine.  Like `safe_pcall`, Rust panics raised inside
+// the coroutine are not returned as errors but continue to propagate.
+unsafe extern "C" fn managed_resume(state: *mut ffi::lua_State) -> c_int {
+    let nargs = ffi::lua_gettop(state);
+    ffi::luaL_checkstack(state, 3, ptr::null());
+
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
+    ffi::lua_insert(state, 1);
+    ffi::lua_pushvalue(state, 2);
+    ffi::lua_insert(state, 1);
+    ffi::lua_call(state, nargs, ffi::LUA_MULTRET);
+
+    ffi::luaL_checkstack(state, 2, ptr::null());
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(2));
+    ffi::lua_pushvalue(state, 1);
+    ffi::lua_call(state, 1, 0);
+
+    if ffi::lua_toboolean(state, 2) == 0 && is_wrapped_panic(state, 3) {
+        ffi::lua_settop(state, 3);
+        ffi::lua_error(state);
+    }
+    ffi::lua_remove(state, 1);
+    ffi::lua_gettop(state)
+}
+
+// Creates a coroutine with the create function given as the first upvalue, and returns a function
+// resuming it with the resume function given as the second upvalue.
+unsafe extern "C" fn managed_wrap(state: *mut ffi::lua_State) -> c_int {
+    ffi::lua_settop(state, 1);
+    ffi::luaL_checkstack(state, 3, ptr::null());
+
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
+    ffi::lua_insert(state, 1);
+    ffi::lua_call(state, 1, 1);
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(2));
+    ffi::lua_insert(state, 1);
+    ffi::lua_pushcclosure(state, wrapped_resume, 2);
+    1
+}
+
+// Resumes the coroutine given as the second upvalue, raising any error like `coroutine.wrap`.
+unsafe extern "C" fn wrapped_resume(state: *mut ffi::lua_State) -> c_int {
+    let nargs = ffi::lua_gettop(state);
+    ffi::luaL_checkstack(state, 3, ptr::null());
+
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
+    ffi::lua_insert(state, 1);
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(2));
+    ffi::lua_insert(state, 2);
+    ffi::lua_call(state, nargs + 1, ffi::LUA_MULTRET);
+
+    if ffi::lua_toboolean(state, 1) == 0 {
+        ffi::lua_settop(state, 2);
+        if ffi::lua_type(state, 2) == ffi::LUA_TSTRING {
+            ffi::luaL_where(state, 1);
+            ffi::lua_insert(state, -2);
+            ffi::lua_concat(state, 2);
+        }
+        ffi::lua_error(state);
+    }
+    ffi::lua_remove(state, 1);
+    ffi::lua_gettop(state)
+}
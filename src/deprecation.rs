@@ -0,0 +1,174 @@
+use std::os::raw::c_int;
+use std::string::String as StdString;
+
+use crate::context::Context;
+use crate::error::Result;
+use crate::ffi;
+use crate::function::Function;
+use crate::hook;
+use crate::lua::extra_data;
+use crate::table::Table;
+use crate::types::Integer;
+use crate::util::{assert_stack, protect_lua_closure, StackGuard};
+use crate::value::{FromLuaMulti, MultiValue, Nil, Value};
+
+// A global deprecated with `Context::deprecate_global`.
+pub(crate) struct Deprecation {
+    path: StdString,
+    message: StdString,
+    // Whether the handler has been called for this global.
+    reported: bool,
+}
+
+// Moves the value at `path` out of its containing table, into a storage table read by the
+// `__index` metamethod of the containing table.  The metamethod calls a Rust function which
+// reports the access, and then returns the value unchanged.
+pub(crate) fn deprecate_global<'lua>(lua: Context<'lua>, path: &str, message: &str) -> Result<()> {
+    let mut parts = path.split('.');
+    let key = parts.next_back().unwrap_or_default();
+    let mut table = lua.globals();
+    for part in parts {
+        table = table.get(part)?;
+    }
+
+    let storage = match index_storage(&table)? {
+        Some(storage) => storage,
+        None => {
+            let metatable = match table.get_metatable() {
+                Some(metatable) => metatable,
+                None => {
+                    let metatable = lua.create_table()?;
+                    table.set_metatable(Some(metatable.clone()));
+                    metatable
+                }
+            };
+            let storage = lua.create_table()?;
+            let previous: Value = metatable.raw_get("__index")?;
+            let report = lua.create_callback(Box::new(|lua, args| {
+                let id = Integer::from_lua_multi(args, lua)?;
+                unsafe { report(lua.state, id as usize) };
+                Ok(MultiValue::new())
+            }))?;
+
+            unsafe {
+                let _sg = StackGuard::new(lua.state);
+                assert_stack(lua.state, 4);
+
+                lua.push_ref(&storage.0);
+                lua.push_value(previous)?;
+                lua.push_ref(&report.0);
+                protect_lua_closure(lua.state, 3, 1, |state| {
+                    ffi::lua_pushcclosure(state, deprecated_index, 3);
+                })?;
+                metatable.raw_set("__index", Function(lua.pop_ref()))?;
+            }
+            storage
+        }
+    };
+
+    let deprecation = Deprecation {
+        path: path.to_owned(),
+        message: message.to_owned(),
+        reported: false,
+    };
+    match storage.raw_get::<_, Option<Table>>(key)? {
+        // The global has been deprecated before, and its value is already in the storage table, so
+        // only its deprecation is replaced.
+        Some(entry) => unsafe {
+            let id = entry.raw_get::<_, Integer>(2)? as usize;
+            (&mut *extra_data(lua.state)).deprecations[id] = deprecation;
+        },
+        None => {
+            let entry = lua.create_table()?;
+            entry.raw_set(1, table.raw_get::<_, Value>(key)?)?;
+            unsafe {
+                let deprecations = &mut (*extra_data(lua.state)).deprecations;
+                entry.raw_set(2, deprecations.len() as Integer)?;
+                deprecations.push(deprecation);
+            }
+            table.raw_set(key, Nil)?;
+            storage.raw_set(key, entry)?;
+        }
+    }
+    Ok(())
+}
+
+// Returns the storage table if the `__index` metamethod of `table` has already been set up by
+// `deprecate_global`.
+fn index_storage<'lua>(table: &Table<'lua>) -> Result<Option<Table<'lua>>> {
+    let metatable = match table.get_metatable() {
+        Some(metatable) => metatable,
+        None => return Ok(None),
+    };
+    let lua = table.0.lua;
+    unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 3);
+
+        lua.push_ref(&metatable.0);
+        ffi::lua_pushstring(lua.state, cstr!("__index"));
+        ffi::lua_rawget(lua.state, -2);
+        match ffi::lua_tocfunction(lua.state, -1) {
+            Some(f) if f as usize == deprecated_index as ffi::lua_CFunction as usize => {
+                ffi::lua_getupvalue(lua.state, -1, 1);
+                Ok(Some(Table(lua.pop_ref())))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+// The `__index` metamethod of a table with deprecated globals.  Its upvalues are the storage
+// table, the previous `__index` metamethod, and the function which reports an access.
+unsafe extern "C" fn deprecated_index(state: *mut ffi::lua_State) -> c_int {
+    ffi::lua_settop(state, 2);
+    ffi::lua_pushvalue(state, 2);
+    if ffi::lua_rawget(state, ffi::lua_upvalueindex(1)) == ffi::LUA_TTABLE {
+        ffi::lua_pushvalue(state, ffi::lua_upvalueindex(3));
+        ffi::lua_rawgeti(state, -2, 2);
+        ffi::lua_call(state, 1, 0);
+        ffi::lua_rawgeti(state, -1, 1);
+        return 1;
+    }
+
+    match ffi::lua_type(state, ffi::lua_upvalueindex(2)) {
+        ffi::LUA_TNIL => ffi::lua_pushnil(state),
+        ffi::LUA_TFUNCTION => {
+            ffi::lua_pushvalue(state, ffi::lua_upvalueindex(2));
+            ffi::lua_pushvalue(state, 1);
+            ffi::lua_pushvalue(state, 2);
+            ffi::lua_call(state, 2, 1);
+        }
+        _ => {
+            ffi::lua_pushvalue(state, 2);
+            ffi::lua_gettable(state, ffi::lua_upvalueindex(2));
+        }
+    }
+    1
+}
+
+// Calls the deprecation handler for the deprecation with the given id, unless it has already been
+// reported and only the first access is reported.
+unsafe fn report(state: *mut ffi::lua_State, id: usize) {
+    let extra = extra_data(state);
+    let handler = match (*extra).deprecation_handler.clone() {
+        Some(handler) => handler,
+        None => return,
+    };
+    let report_every_access = (*extra).report_every_deprecated_access;
+    let deprecation = &mut (&mut *extra).deprecations[id];
+    if deprecation.reported && !report_every_access {
+        return;
+    }
+    deprecation.reported = true;
+    let path = deprecation.path.clone();
+    let message = deprecation.message.clone();
+
+    // Level 0 is the reporting function and level 1 is `deprecated_index`, so the code which
+    // accessed the global is at level 2.
+    let location = hook::stack_frame(state, 2).filter(|frame| frame.what != "C");
+    // A handler which accesses a deprecated global itself is not called again.
+    if let Ok(mut handler) = handler.try_borrow_mut() {
+        (*handler)(&path, &message, location);
+    };
+}
@@ -1,9 +1,14 @@
 use std::error::Error as StdError;
 use std::fmt;
+use std::iter;
+use std::ops::Range;
 use std::result::Result as StdResult;
 use std::string::String as StdString;
 use std::sync::Arc;
 
+use crate::types::RegistryKey;
+use crate::userdata::MetaMethod;
+
 /// Error type returned by `rlua` methods.
 #[derive(Debug, Clone)]
 pub enum Error {
@@ -16,6 +21,14 @@ pub enum Error {
         /// This is useful for implementing REPLs as they can query the user for more input if this
         /// is set.
         incomplete_input: bool,
+        /// The line of the token the error was found at, counting from 1, if it is known.
+        line: Option<usize>,
+        /// The column in bytes of the start of the token the error was found at, counting from 1,
+        /// if the token could be found in the source.
+        column: Option<usize>,
+        /// The byte range of the token the error was found at within the source of the chunk, if it
+        /// could be found.  At the end of the source this is an empty range.
+        span: Option<Range<usize>>,
     },
     /// Lua runtime error, aka `LUA_ERRRUN`.
     ///
@@ -23,6 +36,27 @@ pub enum Error {
     /// Among other things, this includes invoking operators on wrong types (such as calling or
     /// indexing a `nil` value).
     RuntimeError(StdString),
+    /// Lua runtime error with an error value that is not a string or a number, such as a table
+    /// passed to `error`.
+    ///
+    /// The error value is kept in the registry so it can be inspected with
+    /// [`Context::registry_value`].  Returning this error from a Rust callback raises the original
+    /// value as the Lua error, so such values can pass through Rust unchanged, and
+    /// [`Context::create_error`] creates one from any Lua value.
+    ///
+    /// Once the error and all of its clones are dropped, the value's registry slot is released the
+    /// next time such an error is created, or by [`Context::expire_registry_values`], so errors
+    /// raised over and over do not fill up the registry.
+    ///
+    /// [`Context::registry_value`]: struct.Context.html#method.registry_value
+    /// [`Context::create_error`]: struct.Context.html#method.create_error
+    /// [`Context::expire_registry_values`]: struct.Context.html#method.expire_registry_values
+    RuntimeErrorValue {
+        /// A description of the error value, for display purposes.
+        message: StdString,
+        /// Registry key holding the error value.
+        value: Arc<RegistryKey>,
+    },
     /// Lua memory error, aka `LUA_ERRMEM`
     ///
     /// The Lua VM returns this error when the allocator does not return the requested memory, aka
@@ -89,6 +123,11 @@ pub enum Error {
     /// [`AnyUserData`]: struct.AnyUserData.html
     /// [`UserDataMethods`]: trait.UserDataMethods.html
     UserDataTypeMismatch,
+    /// An [`AnyUserData`] was not created by `rlua`, but by C code such as a C library, and so
+    /// cannot be borrowed as any Rust type.
+    ///
+    /// [`AnyUserData`]: struct.AnyUserData.html
+    ForeignUserData,
     /// An [`AnyUserData`] immutable borrow failed because it is already borrowed mutably.
     ///
     /// This error can occur when a method on a [`UserData`] type calls back into Lua, which then
@@ -109,6 +148,91 @@ pub enum Error {
     UserDataBorrowMutError,
     /// A `RegistryKey` produced from a different Lua state was used.
     MismatchedRegistryKey,
+    /// More values were passed to or returned from a function than allowed by
+    /// [`Lua::set_multivalue_limit`].
+    ///
+    /// [`Lua::set_multivalue_limit`]: struct.Lua.html#method.set_multivalue_limit
+    TooManyValues {
+        /// The configured limit.
+        limit: usize,
+        /// The number of values which were passed or returned.
+        got: usize,
+    },
+    /// The source of a chunk was larger than allowed by [`Lua::set_max_chunk_size`].
+    ///
+    /// [`Lua::set_max_chunk_size`]: struct.Lua.html#method.set_max_chunk_size
+    ChunkTooLarge {
+        /// The configured limit in bytes.
+        limit: usize,
+        /// The size of the chunk in bytes.
+        size: usize,
+    },
+    /// The deadline given to [`Lua::run_sandboxed`] passed before the function returned.
+    ///
+    /// [`Lua::run_sandboxed`]: struct.Lua.html#method.run_sandboxed
+    DeadlineExceeded,
+    /// The function run by [`Lua::run_sandboxed`] tried to allocate more memory than allowed.
+    ///
+    /// [`Lua::run_sandboxed`]: struct.Lua.html#method.run_sandboxed
+    MemoryLimitExceeded {
+        /// The configured limit in bytes.
+        limit: usize,
+    },
+    /// The function run by [`Lua::run_sandboxed`] nested calls deeper than allowed.
+    ///
+    /// [`Lua::run_sandboxed`]: struct.Lua.html#method.run_sandboxed
+    StackDepthExceeded {
+        /// The configured limit on the number of active calls.
+        limit: usize,
+    },
+    /// A userdata type added a metamethod more than once in a way that is not allowed, such as
+    /// adding a `__tostring` metamethod with [`UserDataMethods::add_tostring_from_display`] and
+    /// another one explicitly.
+    ///
+    /// Neither of the metamethods takes precedence.  This is returned when a userdata of the type
+    /// is created, rather than when the metamethods are added.
+    ///
+    /// [`UserDataMethods::add_tostring_from_display`]: trait.UserDataMethods.html#method.add_tostring_from_display
+    DuplicateMetaMethod {
+        /// The name of the userdata type.
+        type_name: &'static str,
+        /// The metamethod which was added more than once.
+        meta: MetaMethod,
+    },
+    /// A value could not be copied to another Lua state by [`Context::transfer`].
+    ///
+    /// Only plain data (and userdata types explicitly allowed through [`TransferOptions`]) can be
+    /// transferred between states, and only up to a limited depth of nested tables.
+    ///
+    /// [`Context::transfer`]: struct.Context.html#method.transfer
+    /// [`TransferOptions`]: struct.TransferOptions.html
+    NotTransferable {
+        /// Name of the Lua type that could not be transferred.
+        type_name: &'static str,
+        /// Location of the value inside the transferred value, such as `items[2].callback`.  Empty
+        /// if the value passed to `transfer` was itself not transferable.
+        path: StdString,
+    },
+    /// An entry of a table could not be created by a [`TableBuilder`].
+    ///
+    /// [`TableBuilder`]: struct.TableBuilder.html
+    TableBuildError {
+        /// Location of the entry inside the built table, such as `mylib.util.parse`.
+        path: StdString,
+        /// The error which occurred while creating the entry.
+        cause: Arc<Error>,
+    },
+    /// The operands of a binary metamethod could not be converted to [`BinaryOperands`].
+    ///
+    /// [`BinaryOperands`]: enum.BinaryOperands.html
+    BinaryOperandsError {
+        /// Name of the Lua type of the left operand.
+        lhs: &'static str,
+        /// Name of the Lua type of the right operand.
+        rhs: &'static str,
+        /// The error converting the other operand, if one of them is the expected userdata.
+        cause: Option<Arc<Error>>,
+    },
     /// A Rust callback returned `Err`, raising the contained `Error` as a Lua error.
     CallbackError {
         /// Lua call stack backtrace.
@@ -134,6 +258,9 @@ impl fmt::Display for Error {
         match *self {
             Error::SyntaxError { ref message, .. } => write!(fmt, "syntax error: {}", message),
             Error::RuntimeError(ref msg) => write!(fmt, "runtime error: {}", msg),
+            Error::RuntimeErrorValue { ref message, .. } => {
+                write!(fmt, "runtime error: {}", message)
+            }
             Error::MemoryError(ref msg) => {
                 write!(fmt, "memory error: {}", msg)
             }
@@ -177,11 +304,61 @@ impl fmt::Display for Error {
             }
             Error::CoroutineInactive => write!(fmt, "cannot resume inactive coroutine"),
             Error::UserDataTypeMismatch => write!(fmt, "userdata is not expected type"),
+            Error::ForeignUserData => write!(fmt, "userdata was not created by rlua"),
             Error::UserDataBorrowError => write!(fmt, "userdata already mutably borrowed"),
             Error::UserDataBorrowMutError => write!(fmt, "userdata already borrowed"),
             Error::MismatchedRegistryKey => {
                 write!(fmt, "RegistryKey used from different Lua state")
             }
+            Error::TooManyValues { limit, got } => write!(
+                fmt,
+                "too many values ({} values, the limit is {})",
+                got, limit
+            ),
+            Error::ChunkTooLarge { limit, size } => write!(
+                fmt,
+                "chunk too large ({} bytes, the limit is {} bytes)",
+                size, limit
+            ),
+            Error::DeadlineExceeded => write!(fmt, "deadline exceeded"),
+            Error::MemoryLimitExceeded { limit } => {
+                write!(fmt, "memory limit exceeded (the limit is {} bytes)", limit)
+            }
+            Error::StackDepthExceeded { limit } => {
+                write!(fmt, "stack depth exceeded (the limit is {} calls)", limit)
+            }
+            Error::DuplicateMetaMethod { type_name, meta } => write!(
+                fmt,
+                "metamethod {} added more than once for userdata type {}",
+                StdString::from_utf8_lossy(meta.name()),
+                type_name
+            ),
+            Error::NotTransferable {
+                type_name,
+                ref path,
+            } => {
+                write!(fmt, "cannot transfer Lua {} to another Lua state", type_name)?;
+                if path.is_empty() {
+                    Ok(())
+                } else {
+                    write!(fmt, " (at `{}`)", path)
+                }
+            }
+            Error::TableBuildError {
+                ref path,
+                ref cause,
+            } => write!(fmt, "error building table entry `{}`: {}", path, cause),
+            Error::BinaryOperandsError {
+                lhs,
+                rhs,
+                ref cause,
+            } => {
+                write!(fmt, "unsupported operand types {} and {}", lhs, rhs)?;
+                match *cause {
+                    Some(ref cause) => write!(fmt, ": {}", cause),
+                    None => Ok(()),
+                }
+            }
             Error::CallbackError { ref traceback, .. } => {
                 write!(fmt, "callback error: {}", traceback)
             }
@@ -193,6 +370,11 @@ impl fmt::Display for Error {
 impl StdError for Error {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match *self {
+            Error::TableBuildError { ref cause, .. } => Some(cause.as_ref()),
+            Error::BinaryOperandsError {
+                cause: Some(ref cause),
+                ..
+            } => Some(cause.as_ref()),
             Error::CallbackError { ref cause, .. } => Some(cause.as_ref()),
             Error::ExternalError(ref err) => err.source(),
             _ => None,
@@ -204,6 +386,71 @@ impl Error {
     pub fn external<T: Into<Box<dyn StdError + Send + Sync>>>(err: T) -> Error {
         Error::ExternalError(err.into().into())
     }
+
+    /// Creates an `Error::FromLuaConversionError`, for use in `FromLua` implementations which
+    /// should fail the same way as the conversions provided by rlua.
+    ///
+    /// `from` is the Lua type name of the value, usually from [`Value::type_name`], and `to` is the
+    /// name of the Rust type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Context, Error, FromLua, Lua, Result, Value};
+    /// struct Even(i64);
+    ///
+    /// impl<'lua> FromLua<'lua> for Even {
+    ///     fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Even> {
+    ///         let from = value.type_name();
+    ///         match i64::from_lua(value, lua)? {
+    ///             n if n % 2 == 0 => Ok(Even(n)),
+    ///             n => Err(Error::from_lua_conversion(
+    ///                 from,
+    ///                 "Even",
+    ///                 Some(format!("{} is odd", n)),
+    ///             )),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// # fn main() {
+    /// Lua::new().context(|lua_context| {
+    ///     assert_eq!(lua_context.load("4").eval::<Even>().unwrap().0, 4);
+    ///     let err = lua_context.load("3").eval::<Even>().err().unwrap();
+    ///     assert_eq!(
+    ///         err.to_string(),
+    ///         "error converting Lua integer to Even (3 is odd)"
+    ///     );
+    /// });
+    /// # }
+    /// ```
+    ///
+    /// [`Value::type_name`]: enum.Value.html#method.type_name
+    pub fn from_lua_conversion(
+        from: &'static str,
+        to: &'static str,
+        message: Option<StdString>,
+    ) -> Error {
+        Error::FromLuaConversionError { from, to, message }
+    }
+
+    /// Iterates over this error and the causes of any nested `CallbackError`s and
+    /// `TableBuildError`s.
+    ///
+    /// The first item is this error itself, and the last is the innermost error, which is the
+    /// original error returned by a callback when this is a `CallbackError`.
+    pub fn chain(&self) -> impl Iterator<Item = &Error> {
+        let mut next = Some(self);
+        iter::from_fn(move || {
+            let current = next?;
+            next = match *current {
+                Error::CallbackError { ref cause, .. }
+                | Error::TableBuildError { ref cause, .. } => Some(cause.as_ref()),
+                _ => None,
+            };
+            Some(current)
+        })
+    }
 }
 
 pub trait ExternalError {
@@ -21,6 +21,11 @@ pub type lua_KFunction =
     unsafe extern "C" fn(state: *mut lua_State, status: c_int, ctx: lua_KContext) -> c_int;
 pub type lua_CFunction = unsafe extern "C" fn(state: *mut lua_State) -> c_int;
 pub type lua_Hook = unsafe extern "C" fn(state: *mut lua_State, ar: *mut lua_Debug);
+pub type lua_Reader = unsafe extern "C" fn(
+    state: *mut lua_State,
+    data: *mut c_void,
+    size: *mut usize,
+) -> *const c_char;
 
 #[repr(C)]
 pub struct lua_Debug {
@@ -40,6 +45,15 @@ pub struct lua_Debug {
     i_ci: *mut c_void,
 }
 
+#[repr(C)]
+pub struct luaL_Buffer {
+    pub b: *mut c_char,
+    pub size: usize,
+    pub n: usize,
+    pub L: *mut lua_State,
+    pub initb: [c_char; LUAL_BUFFERSIZE],
+}
+
 pub const LUA_OK: c_int = 0;
 pub const LUA_YIELD: c_int = 1;
 pub const LUA_ERRRUN: c_int = 2;
@@ -58,6 +72,8 @@ pub const LUA_RIDX_MAINTHREAD: lua_Integer = 1;
 pub const LUA_RIDX_GLOBALS: lua_Integer = 2;
 pub const LUA_IDSIZE: c_int = 60;
 pub const LUA_MINSTACK: c_int = 20;
+pub const LUAL_BUFFERSIZE: usize =
+    0x80 * mem::size_of::<*mut c_void>() * mem::size_of::<lua_Integer>();
 // Not actually defined in lua.h / luaconf.h
 pub const LUA_MAX_UPVALUES: c_int = 255;
 
@@ -87,6 +103,12 @@ pub const LUA_MASKRET: c_int = 2;
 pub const LUA_MASKLINE: c_int = 4;
 pub const LUA_MASKCOUNT: c_int = 8;
 
+pub const LUA_HOOKCALL: c_int = 0;
+pub const LUA_HOOKRET: c_int = 1;
+pub const LUA_HOOKLINE: c_int = 2;
+pub const LUA_HOOKCOUNT: c_int = 3;
+pub const LUA_HOOKTAILCALL: c_int = 4;
+
 extern "C" {
     pub fn lua_newstate(alloc: lua_Alloc, ud: *mut c_void) -> *mut lua_State;
     pub fn lua_close(state: *mut lua_State);
@@ -107,6 +129,13 @@ extern "C" {
         k: Option<lua_KFunction>,
     ) -> c_int;
     pub fn lua_resume(state: *mut lua_State, from: *mut lua_State, nargs: c_int) -> c_int;
+    pub fn lua_load(
+        state: *mut lua_State,
+        reader: lua_Reader,
+        data: *mut c_void,
+        chunkname: *const c_char,
+        mode: *const c_char,
+    ) -> c_int;
     pub fn lua_status(state: *mut lua_State) -> c_int;
 
     pub fn lua_pushnil(state: *mut lua_State);
@@ -125,6 +154,7 @@ extern "C" {
     pub fn lua_toboolean(state: *mut lua_State, index: c_int) -> c_int;
     pub fn lua_tonumberx(state: *mut lua_State, index: c_int, isnum: *mut c_int) -> lua_Number;
     pub fn lua_touserdata(state: *mut lua_State, index: c_int) -> *mut c_void;
+    pub fn lua_tocfunction(state: *mut lua_State, index: c_int) -> Option<lua_CFunction>;
     pub fn lua_tothread(state: *mut lua_State, index: c_int) -> *mut lua_State;
     pub fn lua_topointer(state: *mut lua_State, index: c_int) -> *const c_void;
 
@@ -167,14 +197,18 @@ extern "C" {
     pub fn lua_len(state: *mut lua_State, index: c_int);
     pub fn lua_rawlen(state: *mut lua_State, index: c_int) -> usize;
     pub fn lua_next(state: *mut lua_State, index: c_int) -> c_int;
+    pub fn lua_concat(state: *mut lua_State, n: c_int);
     pub fn lua_rawequal(state: *mut lua_State, index1: c_int, index2: c_int) -> c_int;
 
     pub fn lua_error(state: *mut lua_State) -> !;
     pub fn lua_atpanic(state: *mut lua_State, panic: lua_CFunction) -> lua_CFunction;
     pub fn lua_gc(state: *mut lua_State, what: c_int, data: c_int) -> c_int;
+    pub fn lua_getstack(state: *mut lua_State, level: c_int, ar: *mut lua_Debug) -> c_int;
     pub fn lua_getinfo(state: *mut lua_State, what: *const c_char, ar: *mut lua_Debug) -> c_int;
 
     pub fn lua_sethook(state: *mut lua_State, f: Option<lua_Hook>, mask: c_int, count: c_int);
+    pub fn lua_gethookmask(state: *mut lua_State) -> c_int;
+    pub fn lua_gethookcount(state: *mut lua_State) -> c_int;
 
     pub fn luaopen_base(state: *mut lua_State) -> c_int;
     pub fn luaopen_coroutine(state: *mut lua_State) -> c_int;
@@ -212,8 +246,14 @@ extern "C" {
         msg: *const c_char,
         level: c_int,
     );
+    pub fn luaL_where(state: *mut lua_State, level: c_int);
+    pub fn luaL_error(state: *mut lua_State, fmt: *const c_char, ...) -> c_int;
     pub fn luaL_len(push_state: *mut lua_State, index: c_int) -> lua_Integer;
     pub fn luaL_tolstring(state: *mut lua_State, index: c_int, len: *mut usize) -> *const c_char;
+
+    pub fn luaL_buffinit(state: *mut lua_State, buffer: *mut luaL_Buffer);
+    pub fn luaL_addlstring(buffer: *mut luaL_Buffer, s: *const c_char, l: usize);
+    pub fn luaL_pushresult(buffer: *mut luaL_Buffer);
 }
 
 // The following are re-implementations of what are macros in the Lua C API
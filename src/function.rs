@@ -1,13 +1,19 @@
+use std::ffi::CStr;
 use std::os::raw::c_int;
 use std::ptr;
 
 use crate::error::{Error, Result};
 use crate::ffi;
+use crate::lua::check_multivalue_limit;
+use crate::table::Table;
+use crate::thread::{Thread, ThreadStatus};
 use crate::types::LuaRef;
+use crate::userdata::AnyUserData;
 use crate::util::{
-    assert_stack, check_stack, error_traceback, pop_error, protect_lua_closure, StackGuard,
+    assert_stack, check_stack, error_traceback, is_wrapped_panic, pop_error, protect_lua_closure,
+    StackGuard,
 };
-use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti};
+use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
 
 /// Handle to an internal Lua function.
 #[derive(Clone, Debug)]
@@ -58,36 +64,158 @@ impl<'lua> Function<'lua> {
     /// ```
     pub fn call<A: ToLuaMulti<'lua>, R: FromLuaMulti<'lua>>(&self, args: A) -> Result<R> {
         let lua = self.0.lua;
+        let results = protected_call(&self.0, args.to_lua_multi(lua)?, None)?;
+        R::from_lua_multi(results, lua)
+    }
 
-        let args = args.to_lua_multi(lua)?;
-        let nargs = args.len() as c_int;
+    /// Calls the function with a single argument, returning only its first return value.
+    ///
+    /// This is equivalent to `call::<_, R>(arg)`, but is faster as it passes the argument and the
+    /// result directly, rather than through a `MultiValue`.  Further return values are discarded,
+    /// and a missing return value is converted from `nil`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Function, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let square: Function = lua_context.load("function(x) return x * x end").eval()?;
+    /// let mut total = 0;
+    /// for i in 0..100 {
+    ///     total += square.call1::<_, i64>(i)?;
+    /// }
+    /// assert_eq!(total, 328350);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn call1<A: ToLua<'lua>, R: FromLua<'lua>>(&self, arg: A) -> Result<R> {
+        let lua = self.0.lua;
+        let arg = arg.to_lua(lua)?;
+        self.call_single(1, move || unsafe { lua.push_value(arg) })
+    }
+
+    /// Calls the function without arguments, returning only its first return value.
+    ///
+    /// This is the equivalent of [`call1`] for `call::<_, R>(())`.
+    ///
+    /// [`call1`]: #method.call1
+    pub fn call0<R: FromLua<'lua>>(&self) -> Result<R> {
+        self.call_single(0, || Ok(()))
+    }
 
-        let results = unsafe {
+    // Calls the function with the `nargs` arguments pushed by `push_args`, keeping one result.
+    fn call_single<R, F>(&self, nargs: c_int, push_args: F) -> Result<R>
+    where
+        R: FromLua<'lua>,
+        F: FnOnce() -> Result<()>,
+    {
+        let lua = self.0.lua;
+        let result = unsafe {
             let _sg = StackGuard::new(lua.state);
             check_stack(lua.state, nargs + 3)?;
 
             ffi::lua_pushcfunction(lua.state, error_traceback);
             let stack_start = ffi::lua_gettop(lua.state);
             lua.push_ref(&self.0);
-            for arg in args {
-                lua.push_value(arg)?;
-            }
-            let ret = ffi::lua_pcall(lua.state, nargs, ffi::LUA_MULTRET, stack_start);
+            push_args()?;
+            let ret = ffi::lua_pcall(lua.state, nargs, 1, stack_start);
             if ret != ffi::LUA_OK {
                 return Err(pop_error(lua.state, ret));
             }
-            let nresults = ffi::lua_gettop(lua.state) - stack_start;
-            let mut results = MultiValue::new();
-            assert_stack(lua.state, 2);
-            for _ in 0..nresults {
-                results.push_front(lua.pop_value());
-            }
-            ffi::lua_pop(lua.state, 1);
-            results
+            lua.pop_value()
         };
+        R::from_lua(result, lua)
+    }
+
+    /// Calls the function, passing `args` as function arguments, with `handler` as the Lua message
+    /// handler.
+    ///
+    /// If the function raises an error, `handler` is called with the error value, as with Lua's
+    /// `xpcall`, and whatever it returns becomes the error.  The default handler, which adds a
+    /// traceback to the error, is not used.  Rust panics raised inside the call are never passed
+    /// to `handler`, they continue unwinding as usual.  If `handler` itself raises an error, the
+    /// call fails with `Error::RuntimeError`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Error, Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let fail: Function = lua_context.load("function() error('no ammo', 0) end").eval()?;
+    /// let decorate: Function = lua_context.load("function(err) return 'turn 3: ' .. err end").eval()?;
+    ///
+    /// match fail.call_with_handler::<_, ()>((), decorate) {
+    ///     Err(Error::RuntimeError(msg)) => assert_eq!(msg, "turn 3: no ammo"),
+    ///     r => panic!("unexpected result {:?}", r),
+    /// }
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn call_with_handler<A, R>(&self, args: A, handler: Function<'lua>) -> Result<R>
+    where
+        A: ToLuaMulti<'lua>,
+        R: FromLuaMulti<'lua>,
+    {
+        let lua = self.0.lua;
+        let results = protected_call(&self.0, args.to_lua_multi(lua)?, Some(&handler))?;
         R::from_lua_multi(results, lua)
     }
 
+    /// Runs the function as a coroutine until it finishes, collecting the values it yields.
+    ///
+    /// A new thread is created for the function and resumed with `args`, then resumed without
+    /// arguments after each yield.  The first value of each yield is converted to `R`, and the
+    /// values returned when the function finishes are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let squares: Function = lua_context.load(r#"
+    ///     function(n)
+    ///         for i = 1, n do
+    ///             coroutine.yield(i * i)
+    ///         end
+    ///     end
+    /// "#).eval()?;
+    ///
+    /// assert_eq!(squares.collect_yields::<_, u32>(4)?, vec![1, 4, 9, 16]);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn collect_yields<A, R>(&self, args: A) -> Result<Vec<R>>
+    where
+        A: ToLuaMulti<'lua>,
+        R: FromLua<'lua>,
+    {
+        let lua = self.0.lua;
+        let thread = lua.create_thread(self.clone())?;
+
+        let mut results = Vec::new();
+        let mut values: MultiValue = thread.resume(args)?;
+        while thread.status() == ThreadStatus::Resumable {
+            let value = values.into_iter().next().unwrap_or(Nil);
+            results.push(R::from_lua(value, lua)?);
+            values = thread.resume(())?;
+        }
+        Ok(results)
+    }
+
+    /// Wraps this function into a new thread (or coroutine), like [`Context::create_thread`].
+    ///
+    /// [`Context::create_thread`]: struct.Context.html#method.create_thread
+    pub fn into_thread(self) -> Result<Thread<'lua>> {
+        let lua = self.0.lua;
+        lua.create_thread(self)
+    }
+
     /// Returns a function that, when called, calls `self`, passing `args` as the first set of
     /// arguments.
     ///
@@ -161,4 +289,218 @@ impl<'lua> Function<'lua> {
             Ok(Function(lua.pop_ref()))
         }
     }
+
+    /// Returns the environment of the function, the table held in its `_ENV` upvalue.
+    ///
+    /// Returns `None` if the function has no `_ENV` upvalue, which is the case for Rust and C
+    /// functions, and for Lua functions that are not chunks and do not access any global
+    /// variables.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let chunk = lua_context.load("return x").into_function()?;
+    /// let env = chunk.environment()?.unwrap();
+    /// assert!(env.contains_key("print")?);
+    ///
+    /// let double: Function = lua_context.load("function(x) return x * 2 end").eval()?;
+    /// assert!(double.environment()?.is_none());
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn environment(&self) -> Result<Option<Table<'lua>>> {
+        let lua = self.0.lua;
+        let value = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 2);
+
+            lua.push_ref(&self.0);
+            match env_upvalue(lua.state) {
+                Some(n) => {
+                    ffi::lua_getupvalue(lua.state, -1, n);
+                    lua.pop_value()
+                }
+                None => return Ok(None),
+            }
+        };
+        Option::<Table>::from_lua(value, lua)
+    }
+
+    /// Replaces the environment of the function, the table held in its `_ENV` upvalue.
+    ///
+    /// Returns `false` and does nothing if the function has no `_ENV` upvalue, see
+    /// [`environment`].
+    ///
+    /// An upvalue can be shared between functions: all functions defined in a chunk share its
+    /// `_ENV`, so they see the new environment as well.
+    ///
+    /// [`environment`]: #method.environment
+    pub fn set_environment(&self, env: Table<'lua>) -> Result<bool> {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 2);
+
+            lua.push_ref(&self.0);
+            match env_upvalue(lua.state) {
+                Some(n) => {
+                    lua.push_ref(&env.0);
+                    ffi::lua_setupvalue(lua.state, -2, n);
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+    }
+}
+
+// Finds the index of the `_ENV` upvalue of the function at the top of the stack.  Uses 1 stack
+// space.
+unsafe fn env_upvalue(state: *mut ffi::lua_State) -> Option<c_int> {
+    let mut n = 1;
+    loop {
+        let name = ffi::lua_getupvalue(state, -1, n);
+        if name.is_null() {
+            return None;
+        }
+        ffi::lua_pop(state, 1);
+        if CStr::from_ptr(name).to_bytes() == b"_ENV" {
+            return Some(n);
+        }
+        n += 1;
+    }
+}
+
+/// A Lua value which can be called like a function: a function, or a table or userdata with a
+/// `__call` metamethod.
+///
+/// Converting a value to `Callable` keeps the original value, which is returned by [`value`] and
+/// by the `ToLua` conversion, so it can still be compared with other values.  Calling a table or
+/// userdata passes the value itself as the first argument to its `__call` metamethod, exactly as
+/// Lua does.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Callable, Lua, Result};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let double: Callable = lua_context.load("function(x) return x * 2 end").eval()?;
+/// assert_eq!(double.call::<_, i64>(4)?, 8);
+///
+/// let counter: Callable = lua_context.load(r#"
+///     setmetatable({ step = 3 }, { __call = function(self, x) return x + self.step end })
+/// "#).eval()?;
+/// assert_eq!(counter.call::<_, i64>(4)?, 7);
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+///
+/// [`value`]: #method.value
+#[derive(Clone, Debug)]
+pub struct Callable<'lua>(pub(crate) Value<'lua>);
+
+impl<'lua> Callable<'lua> {
+    /// Calls the value, passing `args` as function arguments.
+    ///
+    /// The return values are converted to the generic type `R`.
+    pub fn call<A: ToLuaMulti<'lua>, R: FromLuaMulti<'lua>>(&self, args: A) -> Result<R> {
+        let callee = self.callee()?;
+        let lua = callee.lua;
+        let results = protected_call(callee, args.to_lua_multi(lua)?, None)?;
+        R::from_lua_multi(results, lua)
+    }
+
+    /// Returns the original value.
+    pub fn value(&self) -> &Value<'lua> {
+        &self.0
+    }
+
+    /// Converts this `Callable` back into the original value.
+    pub fn into_value(self) -> Value<'lua> {
+        self.0
+    }
+
+    // Returns the reference to call.  `FromLua` only accepts functions, tables and userdata, but
+    // any other value is reported as an error rather than trusted not to occur.
+    fn callee(&self) -> Result<&LuaRef<'lua>> {
+        match self.0 {
+            Value::Function(Function(ref r)) => Ok(r),
+            Value::Table(Table(ref r)) => Ok(r),
+            Value::UserData(AnyUserData(ref r)) => Ok(r),
+            Value::Nil
+            | Value::Boolean(_)
+            | Value::LightUserData(_)
+            | Value::Integer(_)
+            | Value::Number(_)
+            | Value::String(_)
+            | Value::Thread(_)
+            | Value::Error(_) => Err(Error::FromLuaConversionError {
+                from: self.0.type_name(),
+                to: "Callable",
+                message: Some("value is not callable".to_owned()),
+            }),
+        }
+    }
+}
+
+// Calls `callee`, which may be any value callable by Lua, with the given message handler, or
+// `error_traceback` if none is given.
+fn protected_call<'lua>(
+    callee: &LuaRef<'lua>,
+    args: MultiValue<'lua>,
+    handler: Option<&Function<'lua>>,
+) -> Result<MultiValue<'lua>> {
+    // Passes errors to the user handler given as the first upvalue, except for Rust panics.
+    unsafe extern "C" fn handler_msgh(state: *mut ffi::lua_State) -> c_int {
+        ffi::luaL_checkstack(state, 2, ptr::null());
+
+        if !is_wrapped_panic(state, -1) {
+            ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
+            ffi::lua_insert(state, -2);
+            ffi::lua_call(state, 1, 1);
+        }
+        1
+    }
+
+    let lua = callee.lua;
+    let nargs = args.len() as c_int;
+
+    unsafe {
+        let _sg = StackGuard::new(lua.state);
+        check_stack(lua.state, nargs + 3)?;
+
+        match handler {
+            Some(handler) => {
+                lua.push_ref(&handler.0);
+                protect_lua_closure(lua.state, 1, 1, |state| {
+                    ffi::lua_pushcclosure(state, handler_msgh, 1);
+                })?;
+            }
+            None => ffi::lua_pushcfunction(lua.state, error_traceback),
+        }
+        let stack_start = ffi::lua_gettop(lua.state);
+        lua.push_ref(callee);
+        for arg in args {
+            lua.push_value(arg)?;
+        }
+        let ret = ffi::lua_pcall(lua.state, nargs, ffi::LUA_MULTRET, stack_start);
+        if ret != ffi::LUA_OK {
+            return Err(pop_error(lua.state, ret));
+        }
+        let nresults = ffi::lua_gettop(lua.state) - stack_start;
+        check_multivalue_limit(lua.state, nresults as usize)?;
+        let mut results = MultiValue::new();
+        assert_stack(lua.state, 2);
+        for _ in 0..nresults {
+            results.push_front(lua.pop_value());
+        }
+        ffi::lua_pop(lua.state, 1);
+        Ok(results)
+    }
 }
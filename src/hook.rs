@@ -1,11 +1,13 @@
 use std::ffi::CStr;
 use std::marker::PhantomData;
+use std::mem;
 use std::os::raw::{c_char, c_int};
+use std::string::String as StdString;
 
-use crate::context::Context;
+use crate::context::{callback_name, Context};
 use crate::ffi::{self, lua_Debug, lua_State};
 use crate::lua::extra_data;
-use crate::util::callback_error;
+use crate::util::{assert_stack, callback_error};
 
 /// Contains information about currently executing Lua code.
 ///
@@ -78,6 +80,11 @@ impl<'a> Debug<'a> {
         }
     }
 
+    // The kind of event which called the hook, one of the `LUA_HOOK*` constants.
+    pub(crate) fn event(&self) -> c_int {
+        unsafe { (*self.ar).event }
+    }
+
     /// Corresponds to the `u` what mask.
     pub fn stack(&self) -> DebugStack {
         unsafe {
@@ -116,6 +123,68 @@ pub struct DebugStack {
     pub is_vararg: bool,
 }
 
+/// A single level of the Lua call stack, returned by [`Context::backtrace`].
+///
+/// Strings which are not valid UTF-8 are converted lossily.
+///
+/// [`Context::backtrace`]: struct.Context.html#method.backtrace
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StackFrame {
+    /// A printable version of the source of the function, as used in error messages, such as
+    /// `[string "chunk"]` or `[C]`.
+    pub source: StdString,
+    /// The line currently executing, or `None` for functions which have no line information,
+    /// such as Rust or C functions.
+    pub current_line: Option<u32>,
+    /// A name for the function, guessed from how it was called if one could be found, or the name
+    /// given to [`Context::create_named_function`].
+    ///
+    /// [`Context::create_named_function`]: struct.Context.html#method.create_named_function
+    pub name: Option<StdString>,
+    /// `"Lua"` for a Lua function, `"C"` for a Rust or C function, or `"main"` for the main part
+    /// of a chunk.
+    pub what: StdString,
+}
+
+pub(crate) unsafe fn backtrace(state: *mut lua_State) -> Vec<StackFrame> {
+    let mut frames = Vec::new();
+    while let Some(frame) = stack_frame(state, frames.len() as c_int) {
+        frames.push(frame);
+    }
+    frames
+}
+
+// Returns the given level of the call stack, where level 0 is the running function, or `None` if
+// the stack is not that deep.
+pub(crate) unsafe fn stack_frame(state: *mut lua_State, level: c_int) -> Option<StackFrame> {
+    assert_stack(state, 2);
+
+    let mut ar: lua_Debug = mem::zeroed();
+    if ffi::lua_getstack(state, level, &mut ar) == 0 {
+        return None;
+    }
+    rlua_assert!(
+        ffi::lua_getinfo(state, cstr!("Slnf"), &mut ar) != 0,
+        "lua_getinfo failed with `Slnf`"
+    );
+    let callback_name = callback_name(state, -1);
+    ffi::lua_pop(state, 1);
+    let to_string = |s| StdString::from_utf8_lossy(s).into_owned();
+    Some(StackFrame {
+        source: to_string(CStr::from_ptr(ar.short_src.as_ptr()).to_bytes()),
+        current_line: if ar.currentline >= 0 {
+            Some(ar.currentline as u32)
+        } else {
+            None
+        },
+        name: match callback_name {
+            Some(name) => Some(StdString::from_utf8_lossy(&name).into_owned()),
+            None => ptr_to_str(ar.name).map(to_string),
+        },
+        what: ptr_to_str(ar.what).map(to_string).unwrap_or_default(),
+    })
+}
+
 /// Determines when a hook function will be called by Lua.
 #[derive(Clone, Copy, Debug, Default)]
 pub struct HookTriggers {
@@ -169,10 +238,40 @@ pub(crate) unsafe extern "C" fn hook_proc(state: *mut lua_State, ar: *mut lua_De
             _phantom: PhantomData,
         };
 
-        let cb = rlua_expect!(
-            (*extra_data(state)).hook_callback.clone(),
-            "no hook callback set in hook_proc"
-        );
+        // A hook set on this specific thread takes priority over the global hook.
+        let extra = extra_data(state);
+        let cb = match (*extra).thread_hooks.get(&state) {
+            Some((_, cb)) => cb.clone(),
+            None => {
+                // Threads keep the hook inherited from the state that created them after the
+                // global hook is replaced or removed, such as at the end of `Lua::run_sandboxed`.
+                // Such a thread is brought up to date here, and the global hook is only called for
+                // the events it asked for.
+                let triggers = (*extra).hook_triggers;
+                match (*extra).hook_callback.clone() {
+                    Some(cb) => {
+                        if ffi::lua_gethookmask(state) != triggers.mask()
+                            || ffi::lua_gethookcount(state) != triggers.count()
+                        {
+                            ffi::lua_sethook(
+                                state,
+                                Some(hook_proc),
+                                triggers.mask(),
+                                triggers.count(),
+                            );
+                            if triggers.mask() & event_mask(debug.event()) == 0 {
+                                return Ok(());
+                            }
+                        }
+                        cb
+                    }
+                    None => {
+                        ffi::lua_sethook(state, None, 0, 0);
+                        return Ok(());
+                    }
+                }
+            }
+        };
         let outcome = match cb.try_borrow_mut() {
             Ok(mut b) => (&mut *b)(context, debug),
             Err(_) => rlua_panic!("Lua should not allow hooks to be called within another hook"),
@@ -181,6 +280,16 @@ pub(crate) unsafe extern "C" fn hook_proc(state: *mut lua_State, ar: *mut lua_De
     });
 }
 
+// Returns the `LUA_MASK*` bit which enables hook events of the kind `event`.
+fn event_mask(event: c_int) -> c_int {
+    match event {
+        ffi::LUA_HOOKCALL | ffi::LUA_HOOKTAILCALL => ffi::LUA_MASKCALL,
+        ffi::LUA_HOOKRET => ffi::LUA_MASKRET,
+        ffi::LUA_HOOKLINE => ffi::LUA_MASKLINE,
+        _ => ffi::LUA_MASKCOUNT,
+    }
+}
+
 unsafe fn ptr_to_str<'a>(input: *const c_char) -> Option<&'a [u8]> {
     if input.is_null() {
         None
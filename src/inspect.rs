@@ -0,0 +1,302 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::os::raw::c_void;
+use std::string::String as StdString;
+
+use crate::error::Result;
+use crate::number_format::{format_number, NumberFormat};
+use crate::table::Table;
+use crate::transfer::ref_pointer;
+use crate::value::Value;
+
+/// Controls how values are rendered by [`Context::inspect`].
+///
+/// [`Context::inspect`]: struct.Context.html#method.inspect
+#[derive(Clone, Debug)]
+pub struct InspectOptions {
+    max_depth: usize,
+    max_entries: usize,
+    max_string_len: usize,
+}
+
+impl Default for InspectOptions {
+    fn default() -> InspectOptions {
+        InspectOptions {
+            max_depth: 4,
+            max_entries: 32,
+            max_string_len: 64,
+        }
+    }
+}
+
+impl InspectOptions {
+    /// Creates the default set of options, which render up to 4 levels of nested tables, 32
+    /// entries per table and 64 characters per string.
+    pub fn new() -> InspectOptions {
+        InspectOptions::default()
+    }
+
+    /// Sets how many levels of tables are rendered, tables nested deeper are shown as `{...}`.
+    pub fn max_depth(mut self, depth: usize) -> InspectOptions {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Sets how many entries of each table are rendered before the rest are elided.
+    ///
+    /// Only the rendered entries are sorted, so the entries kept for a large table are not
+    /// necessarily those with the smallest keys.
+    pub fn max_entries(mut self, entries: usize) -> InspectOptions {
+        self.max_entries = entries;
+        self
+    }
+
+    /// Sets how many characters of each string are rendered before the rest are elided.
+    pub fn max_string_len(mut self, len: usize) -> InspectOptions {
+        self.max_string_len = len;
+        self
+    }
+}
+
+pub(crate) fn inspect(
+    value: &Value,
+    options: &InspectOptions,
+    number_format: NumberFormat,
+) -> Result<StdString> {
+    let mut inspector = Inspector {
+        options,
+        number_format,
+        counts: HashMap::new(),
+        ids: HashMap::new(),
+        out: StdString::new(),
+    };
+    inspector.count(value, 0)?;
+    inspector.value(value, 0)?;
+    Ok(inspector.out)
+}
+
+// A table entry to render, sequence values have no key.
+type Entry<'lua> = (Option<Value<'lua>>, Value<'lua>);
+
+struct Inspector<'o> {
+    options: &'o InspectOptions,
+    number_format: NumberFormat,
+    // How many times each table is reached, tables reached more than once are labelled so that
+    // later references (including cycles) can refer back to them.
+    counts: HashMap<*const c_void, usize>,
+    // Labels of the tables which have already been rendered.
+    ids: HashMap<*const c_void, usize>,
+    out: StdString,
+}
+
+impl<'o> Inspector<'o> {
+    fn count(&mut self, value: &Value, depth: usize) -> Result<()> {
+        if let Value::Table(table) = value {
+            let count = self.counts.entry(table_pointer(table)).or_insert(0);
+            *count += 1;
+            if *count == 1 && depth < self.options.max_depth {
+                let (entries, _) = self.entries(table)?;
+                for (key, value) in &entries {
+                    if let Some(key) = key {
+                        self.count(key, depth + 1)?;
+                    }
+                    self.count(value, depth + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, value: &Value, depth: usize) -> Result<()> {
+        match value {
+            Value::Nil => self.out.push_str("nil"),
+            Value::Boolean(b) => write!(self.out, "{}", b).unwrap(),
+            Value::LightUserData(ud) => write!(self.out, "<lightuserdata {:p}>", ud.0).unwrap(),
+            Value::Integer(i) => write!(self.out, "{}", i).unwrap(),
+            Value::Number(n) => match format_number(self.number_format, *n) {
+                Some(s) => self.out.push_str(&s),
+                None => write!(self.out, "{:?}", n).unwrap(),
+            },
+            Value::String(s) => {
+                let s = StdString::from_utf8_lossy(s.as_bytes());
+                match s.char_indices().nth(self.options.max_string_len) {
+                    Some((end, _)) => {
+                        let escaped = format!("{:?}", &s[..end]);
+                        write!(self.out, "{}...\"", &escaped[..escaped.len() - 1]).unwrap();
+                    }
+                    None => write!(self.out, "{:?}", s).unwrap(),
+                }
+            }
+            Value::Table(table) => self.table(table, depth)?,
+            Value::Function(_) => self.out.push_str("<function>"),
+            Value::Thread(_) => self.out.push_str("<thread>"),
+            Value::UserData(ud) => {
+                let name = ud
+                    .get_metatable()
+                    .and_then(|metatable| (metatable.0).raw_get::<_, Option<StdString>>("__name"));
+                match name {
+                    Ok(Some(name)) => write!(self.out, "<userdata {}>", name).unwrap(),
+                    _ => self.out.push_str("<userdata>"),
+                }
+            }
+            Value::Error(err) => write!(self.out, "<error {:?}>", err.to_string()).unwrap(),
+        }
+        Ok(())
+    }
+
+    fn table(&mut self, table: &Table, depth: usize) -> Result<()> {
+        let ptr = table_pointer(table);
+        if let Some(id) = self.ids.get(&ptr) {
+            write!(self.out, "<table {}>", id).unwrap();
+            return Ok(());
+        }
+        if depth >= self.options.max_depth {
+            self.out.push_str("{...}");
+            return Ok(());
+        }
+
+        if self.counts.get(&ptr).cloned().unwrap_or(0) > 1 {
+            let id = self.ids.len() + 1;
+            self.ids.insert(ptr, id);
+            write!(self.out, "<{}>", id).unwrap();
+        }
+
+        let (entries, truncated) = self.entries(table)?;
+        if entries.is_empty() && !truncated {
+            self.out.push_str("{}");
+            return Ok(());
+        }
+
+        self.out.push_str("{ ");
+        for (i, (key, value)) in entries.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            match key {
+                Some(Value::String(s)) if is_identifier(s.as_bytes()) => {
+                    write!(self.out, "{} = ", StdString::from_utf8_lossy(s.as_bytes())).unwrap();
+                }
+                Some(key) => {
+                    self.out.push('[');
+                    self.value(key, depth + 1)?;
+                    self.out.push_str("] = ");
+                }
+                None => {}
+            }
+            self.value(value, depth + 1)?;
+        }
+        if truncated {
+            if !entries.is_empty() {
+                self.out.push_str(", ");
+            }
+            self.out.push_str("...");
+        }
+        self.out.push_str(" }");
+        Ok(())
+    }
+
+    // Returns the entries of a table to render, at most `max_entries` of them, and whether any
+    // entries were left out.  The sequence part of the table comes first with no keys, followed by
+    // the remaining entries sorted by key.
+    fn entries<'lua>(&self, table: &Table<'lua>) -> Result<(Vec<Entry<'lua>>, bool)> {
+        let max_entries = self.options.max_entries;
+        let len = table.raw_len();
+        let mut entries = Vec::new();
+
+        let mut i = 1;
+        while i <= len && entries.len() < max_entries {
+            entries.push((None, table.raw_get(i)?));
+            i += 1;
+        }
+        if i <= len {
+            return Ok((entries, true));
+        }
+
+        let mut truncated = false;
+        let mut rest = Vec::new();
+        for pair in table.clone().pairs::<Value, Value>() {
+            let (key, value) = pair?;
+            if let Value::Integer(i) = key {
+                if i >= 1 && i <= len {
+                    continue;
+                }
+            }
+            if entries.len() + rest.len() == max_entries {
+                truncated = true;
+                break;
+            }
+            rest.push((key, value));
+        }
+        rest.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+        entries.extend(rest.into_iter().map(|(key, value)| (Some(key), value)));
+
+        Ok((entries, truncated))
+    }
+}
+
+fn table_pointer(table: &Table) -> *const c_void {
+    unsafe { ref_pointer(&table.0) }
+}
+
+// Orders numbers first, then strings, then booleans, then all other keys by type name.
+fn compare_keys(a: &Value, b: &Value) -> Ordering {
+    fn rank(v: &Value) -> u8 {
+        match v {
+            Value::Integer(_) | Value::Number(_) => 0,
+            Value::String(_) => 1,
+            Value::Boolean(_) => 2,
+            _ => 3,
+        }
+    }
+    fn number(v: &Value) -> f64 {
+        match *v {
+            Value::Integer(i) => i as f64,
+            Value::Number(n) => n,
+            _ => 0.0,
+        }
+    }
+
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.as_bytes().cmp(b.as_bytes()),
+        (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+        _ if rank(a) == 0 && rank(b) == 0 => {
+            number(a).partial_cmp(&number(b)).unwrap_or(Ordering::Equal)
+        }
+        _ => rank(a)
+            .cmp(&rank(b))
+            .then_with(|| a.type_name().cmp(b.type_name())),
+    }
+}
+
+fn is_identifier(s: &[u8]) -> bool {
+    const KEYWORDS: &[&[u8]] = &[
+        b"and",
+        b"break",
+        b"do",
+        b"else",
+        b"elseif",
+        b"end",
+        b"false",
+        b"for",
+        b"function",
+        b"goto",
+        b"if",
+        b"in",
+        b"local",
+        b"nil",
+        b"not",
+        b"or",
+        b"repeat",
+        b"return",
+        b"then",
+        b"true",
+        b"until",
+        b"while",
+    ];
+    match s.first() {
+        Some(c) if c.is_ascii_alphabetic() || *c == b'_' => {}
+        _ => return false,
+    }
+    s.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_') && !KEYWORDS.contains(&s)
+}
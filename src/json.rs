@@ -0,0 +1,234 @@
+use std::collections::HashSet;
+use std::os::raw::c_void;
+
+use serde_json::{Map, Number as JsonNumber, Value as JsonValue};
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::table::Table;
+use crate::transfer::{key_path, ref_pointer};
+use crate::value::Value;
+
+/// Controls how Lua values are converted to JSON by [`Context::to_json_with`], and JSON to Lua
+/// values by [`Context::from_json_with`].
+///
+/// [`Context::to_json_with`]: struct.Context.html#method.to_json_with
+/// [`Context::from_json_with`]: struct.Context.html#method.from_json_with
+#[derive(Clone, Debug)]
+pub struct JsonOptions {
+    mixed_tables: bool,
+    max_depth: usize,
+}
+
+impl Default for JsonOptions {
+    fn default() -> JsonOptions {
+        JsonOptions {
+            mixed_tables: true,
+            max_depth: 128,
+        }
+    }
+}
+
+impl JsonOptions {
+    /// Creates the default set of options, equivalent to calling [`Context::to_json`].
+    ///
+    /// [`Context::to_json`]: struct.Context.html#method.to_json
+    pub fn new() -> JsonOptions {
+        JsonOptions::default()
+    }
+
+    /// Sets whether tables with both a sequence part and other keys are converted to objects.
+    ///
+    /// When enabled (the default), such tables become objects with their integer keys converted
+    /// to strings.  When disabled, converting them is an error.
+    pub fn allow_mixed_tables(mut self, enabled: bool) -> JsonOptions {
+        self.mixed_tables = enabled;
+        self
+    }
+
+    /// Sets how deeply tables, arrays and objects may be nested, 128 by default.
+    ///
+    /// Converting a value nested more deeply than this is an error, so that deeply nested data
+    /// cannot overflow the Rust stack.  A table containing only plain values has a depth of 1.
+    pub fn max_depth(mut self, depth: usize) -> JsonOptions {
+        self.max_depth = depth;
+        self
+    }
+}
+
+pub(crate) fn to_json(value: Value, options: &JsonOptions) -> Result<JsonValue> {
+    ToJson {
+        options,
+        visiting: HashSet::new(),
+    }
+    .value(value, "")
+}
+
+pub(crate) fn from_json<'lua>(
+    lua: Context<'lua>,
+    json: &JsonValue,
+    options: &JsonOptions,
+) -> Result<Value<'lua>> {
+    from_json_value(lua, json, options.max_depth)
+}
+
+// Converts `json` to a Lua value, with `depth` the number of further levels of arrays and objects
+// allowed.
+fn from_json_value<'lua>(
+    lua: Context<'lua>,
+    json: &JsonValue,
+    depth: usize,
+) -> Result<Value<'lua>> {
+    if depth == 0 && (json.is_array() || json.is_object()) {
+        return Err(Error::ToLuaConversionError {
+            from: "JSON",
+            to: "table",
+            message: Some("JSON is nested too deeply".to_owned()),
+        });
+    }
+    Ok(match *json {
+        JsonValue::Null => Value::Nil,
+        JsonValue::Bool(b) => Value::Boolean(b),
+        JsonValue::Number(ref n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Number(n.as_f64().unwrap_or(0.0)),
+        },
+        JsonValue::String(ref s) => Value::String(lua.create_string(s)?),
+        JsonValue::Array(ref array) => {
+            let table = lua.create_table()?;
+            for (i, json) in array.iter().enumerate() {
+                table.raw_set(i + 1, from_json_value(lua, json, depth - 1)?)?;
+            }
+            Value::Table(table)
+        }
+        JsonValue::Object(ref object) => {
+            let table = lua.create_table()?;
+            for (key, json) in object {
+                table.raw_set(key.as_str(), from_json_value(lua, json, depth - 1)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+struct ToJson<'o> {
+    options: &'o JsonOptions,
+    // Tables currently being converted, used to detect cycles.  Its length is also the current
+    // nesting depth.
+    visiting: HashSet<*const c_void>,
+}
+
+impl<'o> ToJson<'o> {
+    fn value(&mut self, value: Value, path: &str) -> Result<JsonValue> {
+        Ok(match value {
+            Value::Nil => JsonValue::Null,
+            Value::Boolean(b) => JsonValue::Bool(b),
+            Value::Integer(i) => JsonValue::Number(i.into()),
+            Value::Number(n) => match JsonNumber::from_f64(n) {
+                Some(n) => JsonValue::Number(n),
+                None => {
+                    return Err(json_error(
+                        "number",
+                        path,
+                        "NaN and infinity are not allowed",
+                    ))
+                }
+            },
+            Value::String(s) => match s.to_str() {
+                Ok(s) => JsonValue::String(s.to_owned()),
+                Err(_) => return Err(json_error("string", path, "strings must be valid UTF-8")),
+            },
+            Value::Table(t) => self.table(t, path)?,
+            v => {
+                return Err(json_error(
+                    v.type_name(),
+                    path,
+                    "only nil, booleans, numbers, strings and tables can be converted",
+                ))
+            }
+        })
+    }
+
+    fn table(&mut self, table: Table, path: &str) -> Result<JsonValue> {
+        let ptr = unsafe { ref_pointer(&table.0) };
+        if self.visiting.contains(&ptr) {
+            return Err(json_error("table", path, "table contains a cycle"));
+        }
+        if self.visiting.len() == self.options.max_depth {
+            return Err(json_error("table", path, "table is nested too deeply"));
+        }
+        self.visiting.insert(ptr);
+
+        let len = table.raw_len();
+        let entries = table
+            .clone()
+            .pairs::<Value, Value>()
+            .collect::<Result<Vec<_>>>()?;
+
+        let json = if len > 0 && entries.len() == len as usize {
+            let mut array = Vec::with_capacity(entries.len());
+            for i in 1..=len {
+                let value_path = key_path(path, &Value::Integer(i));
+                array.push(self.value(table.raw_get(i)?, &value_path)?);
+            }
+            JsonValue::Array(array)
+        } else {
+            if len > 0 && !self.options.mixed_tables {
+                return Err(json_error(
+                    "table",
+                    path,
+                    "table has both a sequence part and other keys",
+                ));
+            }
+            let mut object = Map::new();
+            for (key, value) in entries {
+                let value_path = key_path(path, &key);
+                let key = match key {
+                    Value::String(s) => match s.to_str() {
+                        Ok(s) => s.to_owned(),
+                        Err(_) => {
+                            return Err(json_error(
+                                "string",
+                                &value_path,
+                                "keys must be valid UTF-8",
+                            ))
+                        }
+                    },
+                    Value::Integer(i) => i.to_string(),
+                    Value::Number(n) if n.is_finite() => n.to_string(),
+                    key => {
+                        return Err(json_error(
+                            key.type_name(),
+                            &value_path,
+                            "keys must be strings or finite numbers",
+                        ))
+                    }
+                };
+                let value = self.value(value, &value_path)?;
+                if object.insert(key, value).is_some() {
+                    return Err(json_error(
+                        "table",
+                        &value_path,
+                        "two keys convert to the same JSON key",
+                    ));
+                }
+            }
+            JsonValue::Object(object)
+        };
+
+        self.visiting.remove(&ptr);
+        Ok(json)
+    }
+}
+
+fn json_error(from: &'static str, path: &str, message: &str) -> Error {
+    Error::FromLuaConversionError {
+        from,
+        to: "JSON",
+        message: Some(if path.is_empty() {
+            message.to_owned()
+        } else {
+            format!("{} (at `{}`)", message, path)
+        }),
+    }
+}
@@ -43,36 +43,73 @@
 #[macro_use]
 mod macros;
 
+mod batch;
+mod callback_registry;
+mod chunk_cache;
 mod context;
 mod conversion;
+mod coroutine;
+mod deprecation;
 mod error;
 mod ffi;
 mod function;
 mod hook;
+mod inspect;
+#[cfg(feature = "json")]
+mod json;
 mod lua;
+mod lua_enum;
 mod markers;
 mod multi;
+mod number_format;
+mod sandbox;
 mod scope;
+mod snapshot;
 mod string;
+mod string_limits;
 mod table;
+mod table_builder;
 mod thread;
+mod transfer;
 mod types;
 mod userdata;
 mod util;
 mod value;
 
+pub use crate::batch::BatchOptions;
+pub use crate::callback_registry::{CallbackRegistry, SubscriptionId};
+pub use crate::chunk_cache::ChunkCacheStats;
 pub use crate::context::{Chunk, Context};
+pub use crate::coroutine::CoroutineConfig;
 pub use crate::error::{Error, ExternalError, ExternalResult, Result};
-pub use crate::function::Function;
-pub use crate::hook::{Debug, DebugNames, DebugSource, DebugStack, HookTriggers};
-pub use crate::lua::{Lua, StdLib};
-pub use crate::multi::Variadic;
+pub use crate::function::{Callable, Function};
+pub use crate::hook::{Debug, DebugNames, DebugSource, DebugStack, HookTriggers, StackFrame};
+pub use crate::inspect::InspectOptions;
+#[cfg(feature = "json")]
+pub use crate::json::JsonOptions;
+pub use crate::lua::{GcStepOutcome, Lua, LuaBuilder, LuaOptions, RegistryReport, StdLib};
+pub use crate::lua_enum::LuaEnum;
+pub use crate::multi::{BinaryOperands, UserDataOperand, Variadic};
+pub use crate::number_format::NumberFormat;
+pub use crate::sandbox::{Limits, SandboxOptions, SandboxReport};
 pub use crate::scope::Scope;
+pub use crate::snapshot::{GlobalsDiff, GlobalsSnapshot};
 pub use crate::string::String;
-pub use crate::table::{Table, TablePairs, TableSequence};
+pub use crate::string_limits::StringLimits;
+pub use crate::table::{Table, TablePairs, TableRawIter, TableSequence};
+pub use crate::table_builder::TableBuilder;
 pub use crate::thread::{Thread, ThreadStatus};
-pub use crate::types::{Integer, LightUserData, Number, RegistryKey};
-pub use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
-pub use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
+pub use crate::transfer::TransferOptions;
+pub use crate::types::{Integer, LightUserData, LuaKey, Number, RegistryKey, TypeCategory};
+pub use crate::userdata::{
+    AnyUserData, MetaMethod, MethodDescriptor, TypeDescriptor, UserData, UserDataHandle,
+    UserDataMetatable, UserDataMethods,
+};
+pub use crate::value::{
+    FromLua, FromLuaMulti, MultiValue, MultiValueBuilder, Nil, ToLua, ToLuaMulti, Value,
+};
+
+#[cfg(feature = "derive")]
+pub use rlua_derive::{FromLua, ToLua};
 
 pub mod prelude;
@@ -1,25 +1,36 @@
 use std::any::TypeId;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::marker::PhantomData;
+use std::mem;
 use std::os::raw::{c_int, c_void};
 use std::ptr;
 use std::rc::Rc;
+use std::slice;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use bitflags::bitflags;
 use libc;
 
-use crate::context::Context;
-use crate::error::Result;
+use crate::chunk_cache::{ChunkCache, ChunkCacheStats};
+use crate::context::{describe_userdata, Context};
+use crate::deprecation::Deprecation;
+use crate::error::{Error, Result};
 use crate::ffi;
-use crate::hook::{hook_proc, Debug, HookTriggers};
+use crate::function::Function;
+use crate::hook::{hook_proc, Debug, HookTriggers, StackFrame};
 use crate::markers::NoRefUnwindSafe;
-use crate::types::Callback;
+use crate::number_format::{set_number_tostring, NumberFormat};
+use crate::sandbox::{Limits, SandboxState};
+use crate::string_limits::{wrap_string_functions, StringLimits, STRING_LIMIT_FUNCTIONS};
+use crate::types::{Callback, DeprecationHandler, ErrorHook, FinalizerErrorHandler, HookCallback};
+use crate::userdata::{TypeDescriptor, UserData};
 use crate::util::{
     assert_stack, init_error_registry, protect_lua_closure, safe_pcall, safe_xpcall,
-    userdata_destructor,
+    userdata_destructor, StackGuard,
 };
+use crate::value::FromLuaMulti;
 
 bitflags! {
     /// Flags describing the set of lua modules to load.
@@ -58,6 +69,266 @@ bitflags! {
     }
 }
 
+/// Options controlling how a new Lua state handles Rust panics, used by [`Lua::new_with_options`].
+///
+/// [`Lua::new_with_options`]: struct.Lua.html#method.new_with_options
+#[derive(Clone, Debug)]
+pub struct LuaOptions {
+    catch_rust_panics: bool,
+    override_pcall: bool,
+}
+
+impl Default for LuaOptions {
+    fn default() -> LuaOptions {
+        LuaOptions {
+            catch_rust_panics: true,
+            override_pcall: true,
+        }
+    }
+}
+
+impl LuaOptions {
+    /// Creates the default options, which match the behavior of [`Lua::new`].
+    ///
+    /// [`Lua::new`]: struct.Lua.html#method.new
+    pub fn new() -> LuaOptions {
+        LuaOptions::default()
+    }
+
+    /// Sets whether a panic inside a Rust callback is caught and resumed once control returns to
+    /// Rust.  This is the default.
+    ///
+    /// When disabled, the panic is instead converted into an `Error::RuntimeError` raised as an
+    /// ordinary Lua error, which scripts can catch with `pcall`, and which is returned to Rust as
+    /// an `Error::CallbackError`.
+    ///
+    /// Like disabling [`override_pcall`], this lets a script carry on running after a panic, even
+    /// though the panic may have left Rust data captured by callbacks in an inconsistent state.
+    /// This cannot cause undefined behavior, but callbacks must not rely on a panic ending the
+    /// script.
+    ///
+    /// [`override_pcall`]: #method.override_pcall
+    pub fn catch_rust_panics(mut self, enabled: bool) -> LuaOptions {
+        self.catch_rust_panics = enabled;
+        self
+    }
+
+    /// Sets whether the global `pcall` and `xpcall` are replaced with versions that cannot catch
+    /// Rust panics.  This is the default.
+    ///
+    /// With the stock `pcall` and `xpcall`, a script can catch a panic raised in a Rust callback
+    /// and carry on running, even though the panic may have left Rust data captured by callbacks
+    /// in an inconsistent state.  As with disabling [`catch_rust_panics`], this cannot cause
+    /// undefined behavior, but only disable it if the stock behavior is required and callbacks do
+    /// not rely on a panic ending the script.
+    ///
+    /// [`catch_rust_panics`]: #method.catch_rust_panics
+    pub fn override_pcall(mut self, enabled: bool) -> LuaOptions {
+        self.override_pcall = enabled;
+        self
+    }
+}
+
+/// Builds a [`Lua`] state with several construction options at once, created by
+/// [`Lua::builder`].
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Lua, Result, StdLib};
+/// # fn main() -> Result<()> {
+/// let lua = Lua::builder()
+///     .std_libs(StdLib::BASE | StdLib::STRING)
+///     .memory_limit(16 * 1024 * 1024)
+///     .multivalue_limit(64)
+///     .build()?;
+/// lua.context(|lua_context| {
+///     assert_eq!(lua_context.load("string.rep('a', 3)").eval::<String>()?, "aaa");
+///     assert!(lua_context.load("table").eval::<Option<rlua::Table>>()?.is_none());
+///     Ok(())
+/// })
+/// # }
+/// ```
+///
+/// [`Lua`]: struct.Lua.html
+/// [`Lua::builder`]: struct.Lua.html#method.builder
+pub struct LuaBuilder {
+    std_libs: StdLib,
+    options: LuaOptions,
+    memory_limit: Option<usize>,
+    multivalue_limit: Option<usize>,
+    max_chunk_size: Option<usize>,
+    registry_expiry_interval: Option<usize>,
+    hook: Option<(
+        HookTriggers,
+        Box<dyn 'static + Send + FnMut(Context, Debug) -> Result<()>>,
+    )>,
+}
+
+impl LuaBuilder {
+    /// Sets the standard libraries to load, by default all of them except `debug`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `lua_mod` contains `StdLib::DEBUG`
+    pub fn std_libs(mut self, lua_mod: StdLib) -> LuaBuilder {
+        assert!(
+            !lua_mod.contains(StdLib::DEBUG),
+            "The lua debug module can't be loaded using `std_libs`. Use `unsafe_std_libs` instead."
+        );
+        self.std_libs = lua_mod;
+        self
+    }
+
+    /// Sets the standard libraries to load, which may include the `debug` library.
+    ///
+    /// # Safety
+    ///
+    /// The `debug` library can be used to break the safety guarantees provided by rlua.
+    pub unsafe fn unsafe_std_libs(mut self, lua_mod: StdLib) -> LuaBuilder {
+        self.std_libs = lua_mod;
+        self
+    }
+
+    /// Sets the [`LuaOptions`] for the state.
+    ///
+    /// [`LuaOptions`]: struct.LuaOptions.html
+    pub fn options(mut self, options: LuaOptions) -> LuaBuilder {
+        self.options = options;
+        self
+    }
+
+    /// Sets whether panics in Rust callbacks are caught and resumed, see
+    /// [`LuaOptions::catch_rust_panics`].
+    ///
+    /// [`LuaOptions::catch_rust_panics`]: struct.LuaOptions.html#method.catch_rust_panics
+    pub fn catch_rust_panics(mut self, enabled: bool) -> LuaBuilder {
+        self.options = self.options.catch_rust_panics(enabled);
+        self
+    }
+
+    /// Sets the memory limit, see [`Lua::set_memory_limit`].
+    ///
+    /// The limit already applies while the standard libraries are loaded.
+    ///
+    /// [`Lua::set_memory_limit`]: struct.Lua.html#method.set_memory_limit
+    pub fn memory_limit(mut self, limit: usize) -> LuaBuilder {
+        self.memory_limit = Some(limit);
+        self
+    }
+
+    /// Sets the multivalue limit, see [`Lua::set_multivalue_limit`].
+    ///
+    /// [`Lua::set_multivalue_limit`]: struct.Lua.html#method.set_multivalue_limit
+    pub fn multivalue_limit(mut self, limit: usize) -> LuaBuilder {
+        self.multivalue_limit = Some(limit);
+        self
+    }
+
+    /// Sets the maximum chunk size, see [`Lua::set_max_chunk_size`].
+    ///
+    /// [`Lua::set_max_chunk_size`]: struct.Lua.html#method.set_max_chunk_size
+    pub fn max_chunk_size(mut self, size: usize) -> LuaBuilder {
+        self.max_chunk_size = Some(size);
+        self
+    }
+
+    /// Sets the registry expiry interval, see [`Lua::set_registry_expiry_interval`].
+    ///
+    /// [`Lua::set_registry_expiry_interval`]: struct.Lua.html#method.set_registry_expiry_interval
+    pub fn registry_expiry_interval(mut self, interval: usize) -> LuaBuilder {
+        self.registry_expiry_interval = Some(interval);
+        self
+    }
+
+    /// Sets a hook on the main thread, see [`Lua::set_hook`].
+    ///
+    /// [`Lua::set_hook`]: struct.Lua.html#method.set_hook
+    pub fn hook<F>(mut self, triggers: HookTriggers, callback: F) -> LuaBuilder
+    where
+        F: 'static + Send + FnMut(Context, Debug) -> Result<()>,
+    {
+        self.hook = Some((triggers, Box::new(callback)));
+        self
+    }
+
+    /// Creates the Lua state.
+    ///
+    /// The limits are set before the standard libraries are loaded, and the hook is set last, so
+    /// it does not run while the state is being built.  Returns an error if loading the standard
+    /// libraries fails, for example because the memory limit is too small.
+    pub fn build(self) -> Result<Lua> {
+        let LuaBuilder {
+            std_libs,
+            options,
+            memory_limit,
+            multivalue_limit,
+            max_chunk_size,
+            registry_expiry_interval,
+            hook,
+        } = self;
+        let override_pcall = options.override_pcall;
+
+        let lua = unsafe { create_lua(options) };
+        lua.set_memory_limit(memory_limit);
+        lua.set_multivalue_limit(multivalue_limit);
+        lua.set_max_chunk_size(max_chunk_size);
+        lua.set_registry_expiry_interval(registry_expiry_interval);
+
+        unsafe {
+            protect_lua_closure(lua.main_state, 0, 0, |state| {
+                load_from_std_lib(state, std_libs);
+                // Installed after the base library, which would replace them.
+                if override_pcall {
+                    install_safe_pcall(state);
+                }
+            })?;
+        }
+
+        if let Some((triggers, callback)) = hook {
+            lua.set_hook(triggers, callback);
+        }
+        Ok(lua)
+    }
+}
+
+/// The result of [`Lua::gc_step_for`].
+///
+/// [`Lua::gc_step_for`]: struct.Lua.html#method.gc_step_for
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GcStepOutcome {
+    /// A garbage collection cycle finished within the time budget.
+    Finished,
+    /// The time budget ran out before the current cycle finished.
+    Unfinished,
+    /// No steps were taken, because the garbage collector is stopped.
+    Disabled,
+}
+
+/// A summary of the contents of the Lua registry, returned by [`Lua::registry_report`].
+///
+/// [`Lua::registry_report`]: struct.Lua.html#method.registry_report
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RegistryReport {
+    /// The number of values held in the registry by a [`RegistryKey`] or by rlua itself, such as
+    /// userdata metatables, keyed by Lua type name.
+    ///
+    /// [`RegistryKey`]: struct.RegistryKey.html
+    pub values: BTreeMap<&'static str, usize>,
+    /// The name and Lua type name of each value with a string key, such as those set by
+    /// [`Context::set_named_registry_value`] and those Lua itself uses like `_LOADED`, sorted by
+    /// name.
+    ///
+    /// [`Context::set_named_registry_value`]: struct.Context.html#method.set_named_registry_value
+    pub named: Vec<(String, &'static str)>,
+    /// The number of [`RegistryKey`]s that have been dropped, whose values are still in the
+    /// registry until the next call to [`Context::expire_registry_values`].
+    ///
+    /// [`RegistryKey`]: struct.RegistryKey.html
+    /// [`Context::expire_registry_values`]: struct.Context.html#method.expire_registry_values
+    pub expired_keys: usize,
+}
+
 /// Top level Lua struct which holds the Lua state itself.
 pub struct Lua {
     main_state: *mut ffi::lua_State,
@@ -85,14 +356,14 @@ impl Drop for Lua {
 impl Lua {
     /// Creates a new Lua state and loads standard library without the `debug` library.
     pub fn new() -> Lua {
-        unsafe { create_lua(StdLib::ALL_NO_DEBUG) }
+        build_default(Lua::builder())
     }
 
     /// Creates a new Lua state and loads the standard library including the `debug` library.
     ///
     /// The debug library is very unsound, it can be used to break the safety guarantees of rlua.
     pub unsafe fn new_with_debug() -> Lua {
-        create_lua(StdLib::ALL)
+        build_default(Lua::builder().unsafe_std_libs(StdLib::ALL))
     }
 
     /// Creates a new Lua state and loads a subset of the standard libraries.
@@ -112,7 +383,39 @@ impl Lua {
             "The lua debug module can't be loaded using `new_with`. Use `unsafe_new_with` instead."
         );
 
-        unsafe { create_lua(lua_mod) }
+        build_default(Lua::builder().std_libs(lua_mod))
+    }
+
+    /// Returns a [`LuaBuilder`] for creating a Lua state with several options set at once.
+    ///
+    /// [`LuaBuilder`]: struct.LuaBuilder.html
+    pub fn builder() -> LuaBuilder {
+        LuaBuilder {
+            std_libs: StdLib::ALL_NO_DEBUG,
+            options: LuaOptions::default(),
+            memory_limit: None,
+            multivalue_limit: None,
+            max_chunk_size: None,
+            registry_expiry_interval: None,
+            hook: None,
+        }
+    }
+
+    /// Creates a new Lua state with the given [`LuaOptions`], and loads a subset of the standard
+    /// libraries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `lua_mod` contains `StdLib::DEBUG`
+    ///
+    /// [`LuaOptions`]: struct.LuaOptions.html
+    pub fn new_with_options(lua_mod: StdLib, options: LuaOptions) -> Lua {
+        assert!(
+            !lua_mod.contains(StdLib::DEBUG),
+            "The lua debug module can't be loaded using `new_with_options`. Use `unsafe_new_with` instead."
+        );
+
+        build_default(Lua::builder().std_libs(lua_mod).options(options))
     }
 
     /// Creates a new Lua state and loads a subset of the standard libraries.
@@ -122,7 +425,7 @@ impl Lua {
     /// This function is unsafe because it can be used to load the `debug` library which can be used
     /// to break the safety guarantees provided by rlua.
     pub unsafe fn unsafe_new_with(lua_mod: StdLib) -> Lua {
-        create_lua(lua_mod)
+        build_default(Lua::builder().unsafe_std_libs(lua_mod))
     }
 
     /// Loads the specified set of safe standard libraries into an existing Lua state.
@@ -161,6 +464,21 @@ impl Lua {
         })
     }
 
+    /// Replaces the global `pcall` and `xpcall` with the versions that cannot catch Rust panics.
+    ///
+    /// rlua installs these when the state is created, but loading the base library afterwards with
+    /// [`Lua::load_from_std_lib`], or a C module which registers its own `pcall` and `xpcall`,
+    /// replaces them with the stock versions.  This restores them.
+    ///
+    /// [`Lua::load_from_std_lib`]: #method.load_from_std_lib
+    pub fn reinstall_safe_pcall(&self) -> Result<()> {
+        unsafe {
+            protect_lua_closure(self.main_state, 0, 0, |state| {
+                install_safe_pcall(state);
+            })
+        }
+    }
+
     /// The main entry point of the rlua API.
     ///
     /// In order to create Lua values, load and execute Lua code, or otherwise interact with the Lua
@@ -229,6 +547,13 @@ impl Lua {
     /// limited form of execution limits by setting [`HookTriggers.every_nth_instruction`] and
     /// erroring once an instruction limit has been reached.
     ///
+    /// The hook is installed on the main Lua thread, and is inherited by any coroutines created
+    /// afterwards.  A hook set on an individual coroutine with [`Thread::set_hook`] takes priority
+    /// over this one.
+    ///
+    /// Hooks are installed through the C API, so they work without the `debug` library being
+    /// loaded, and scripts in a state created with [`Lua::new`] cannot inspect or replace them.
+    ///
     /// # Example
     ///
     /// Shows each line number of code being executed by the Lua interpreter.
@@ -256,12 +581,16 @@ impl Lua {
     ///
     /// [`HookTriggers`]: struct.HookTriggers.html
     /// [`HookTriggers.every_nth_instruction`]: struct.HookTriggers.html#field.every_nth_instruction
+    /// [`Thread::set_hook`]: struct.Thread.html#method.set_hook
+    /// [`Lua::new`]: #method.new
     pub fn set_hook<F>(&self, triggers: HookTriggers, callback: F)
     where
         F: 'static + Send + FnMut(Context, Debug) -> Result<()>,
     {
         unsafe {
-            (*extra_data(self.main_state)).hook_callback = Some(Rc::new(RefCell::new(callback)));
+            let extra = extra_data(self.main_state);
+            (*extra).hook_callback = Some(Rc::new(RefCell::new(callback)));
+            (*extra).hook_triggers = triggers;
             ffi::lua_sethook(
                 self.main_state,
                 Some(hook_proc),
@@ -271,6 +600,96 @@ impl Lua {
         }
     }
 
+    /// Sets a hook which calls `callback` about every `interval_ms` milliseconds of wall-clock
+    /// time while Lua code is executing.
+    ///
+    /// This is a count hook (see [`HookTriggers.every_nth_instruction`]) which measures the time
+    /// between its calls and adjusts its instruction count, so that it checks the time several
+    /// times per interval whatever the speed of the code running.  As with [`Lua::set_hook`],
+    /// returning an error from `callback` interrupts the executing code, which makes this useful
+    /// for timeouts.  It replaces any hook set with [`Lua::set_hook`], and is removed by
+    /// [`Lua::remove_hook`].
+    ///
+    /// The hook is only called while Lua code runs, so time spent in Rust callbacks is only
+    /// noticed once they return.  Timing starts with the first call of the hook, about a thousand
+    /// instructions into the code executed after setting it, so time spent before that does not
+    /// count.  Time between two separate executions does count towards the interval, so set the
+    /// hook again before each execution if they should be timed separately.
+    ///
+    /// Like [`Lua::set_hook`], the hook is installed on the main Lua thread and is inherited by
+    /// coroutines created afterwards.  Coroutines created before the hook was set, and coroutines
+    /// with their own hook set by [`Thread::set_hook`], are not affected.
+    ///
+    /// [`HookTriggers.every_nth_instruction`]: struct.HookTriggers.html#field.every_nth_instruction
+    /// [`Lua::set_hook`]: #method.set_hook
+    /// [`Lua::remove_hook`]: #method.remove_hook
+    /// [`Thread::set_hook`]: struct.Thread.html#method.set_hook
+    pub fn set_hook_every_ms<F>(&self, interval_ms: u64, mut callback: F)
+    where
+        F: 'static + Send + FnMut(Context, Debug) -> Result<()>,
+    {
+        // The number of times the hook checks the time during each interval.
+        const CHECKS_PER_INTERVAL: u32 = 10;
+        const INITIAL_COUNT: u32 = 1000;
+
+        let interval = Duration::from_millis(interval_ms);
+        let check_interval = interval / CHECKS_PER_INTERVAL;
+        let mut count = INITIAL_COUNT;
+        // Both are set by the first call of the hook, so that time passing before the code starts
+        // executing is not counted.
+        let mut last_check: Option<Instant> = None;
+        let mut last_call = Instant::now();
+
+        self.set_hook(
+            HookTriggers {
+                every_nth_instruction: Some(count),
+                ..Default::default()
+            },
+            move |lua, debug| {
+                let now = Instant::now();
+                let elapsed = match last_check.replace(now) {
+                    Some(last_check) => now - last_check,
+                    None => {
+                        last_call = now;
+                        return Ok(());
+                    }
+                };
+
+                // Scale the count towards `check_interval`, by at most a factor of 2 at a time so
+                // that a single slow instruction does not throw it off.
+                let ratio = if elapsed.as_nanos() == 0 {
+                    2.0
+                } else {
+                    (check_interval.as_nanos() as f64 / elapsed.as_nanos() as f64).clamp(0.5, 2.0)
+                };
+                let new_count = ((count as f64 * ratio) as u32).max(1);
+                if new_count != count {
+                    count = new_count;
+                    let triggers = HookTriggers {
+                        every_nth_instruction: Some(count),
+                        ..Default::default()
+                    };
+                    unsafe {
+                        (*extra_data(lua.state)).hook_triggers = triggers;
+                        ffi::lua_sethook(
+                            lua.state,
+                            Some(hook_proc),
+                            triggers.mask(),
+                            triggers.count(),
+                        );
+                    }
+                }
+
+                if now - last_call >= interval {
+                    last_call = now;
+                    callback(lua, debug)
+                } else {
+                    Ok(())
+                }
+            },
+        );
+    }
+
     /// Remove any hook previously set by `set_hook`. This function has no effect if a hook was not
     /// previously set.
     pub fn remove_hook(&self) {
@@ -285,6 +704,130 @@ impl Lua {
         unsafe { (*extra_data(self.main_state)).used_memory }
     }
 
+    /// Enables or disables counting the live userdata of each Rust type in this Lua state, as
+    /// returned by [`Lua::userdata_counts`].
+    ///
+    /// This includes boxed and scoped userdata.  A userdata stops being counted once its value is
+    /// dropped or taken out of Lua, by garbage collection or at the end of a scope.  Userdata
+    /// created before tracking was enabled are not counted, and disabling tracking discards the
+    /// counts.
+    ///
+    /// [`Lua::userdata_counts`]: #method.userdata_counts
+    pub fn enable_userdata_tracking(&self, enabled: bool) {
+        unsafe {
+            let tracking = &mut (*extra_data(self.main_state)).userdata_tracking;
+            if !enabled {
+                *tracking = None;
+            } else if tracking.is_none() {
+                *tracking = Some(UserDataTracking::default());
+            }
+        }
+    }
+
+    /// Returns the name and number of live userdata of each Rust type that has any, sorted by name.
+    ///
+    /// Returns an empty list unless enabled with [`Lua::enable_userdata_tracking`].
+    ///
+    /// [`Lua::enable_userdata_tracking`]: #method.enable_userdata_tracking
+    pub fn userdata_counts(&self) -> Vec<(String, usize)> {
+        let mut counts = unsafe { &(*extra_data(self.main_state)).userdata_tracking }
+            .iter()
+            .flat_map(|tracking| &tracking.counts)
+            .map(|(&name, &count)| (name.to_owned(), count))
+            .collect::<Vec<_>>();
+        counts.sort();
+        counts
+    }
+
+    /// Describes the methods and metamethods that the userdata type `T` adds in
+    /// [`UserData::add_methods`], for generating documentation or autocompletion data.
+    ///
+    /// This calls `T::add_methods` again, without creating any functions in Lua.
+    ///
+    /// [`UserData::add_methods`]: trait.UserData.html#method.add_methods
+    pub fn userdata_descriptor<T: 'static + UserData>(&self) -> TypeDescriptor {
+        describe_userdata::<T>()
+    }
+
+    /// Describes every userdata type that has been passed to Lua in this state, sorted by type
+    /// name.
+    ///
+    /// Types used only through [`Context::scope`] are not included.
+    ///
+    /// [`Context::scope`]: struct.Context.html#method.scope
+    pub fn all_userdata_descriptors(&self) -> Vec<TypeDescriptor> {
+        let describers: Vec<fn() -> TypeDescriptor> = unsafe {
+            (*extra_data(self.main_state))
+                .userdata_descriptors
+                .values()
+                .cloned()
+                .collect()
+        };
+        let mut descriptors: Vec<TypeDescriptor> =
+            describers.into_iter().map(|describe| describe()).collect();
+        descriptors.sort_by_key(|descriptor| descriptor.name);
+        descriptors
+    }
+
+    /// Returns a summary of the values in the Lua registry, to help find the source of a
+    /// registry leak, such as [`RegistryKey`]s which are created and never removed.
+    ///
+    /// [`RegistryKey`]: struct.RegistryKey.html
+    pub fn registry_report(&self) -> RegistryReport {
+        unsafe {
+            let state = self.main_state;
+            let _sg = StackGuard::new(state);
+            assert_stack(state, 3);
+
+            // Slots freed by `luaL_unref` form a linked list starting at index 0, and hold the
+            // index of the next free slot.
+            let mut free = HashSet::new();
+            let mut next = 0;
+            loop {
+                ffi::lua_rawgeti(state, ffi::LUA_REGISTRYINDEX, next);
+                next = ffi::lua_tointeger(state, -1);
+                ffi::lua_pop(state, 1);
+                if next == 0 || !free.insert(next) {
+                    break;
+                }
+            }
+
+            let mut report = RegistryReport::default();
+            ffi::lua_pushnil(state);
+            while ffi::lua_next(state, ffi::LUA_REGISTRYINDEX) != 0 {
+                let type_name = lua_type_name(ffi::lua_type(state, -1));
+                match ffi::lua_type(state, -2) {
+                    ffi::LUA_TNUMBER if ffi::lua_isinteger(state, -2) != 0 => {
+                        let index = ffi::lua_tointeger(state, -2);
+                        if index > ffi::LUA_RIDX_GLOBALS && !free.contains(&index) {
+                            *report.values.entry(type_name).or_insert(0) += 1;
+                        }
+                    }
+                    ffi::LUA_TSTRING => {
+                        let mut size = 0;
+                        let data = ffi::lua_tolstring(state, -2, &mut size);
+                        let name = slice::from_raw_parts(data as *const u8, size);
+                        report
+                            .named
+                            .push((String::from_utf8_lossy(name).into_owned(), type_name));
+                    }
+                    _ => {}
+                }
+                ffi::lua_pop(state, 1);
+            }
+            report.named.sort();
+
+            report.expired_keys = rlua_expect!(
+                (*extra_data(state)).registry_unref_list.lock(),
+                "unref list poisoned"
+            )
+            .as_ref()
+            .map_or(0, |list| list.len());
+
+            report
+        }
+    }
+
     /// Sets a memory limit on this Lua state.  Once an allocation occurs that would pass this
     /// memory limit, a `Error::MemoryError` is generated instead.
     pub fn set_memory_limit(&self, memory_limit: Option<usize>) {
@@ -293,6 +836,438 @@ impl Lua {
         }
     }
 
+    /// Calls `func` with no arguments while enforcing all of the given [`Limits`] together.
+    ///
+    /// The deadline and the stack depth are checked by a hook, which replaces any hook set with
+    /// [`Lua::set_hook`] during the call, and the memory limit is applied as with
+    /// [`Lua::set_memory_limit`], combined with any memory limit already set.  The previous hook
+    /// and memory limit are restored afterwards, whatever the outcome and even if `func` panics,
+    /// so the `Lua` can be used again.  Coroutines which were created or resumed during the call
+    /// switch back to the previous hook the next time they trigger the hook of the call.  Use
+    /// [`Function::bind`] to pass arguments to `func`.
+    ///
+    /// # Errors
+    ///
+    /// Exceeding a limit returns `Error::DeadlineExceeded`, `Error::MemoryLimitExceeded` or
+    /// `Error::StackDepthExceeded`, even if the error passed through Rust callbacks on its way
+    /// out.  If a lower memory limit was already set, running out of memory returns
+    /// `Error::MemoryError` as it would outside of this method.  Scripts can catch these errors
+    /// with `pcall` like any other error, but once the deadline has passed, every following
+    /// instruction raises the error again.
+    ///
+    /// Passing a function which belongs to a different `Lua` returns an `Error::RuntimeError`
+    /// without calling it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::time::{Duration, Instant};
+    /// # use rlua::{Error, Function, Limits, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// lua.context(|lua_context| {
+    ///     let spin: Function = lua_context.load("while true do end").into_function()?;
+    ///     let limits = Limits {
+    ///         deadline: Some(Instant::now() + Duration::from_millis(10)),
+    ///         ..Limits::default()
+    ///     };
+    ///     match lua.run_sandboxed::<()>(spin, limits) {
+    ///         Err(Error::DeadlineExceeded) => {}
+    ///         r => panic!("unexpected result {:?}", r),
+    ///     }
+    ///     Ok(())
+    /// })
+    /// # }
+    /// ```
+    ///
+    /// [`Limits`]: struct.Limits.html
+    /// [`Lua::set_hook`]: #method.set_hook
+    /// [`Lua::set_memory_limit`]: #method.set_memory_limit
+    /// [`Function::bind`]: struct.Function.html#method.bind
+    pub fn run_sandboxed<'lua, R: FromLuaMulti<'lua>>(
+        &self,
+        func: Function<'lua>,
+        limits: Limits,
+    ) -> Result<R> {
+        // The number of instructions run between checks of the deadline.
+        const DEADLINE_CHECK_INSTRUCTIONS: u32 = 1000;
+
+        // The limits are applied to this state, so they would not apply to a function from
+        // another one.
+        if unsafe { extra_data(func.0.lua.state) != extra_data(self.main_state) } {
+            return Err(Error::RuntimeError(
+                "run_sandboxed called with a function from a different Lua state".to_owned(),
+            ));
+        }
+
+        // Puts the previous hook and memory limit back in place, even if the call panics.
+        struct RestoreLimits<'a> {
+            lua: &'a Lua,
+            hook: Option<(Option<HookCallback>, HookTriggers)>,
+            memory_limit: Option<usize>,
+            error_hook: Option<ErrorHook>,
+        }
+
+        impl<'a> Drop for RestoreLimits<'a> {
+            fn drop(&mut self) {
+                self.lua.set_memory_limit(self.memory_limit);
+                unsafe {
+                    (*extra_data(self.lua.main_state)).error_hook = self.error_hook.take();
+                }
+                match self.hook.take() {
+                    Some((Some(callback), triggers)) => unsafe {
+                        let extra = extra_data(self.lua.main_state);
+                        (*extra).hook_callback = Some(callback);
+                        (*extra).hook_triggers = triggers;
+                        ffi::lua_sethook(
+                            self.lua.main_state,
+                            Some(hook_proc),
+                            triggers.mask(),
+                            triggers.count(),
+                        );
+                    },
+                    Some((None, _)) => self.lua.remove_hook(),
+                    None => {}
+                }
+            }
+        }
+
+        let triggers = HookTriggers {
+            on_calls: limits.max_stack_depth.is_some(),
+            every_nth_instruction: limits.deadline.map(|_| DEADLINE_CHECK_INSTRUCTIONS),
+            ..Default::default()
+        };
+        // The error hook is set aside during the call and applied to the final error, so that
+        // exceeded limits are recognized before the hook can transform them.
+        let (memory_limit, error_hook) = unsafe {
+            let extra = extra_data(self.main_state);
+            ((*extra).memory_limit, (*extra).error_hook.take())
+        };
+        let _restore = RestoreLimits {
+            lua: self,
+            hook: if triggers.mask() != 0 {
+                unsafe {
+                    let extra = extra_data(self.main_state);
+                    Some(((*extra).hook_callback.clone(), (*extra).hook_triggers))
+                }
+            } else {
+                None
+            },
+            memory_limit,
+            error_hook: error_hook.clone(),
+        };
+
+        if triggers.mask() != 0 {
+            self.set_hook(triggers, move |lua, debug| {
+                if debug.event() == ffi::LUA_HOOKCOUNT {
+                    if let Some(deadline) = limits.deadline {
+                        if Instant::now() >= deadline {
+                            // Check every instruction from now on, so that a script which catches
+                            // the error is interrupted again as soon as it continues.
+                            let triggers = HookTriggers {
+                                every_nth_instruction: Some(1),
+                                ..triggers
+                            };
+                            unsafe {
+                                (*extra_data(lua.state)).hook_triggers = triggers;
+                                ffi::lua_sethook(
+                                    lua.state,
+                                    Some(hook_proc),
+                                    triggers.mask(),
+                                    triggers.count(),
+                                );
+                            }
+                            return Err(Error::DeadlineExceeded);
+                        }
+                    }
+                } else if let Some(limit) = limits.max_stack_depth {
+                    // Level 0 is the function being called, so a frame at level `limit` means
+                    // more than `limit` calls are active.
+                    let level = limit.min(c_int::MAX as usize) as c_int;
+                    unsafe {
+                        let mut ar: ffi::lua_Debug = mem::zeroed();
+                        if ffi::lua_getstack(lua.state, level, &mut ar) != 0 {
+                            return Err(Error::StackDepthExceeded { limit });
+                        }
+                    }
+                }
+                Ok(())
+            });
+        }
+        // A memory error is only reported as exceeding `max_memory` if that is the limit which
+        // applies, and not a lower limit set with `set_memory_limit`.
+        let max_memory = match (limits.max_memory, memory_limit) {
+            (Some(max_memory), Some(limit)) if limit < max_memory => None,
+            (max_memory, _) => max_memory,
+        };
+        if let Some(max_memory) = max_memory {
+            self.set_memory_limit(Some(max_memory));
+        }
+
+        func.call(()).map_err(|err| {
+            let err = exceeded_limit(&err, max_memory).unwrap_or(err);
+            match error_hook {
+                Some(hook) => hook(err),
+                None => err,
+            }
+        })
+    }
+
+    /// Sets how floating point numbers are converted to strings, see [`NumberFormat`].
+    ///
+    /// The format is used wherever rlua itself converts numbers to strings, which is
+    /// `Context::coerce_string` (and so converting a number to a string with `FromLua`),
+    /// `Context::inspect` and error messages which contain numbers.
+    ///
+    /// Inside Lua, a format other than `NumberFormat::Lua` is applied by giving numbers a
+    /// `__tostring` metamethod, which is used by `tostring`, `print` and `string.format("%s")`.
+    /// Numbers are still converted by Lua itself with the `..` operator, by `string.format` with
+    /// numeric conversions such as `%g`, and by other standard library functions which accept
+    /// numbers in place of strings, such as `table.concat` and `io.write`.  If scripts set a
+    /// metatable for numbers with `debug.setmetatable`, the metamethod is added to that metatable.
+    /// Otherwise the metatable created for it has a `__metatable` field, so `getmetatable(1)`
+    /// returns `false` and scripts cannot change it.
+    ///
+    /// [`NumberFormat`]: enum.NumberFormat.html
+    pub fn set_number_format(&self, format: NumberFormat) -> Result<()> {
+        unsafe {
+            (*extra_data(self.main_state)).number_format = format;
+            set_number_tostring(self.main_state, format != NumberFormat::Lua)
+        }
+    }
+
+    /// Sets the maximum number of values that can be passed to or returned from a function at the
+    /// boundary between Rust and Lua.
+    ///
+    /// This applies to the arguments received and the values returned by Rust callbacks, the
+    /// values returned by calling a Lua function or resuming a thread from Rust, and
+    /// `Context::unpack_multi`.  Exceeding the limit results in an `Error::TooManyValues`.  By
+    /// default there is no limit, other than the size of the Lua stack.
+    pub fn set_multivalue_limit(&self, limit: Option<usize>) {
+        unsafe {
+            (*extra_data(self.main_state)).multivalue_limit = limit;
+        }
+    }
+
+    /// Sets the maximum size in bytes of the source of a chunk loaded with `Context::load`.
+    ///
+    /// Loading a larger chunk results in an `Error::ChunkTooLarge`, without passing the source to
+    /// Lua, so that compiling untrusted input cannot stall the program for a long time.  By
+    /// default there is no limit.
+    ///
+    /// The limit also applies to the `load` installed by [`Context::sandbox`] when text chunks are
+    /// allowed, which stops calling a reader function once the pieces it returned pass the limit.
+    /// The stock `load`, `loadfile`, `dofile` and `require` of the standard library do not check
+    /// it, so scripts which have them can still compile chunks of any size.
+    ///
+    /// [`Context::sandbox`]: struct.Context.html#method.sandbox
+    pub fn set_max_chunk_size(&self, size: Option<usize>) {
+        unsafe {
+            (*extra_data(self.main_state)).max_chunk_size = size;
+        }
+    }
+
+    /// Makes every `interval`th call to `Context::create_registry_value` first call
+    /// `Context::expire_registry_values`, so that the registry does not keep growing when
+    /// `RegistryKey`s are created and dropped without being removed.
+    ///
+    /// `None` (the default) disables automatic expiry, and an interval of 0 is treated as 1.
+    pub fn set_registry_expiry_interval(&self, interval: Option<usize>) {
+        unsafe {
+            let extra = extra_data(self.main_state);
+            (*extra).registry_expiry_interval = interval.map(|interval| interval.max(1));
+            (*extra).registry_values_created = 0;
+        }
+    }
+
+    /// Sets the maximum number of compiled functions kept by [`Context::load_cached`], 64 by
+    /// default.
+    ///
+    /// When the cache is full, the least recently used function is removed to make room for the
+    /// next one.  Lowering the capacity removes functions immediately, and a capacity of 0
+    /// disables caching, so that every load compiles its source.
+    ///
+    /// [`Context::load_cached`]: struct.Context.html#method.load_cached
+    pub fn set_chunk_cache_capacity(&self, capacity: usize) {
+        unsafe {
+            let released = (*extra_data(self.main_state))
+                .chunk_cache
+                .set_capacity(capacity);
+            for id in released {
+                ffi::luaL_unref(self.main_state, ffi::LUA_REGISTRYINDEX, id);
+            }
+        }
+    }
+
+    /// Removes every function cached by [`Context::load_cached`], so that the next load of each
+    /// key compiles its source again.
+    ///
+    /// [`Context::load_cached`]: struct.Context.html#method.load_cached
+    pub fn clear_chunk_cache(&self) {
+        unsafe {
+            let released = (*extra_data(self.main_state)).chunk_cache.clear();
+            for id in released {
+                ffi::luaL_unref(self.main_state, ffi::LUA_REGISTRYINDEX, id);
+            }
+        }
+    }
+
+    /// Returns the number of hits and misses of the cache used by [`Context::load_cached`], and
+    /// the number of functions it holds.
+    ///
+    /// [`Context::load_cached`]: struct.Context.html#method.load_cached
+    pub fn chunk_cache_stats(&self) -> ChunkCacheStats {
+        unsafe { (*extra_data(self.main_state)).chunk_cache.stats() }
+    }
+
+    /// Sets limits on the work done by `string.rep`, `string.find`, `string.match`,
+    /// `string.gmatch` and `string.gsub`.
+    ///
+    /// The first time limits are set, these functions are replaced with wrappers checking the
+    /// limits before calling the original function, both in the `string` table and in the table
+    /// strings index through their metatable.  A call exceeding a limit raises a Lua error naming
+    /// the limit.  Setting `StringLimits::default()` lifts every limit.
+    ///
+    /// Only the `string` library loaded at the time of the call is wrapped.
+    pub fn set_string_limits(&self, limits: StringLimits) -> Result<()> {
+        unsafe {
+            (*extra_data(self.main_state)).string_limits = limits;
+            if limits != StringLimits::default() {
+                wrap_string_functions(self.main_state, STRING_LIMIT_FUNCTIONS)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the limits set with [`Lua::set_string_limits`].
+    ///
+    /// [`Lua::set_string_limits`]: #method.set_string_limits
+    pub fn string_limits(&self) -> StringLimits {
+        unsafe { (*extra_data(self.main_state)).string_limits }
+    }
+
+    /// Sets a handler which receives the errors raised while finalizing the userdata and callbacks
+    /// created by rlua, such as a panic when dropping a `UserData` value.
+    ///
+    /// Without a handler, such an error makes whichever operation happened to run the garbage
+    /// collector fail with an `Error::GarbageCollectorError`, even though the operation had
+    /// nothing to do with the error.  With a handler, the error is passed to the handler instead,
+    /// and the operation carries on.  A panic is passed as an `Error::GarbageCollectorError`
+    /// containing the panic message.
+    ///
+    /// The handler is called during garbage collection, so it cannot use the Lua state.  Errors in
+    /// `__gc` metamethods defined in Lua are not affected, and are still returned as an
+    /// `Error::GarbageCollectorError`.
+    pub fn set_finalizer_error_handler<F>(&self, handler: F)
+    where
+        F: 'static + Send + Fn(Error),
+    {
+        unsafe {
+            (*extra_data(self.main_state)).finalizer_error_handler = Some(Rc::new(handler));
+        }
+    }
+
+    /// Removes any handler set by `set_finalizer_error_handler`, so that errors while finalizing
+    /// userdata are returned from the operation which ran the garbage collector again.
+    pub fn remove_finalizer_error_handler(&self) {
+        unsafe {
+            (*extra_data(self.main_state)).finalizer_error_handler = None;
+        }
+    }
+
+    /// Sets a hook which transforms every error raised by Lua before it is returned to Rust.
+    ///
+    /// The hook is called with each error as it is returned from the failing operation, and
+    /// whatever it returns is what the operation returns.  This can be used to turn particular
+    /// runtime errors into domain specific ones, for example with `Error::external`.  The hook
+    /// sees errors once rlua is done with them: syntax errors already carry their position, and
+    /// exceeded limits of [`Lua::run_sandboxed`] are already reported as such.  While
+    /// `run_sandboxed` runs, the hook is only applied to the error it returns.
+    ///
+    /// An error which is returned from a Rust callback and passes back through Lua is seen by the
+    /// hook again when it reaches Rust, so the hook should leave errors it has already converted
+    /// alone.  The hook cannot use the Lua state.  Setting a new hook replaces any previous one.
+    ///
+    /// [`Lua::run_sandboxed`]: #method.run_sandboxed
+    pub fn set_error_hook<F>(&self, hook: F)
+    where
+        F: 'static + Send + Fn(Error) -> Error,
+    {
+        unsafe {
+            (*extra_data(self.main_state)).error_hook = Some(Rc::new(hook));
+        }
+    }
+
+    /// Removes any hook set by `set_error_hook`, so that errors are returned unchanged again.
+    pub fn remove_error_hook(&self) {
+        unsafe {
+            (*extra_data(self.main_state)).error_hook = None;
+        }
+    }
+
+    /// Sets a handler which is called when Lua code accesses a global deprecated with
+    /// [`Context::deprecate_global`].
+    ///
+    /// The handler receives the path and message given to `deprecate_global`, and the frame of
+    /// the Lua function which accessed the global, which is `None` if it was not accessed from a
+    /// Lua function.  By default, the handler is only called for the first access of each
+    /// deprecated global, see [`set_report_every_deprecated_access`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::sync::{Arc, Mutex};
+    /// # use rlua::{Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// let lua = Lua::new();
+    /// let warnings = Arc::new(Mutex::new(Vec::new()));
+    /// let log = warnings.clone();
+    /// lua.set_deprecation_handler(move |path, message, location| {
+    ///     let line = location.and_then(|frame| frame.current_line);
+    ///     log.lock().unwrap().push(format!("{} at line {:?}: {}", path, line, message));
+    /// });
+    ///
+    /// lua.context(|lua_context| {
+    ///     lua_context.globals().set("spawn_unit", lua_context.create_function(|_, ()| Ok(()))?)?;
+    ///     lua_context.deprecate_global("spawn_unit", "use units.spawn instead")?;
+    ///     lua_context.load("spawn_unit()").exec()
+    /// })?;
+    ///
+    /// assert_eq!(
+    ///     *warnings.lock().unwrap(),
+    ///     vec!["spawn_unit at line Some(1): use units.spawn instead"]
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Context::deprecate_global`]: struct.Context.html#method.deprecate_global
+    /// [`set_report_every_deprecated_access`]: #method.set_report_every_deprecated_access
+    pub fn set_deprecation_handler<F>(&self, handler: F)
+    where
+        F: 'static + Send + FnMut(&str, &str, Option<StackFrame>),
+    {
+        unsafe {
+            (*extra_data(self.main_state)).deprecation_handler =
+                Some(Rc::new(RefCell::new(handler)));
+        }
+    }
+
+    /// Removes any handler set by `set_deprecation_handler`.
+    pub fn remove_deprecation_handler(&self) {
+        unsafe {
+            (*extra_data(self.main_state)).deprecation_handler = None;
+        }
+    }
+
+    /// Sets whether the deprecation handler is called for every access of a deprecated global,
+    /// rather than only the first.  This is false by default.
+    pub fn set_report_every_deprecated_access(&self, enabled: bool) {
+        unsafe {
+            (*extra_data(self.main_state)).report_every_deprecated_access = enabled;
+        }
+    }
+
     /// Returns true if the garbage collector is currently running automatically.
     pub fn gc_is_running(&self) -> bool {
         unsafe { ffi::lua_gc(self.main_state, ffi::LUA_GCISRUNNING, 0) != 0 }
@@ -343,6 +1318,34 @@ impl Lua {
         }
     }
 
+    /// Steps the garbage collector one indivisible step at a time, until a collection cycle
+    /// finishes or `budget` has elapsed.
+    ///
+    /// This spreads incremental collection over a host loop by time rather than by amount of
+    /// memory, such as a fixed part of each frame.  At least one step is always performed, so this
+    /// can take longer than a very small budget.  Does nothing and returns
+    /// `GcStepOutcome::Disabled` while the collector is stopped with [`Lua::gc_stop`].
+    ///
+    /// Like the other garbage collector methods, this can also be called from within a Rust
+    /// callback.
+    ///
+    /// [`Lua::gc_stop`]: #method.gc_stop
+    pub fn gc_step_for(&self, budget: Duration) -> Result<GcStepOutcome> {
+        if !self.gc_is_running() {
+            return Ok(GcStepOutcome::Disabled);
+        }
+
+        let start = Instant::now();
+        loop {
+            if self.gc_step()? {
+                return Ok(GcStepOutcome::Finished);
+            }
+            if start.elapsed() >= budget {
+                return Ok(GcStepOutcome::Unfinished);
+            }
+        }
+    }
+
     /// Sets the 'pause' value of the collector.
     ///
     /// Returns the previous value of 'pause'.  More information can be found in the [Lua 5.3
@@ -373,7 +1376,19 @@ impl Default for Lua {
 // Data associated with the main lua_State via lua_getextraspace.
 pub(crate) struct ExtraData {
     pub registered_userdata: HashMap<TypeId, c_int>,
+    // Like `registered_userdata`, for the metatables of userdata created by
+    // `Context::create_userdata_boxed`.
+    pub registered_boxed_userdata: HashMap<TypeId, c_int>,
+    // The reverse of `registered_userdata` and `registered_boxed_userdata`, keyed by the address
+    // of each registered metatable.
+    pub registered_userdata_types: HashMap<*const c_void, TypeId>,
+    // Describes each userdata type with a metatable in `registered_userdata` or
+    // `registered_boxed_userdata`, for `Lua::all_userdata_descriptors`.
+    pub userdata_descriptors: HashMap<TypeId, fn() -> TypeDescriptor>,
     pub registry_unref_list: Arc<Mutex<Option<Vec<c_int>>>>,
+    pub registry_expiry_interval: Option<usize>,
+    // Registry values created since dropped keys were last expired automatically.
+    pub registry_values_created: usize,
 
     pub ref_thread: *mut ffi::lua_State,
     pub ref_stack_size: c_int,
@@ -382,15 +1397,114 @@ pub(crate) struct ExtraData {
 
     used_memory: usize,
     memory_limit: Option<usize>,
+    multivalue_limit: Option<usize>,
+    max_chunk_size: Option<usize>,
+
+    pub hook_callback: Option<HookCallback>,
+    pub hook_triggers: HookTriggers,
+    // Hooks installed with `Thread::set_hook`, keyed by the thread's state.  Each thread is also
+    // held in the registry by the given id, so that it cannot be collected (and its state pointer
+    // reused) while a hook is installed on it.
+    pub thread_hooks: HashMap<*mut ffi::lua_State, (c_int, HookCallback)>,
+    pub finalizer_error_handler: Option<FinalizerErrorHandler>,
+    pub error_hook: Option<ErrorHook>,
+
+    // Globals deprecated with `Context::deprecate_global`, indexed by the id kept with each value.
+    pub deprecations: Vec<Deprecation>,
+    // Set by `Lua::set_deprecation_handler` and `Lua::set_report_every_deprecated_access`.
+    pub deprecation_handler: Option<DeprecationHandler>,
+    pub report_every_deprecated_access: bool,
+
+    // Set by `LuaOptions::catch_rust_panics`, if false panics in callbacks become Lua errors.
+    pub catch_rust_panics: bool,
+
+    // Set by `Lua::enable_userdata_tracking`.
+    pub userdata_tracking: Option<UserDataTracking>,
+
+    // The full chunk name of each chunk loaded with `Context::load_resident`, with its line offset
+    // and source.  The most recently loaded chunk is last.
+    pub resident_sources: Vec<(Vec<u8>, usize, Vec<u8>)>,
+
+    // The registry id of each string created by `Context::intern_string`, keyed by its contents.
+    pub interned_strings: HashMap<Vec<u8>, c_int>,
 
-    pub hook_callback: Option<Rc<RefCell<dyn FnMut(Context, Debug) -> Result<()>>>>,
+    // The functions compiled by `Context::load_cached`.
+    pub chunk_cache: ChunkCache,
+
+    // Set by `Context::sandbox`.
+    pub sandbox: Option<SandboxState>,
+
+    // Set by `Lua::set_string_limits`, and checked by the wrapped `string` functions.
+    pub string_limits: StringLimits,
+
+    // Set by `Lua::set_number_format`.
+    pub number_format: NumberFormat,
+}
+
+#[derive(Default)]
+pub(crate) struct UserDataTracking {
+    // The number of live userdata with each type name.
+    pub counts: HashMap<&'static str, usize>,
+    // The addresses of the counted userdata, so that userdata created before tracking was enabled
+    // are not subtracted from the counts.
+    pub live: HashSet<*const c_void>,
+}
+
+fn lua_type_name(lua_type: c_int) -> &'static str {
+    match lua_type {
+        ffi::LUA_TNIL => "nil",
+        ffi::LUA_TBOOLEAN => "boolean",
+        ffi::LUA_TLIGHTUSERDATA => "lightuserdata",
+        ffi::LUA_TNUMBER => "number",
+        ffi::LUA_TSTRING => "string",
+        ffi::LUA_TTABLE => "table",
+        ffi::LUA_TFUNCTION => "function",
+        ffi::LUA_TUSERDATA => "userdata",
+        ffi::LUA_TTHREAD => "thread",
+        _ => "<unknown>",
+    }
 }
 
 pub(crate) unsafe fn extra_data(state: *mut ffi::lua_State) -> *mut ExtraData {
     *(ffi::lua_getextraspace(state) as *mut *mut ExtraData)
 }
 
-unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
+// Returns an error if `got` values exceeds the limit set by `Lua::set_multivalue_limit`.
+pub(crate) unsafe fn check_multivalue_limit(state: *mut ffi::lua_State, got: usize) -> Result<()> {
+    match (*extra_data(state)).multivalue_limit {
+        Some(limit) if got > limit => Err(Error::TooManyValues { limit, got }),
+        _ => Ok(()),
+    }
+}
+
+// Returns an error if a chunk of `size` bytes exceeds the limit set by `Lua::set_max_chunk_size`.
+pub(crate) unsafe fn check_chunk_size(state: *mut ffi::lua_State, size: usize) -> Result<()> {
+    match (*extra_data(state)).max_chunk_size {
+        Some(limit) if size > limit => Err(Error::ChunkTooLarge { limit, size }),
+        _ => Ok(()),
+    }
+}
+
+// Finds the error for the limit of `Lua::run_sandboxed` which caused `err`, looking through the
+// callbacks the error passed through.
+fn exceeded_limit(err: &Error, max_memory: Option<usize>) -> Option<Error> {
+    match *err {
+        Error::DeadlineExceeded | Error::StackDepthExceeded { .. } => Some(err.clone()),
+        Error::MemoryError(_) => max_memory.map(|limit| Error::MemoryLimitExceeded { limit }),
+        Error::CallbackError { ref cause, .. } => exceeded_limit(cause, max_memory),
+        _ => None,
+    }
+}
+
+// Builds a state for the constructors of `Lua`, which set no memory limit, so building can only
+// fail if the process runs out of memory.
+fn build_default(builder: LuaBuilder) -> Lua {
+    rlua_expect!(builder.build(), "cannot create a Lua state")
+}
+
+// Creates a state without any of the standard libraries, which `LuaBuilder::build` loads
+// afterwards.
+unsafe fn create_lua(options: LuaOptions) -> Lua {
     unsafe extern "C" fn allocator(
         extra_data: *mut c_void,
         ptr: *mut c_void,
@@ -435,7 +1549,12 @@ unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
 
     let mut extra = Box::new(ExtraData {
         registered_userdata: HashMap::new(),
+        registered_boxed_userdata: HashMap::new(),
+        registered_userdata_types: HashMap::new(),
+        userdata_descriptors: HashMap::new(),
         registry_unref_list: Arc::new(Mutex::new(Some(Vec::new()))),
+        registry_expiry_interval: None,
+        registry_values_created: 0,
         ref_thread: ptr::null_mut(),
         // We need 1 extra stack space to move values in and out of the ref stack.
         ref_stack_size: ffi::LUA_MINSTACK - 1,
@@ -443,15 +1562,30 @@ unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
         ref_free: Vec::new(),
         used_memory: 0,
         memory_limit: None,
+        multivalue_limit: None,
+        max_chunk_size: None,
         hook_callback: None,
+        hook_triggers: HookTriggers::default(),
+        thread_hooks: HashMap::new(),
+        finalizer_error_handler: None,
+        error_hook: None,
+        deprecations: Vec::new(),
+        deprecation_handler: None,
+        report_every_deprecated_access: false,
+        catch_rust_panics: options.catch_rust_panics,
+        userdata_tracking: None,
+        resident_sources: Vec::new(),
+        interned_strings: HashMap::new(),
+        chunk_cache: ChunkCache::new(),
+        sandbox: None,
+        string_limits: StringLimits::default(),
+        number_format: NumberFormat::Lua,
     });
 
     let state = ffi::lua_newstate(allocator, &mut *extra as *mut ExtraData as *mut c_void);
 
     extra.ref_thread = rlua_expect!(
         protect_lua_closure(state, 0, 0, |state| {
-            load_from_std_lib(state, lua_mod_to_load);
-
             init_error_registry(state);
 
             // Create the function metatable
@@ -473,19 +1607,23 @@ unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
 
             ffi::lua_rawset(state, ffi::LUA_REGISTRYINDEX);
 
-            // Override pcall and xpcall with versions that cannot be used to catch rust panics.
+            // Create the table of thread bodies, which maps each thread created by
+            // `Context::create_thread` to its function without keeping the thread alive.
 
-            ffi::lua_rawgeti(state, ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_GLOBALS);
+            ffi::lua_pushlightuserdata(
+                state,
+                &THREAD_BODIES_REGISTRY_KEY as *const u8 as *mut c_void,
+            );
 
-            ffi::lua_pushstring(state, cstr!("pcall"));
-            ffi::lua_pushcfunction(state, safe_pcall);
-            ffi::lua_rawset(state, -3);
+            ffi::lua_newtable(state);
 
-            ffi::lua_pushstring(state, cstr!("xpcall"));
-            ffi::lua_pushcfunction(state, safe_xpcall);
+            ffi::lua_newtable(state);
+            ffi::lua_pushstring(state, cstr!("__mode"));
+            ffi::lua_pushstring(state, cstr!("k"));
             ffi::lua_rawset(state, -3);
+            ffi::lua_setmetatable(state, -2);
 
-            ffi::lua_pop(state, 1);
+            ffi::lua_rawset(state, ffi::LUA_REGISTRYINDEX);
 
             // Create ref stack thread and place it in the registry to prevent it from being garbage
             // collected.
@@ -509,6 +1647,21 @@ unsafe fn create_lua(lua_mod_to_load: StdLib) -> Lua {
     }
 }
 
+// Overrides pcall and xpcall with versions that cannot be used to catch rust panics.
+unsafe fn install_safe_pcall(state: *mut ffi::lua_State) {
+    ffi::lua_rawgeti(state, ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_GLOBALS);
+
+    ffi::lua_pushstring(state, cstr!("pcall"));
+    ffi::lua_pushcfunction(state, safe_pcall);
+    ffi::lua_rawset(state, -3);
+
+    ffi::lua_pushstring(state, cstr!("xpcall"));
+    ffi::lua_pushcfunction(state, safe_xpcall);
+    ffi::lua_rawset(state, -3);
+
+    ffi::lua_pop(state, 1);
+}
+
 unsafe fn load_from_std_lib(state: *mut ffi::lua_State, lua_mod: StdLib) {
     if lua_mod.contains(StdLib::BASE) {
         ffi::luaL_requiref(state, cstr!("_G"), ffi::luaopen_base, 1);
@@ -553,3 +1706,4 @@ unsafe fn load_from_std_lib(state: *mut ffi::lua_State, lua_mod: StdLib) {
 }
 
 pub(crate) static FUNCTION_METATABLE_REGISTRY_KEY: u8 = 0;
+pub(crate) static THREAD_BODIES_REGISTRY_KEY: u8 = 0;
@@ -0,0 +1,176 @@
+use std::any::type_name;
+use std::os::raw::c_int;
+use std::string::String as StdString;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::ffi;
+use crate::function::Function;
+use crate::table::Table;
+use crate::types::{Integer, Number};
+use crate::util::{assert_stack, StackGuard};
+use crate::value::Value;
+
+/// A Rust enum which is exposed to Lua as a table of integer constants, see
+/// [`Context::register_enum`].
+///
+/// There is no blanket `FromLua` implementation for `LuaEnum` types, but one can be written by
+/// forwarding to [`from_lua_enum`]:
+///
+/// ```
+/// # use rlua::{Context, FromLua, Integer, LuaEnum, Result, Value};
+/// #[derive(Clone, Copy, Debug, PartialEq)]
+/// enum Direction {
+///     North = 1,
+///     South = 2,
+/// }
+///
+/// impl LuaEnum for Direction {
+///     fn variants() -> &'static [(&'static str, Integer)] {
+///         &[("NORTH", 1), ("SOUTH", 2)]
+///     }
+///
+///     fn from_integer(value: Integer) -> Option<Direction> {
+///         match value {
+///             1 => Some(Direction::North),
+///             2 => Some(Direction::South),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// impl<'lua> FromLua<'lua> for Direction {
+///     fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Direction> {
+///         Direction::from_lua_enum(value, lua)
+///     }
+/// }
+///
+/// # fn main() -> Result<()> {
+/// # rlua::Lua::new().context(|lua_context| {
+/// lua_context.register_enum::<Direction>("Direction")?;
+/// let direction: Direction = lua_context.load("Direction.SOUTH").eval()?;
+/// assert_eq!(direction, Direction::South);
+/// let direction: Direction = lua_context.load("'NORTH'").eval()?;
+/// assert_eq!(direction, Direction::North);
+/// # Ok(())
+/// # })
+/// # }
+/// ```
+///
+/// [`Context::register_enum`]: struct.Context.html#method.register_enum
+/// [`from_lua_enum`]: #method.from_lua_enum
+pub trait LuaEnum: Sized {
+    /// Returns the name and integer value of every variant.
+    fn variants() -> &'static [(&'static str, Integer)];
+
+    /// Returns the variant with the given integer value, if there is one.
+    fn from_integer(value: Integer) -> Option<Self>;
+
+    /// Converts either the integer value or the name of a variant to this type.
+    ///
+    /// Any other value results in an `Error::FromLuaConversionError` listing the valid variants.
+    fn from_lua_enum<'lua>(value: Value<'lua>, _lua: Context<'lua>) -> Result<Self> {
+        let variants = Self::variants();
+        let (found, given) = match value {
+            Value::Integer(i) => (Self::from_integer(i), i.to_string()),
+            Value::Number(n) if n as Integer as Number == n => {
+                (Self::from_integer(n as Integer), n.to_string())
+            }
+            Value::String(ref s) => (
+                variants
+                    .iter()
+                    .find(|&&(name, _)| name.as_bytes() == s.as_bytes())
+                    .and_then(|&(_, value)| Self::from_integer(value)),
+                format!("{:?}", StdString::from_utf8_lossy(s.as_bytes())),
+            ),
+            _ => (None, format!("a {}", value.type_name())),
+        };
+
+        found.ok_or_else(|| {
+            let expected = variants
+                .iter()
+                .map(|&(name, value)| format!("{} ({})", name, value))
+                .collect::<Vec<_>>()
+                .join(", ");
+            Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: type_name::<Self>(),
+                message: Some(format!(
+                    "{} is not a valid variant, expected one of {}",
+                    given, expected
+                )),
+            }
+        })
+    }
+}
+
+pub(crate) fn create_enum_table<'lua, E: LuaEnum>(lua: Context<'lua>) -> Result<Table<'lua>> {
+    let variants = E::variants();
+    // The reverse lookup table is stored beside the variants, so a variant of the same name would
+    // replace it.
+    if variants.iter().any(|&(name, _)| name == "names") {
+        return Err(Error::RuntimeError(format!(
+            "enum {} has a variant called `names`, which is reserved for the table of variant names",
+            type_name::<E>()
+        )));
+    }
+    let constants = lua.create_table_from(variants.iter().cloned())?;
+    let names = lua.create_table_from(variants.iter().map(|&(name, value)| (value, name)))?;
+
+    let index = lua.create_table_from(variants.iter().cloned())?;
+    index.raw_set("names", read_only(lua, names.clone(), names)?)?;
+    read_only(lua, index, constants)
+}
+
+// Creates an empty proxy table which reads from `index`, iterates over `constants` with `pairs`,
+// and raises an error on assignment.
+fn read_only<'lua>(
+    lua: Context<'lua>,
+    index: Table<'lua>,
+    constants: Table<'lua>,
+) -> Result<Table<'lua>> {
+    let metatable = lua.create_table()?;
+    metatable.raw_set("__index", index)?;
+    metatable.raw_set("__newindex", c_function(lua, read_only_error))?;
+    metatable.raw_set("__pairs", c_function(lua, constants_pairs))?;
+    metatable.raw_set("__metatable", false)?;
+    metatable.raw_set("constants", constants)?;
+
+    let proxy = lua.create_table()?;
+    proxy.set_metatable(Some(metatable));
+    Ok(proxy)
+}
+
+fn c_function<'lua>(lua: Context<'lua>, function: ffi::lua_CFunction) -> Function<'lua> {
+    unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 1);
+        ffi::lua_pushcfunction(lua.state, function);
+        Function(lua.pop_ref())
+    }
+}
+
+unsafe extern "C" fn read_only_error(state: *mut ffi::lua_State) -> c_int {
+    ffi::lua_pushstring(state, cstr!("attempt to modify a read-only enum table"));
+    ffi::lua_error(state)
+}
+
+unsafe extern "C" fn constants_pairs(state: *mut ffi::lua_State) -> c_int {
+    ffi::lua_pushcfunction(state, constants_next);
+    ffi::lua_getmetatable(state, 1);
+    ffi::lua_pushstring(state, cstr!("constants"));
+    ffi::lua_rawget(state, -2);
+    ffi::lua_remove(state, -2);
+    ffi::lua_pushnil(state);
+    3
+}
+
+unsafe extern "C" fn constants_next(state: *mut ffi::lua_State) -> c_int {
+    ffi::lua_settop(state, 2);
+    if ffi::lua_next(state, 1) != 0 {
+        2
+    } else {
+        ffi::lua_pushnil(state);
+        1
+    }
+}
@@ -1,10 +1,18 @@
+use std::cell::{Ref, RefMut};
+use std::fmt;
 use std::iter::FromIterator;
+use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 use std::result::Result as StdResult;
+use std::sync::Arc;
+
+#[cfg(feature = "either")]
+use either::Either;
 
 use crate::context::Context;
-use crate::error::Result;
-use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti};
+use crate::error::{Error, Result};
+use crate::userdata::{AnyUserData, UserData};
+use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti, Value};
 
 /// Result is convertible to `MultiValue` following the common Lua idiom of returning the result
 /// on success, or in the case of an error, returning `nil` and an error message.
@@ -24,6 +32,20 @@ impl<'lua, T: ToLua<'lua>, E: ToLua<'lua>> ToLuaMulti<'lua> for StdResult<T, E>
     }
 }
 
+/// Result can be converted from `MultiValue` following the same idiom: a `nil` followed by
+/// another value is converted to `Err` from the second value, anything else is converted to `Ok`
+/// from the first value.
+impl<'lua, T: FromLua<'lua>, E: FromLua<'lua>> FromLuaMulti<'lua> for StdResult<T, E> {
+    fn from_lua_multi(mut values: MultiValue<'lua>, lua: Context<'lua>) -> Result<Self> {
+        let first = values.pop_front().unwrap_or(Nil);
+        match (first, values.pop_front().unwrap_or(Nil)) {
+            (Nil, Nil) => Ok(Ok(T::from_lua(Nil, lua)?)),
+            (Nil, e) => Ok(Err(E::from_lua(e, lua)?)),
+            (v, _) => Ok(Ok(T::from_lua(v, lua)?)),
+        }
+    }
+}
+
 impl<'lua, T: ToLua<'lua>> ToLuaMulti<'lua> for T {
     fn to_lua_multi(self, lua: Context<'lua>) -> Result<MultiValue<'lua>> {
         let mut v = MultiValue::new();
@@ -138,6 +160,185 @@ impl<'lua, T: FromLua<'lua>> FromLuaMulti<'lua> for Variadic<T> {
     }
 }
 
+/// The two operands of a binary metamethod, one of which is a userdata of type `T`, in the order
+/// they were given.
+///
+/// Lua calls a binary metamethod such as `__add` with the operands in their original order, so
+/// the userdata the metamethod belongs to may be either one.  Using this type as the argument of
+/// a function set with [`add_meta_function`] finds the `T` operand and converts the other one to
+/// `U`.  The `T` operand is not copied out of the userdata, it is borrowed through the
+/// [`UserDataOperand`].  If both operands could be the `T`, the left one is used.
+///
+/// # Errors
+///
+/// Returns a `BinaryOperandsError` naming the types of both operands if neither is a `T`, or if
+/// the other operand cannot be converted to `U`, in which case the conversion error is kept as its
+/// cause.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{BinaryOperands, Lua, MetaMethod, Result, UserData, UserDataMethods};
+/// # fn main() -> Result<()> {
+/// struct Meters(f64);
+///
+/// impl UserData for Meters {
+///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+///         methods.add_meta_function(MetaMethod::Mul, |_, operands: BinaryOperands<Meters, f64>| {
+///             Ok(match operands {
+///                 BinaryOperands::SelfLhs(m, n) | BinaryOperands::SelfRhs(n, m) => {
+///                     Meters(m.borrow()?.0 * n)
+///                 }
+///             })
+///         });
+///         methods.add_method("value", |_, m, ()| Ok(m.0));
+///     }
+/// }
+///
+/// Lua::new().context(|lua_context| {
+///     lua_context.globals().set("m", Meters(2.0))?;
+///     assert_eq!(lua_context.load("(m * 3):value()").eval::<f64>()?, 6.0);
+///     assert_eq!(lua_context.load("(3 * m):value()").eval::<f64>()?, 6.0);
+///     Ok(())
+/// })
+/// # }
+/// ```
+///
+/// [`add_meta_function`]: trait.UserDataMethods.html#method.add_meta_function
+/// [`UserDataOperand`]: struct.UserDataOperand.html
+#[derive(Debug, Clone)]
+pub enum BinaryOperands<'lua, T, U> {
+    /// The userdata is the left operand.
+    SelfLhs(UserDataOperand<'lua, T>, U),
+    /// The userdata is the right operand.
+    SelfRhs(U, UserDataOperand<'lua, T>),
+}
+
+impl<'lua, T, U> FromLuaMulti<'lua> for BinaryOperands<'lua, T, U>
+where
+    T: 'static + UserData,
+    U: FromLua<'lua>,
+{
+    fn from_lua_multi(mut values: MultiValue<'lua>, lua: Context<'lua>) -> Result<Self> {
+        let lhs = values.pop_front().unwrap_or(Nil);
+        let rhs = values.pop_front().unwrap_or(Nil);
+        let (lhs_type, rhs_type) = (lhs.type_name(), rhs.type_name());
+
+        let as_self = |value: &Value<'lua>| match value {
+            Value::UserData(ud) if ud.is::<T>() => Some(UserDataOperand {
+                userdata: ud.clone(),
+                _type: PhantomData,
+            }),
+            _ => None,
+        };
+        let (lhs_self, rhs_self) = (as_self(&lhs), as_self(&rhs));
+
+        let mut cause = None;
+        if let Some(operand) = lhs_self {
+            match U::from_lua(rhs.clone(), lua) {
+                Ok(other) => return Ok(BinaryOperands::SelfLhs(operand, other)),
+                Err(err) => cause = Some(Arc::new(err)),
+            }
+        }
+        if let Some(operand) = rhs_self {
+            match U::from_lua(lhs, lua) {
+                Ok(other) => return Ok(BinaryOperands::SelfRhs(other, operand)),
+                Err(err) => cause = cause.or_else(|| Some(Arc::new(err))),
+            }
+        }
+        Err(Error::BinaryOperandsError {
+            lhs: lhs_type,
+            rhs: rhs_type,
+            cause,
+        })
+    }
+}
+
+/// The userdata operand of [`BinaryOperands`], which is known to hold a `T`.
+///
+/// [`BinaryOperands`]: enum.BinaryOperands.html
+pub struct UserDataOperand<'lua, T> {
+    userdata: AnyUserData<'lua>,
+    _type: PhantomData<T>,
+}
+
+impl<'lua, T: 'static + UserData> UserDataOperand<'lua, T> {
+    /// Borrow the operand immutably.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `UserDataBorrowError` if the userdata is already mutably borrowed.
+    pub fn borrow(&self) -> Result<Ref<'_, T>> {
+        self.userdata.borrow()
+    }
+
+    /// Borrow the operand mutably.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `UserDataBorrowMutError` if the userdata is already borrowed.
+    pub fn borrow_mut(&self) -> Result<RefMut<'_, T>> {
+        self.userdata.borrow_mut()
+    }
+
+    /// Returns the untyped handle to the operand.
+    pub fn as_any(&self) -> &AnyUserData<'lua> {
+        &self.userdata
+    }
+}
+
+impl<'lua, T> Clone for UserDataOperand<'lua, T> {
+    fn clone(&self) -> Self {
+        UserDataOperand {
+            userdata: self.userdata.clone(),
+            _type: PhantomData,
+        }
+    }
+}
+
+impl<'lua, T> fmt::Debug for UserDataOperand<'lua, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("UserDataOperand")
+            .field(&self.userdata)
+            .finish()
+    }
+}
+
+/// Either side is converted with its own `ToLuaMulti` implementation, so a callback can return a
+/// different number or type of values depending on the case.
+///
+/// # Examples
+///
+/// ```
+/// # use either::Either;
+/// # use rlua::{Lua, Result};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let lookup = lua_context.create_function(|_, key: String| {
+///     Ok(match key.as_str() {
+///         "pair" => Either::Right((1, 2)),
+///         _ => Either::Left("unknown"),
+///     })
+/// })?;
+/// lua_context.globals().set("lookup", lookup)?;
+/// lua_context.load(r##"
+///     local a, b = lookup("pair")
+///     assert(a == 1 and b == 2)
+///     assert(select("#", lookup("other")) == 1)
+/// "##).exec()
+/// # })
+/// # }
+/// ```
+#[cfg(feature = "either")]
+impl<'lua, L: ToLuaMulti<'lua>, R: ToLuaMulti<'lua>> ToLuaMulti<'lua> for Either<L, R> {
+    fn to_lua_multi(self, lua: Context<'lua>) -> Result<MultiValue<'lua>> {
+        match self {
+            Either::Left(l) => l.to_lua_multi(lua),
+            Either::Right(r) => r.to_lua_multi(lua),
+        }
+    }
+}
+
 macro_rules! impl_tuple {
     () => (
         impl<'lua> ToLuaMulti<'lua> for () {
@@ -0,0 +1,154 @@
+use std::os::raw::c_int;
+use std::ptr;
+
+use crate::error::Result;
+use crate::ffi;
+use crate::lua::extra_data;
+use crate::util::{
+    assert_stack, callback_error, check_stack, protect_lua_closure, push_string, StackGuard,
+};
+
+/// How floating point numbers are converted to strings, set with [`Lua::set_number_format`].
+///
+/// Integers are always formatted as plain decimal numbers.  Apart from [`NumberFormat::Lua`], the
+/// formats do not depend on the C library or its locale.  Infinities are formatted as `inf` and
+/// `-inf` and NaN is always `nan`.  Like Lua, a result which would look like an integer has `.0`
+/// appended, so `1.0` is not formatted as `1`.
+///
+/// [`Lua::set_number_format`]: struct.Lua.html#method.set_number_format
+/// [`NumberFormat::Lua`]: #variant.Lua
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum NumberFormat {
+    /// The default format used by Lua itself, which is `%.14g`, formatted by the C library.
+    Lua,
+    /// The shortest representation which converts back to the same number, such as `0.1` or
+    /// `0.30000000000000004`.  Exponents are used below `1e-4` and from `1e16`.
+    Shortest,
+    /// Like the C format `%.Ng` with the given number of significant digits, so `Precision(14)`
+    /// is the same as `NumberFormat::Lua` in the "C" locale.
+    Precision(u8),
+}
+
+// Formats a float with the given format, or returns `None` for `NumberFormat::Lua`, where each
+// caller keeps its usual formatting.
+pub(crate) fn format_number(format: NumberFormat, n: f64) -> Option<String> {
+    let mut s = match format {
+        NumberFormat::Lua => return None,
+        _ if n.is_nan() => return Some("nan".to_owned()),
+        _ if n.is_infinite() => return Some(if n > 0.0 { "inf" } else { "-inf" }.to_owned()),
+        NumberFormat::Shortest => {
+            // `Debug` gives the shortest representation, and switches to an exponent at the
+            // same points as described above.
+            let s = format!("{:?}", n);
+            match s.find('e') {
+                Some(e) => c_exponent(&s[..e], s[e + 1..].parse().unwrap()),
+                None => s,
+            }
+        }
+        NumberFormat::Precision(precision) => {
+            let precision = precision.max(1) as usize;
+            let s = format!("{:.*e}", precision - 1, n);
+            let e = s.find('e').unwrap();
+            let exponent: i32 = s[e + 1..].parse().unwrap();
+            if exponent < -4 || exponent >= precision as i32 {
+                c_exponent(trim_zeros(&s[..e]), exponent)
+            } else {
+                let decimals = (precision as i32 - 1 - exponent) as usize;
+                trim_zeros(&format!("{:.*}", decimals, n)).to_owned()
+            }
+        }
+    };
+    if s.bytes().all(|c| c == b'-' || c.is_ascii_digit()) {
+        s.push_str(".0");
+    }
+    Some(s)
+}
+
+// Removes trailing zeros after the decimal point, and the point itself if nothing follows it.
+fn trim_zeros(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+// Adds an exponent the way C does, with a sign and at least two digits.
+fn c_exponent(mantissa: &str, exponent: i32) -> String {
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+// Installs or removes the `__tostring` metamethod of numbers, which formats floats with the
+// current number format.
+//
+// A metatable created here has its `__metatable` field set to `false`, so that scripts cannot get
+// at it with `getmetatable` and change how every number behaves.
+pub(crate) unsafe fn set_number_tostring(state: *mut ffi::lua_State, enabled: bool) -> Result<()> {
+    let _sg = StackGuard::new(state);
+    assert_stack(state, 6);
+
+    // All numbers share a single metatable.
+    ffi::lua_pushinteger(state, 0);
+    if ffi::lua_getmetatable(state, -1) == 0 {
+        if !enabled {
+            return Ok(());
+        }
+        protect_lua_closure(state, 0, 1, |state| ffi::lua_newtable(state))?;
+        ffi::lua_pushvalue(state, -1);
+        ffi::lua_setmetatable(state, -3);
+
+        ffi::lua_pushvalue(state, -1);
+        push_string(state, "__metatable")?;
+        ffi::lua_pushboolean(state, 0);
+        protect_lua_closure(state, 3, 0, |state| ffi::lua_rawset(state, -3))?;
+    }
+
+    ffi::lua_pushvalue(state, -1);
+    push_string(state, "__tostring")?;
+    if enabled {
+        ffi::lua_pushcfunction(state, number_tostring);
+    } else {
+        ffi::lua_pushnil(state);
+    }
+    protect_lua_closure(state, 3, 0, |state| ffi::lua_rawset(state, -3))?;
+
+    // Remove the metatable again if it only held the metamethod, and the `__metatable` field set
+    // when it was created.
+    push_string(state, "__metatable")?;
+    ffi::lua_rawget(state, -2);
+    let sealed = ffi::lua_isboolean(state, -1) != 0 && ffi::lua_toboolean(state, -1) == 0;
+    ffi::lua_pop(state, 1);
+    let mut entries = 0;
+    ffi::lua_pushnil(state);
+    while entries <= sealed as usize && ffi::lua_next(state, -2) != 0 {
+        ffi::lua_pop(state, 1);
+        entries += 1;
+    }
+    if entries <= sealed as usize {
+        ffi::lua_pushnil(state);
+        ffi::lua_setmetatable(state, -3);
+    }
+    Ok(())
+}
+
+unsafe extern "C" fn number_tostring(state: *mut ffi::lua_State) -> c_int {
+    callback_error(state, |_| {
+        check_stack(state, 4)?;
+        // The number is the first argument, after the space reserved by `callback_error`.
+        if ffi::lua_isinteger(state, 2) == 0 {
+            let format = (*extra_data(state)).number_format;
+            if let Some(s) = format_number(format, ffi::lua_tonumber(state, 2)) {
+                push_string(state, &s)?;
+                return Ok(1);
+            }
+        }
+
+        // Integers, and floats while the format is `NumberFormat::Lua`, are converted by Lua.
+        ffi::lua_pushvalue(state, 2);
+        protect_lua_closure(state, 1, 1, |state| {
+            ffi::lua_tolstring(state, -1, ptr::null_mut());
+        })?;
+        Ok(1)
+    })
+}
@@ -1,14 +1,31 @@
 //! Re-exports most types with an extra `Lua*` prefix to prevent name clashes.
 
 pub use crate::{
-    AnyUserData as LuaAnyUserData, Chunk as LuaChunk, Context as LuaContext, Debug as LuaDebug,
-    DebugNames as LuaDebugNames, DebugSource as LuaDebugSource, DebugStack as LuaDebugStack,
-    Error as LuaError, ExternalError as LuaExternalError, ExternalResult as LuaExternalResult,
-    FromLua, FromLuaMulti, Function as LuaFunction, HookTriggers as LuaHookTriggers,
-    Integer as LuaInteger, LightUserData as LuaLightUserData, Lua, MetaMethod as LuaMetaMethod,
-    MultiValue as LuaMultiValue, Nil as LuaNil, Number as LuaNumber, RegistryKey as LuaRegistryKey,
-    Result as LuaResult, Scope as LuaScope, String as LuaString, Table as LuaTable,
-    TablePairs as LuaTablePairs, TableSequence as LuaTableSequence, Thread as LuaThread,
-    ThreadStatus as LuaThreadStatus, ToLua, ToLuaMulti, UserData as LuaUserData,
-    UserDataMethods as LuaUserDataMethods, Value as LuaValue,
+    AnyUserData as LuaAnyUserData, BatchOptions as LuaBatchOptions,
+    BinaryOperands as LuaBinaryOperands, Callable as LuaCallable,
+    CallbackRegistry as LuaCallbackRegistry, Chunk as LuaChunk,
+    ChunkCacheStats as LuaChunkCacheStats, Context as LuaContext,
+    CoroutineConfig as LuaCoroutineConfig, Debug as LuaDebug, DebugNames as LuaDebugNames,
+    DebugSource as LuaDebugSource, DebugStack as LuaDebugStack, Error as LuaError,
+    ExternalError as LuaExternalError, ExternalResult as LuaExternalResult, FromLua, FromLuaMulti,
+    Function as LuaFunction, GcStepOutcome as LuaGcStepOutcome, GlobalsDiff as LuaGlobalsDiff,
+    GlobalsSnapshot as LuaGlobalsSnapshot, HookTriggers as LuaHookTriggers,
+    InspectOptions as LuaInspectOptions, Integer as LuaInteger, LightUserData as LuaLightUserData,
+    Limits as LuaLimits, Lua, LuaBuilder, LuaEnum, LuaKey, LuaOptions, MetaMethod as LuaMetaMethod,
+    MethodDescriptor as LuaMethodDescriptor, MultiValue as LuaMultiValue,
+    MultiValueBuilder as LuaMultiValueBuilder, Nil as LuaNil, Number as LuaNumber,
+    NumberFormat as LuaNumberFormat, RegistryKey as LuaRegistryKey,
+    RegistryReport as LuaRegistryReport, Result as LuaResult, SandboxOptions as LuaSandboxOptions,
+    SandboxReport as LuaSandboxReport, Scope as LuaScope, StackFrame as LuaStackFrame,
+    String as LuaString, StringLimits as LuaStringLimits, SubscriptionId as LuaSubscriptionId,
+    Table as LuaTable, TableBuilder as LuaTableBuilder, TablePairs as LuaTablePairs,
+    TableRawIter as LuaTableRawIter, TableSequence as LuaTableSequence, Thread as LuaThread,
+    ThreadStatus as LuaThreadStatus, ToLua, ToLuaMulti, TransferOptions as LuaTransferOptions,
+    TypeCategory as LuaTypeCategory, TypeDescriptor as LuaTypeDescriptor, UserData as LuaUserData,
+    UserDataHandle as LuaUserDataHandle, UserDataMetatable as LuaUserDataMetatable,
+    UserDataMethods as LuaUserDataMethods, UserDataOperand as LuaUserDataOperand,
+    Value as LuaValue,
 };
+
+#[cfg(feature = "json")]
+pub use crate::JsonOptions as LuaJsonOptions;
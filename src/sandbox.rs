@@ -0,0 +1,457 @@
+use std::os::raw::c_int;
+use std::string::String as StdString;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::ffi;
+use crate::function::Function;
+use crate::lua::{check_chunk_size, extra_data};
+use crate::string::String;
+use crate::string_limits::wrap_string_functions;
+use crate::table::Table;
+use crate::types::{SandboxPrintHandler, TypeCategory};
+use crate::util::{assert_stack, protect_lua_closure, StackGuard};
+use crate::value::{MultiValue, Nil, Value};
+
+/// Controls which parts of the global environment are kept by [`Context::sandbox`].
+///
+/// By default everything described by [`Context::sandbox`] is locked down, `print` discards its
+/// output, and `string.rep` refuses to build strings larger than 1 MiB.
+///
+/// [`Context::sandbox`]: struct.Context.html#method.sandbox
+#[derive(Clone)]
+pub struct SandboxOptions {
+    keep: Vec<StdString>,
+    load_text_chunks: bool,
+    print: Option<SandboxPrintHandler>,
+    max_string_rep_len: Option<usize>,
+    seal_string_metatable: bool,
+}
+
+impl Default for SandboxOptions {
+    fn default() -> SandboxOptions {
+        SandboxOptions {
+            keep: Vec::new(),
+            load_text_chunks: false,
+            print: None,
+            max_string_rep_len: Some(1 << 20),
+            seal_string_metatable: true,
+        }
+    }
+}
+
+impl SandboxOptions {
+    /// Creates the default set of options, which lock down everything.
+    pub fn new() -> SandboxOptions {
+        SandboxOptions::default()
+    }
+
+    /// Keeps a global or library field that would otherwise be removed or replaced, such as
+    /// `"collectgarbage"`, `"os.time"` or `"package.searchers"`.
+    ///
+    /// Keeping a whole library, such as `"os"`, keeps all of its fields.  Keeping only some fields
+    /// of a removed library replaces it with a table holding just those fields.
+    pub fn keep(mut self, path: &str) -> SandboxOptions {
+        self.keep.push(path.to_owned());
+        self
+    }
+
+    /// Sets whether `load` is replaced with a version that only loads text chunks, rather than
+    /// being removed.
+    ///
+    /// The replacement applies the limit set by [`Lua::set_max_chunk_size`], returning `nil` and
+    /// an error message for a larger chunk, like it does for a syntax error.
+    ///
+    /// [`Lua::set_max_chunk_size`]: struct.Lua.html#method.set_max_chunk_size
+    pub fn allow_load_text_chunks(mut self, enabled: bool) -> SandboxOptions {
+        self.load_text_chunks = enabled;
+        self
+    }
+
+    /// Sets a function receiving each line printed by scripts, in place of the default which
+    /// discards them.
+    pub fn print_handler<F>(mut self, handler: F) -> SandboxOptions
+    where
+        F: 'static + Send + Sync + Fn(&str),
+    {
+        self.print = Some(Arc::new(handler));
+        self
+    }
+
+    /// Sets the largest string in bytes that `string.rep` may build, as the `max_rep_output`
+    /// limit of [`Lua::set_string_limits`].  A lower limit which is already set is kept.  `None`
+    /// leaves `string.rep` and its limit unchanged.
+    ///
+    /// [`Lua::set_string_limits`]: struct.Lua.html#method.set_string_limits
+    pub fn max_string_rep_len(mut self, len: Option<usize>) -> SandboxOptions {
+        self.max_string_rep_len = len;
+        self
+    }
+
+    /// Sets whether the string metatable is sealed with [`Context::seal_string_metatable`].
+    ///
+    /// [`Context::seal_string_metatable`]: struct.Context.html#method.seal_string_metatable
+    pub fn seal_string_metatable(mut self, enabled: bool) -> SandboxOptions {
+        self.seal_string_metatable = enabled;
+        self
+    }
+
+    fn keeps(&self, path: &str) -> bool {
+        self.keep.iter().any(|keep| {
+            keep == path || path.starts_with(keep.as_str()) && path[keep.len()..].starts_with('.')
+        })
+    }
+}
+
+/// The changes made by [`Context::sandbox`].
+///
+/// [`Context::sandbox`]: struct.Context.html#method.sandbox
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SandboxReport {
+    /// The globals and library fields that were removed, such as `"dofile"` or `"os"`.
+    pub removed: Vec<StdString>,
+    /// The globals and library fields that were replaced with restricted versions, such as
+    /// `"print"`, and `"string metatable"` or `"number metatable"` if those metatables were
+    /// sealed.
+    pub replaced: Vec<StdString>,
+}
+
+/// Resource limits enforced by [`Lua::run_sandboxed`].
+///
+/// Each limit is `None` by default, meaning that it is not enforced.
+///
+/// [`Lua::run_sandboxed`]: struct.Lua.html#method.run_sandboxed
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Limits {
+    /// The time by which the function must return, checked every 1000 Lua instructions.
+    pub deadline: Option<Instant>,
+    /// The most memory in bytes the Lua state may use, as with [`Lua::set_memory_limit`].  This
+    /// counts all of the memory used by the state, not only the memory allocated by the function.
+    ///
+    /// [`Lua::set_memory_limit`]: struct.Lua.html#method.set_memory_limit
+    pub max_memory: Option<usize>,
+    /// The most Lua and Rust function calls that may be active at once, counting the calls which
+    /// were already active when the function was called.
+    pub max_stack_depth: Option<usize>,
+}
+
+// The state kept by `Context::sandbox` between calls.
+pub(crate) struct SandboxState {
+    // The registry id of a table whose keys are the functions and tables installed by
+    // `Context::sandbox`, so that calling it again does not replace them.
+    installed: c_int,
+    print: Option<SandboxPrintHandler>,
+}
+
+pub(crate) fn sandbox<'lua>(lua: Context<'lua>, options: SandboxOptions) -> Result<SandboxReport> {
+    let mut sandbox = Sandbox {
+        lua,
+        installed: installed_table(lua)?,
+        options,
+        report: SandboxReport::default(),
+    };
+    unsafe {
+        if let Some(state) = (*extra_data(lua.state)).sandbox.as_mut() {
+            state.print = sandbox.options.print.clone();
+        }
+    }
+
+    let globals = lua.globals();
+    for name in &["dofile", "loadfile", "collectgarbage"] {
+        sandbox.remove(&globals, name, name)?;
+    }
+    if sandbox.options.load_text_chunks {
+        sandbox.replace(&globals, "load", "load", load_text)?;
+    } else {
+        sandbox.remove(&globals, "load", "load")?;
+    }
+    for name in &["io", "os", "debug"] {
+        sandbox.strip_library(&globals, name)?;
+    }
+
+    // Strings index the `string` table through their metatable, which is a separate copy once
+    // the metatable has been sealed.
+    let mut string_tables = Vec::new();
+    if let Some(string) = globals.raw_get::<_, Option<Table>>("string")? {
+        string_tables.push(string);
+    }
+    if let Some(metatable) = lua.metatable_of(TypeCategory::String)? {
+        if let Value::Table(index) = metatable.raw_get("__index")? {
+            string_tables.push(index);
+        }
+    }
+    for string in &string_tables {
+        sandbox.remove(string, "dump", "string.dump")?;
+    }
+    // `string.rep` is capped by the same wrapper as `Lua::set_string_limits`, never raising a
+    // limit the host already set.
+    if let Some(len) = sandbox.options.max_string_rep_len {
+        if !sandbox.options.keeps("string.rep") {
+            let wrapped = unsafe {
+                let limits = &mut (*extra_data(lua.state)).string_limits;
+                limits.max_rep_output = Some(limits.max_rep_output.map_or(len, |max| max.min(len)));
+                wrap_string_functions(lua.state, &["rep"])?
+            };
+            if !wrapped.is_empty() {
+                sandbox.report("string.rep", true);
+            }
+        }
+    }
+
+    if let Some(package) = globals.raw_get::<_, Option<Table>>("package")? {
+        sandbox.remove(&package, "loadlib", "package.loadlib")?;
+        // Only `package.preload` is searched, so `require` cannot load files or C libraries.
+        if let Some(searchers) = package.raw_get::<_, Option<Table>>("searchers")? {
+            if !sandbox.options.keeps("package.searchers") && searchers.raw_len() > 1 {
+                for i in 2..=searchers.raw_len() {
+                    searchers.raw_set(i, Nil)?;
+                }
+                sandbox.report("package.searchers", true);
+            }
+        }
+    }
+
+    sandbox.replace(&globals, "print", "print", print)?;
+
+    if sandbox.options.seal_string_metatable {
+        let sealed = match lua.metatable_of(TypeCategory::String)? {
+            Some(metatable) => metatable.raw_get::<_, Option<bool>>("__metatable")? == Some(false),
+            None => false,
+        };
+        if !sealed {
+            lua.seal_string_metatable(true)?;
+            sandbox.report("string metatable", true);
+        }
+    }
+    // Numbers only have a metatable if the host gave them one, such as for
+    // `Lua::set_number_format`, and scripts must not be able to change it.
+    if let Some(metatable) = lua.metatable_of(TypeCategory::Number)? {
+        if let Nil = metatable.raw_get::<_, Value>("__metatable")? {
+            metatable.raw_set("__metatable", false)?;
+            sandbox.report("number metatable", true);
+        }
+    }
+
+    Ok(sandbox.report)
+}
+
+struct Sandbox<'lua> {
+    lua: Context<'lua>,
+    installed: Table<'lua>,
+    options: SandboxOptions,
+    report: SandboxReport,
+}
+
+impl<'lua> Sandbox<'lua> {
+    fn report(&mut self, path: &str, replaced: bool) {
+        let list = if replaced {
+            &mut self.report.replaced
+        } else {
+            &mut self.report.removed
+        };
+        if !list.iter().any(|p| p == path) {
+            list.push(path.to_owned());
+        }
+    }
+
+    fn remove(&mut self, table: &Table<'lua>, key: &str, path: &str) -> Result<()> {
+        if self.options.keeps(path) || !table.raw_contains_key(key)? {
+            return Ok(());
+        }
+        table.raw_set(key, Nil)?;
+        self.report(path, false);
+        Ok(())
+    }
+
+    fn replace<F>(&mut self, table: &Table<'lua>, key: &str, path: &str, create: F) -> Result<()>
+    where
+        F: FnOnce(Context<'lua>) -> Result<Function<'lua>>,
+    {
+        if self.options.keeps(path) {
+            return Ok(());
+        }
+        let current = table.raw_get::<_, Value>(key)?;
+        if let Value::Nil = current {
+            return Ok(());
+        }
+        if self.installed.raw_get::<_, bool>(current)? {
+            return Ok(());
+        }
+        let function = create(self.lua)?;
+        self.installed.raw_set(function.clone(), true)?;
+        table.raw_set(key, function)?;
+        self.report(path, true);
+        Ok(())
+    }
+
+    // Removes a library from the globals and from `package.loaded`, or replaces it with a table
+    // holding only the kept fields.
+    fn strip_library(&mut self, globals: &Table<'lua>, name: &str) -> Result<()> {
+        if self.options.keeps(name) {
+            return Ok(());
+        }
+        let library = match globals.raw_get::<_, Option<Table>>(name)? {
+            Some(library) => library,
+            None => return Ok(()),
+        };
+        if self.installed.raw_get::<_, bool>(library.clone())? {
+            return Ok(());
+        }
+
+        let prefix = format!("{}.", name);
+        let kept = self
+            .options
+            .keep
+            .iter()
+            .filter_map(|keep| keep.strip_prefix(prefix.as_str()))
+            .collect::<Vec<_>>();
+        let replacement = if kept.is_empty() {
+            self.report(name, false);
+            None
+        } else {
+            let replacement = self.lua.create_table()?;
+            for field in kept {
+                replacement.raw_set(field, library.raw_get::<_, Value>(field)?)?;
+            }
+            self.installed.raw_set(replacement.clone(), true)?;
+            self.report(name, true);
+            Some(replacement)
+        };
+
+        globals.raw_set(name, replacement.clone())?;
+        if let Some(package) = globals.raw_get::<_, Option<Table>>("package")? {
+            if let Some(loaded) = package.raw_get::<_, Option<Table>>("loaded")? {
+                loaded.raw_set(name, replacement)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Returns the table of installed functions, creating it on the first call.
+fn installed_table<'lua>(lua: Context<'lua>) -> Result<Table<'lua>> {
+    unsafe {
+        let extra = extra_data(lua.state);
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 2);
+
+        match &(*extra).sandbox {
+            Some(state) => {
+                ffi::lua_rawgeti(
+                    lua.state,
+                    ffi::LUA_REGISTRYINDEX,
+                    state.installed as ffi::lua_Integer,
+                );
+                Ok(Table(lua.pop_ref()))
+            }
+            None => {
+                let installed = lua.create_table()?;
+                lua.push_ref(&installed.0);
+                let id = protect_lua_closure(lua.state, 1, 0, |state| {
+                    ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+                })?;
+                (*extra).sandbox = Some(SandboxState {
+                    installed: id,
+                    print: None,
+                });
+                Ok(installed)
+            }
+        }
+    }
+}
+
+// Passes the arguments, converted by the global `tostring` and separated by tabs, to the print
+// handler.
+fn print<'lua>(lua: Context<'lua>) -> Result<Function<'lua>> {
+    lua.create_named_function("print", |lua, args: MultiValue| {
+        let handler = unsafe {
+            (*extra_data(lua.state))
+                .sandbox
+                .as_ref()
+                .and_then(|state| state.print.clone())
+        };
+        if let Some(handler) = handler {
+            let tostring: Function = lua.globals().get("tostring")?;
+            let mut line = Vec::new();
+            for (i, arg) in args.into_iter().enumerate() {
+                if i > 0 {
+                    line.push(b'\t');
+                }
+                line.extend(tostring.call::<_, String>(arg)?.as_bytes());
+            }
+            handler(&StdString::from_utf8_lossy(&line));
+        }
+        Ok(())
+    })
+}
+
+// Like `load`, but only loads text chunks, whatever the mode argument allows.
+fn load_text<'lua>(lua: Context<'lua>) -> Result<Function<'lua>> {
+    lua.create_named_function("load", |lua, args: MultiValue| {
+        let nargs = args.len();
+        let mut args = args.into_iter();
+        let chunk = args.next().unwrap_or(Nil);
+        let name = lua.unpack::<Option<String>>(args.next().unwrap_or(Nil))?;
+        let mode = lua.unpack::<Option<String>>(args.next().unwrap_or(Nil))?;
+        let env = if nargs >= 4 { args.next() } else { None };
+
+        let (source, default_name) = match chunk {
+            Value::String(s) => (s.as_bytes().to_vec(), s.as_bytes().to_vec()),
+            Value::Function(reader) => {
+                let mut source = Vec::new();
+                loop {
+                    match reader.call::<_, Value>(())? {
+                        Value::Nil => break,
+                        Value::String(piece) if piece.as_bytes().is_empty() => break,
+                        Value::String(piece) => source.extend(piece.as_bytes()),
+                        _ => {
+                            return Err(Error::RuntimeError(
+                                "reader function must return a string".to_owned(),
+                            ))
+                        }
+                    }
+                    // Stop reading as soon as the chunk is too large, rather than collecting
+                    // whatever the reader keeps returning.
+                    if let Err(err) = unsafe { check_chunk_size(lua.state, source.len()) } {
+                        return lua.pack_multi((Nil, err.to_string()));
+                    }
+                }
+                (source, b"=(load)".to_vec())
+            }
+            chunk => {
+                return Err(Error::RuntimeError(format!(
+                    "bad argument #1 to 'load' (string expected, got {})",
+                    chunk.type_name()
+                )))
+            }
+        };
+        if let Some(mode) = mode {
+            if !mode.as_bytes().contains(&b't') {
+                let message = format!(
+                    "attempt to load a text chunk (mode is '{}')",
+                    StdString::from_utf8_lossy(mode.as_bytes())
+                );
+                return lua.pack_multi((Nil, message));
+            }
+        }
+
+        let name = match &name {
+            Some(name) => name.as_bytes(),
+            None => &default_name,
+        };
+        let name = name.split(|&b| b == 0).next().unwrap_or_default();
+        let mut chunk = lua.load(&source).set_name(name)?;
+        if let Some(env) = env {
+            chunk = chunk.set_environment(env)?;
+        }
+        // Syntax errors are returned to the script before the error hook can transform them.
+        match chunk.into_function_unhooked() {
+            Ok(function) => lua.pack_multi(function),
+            Err(Error::SyntaxError { message, .. }) => lua.pack_multi((Nil, message)),
+            Err(err @ Error::ChunkTooLarge { .. }) => lua.pack_multi((Nil, err.to_string())),
+            Err(err) => Err(err),
+        }
+    })
+}
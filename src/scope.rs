@@ -1,5 +1,6 @@
-use std::any::Any;
+use std::any::{type_name, Any};
 use std::cell::RefCell;
+use std::fmt;
 use std::marker::PhantomData;
 use std::mem;
 use std::os::raw::c_void;
@@ -11,10 +12,10 @@ use crate::ffi;
 use crate::function::Function;
 use crate::markers::Invariant;
 use crate::types::{Callback, LuaRef};
-use crate::userdata::{AnyUserData, MetaMethod, UserData, UserDataMethods};
+use crate::userdata::{check_tostring_helper, AnyUserData, MetaMethod, UserData, UserDataMethods};
 use crate::util::{
     assert_stack, init_userdata_metatable, protect_lua_closure, push_string, push_userdata,
-    take_userdata, StackGuard,
+    take_userdata, track_userdata, untrack_userdata, StackGuard,
 };
 use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti, Value};
 
@@ -115,6 +116,7 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
                 u.lua.push_ref(&u);
                 // We know the destructor has not run yet because we hold a reference to the
                 // userdata.
+                untrack_userdata(state, type_name::<T>());
                 Box::new(take_userdata::<RefCell<T>>(state))
             }));
             Ok(u)
@@ -232,6 +234,11 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
 
         let mut ud_methods = NonStaticUserDataMethods::default();
         T::add_methods(&mut ud_methods);
+        check_tostring_helper(
+            type_name::<T>(),
+            ud_methods.tostring_helper,
+            ud_methods.meta_methods.iter().map(|&(meta, _)| meta),
+        )?;
 
         unsafe {
             let lua = self.lua;
@@ -275,7 +282,20 @@ impl<'lua, 'scope> Scope<'lua, 'scope> {
 
             ffi::lua_setmetatable(lua.state, -2);
 
-            Ok(AnyUserData(lua.pop_ref()))
+            // The value is shared with the methods rather than owned by the userdata, and is
+            // dropped with them when the scope ends.
+            track_userdata(lua.state, type_name::<T>());
+            let u = AnyUserData(lua.pop_ref());
+            self.destructors.borrow_mut().push((u.0.clone(), |u| {
+                let state = u.lua.state;
+                assert_stack(state, 1);
+                u.lua.push_ref(&u);
+                untrack_userdata(state, type_name::<T>());
+                ffi::lua_pop(state, 1);
+                Box::new(())
+            }));
+
+            Ok(u)
         }
     }
 
@@ -339,6 +359,8 @@ enum NonStaticMethod<'lua, T> {
 struct NonStaticUserDataMethods<'lua, T: UserData> {
     methods: Vec<(Vec<u8>, NonStaticMethod<'lua, T>)>,
     meta_methods: Vec<(MetaMethod, NonStaticMethod<'lua, T>)>,
+    // Whether `__tostring` was added by `add_tostring_from_display` or `add_tostring_from_debug`.
+    tostring_helper: bool,
 }
 
 impl<'lua, T: UserData> Default for NonStaticUserDataMethods<'lua, T> {
@@ -346,6 +368,7 @@ impl<'lua, T: UserData> Default for NonStaticUserDataMethods<'lua, T> {
         NonStaticUserDataMethods {
             methods: Vec::new(),
             meta_methods: Vec::new(),
+            tostring_helper: false,
         }
     }
 }
@@ -466,4 +489,22 @@ impl<'lua, T: UserData> UserDataMethods<'lua, T> for NonStaticUserDataMethods<'l
             })),
         ));
     }
+
+    fn add_tostring_from_display(&mut self)
+    where
+        T: fmt::Display,
+    {
+        self.tostring_helper = true;
+        self.add_meta_method(MetaMethod::ToString, |_, data, ()| Ok(data.to_string()));
+    }
+
+    fn add_tostring_from_debug(&mut self)
+    where
+        T: fmt::Debug,
+    {
+        self.tostring_helper = true;
+        self.add_meta_method(MetaMethod::ToString, |_, data, ()| {
+            Ok(format!("{:?}", data))
+        });
+    }
 }
@@ -0,0 +1,354 @@
+use std::collections::{HashMap, HashSet};
+use std::os::raw::c_void;
+use std::string::String as StdString;
+
+use crate::context::Context;
+use crate::error::Result;
+use crate::ffi;
+use crate::table::Table;
+use crate::transfer::ref_pointer;
+use crate::types::RegistryKey;
+use crate::util::{assert_stack, StackGuard};
+use crate::value::{Nil, Value};
+
+/// A saved copy of the global environment of a Lua state, created by
+/// [`Context::snapshot_globals`].
+///
+/// [`Context::snapshot_globals`]: struct.Context.html#method.snapshot_globals
+#[derive(Debug)]
+pub struct GlobalsSnapshot {
+    // Maps every table reachable from the globals to a shallow copy of its contents at the time of
+    // the snapshot.
+    contents: RegistryKey,
+    // Maps every table reachable from the globals which had a metatable to that metatable.
+    metatables: RegistryKey,
+}
+
+pub(crate) fn snapshot<'lua>(lua: Context<'lua>, include_loaded: bool) -> Result<GlobalsSnapshot> {
+    let mut snapshot = Snapshot {
+        lua,
+        contents: lua.create_table()?,
+        metatables: lua.create_table()?,
+        skip: None,
+    };
+    if !include_loaded {
+        snapshot.skip = loaded_table(lua)?.map(|loaded| unsafe { ref_pointer(&loaded.0) });
+    }
+    snapshot.table(lua.globals())?;
+
+    Ok(GlobalsSnapshot {
+        contents: lua.create_registry_value(snapshot.contents)?,
+        metatables: lua.create_registry_value(snapshot.metatables)?,
+    })
+}
+
+pub(crate) fn restore<'lua>(lua: Context<'lua>, snapshot: &GlobalsSnapshot) -> Result<()> {
+    let contents: Table = lua.registry_value(&snapshot.contents)?;
+    let metatables: Table = lua.registry_value(&snapshot.metatables)?;
+
+    for pair in contents.pairs::<Table, Table>() {
+        let (table, saved) = pair?;
+
+        let keys = table
+            .clone()
+            .pairs::<Value, Value>()
+            .map(|pair| pair.map(|(key, _)| key))
+            .collect::<Result<Vec<_>>>()?;
+        for key in keys {
+            table.raw_set(key, Nil)?;
+        }
+        for pair in saved.pairs::<Value, Value>() {
+            let (key, value) = pair?;
+            table.raw_set(key, value)?;
+        }
+
+        table.set_metatable(metatables.raw_get(table.clone())?);
+    }
+
+    Ok(())
+}
+
+/// The globals changed since a [`GlobalsSnapshot`] was taken, returned by
+/// [`Context::diff_globals`].
+///
+/// Each list holds the names of globals, sorted.  Globals whose keys are not strings are not
+/// reported, and names which are not valid UTF-8 are converted lossily.
+///
+/// [`GlobalsSnapshot`]: struct.GlobalsSnapshot.html
+/// [`Context::diff_globals`]: struct.Context.html#method.diff_globals
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct GlobalsDiff {
+    /// Globals which did not exist in the snapshot.
+    pub added: Vec<StdString>,
+    /// Globals whose value has changed.
+    pub modified: Vec<StdString>,
+    /// Globals which existed in the snapshot but are now nil.
+    pub removed: Vec<StdString>,
+    /// Globals which now refer to a different table with the same contents and metatable as the
+    /// table they referred to in the snapshot.  Only filled in by a deep comparison.
+    pub replaced_with_equal: Vec<StdString>,
+}
+
+pub(crate) fn diff<'lua>(
+    lua: Context<'lua>,
+    snapshot: &GlobalsSnapshot,
+    deep: bool,
+) -> Result<GlobalsDiff> {
+    let diff = Diff {
+        lua,
+        globals: unsafe { ref_pointer(&lua.globals().0) },
+        contents: lua.registry_value(&snapshot.contents)?,
+        metatables: lua.registry_value(&snapshot.metatables)?,
+        changed: HashMap::new(),
+        compared: HashSet::new(),
+    };
+    diff.globals(deep)
+}
+
+struct Diff<'lua> {
+    lua: Context<'lua>,
+    contents: Table<'lua>,
+    metatables: Table<'lua>,
+    globals: *const c_void,
+    // Whether the entries or the metatable of each table in the snapshot have changed.
+    changed: HashMap<*const c_void, bool>,
+    // Pairs of old and new tables assumed to be structurally equal while comparing them.
+    compared: HashSet<(*const c_void, *const c_void)>,
+}
+
+impl<'lua> Diff<'lua> {
+    fn globals(mut self, deep: bool) -> Result<GlobalsDiff> {
+        let globals = self.lua.globals();
+        let saved: Table = self.contents.raw_get(globals.clone())?;
+        let mut diff = GlobalsDiff::default();
+
+        for pair in saved.clone().pairs::<Value, Value>() {
+            let (key, old) = pair?;
+            let name = match &key {
+                Value::String(name) => StdString::from_utf8_lossy(name.as_bytes()).into_owned(),
+                _ => continue,
+            };
+            let new = globals.raw_get::<_, Value>(key)?;
+            if let Nil = new {
+                diff.removed.push(name);
+            } else if raw_equal(self.lua, &old, &new)? {
+                if let (Value::Table(table), true) = (&old, deep) {
+                    if self.table_changed(table)? {
+                        diff.modified.push(name);
+                    }
+                }
+            } else if deep && self.structurally_equal(&old, &new)? {
+                diff.replaced_with_equal.push(name);
+            } else {
+                diff.modified.push(name);
+            }
+        }
+        for pair in globals.pairs::<Value, Value>() {
+            if let (Value::String(name), _) = pair? {
+                if let Nil = saved.raw_get::<_, Value>(name.clone())? {
+                    diff.added
+                        .push(StdString::from_utf8_lossy(name.as_bytes()).into_owned());
+                }
+            }
+        }
+
+        diff.added.sort();
+        diff.modified.sort();
+        diff.removed.sort();
+        diff.replaced_with_equal.sort();
+        Ok(diff)
+    }
+
+    // Returns whether `table` or a table it reaches has different contents or metatable than in
+    // the snapshot.  Tables which are not part of the snapshot are not followed, and neither is
+    // the globals table, whose changes are reported global by global.
+    fn table_changed(&mut self, table: &Table<'lua>) -> Result<bool> {
+        let mut visited = HashSet::new();
+        visited.insert(self.globals);
+        let mut pending = vec![table.clone()];
+        while let Some(table) = pending.pop() {
+            let ptr = unsafe { ref_pointer(&table.0) };
+            if !visited.insert(ptr) {
+                continue;
+            }
+            let saved = match self.contents.raw_get::<_, Option<Table>>(table.clone())? {
+                Some(saved) => saved,
+                None => continue,
+            };
+            if self.entries_changed(ptr, &saved, &table)? {
+                return Ok(true);
+            }
+            for pair in saved.pairs::<Value, Value>() {
+                let (key, value) = pair?;
+                for value in [key, value] {
+                    if let Value::Table(t) = value {
+                        pending.push(t);
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    // Returns whether the entries or the metatable of `table` differ from its snapshot `saved`,
+    // comparing values as with `rawequal`.
+    fn entries_changed(
+        &mut self,
+        ptr: *const c_void,
+        saved: &Table<'lua>,
+        table: &Table<'lua>,
+    ) -> Result<bool> {
+        if let Some(&changed) = self.changed.get(&ptr) {
+            return Ok(changed);
+        }
+        let metatable = self.metatables.raw_get::<_, Option<Table>>(table.clone())?;
+        let mut changed = !option_equal(self.lua, metatable, table.get_metatable())?;
+        let mut saved_len = 0;
+        for pair in saved.clone().pairs::<Value, Value>() {
+            if changed {
+                break;
+            }
+            let (key, value) = pair?;
+            saved_len += 1;
+            changed = !raw_equal(self.lua, &value, &table.raw_get(key)?)?;
+        }
+        changed = changed || saved_len != table.clone().pairs::<Value, Value>().count();
+        self.changed.insert(ptr, changed);
+        Ok(changed)
+    }
+
+    // Returns whether `old`, with its contents as they were in the snapshot, and `new`, with its
+    // current contents, are equal, comparing tables by their entries and metatables and other
+    // values as with `rawequal`.  Nested tables are compared through a worklist rather than by
+    // recursing, so that deeply nested tables cannot overflow the Rust stack.
+    fn structurally_equal(&mut self, old: &Value<'lua>, new: &Value<'lua>) -> Result<bool> {
+        let mut pending = vec![(old.clone(), new.clone())];
+        while let Some((old, new)) = pending.pop() {
+            let (old, new) = match (old, new) {
+                (Value::Table(old), Value::Table(new)) => (old, new),
+                (old, new) => {
+                    if !raw_equal(self.lua, &old, &new)? {
+                        return Ok(false);
+                    }
+                    continue;
+                }
+            };
+            let pair = unsafe { (ref_pointer(&old.0), ref_pointer(&new.0)) };
+            if pair.0 == pair.1 {
+                if self.table_changed(&old)? {
+                    return Ok(false);
+                }
+                continue;
+            }
+            if !self.compared.insert(pair) {
+                continue;
+            }
+
+            let old_metatable = self.metatables.raw_get::<_, Option<Table>>(old.clone())?;
+            match (old_metatable, new.get_metatable()) {
+                (Some(old_metatable), Some(new_metatable)) => {
+                    pending.push((Value::Table(old_metatable), Value::Table(new_metatable)));
+                }
+                (None, None) => {}
+                _ => return Ok(false),
+            }
+
+            let saved = self
+                .contents
+                .raw_get::<_, Option<Table>>(old.clone())?
+                .unwrap_or_else(|| old.clone());
+            let mut old_len = 0;
+            for pair in saved.pairs::<Value, Value>() {
+                let (key, value) = pair?;
+                old_len += 1;
+                let other = new.raw_get::<_, Value>(key)?;
+                pending.push((value, other));
+            }
+            if old_len != new.clone().pairs::<Value, Value>().count() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn option_equal<'lua>(
+    lua: Context<'lua>,
+    a: Option<Table<'lua>>,
+    b: Option<Table<'lua>>,
+) -> Result<bool> {
+    match (a, b) {
+        (Some(a), Some(b)) => raw_equal(lua, &Value::Table(a), &Value::Table(b)),
+        (None, None) => Ok(true),
+        _ => Ok(false),
+    }
+}
+
+fn raw_equal<'lua>(lua: Context<'lua>, a: &Value<'lua>, b: &Value<'lua>) -> Result<bool> {
+    unsafe {
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 2);
+        lua.push_value(a.clone())?;
+        lua.push_value(b.clone())?;
+        Ok(ffi::lua_rawequal(lua.state, -1, -2) != 0)
+    }
+}
+
+struct Snapshot<'lua> {
+    lua: Context<'lua>,
+    contents: Table<'lua>,
+    metatables: Table<'lua>,
+    // The `package.loaded` table, if it should be left out of the snapshot.
+    skip: Option<*const c_void>,
+}
+
+impl<'lua> Snapshot<'lua> {
+    // Saves `table` and every table reachable from it, following a worklist rather than recursing
+    // so that deeply nested tables cannot overflow the Rust stack.
+    fn table(&mut self, table: Table<'lua>) -> Result<()> {
+        let mut pending = vec![table];
+        while let Some(table) = pending.pop() {
+            if self
+                .contents
+                .raw_get::<_, Option<Table>>(table.clone())?
+                .is_some()
+            {
+                continue;
+            }
+            if self.skip.is_some() && self.skip == Some(unsafe { ref_pointer(&table.0) }) {
+                continue;
+            }
+
+            let saved = self.lua.create_table()?;
+            self.contents.raw_set(table.clone(), saved.clone())?;
+
+            if let Some(metatable) = table.get_metatable() {
+                self.metatables.raw_set(table.clone(), metatable.clone())?;
+                pending.push(metatable);
+            }
+
+            for pair in table.pairs::<Value, Value>() {
+                let (key, value) = pair?;
+                if let Value::Table(t) = &key {
+                    pending.push(t.clone());
+                }
+                if let Value::Table(t) = &value {
+                    pending.push(t.clone());
+                }
+                saved.raw_set(key, value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn loaded_table<'lua>(lua: Context<'lua>) -> Result<Option<Table<'lua>>> {
+    match lua.globals().raw_get::<_, Value>("package")? {
+        Value::Table(package) => match package.raw_get::<_, Value>("loaded")? {
+            Value::Table(loaded) => Ok(Some(loaded)),
+            _ => Ok(None),
+        },
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,257 @@
+use std::os::raw::c_int;
+use std::ptr;
+
+use crate::error::Result;
+use crate::ffi;
+use crate::lua::extra_data;
+use crate::util::{assert_stack, protect_lua_closure, push_string, StackGuard};
+
+/// Limits on the work done by functions of the `string` library, set with
+/// [`Lua::set_string_limits`].
+///
+/// Each limit is `None` by default, meaning that it is not enforced.  Exceeding a limit raises a
+/// Lua error naming the limit.
+///
+/// [`Lua::set_string_limits`]: struct.Lua.html#method.set_string_limits
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct StringLimits {
+    /// The largest string in bytes that `string.rep` may build.
+    pub max_rep_output: Option<usize>,
+    /// The longest subject string in bytes accepted by `string.find`, `string.match`,
+    /// `string.gmatch` and `string.gsub`.
+    pub max_pattern_subject: Option<usize>,
+    /// The deepest recursion of the pattern matcher allowed by the pattern passed to
+    /// `string.find`, `string.match`, `string.gmatch` and `string.gsub`.
+    ///
+    /// The matcher recurses once for each capture and for each item with a `?`, `*`, `+` or `-`
+    /// quantifier, so this limits how deeply patterns may nest and backtrack.  It does not bound
+    /// the time a match takes, which can still grow quickly with the subject length; use
+    /// [`Limits::deadline`] with [`Lua::run_sandboxed`] to bound CPU time.  Plain searches with
+    /// `string.find` are not limited.
+    ///
+    /// [`Limits::deadline`]: struct.Limits.html#structfield.deadline
+    /// [`Lua::run_sandboxed`]: struct.Lua.html#method.run_sandboxed
+    pub max_match_depth: Option<usize>,
+}
+
+const WRAPPERS: &[(&str, ffi::lua_CFunction)] = &[
+    ("rep", limited_rep),
+    ("find", limited_find),
+    ("match", limited_match),
+    ("gmatch", limited_match),
+    ("gsub", limited_match),
+];
+
+pub(crate) const STRING_LIMIT_FUNCTIONS: &[&str] = &["rep", "find", "match", "gmatch", "gsub"];
+
+// Wraps the functions named in `names` in the `string` table, and in the table strings index
+// through their metatable if that is a copy, with versions checking the limits set by
+// `Lua::set_string_limits` before calling the original function.  Functions which are already
+// wrapped are skipped.  Returns the names of the functions that were wrapped.
+pub(crate) unsafe fn wrap_string_functions(
+    state: *mut ffi::lua_State,
+    names: &[&'static str],
+) -> Result<Vec<&'static str>> {
+    let _sg = StackGuard::new(state);
+    assert_stack(state, 6);
+
+    ffi::lua_rawgeti(state, ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_GLOBALS);
+    push_string(state, "string")?;
+    ffi::lua_rawget(state, -2);
+    let string = ffi::lua_gettop(state);
+    push_string(state, "")?;
+    if ffi::lua_getmetatable(state, -1) == 0 {
+        ffi::lua_pushnil(state);
+    } else {
+        push_string(state, "__index")?;
+        ffi::lua_rawget(state, -2);
+    }
+    let index = ffi::lua_gettop(state);
+
+    let mut wrapped = Vec::new();
+    for &table in &[string, index] {
+        if ffi::lua_type(state, table) != ffi::LUA_TTABLE {
+            continue;
+        }
+        for &(name, wrapper) in WRAPPERS {
+            if !names.contains(&name) {
+                continue;
+            }
+            push_string(state, name)?;
+            ffi::lua_rawget(state, table);
+            let is_wrapper = match ffi::lua_tocfunction(state, -1) {
+                Some(f) => WRAPPERS.iter().any(|&(_, w)| f as usize == w as usize),
+                None => false,
+            };
+            if ffi::lua_type(state, -1) != ffi::LUA_TFUNCTION || is_wrapper {
+                ffi::lua_pop(state, 1);
+                continue;
+            }
+
+            protect_lua_closure(state, 1, 1, |state| {
+                ffi::lua_pushcclosure(state, wrapper, 1);
+            })?;
+            ffi::lua_pushvalue(state, table);
+            ffi::lua_insert(state, -2);
+            push_string(state, name)?;
+            ffi::lua_insert(state, -2);
+            protect_lua_closure(state, 3, 0, |state| ffi::lua_rawset(state, -3))?;
+            if !wrapped.contains(&name) {
+                wrapped.push(name);
+            }
+        }
+    }
+    Ok(wrapped)
+}
+
+// Calls the original function, given as the first upvalue, with all of the arguments.
+unsafe fn call_original(state: *mut ffi::lua_State) -> c_int {
+    let nargs = ffi::lua_gettop(state);
+    ffi::luaL_checkstack(state, 1, ptr::null());
+    ffi::lua_pushvalue(state, ffi::lua_upvalueindex(1));
+    ffi::lua_insert(state, 1);
+    ffi::lua_call(state, nargs, ffi::LUA_MULTRET);
+    ffi::lua_gettop(state)
+}
+
+// Returns the length of the string or number at `index`, or `None` for other values, which are
+// left for the original function to reject.
+unsafe fn string_len(state: *mut ffi::lua_State, index: c_int) -> Option<usize> {
+    match ffi::lua_type(state, index) {
+        ffi::LUA_TSTRING | ffi::LUA_TNUMBER => {
+            let mut len = 0;
+            ffi::lua_tolstring(state, index, &mut len);
+            Some(len)
+        }
+        _ => None,
+    }
+}
+
+unsafe extern "C" fn limited_rep(state: *mut ffi::lua_State) -> c_int {
+    if let Some(limit) = (*extra_data(state)).string_limits.max_rep_output {
+        let mut isnum = 0;
+        let n = ffi::lua_tointegerx(state, 2, &mut isnum);
+        let sep_len = if ffi::lua_type(state, 3) <= ffi::LUA_TNIL {
+            Some(0)
+        } else {
+            string_len(state, 3)
+        };
+        if let (Some(len), Some(sep_len), true) = (string_len(state, 1), sep_len, isnum != 0) {
+            if n > 0 {
+                let n = n as u128;
+                let output = len as u128 * n + sep_len as u128 * (n - 1);
+                if output > limit as u128 {
+                    return ffi::luaL_error(
+                        state,
+                        cstr!("string.rep output exceeds max_rep_output (%I bytes)"),
+                        limit as ffi::lua_Integer,
+                    );
+                }
+            }
+        }
+    }
+    call_original(state)
+}
+
+unsafe extern "C" fn limited_find(state: *mut ffi::lua_State) -> c_int {
+    let plain = ffi::lua_toboolean(state, 4) != 0;
+    check_pattern(state, !plain);
+    call_original(state)
+}
+
+unsafe extern "C" fn limited_match(state: *mut ffi::lua_State) -> c_int {
+    check_pattern(state, true);
+    call_original(state)
+}
+
+// Raises an error if the subject or the pattern passed to a pattern matching function exceed the
+// limits.
+unsafe fn check_pattern(state: *mut ffi::lua_State, check_depth: bool) {
+    let limits = (*extra_data(state)).string_limits;
+    if let Some(limit) = limits.max_pattern_subject {
+        if string_len(state, 1).unwrap_or(0) > limit {
+            ffi::luaL_error(
+                state,
+                cstr!("pattern subject exceeds max_pattern_subject (%I bytes)"),
+                limit as ffi::lua_Integer,
+            );
+        }
+    }
+    if let (Some(limit), true) = (limits.max_match_depth, check_depth) {
+        if let Some(len) = string_len(state, 2) {
+            let pattern = ffi::lua_tolstring(state, 2, ptr::null_mut()) as *const u8;
+            if match_depth(std::slice::from_raw_parts(pattern, len)) > limit {
+                ffi::luaL_error(
+                    state,
+                    cstr!("pattern exceeds max_match_depth (%I)"),
+                    limit as ffi::lua_Integer,
+                );
+            }
+        }
+    }
+}
+
+// Returns how deep the pattern matcher of the `string` library may recurse for `pattern`, which is
+// once for each capture and for each quantified item.  Follows `do_match` and `classEnd` in
+// `lstrlib.c`.
+fn match_depth(pattern: &[u8]) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < pattern.len() {
+        match (pattern[i], pattern.get(i + 1)) {
+            (b'(', _) | (b')', _) => {
+                depth += 1;
+                i += 1;
+                continue;
+            }
+            (b'%', Some(b'b')) => {
+                i += 4;
+                continue;
+            }
+            (b'%', Some(b'f')) => {
+                i = class_end(pattern, i + 2);
+                continue;
+            }
+            (b'%', Some(c)) if c.is_ascii_digit() => {
+                i += 2;
+                continue;
+            }
+            _ => {}
+        }
+        i = class_end(pattern, i);
+        if let Some(b'?') | Some(b'*') | Some(b'+') | Some(b'-') = pattern.get(i) {
+            depth += 1;
+            i += 1;
+        }
+    }
+    depth
+}
+
+// Returns the index after the single character class starting at `i`.
+fn class_end(pattern: &[u8], i: usize) -> usize {
+    let mut j = i + 1;
+    match pattern.get(i) {
+        Some(b'%') => j + 1,
+        Some(b'[') => {
+            if pattern.get(j) == Some(&b'^') {
+                j += 1;
+            }
+            // The first character of a set is never its end, even if it is `]`.
+            loop {
+                if j >= pattern.len() {
+                    return pattern.len();
+                }
+                let c = pattern[j];
+                j += 1;
+                if c == b'%' {
+                    j += 1;
+                }
+                if j >= pattern.len() || pattern[j] == b']' {
+                    break;
+                }
+            }
+            j + 1
+        }
+        _ => j,
+    }
+}
@@ -0,0 +1,132 @@
+use std::string::String as StdString;
+use std::sync::Arc;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::table::Table;
+use crate::value::{FromLuaMulti, ToLua, ToLuaMulti, Value};
+
+/// A builder for tables of functions and constants, such as the module tables of Lua APIs
+/// implemented in Rust, created by [`Context::table_builder`].
+///
+/// Each method adds a single entry, and the table is only created by [`build`].  If creating any
+/// entry fails, [`build`] returns an [`Error::TableBuildError`] holding the path of that entry.
+///
+/// [`Context::table_builder`]: struct.Context.html#method.table_builder
+/// [`build`]: #method.build
+/// [`Error::TableBuildError`]: enum.Error.html#variant.TableBuildError
+pub struct TableBuilder<'lua> {
+    lua: Context<'lua>,
+    // Path of the table being built, used as the prefix of entry paths in errors.
+    path: StdString,
+    entries: Vec<(StdString, Value<'lua>)>,
+    metatable: Option<Table<'lua>>,
+    // The first error encountered, later entries are ignored once this is set.
+    error: Option<Error>,
+}
+
+impl<'lua> TableBuilder<'lua> {
+    pub(crate) fn new(lua: Context<'lua>, path: StdString) -> TableBuilder<'lua> {
+        TableBuilder {
+            lua,
+            path,
+            entries: Vec::new(),
+            metatable: None,
+            error: None,
+        }
+    }
+
+    /// Adds a Rust function as the entry `name`.
+    ///
+    /// Refer to [`Context::create_function`] for details of how the function is wrapped.
+    ///
+    /// [`Context::create_function`]: struct.Context.html#method.create_function
+    pub fn function<A, R, F>(self, name: &str, func: F) -> TableBuilder<'lua>
+    where
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        F: 'static + Send + Fn(Context<'lua>, A) -> Result<R>,
+    {
+        if self.error.is_some() {
+            return self;
+        }
+        let func = self.lua.create_function(func);
+        self.entry(name, func.map(Value::Function))
+    }
+
+    /// Adds any value which can be converted to Lua as the entry `name`.
+    pub fn value<V: ToLua<'lua>>(self, name: &str, value: V) -> TableBuilder<'lua> {
+        if self.error.is_some() {
+            return self;
+        }
+        let value = value.to_lua(self.lua);
+        self.entry(name, value)
+    }
+
+    /// Adds a nested table as the entry `name`, with its contents added by `build`.
+    pub fn table<F>(self, name: &str, build: F) -> TableBuilder<'lua>
+    where
+        F: FnOnce(TableBuilder<'lua>) -> TableBuilder<'lua>,
+    {
+        if self.error.is_some() {
+            return self;
+        }
+        let table = build(TableBuilder::new(self.lua, self.child_path(name))).build();
+        match table {
+            Ok(table) => self.entry(name, Ok(Value::Table(table))),
+            // Errors from nested builders already hold the full path.
+            Err(err) => TableBuilder {
+                error: Some(err),
+                ..self
+            },
+        }
+    }
+
+    /// Sets the metatable of the table, with its contents added by `build`.
+    ///
+    /// Calling this more than once replaces the previous metatable.
+    pub fn metatable<F>(mut self, build: F) -> TableBuilder<'lua>
+    where
+        F: FnOnce(TableBuilder<'lua>) -> TableBuilder<'lua>,
+    {
+        if self.error.is_some() {
+            return self;
+        }
+        match build(TableBuilder::new(self.lua, self.child_path("<metatable>"))).build() {
+            Ok(metatable) => self.metatable = Some(metatable),
+            Err(err) => self.error = Some(err),
+        }
+        self
+    }
+
+    /// Creates the table, or returns the error from the first entry which could not be created.
+    pub fn build(self) -> Result<Table<'lua>> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        let table = self.lua.create_table_from(self.entries)?;
+        table.set_metatable(self.metatable);
+        Ok(table)
+    }
+
+    fn entry(mut self, name: &str, value: Result<Value<'lua>>) -> TableBuilder<'lua> {
+        match value {
+            Ok(value) => self.entries.push((name.to_owned(), value)),
+            Err(err) => {
+                self.error = Some(Error::TableBuildError {
+                    path: self.child_path(name),
+                    cause: Arc::new(err),
+                })
+            }
+        }
+        self
+    }
+
+    fn child_path(&self, name: &str) -> StdString {
+        if self.path.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}.{}", self.path, name)
+        }
+    }
+}
@@ -1,12 +1,19 @@
-use std::os::raw::c_int;
+use std::cell::RefCell;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::rc::Rc;
 
+use crate::context::Context;
 use crate::error::{Error, Result};
 use crate::ffi;
-use crate::types::LuaRef;
+use crate::function::Function;
+use crate::hook::{hook_proc, Debug, HookTriggers};
+use crate::lua::{check_multivalue_limit, extra_data, THREAD_BODIES_REGISTRY_KEY};
+use crate::types::{HookCallback, LuaRef};
 use crate::util::{
     assert_stack, check_stack, error_traceback, pop_error, protect_lua_closure, StackGuard,
 };
-use crate::value::{FromLuaMulti, MultiValue, ToLuaMulti};
+use crate::value::{FromLua, FromLuaMulti, MultiValue, Nil, ToLua, ToLuaMulti};
 
 /// Status of a Lua thread (or coroutine).
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -78,39 +85,18 @@ impl<'lua> Thread<'lua> {
         let args = args.to_lua_multi(lua)?;
         let results = unsafe {
             let _sg = StackGuard::new(lua.state);
-            assert_stack(lua.state, 3);
-
-            lua.push_ref(&self.0);
-            let thread_state = ffi::lua_tothread(lua.state, -1);
-
-            let status = ffi::lua_status(thread_state);
-            if status != ffi::LUA_YIELD && ffi::lua_gettop(thread_state) == 0 {
-                return Err(Error::CoroutineInactive);
-            }
-
-            ffi::lua_pop(lua.state, 1);
-
             let nargs = args.len() as c_int;
-            check_stack(lua.state, nargs)?;
-            check_stack(thread_state, nargs + 1)?;
-
-            for arg in args {
-                lua.push_value(arg)?;
-            }
-            ffi::lua_xmove(lua.state, thread_state, nargs);
-
-            let ret = ffi::lua_resume(thread_state, lua.state, nargs);
-            if ret != ffi::LUA_OK && ret != ffi::LUA_YIELD {
-                protect_lua_closure(lua.state, 0, 0, |_| {
-                    error_traceback(thread_state);
-                    0
-                })?;
-                return Err(pop_error(thread_state, ret));
-            }
+            let thread_state = self.resume_with(nargs, move || {
+                for arg in args {
+                    lua.push_value(arg)?;
+                }
+                Ok(())
+            })?;
 
             let nresults = ffi::lua_gettop(thread_state);
             let mut results = MultiValue::new();
             ffi::lua_xmove(thread_state, lua.state, nresults);
+            check_multivalue_limit(lua.state, nresults as usize)?;
 
             assert_stack(lua.state, 2);
             for _ in 0..nresults {
@@ -121,6 +107,98 @@ impl<'lua> Thread<'lua> {
         R::from_lua_multi(results, lua)
     }
 
+    /// Resumes execution of this thread with a single argument, returning only the first value it
+    /// yields or returns.
+    ///
+    /// This is equivalent to `resume::<_, R>(arg)`, but is faster as it passes the argument and the
+    /// result directly, rather than through a `MultiValue`.  Further yielded or returned values are
+    /// discarded, and a missing value is converted from `nil`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Thread, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let counter: Thread = lua_context.load(r#"
+    ///     coroutine.create(function(step)
+    ///         local n = 0
+    ///         while true do
+    ///             n = n + step
+    ///             step = coroutine.yield(n)
+    ///         end
+    ///     end)
+    /// "#).eval()?;
+    ///
+    /// let mut last = 0;
+    /// for step in 1..=100 {
+    ///     last = counter.resume1::<_, i64>(step)?;
+    /// }
+    /// assert_eq!(last, 5050);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    pub fn resume1<A: ToLua<'lua>, R: FromLua<'lua>>(&self, arg: A) -> Result<R> {
+        let lua = self.0.lua;
+        let arg = arg.to_lua(lua)?;
+        let result = unsafe {
+            let _sg = StackGuard::new(lua.state);
+            let thread_state = self.resume_with(1, move || lua.push_value(arg))?;
+
+            if ffi::lua_gettop(thread_state) == 0 {
+                Nil
+            } else {
+                // The first value is at the bottom of the thread's stack.
+                assert_stack(lua.state, 2);
+                ffi::lua_settop(thread_state, 1);
+                ffi::lua_xmove(thread_state, lua.state, 1);
+                lua.pop_value()
+            }
+        };
+        R::from_lua(result, lua)
+    }
+
+    // Resumes the thread with the `nargs` arguments pushed by `push_args`, returning the thread's
+    // state with the yielded or returned values as its whole stack.  Must be called inside a
+    // `StackGuard`.
+    unsafe fn resume_with<F>(&self, nargs: c_int, push_args: F) -> Result<*mut ffi::lua_State>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let lua = self.0.lua;
+        assert_stack(lua.state, 3);
+
+        lua.push_ref(&self.0);
+        let thread_state = ffi::lua_tothread(lua.state, -1);
+
+        let status = ffi::lua_status(thread_state);
+        if status != ffi::LUA_YIELD && ffi::lua_gettop(thread_state) == 0 {
+            return Err(Error::CoroutineInactive);
+        }
+
+        ffi::lua_pop(lua.state, 1);
+
+        check_stack(lua.state, nargs)?;
+        check_stack(thread_state, nargs + 1)?;
+
+        push_args()?;
+        ffi::lua_xmove(lua.state, thread_state, nargs);
+
+        let ret = ffi::lua_resume(thread_state, lua.state, nargs);
+        if ret != ffi::LUA_OK && ret != ffi::LUA_YIELD {
+            protect_lua_closure(lua.state, 0, 0, |_| {
+                error_traceback(thread_state);
+                0
+            })?;
+            // The error value may need to be placed in the registry, which cannot be done
+            // from the errored thread.
+            ffi::lua_xmove(thread_state, lua.state, 1);
+            return Err(pop_error(lua.state, ret));
+        }
+        Ok(thread_state)
+    }
+
     /// Gets the status of the thread.
     pub fn status(&self) -> ThreadStatus {
         let lua = self.0.lua;
@@ -142,4 +220,191 @@ impl<'lua> Thread<'lua> {
             }
         }
     }
+
+    /// Returns true if this thread was created to run the function `f`.
+    ///
+    /// Threads created with [`Context::create_thread`] or [`Function::into_thread`] remember their
+    /// function for as long as they exist.  For threads created by `coroutine.create` in Lua, the
+    /// function is found on the thread's stack, so this returns false once they have finished.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Function, Lua, Result};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let task: Function = lua_context.load("function() coroutine.yield() end").eval()?;
+    /// let other: Function = lua_context.load("function() end").eval()?;
+    ///
+    /// let thread = task.clone().into_thread()?;
+    /// thread.resume::<_, ()>(())?;
+    /// thread.resume::<_, ()>(())?;
+    /// assert!(thread.body_matches(&task));
+    /// assert!(!thread.body_matches(&other));
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Context::create_thread`]: struct.Context.html#method.create_thread
+    /// [`Function::into_thread`]: struct.Function.html#method.into_thread
+    pub fn body_matches(&self, f: &Function<'lua>) -> bool {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 4);
+
+            lua.push_ref(&f.0);
+            lua.push_ref(&self.0);
+            let thread_state = ffi::lua_tothread(lua.state, -1);
+
+            ffi::lua_pushlightuserdata(
+                lua.state,
+                &THREAD_BODIES_REGISTRY_KEY as *const u8 as *mut c_void,
+            );
+            ffi::lua_rawget(lua.state, ffi::LUA_REGISTRYINDEX);
+            ffi::lua_insert(lua.state, -2);
+            ffi::lua_rawget(lua.state, -2);
+            if ffi::lua_type(lua.state, -1) == ffi::LUA_TFUNCTION {
+                return ffi::lua_rawequal(lua.state, -1, -3) != 0;
+            }
+
+            // The function of a thread that has started is the outermost function on its call
+            // stack, and that of a thread that has not is the only value on its stack.
+            if ffi::lua_checkstack(thread_state, 1) == 0 {
+                return false;
+            }
+            let mut ar: ffi::lua_Debug = mem::zeroed();
+            let mut level = 0;
+            while ffi::lua_getstack(thread_state, level, &mut ar) != 0 {
+                level += 1;
+            }
+            if level > 0 {
+                ffi::lua_getstack(thread_state, level - 1, &mut ar);
+                ffi::lua_getinfo(thread_state, cstr!("f"), &mut ar);
+            } else if ffi::lua_status(thread_state) == ffi::LUA_OK
+                && ffi::lua_gettop(thread_state) > 0
+            {
+                ffi::lua_pushvalue(thread_state, 1);
+            } else {
+                return false;
+            }
+            ffi::lua_xmove(thread_state, lua.state, 1);
+            ffi::lua_rawequal(lua.state, -1, -4) != 0
+        }
+    }
+
+    /// Sets a 'hook' function that will periodically be called as Lua code executes on this
+    /// thread only.
+    ///
+    /// This works like [`Lua::set_hook`], except that the hook is installed only on the Lua state
+    /// backing this coroutine.  A hook set on a thread takes priority over the global hook set with
+    /// [`Lua::set_hook`], and other coroutines are unaffected by it.  The `Context` passed to the
+    /// hook function belongs to the thread it fired on, so [`Context::current_thread`] may be used
+    /// to tell which thread that is.
+    ///
+    /// The thread will not be garbage collected while a hook is set on it, call [`remove_hook`] to
+    /// release it.
+    ///
+    /// # Example
+    ///
+    /// Limits the number of instructions a single coroutine may execute.
+    ///
+    /// ```
+    /// # use rlua::{Lua, HookTriggers, Error, Result, Thread};
+    /// # fn main() -> Result<()> {
+    /// # Lua::new().context(|lua_context| {
+    /// let thread: Thread = lua_context.load(r#"
+    ///     coroutine.create(function()
+    ///         while true do end
+    ///     end)
+    /// "#).eval()?;
+    ///
+    /// thread.set_hook(HookTriggers {
+    ///     every_nth_instruction: Some(10_000), ..Default::default()
+    /// }, |_lua_context, _debug| {
+    ///     Err(Error::RuntimeError("instruction budget exhausted".to_owned()))
+    /// })?;
+    ///
+    /// assert!(thread.resume::<_, ()>(()).is_err());
+    ///
+    /// // Lets the thread be garbage collected again.
+    /// thread.remove_hook();
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`Lua::set_hook`]: struct.Lua.html#method.set_hook
+    /// [`Context::current_thread`]: struct.Context.html#method.current_thread
+    /// [`remove_hook`]: #method.remove_hook
+    pub fn set_hook<F>(&self, triggers: HookTriggers, callback: F) -> Result<()>
+    where
+        F: 'static + Send + FnMut(Context, Debug) -> Result<()>,
+    {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 3);
+
+            lua.push_ref(&self.0);
+            let thread_state = ffi::lua_tothread(lua.state, -1);
+
+            let extra = extra_data(lua.state);
+            let callback: HookCallback = Rc::new(RefCell::new(callback));
+            if let Some(hook) = (*extra).thread_hooks.get_mut(&thread_state) {
+                hook.1 = callback;
+            } else {
+                let registry_id = protect_lua_closure(lua.state, 1, 0, |state| {
+                    ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+                })?;
+                (*extra)
+                    .thread_hooks
+                    .insert(thread_state, (registry_id, callback));
+            }
+
+            ffi::lua_sethook(
+                thread_state,
+                Some(hook_proc),
+                triggers.mask(),
+                triggers.count(),
+            );
+            Ok(())
+        }
+    }
+
+    /// Remove any hook previously set on this thread by `set_hook`.
+    ///
+    /// If a global hook is set with [`Lua::set_hook`], the thread will use the global hook again.
+    /// This function has no effect if a hook was not previously set on this thread.
+    ///
+    /// [`Lua::set_hook`]: struct.Lua.html#method.set_hook
+    pub fn remove_hook(&self) {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 1);
+
+            lua.push_ref(&self.0);
+            let thread_state = ffi::lua_tothread(lua.state, -1);
+            ffi::lua_pop(lua.state, 1);
+
+            let extra = extra_data(lua.state);
+            if let Some((registry_id, _)) = (*extra).thread_hooks.remove(&thread_state) {
+                ffi::luaL_unref(lua.state, ffi::LUA_REGISTRYINDEX, registry_id);
+
+                if (*extra).hook_callback.is_some() {
+                    let triggers = (*extra).hook_triggers;
+                    ffi::lua_sethook(
+                        thread_state,
+                        Some(hook_proc),
+                        triggers.mask(),
+                        triggers.count(),
+                    );
+                } else {
+                    ffi::lua_sethook(thread_state, None, 0, 0);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::string::String as StdString;
+
+use crate::context::Context;
+use crate::error::{Error, Result};
+use crate::ffi;
+use crate::table::Table;
+use crate::types::LuaRef;
+use crate::userdata::{AnyUserData, UserData};
+use crate::util::{assert_stack, StackGuard};
+use crate::value::Value;
+
+// The deepest nesting of tables that is transferred, so that deeply nested data cannot overflow the
+// Rust stack.
+const MAX_TRANSFER_DEPTH: usize = 200;
+
+type UserDataCloner =
+    for<'a, 'b> fn(&AnyUserData<'a>, Context<'b>) -> Option<Result<AnyUserData<'b>>>;
+
+/// Controls how values are copied between Lua states by [`Context::transfer_with`].
+///
+/// By default only plain data is transferred: nil, booleans, numbers, strings, light userdata,
+/// errors and tables containing only those, and metatables are dropped.
+///
+/// [`Context::transfer_with`]: struct.Context.html#method.transfer_with
+#[derive(Clone, Default)]
+pub struct TransferOptions {
+    metatables: bool,
+    userdata: Vec<UserDataCloner>,
+}
+
+impl TransferOptions {
+    /// Creates the default set of options, equivalent to calling [`Context::transfer`].
+    ///
+    /// [`Context::transfer`]: struct.Context.html#method.transfer
+    pub fn new() -> TransferOptions {
+        TransferOptions::default()
+    }
+
+    /// Sets whether table metatables are transferred along with the tables.
+    ///
+    /// Metatables must themselves be plain data to be transferred, a metatable containing
+    /// functions will cause the transfer to fail.
+    pub fn transfer_metatables(mut self, enabled: bool) -> TransferOptions {
+        self.metatables = enabled;
+        self
+    }
+
+    /// Allows userdata of type `T` to be transferred, by cloning the Rust value and creating a new
+    /// userdata with it in the target state.
+    pub fn allow_userdata<T>(mut self) -> TransferOptions
+    where
+        T: 'static + Send + Clone + UserData,
+    {
+        self.userdata.push(clone_userdata::<T>);
+        self
+    }
+}
+
+pub(crate) struct Transfer<'b, 'o> {
+    target: Context<'b>,
+    options: &'o TransferOptions,
+    // Tables already copied to the target, keyed by the address of the source table.
+    tables: HashMap<*const c_void, Table<'b>>,
+    // The number of tables currently being copied.
+    depth: usize,
+}
+
+impl<'b, 'o> Transfer<'b, 'o> {
+    pub(crate) fn new(target: Context<'b>, options: &'o TransferOptions) -> Transfer<'b, 'o> {
+        Transfer {
+            target,
+            options,
+            tables: HashMap::new(),
+            depth: 0,
+        }
+    }
+
+    pub(crate) fn value<'a>(&mut self, value: Value<'a>, path: &str) -> Result<Value<'b>> {
+        Ok(match value {
+            Value::Nil => Value::Nil,
+            Value::Boolean(b) => Value::Boolean(b),
+            Value::LightUserData(ud) => Value::LightUserData(ud),
+            Value::Integer(i) => Value::Integer(i),
+            Value::Number(n) => Value::Number(n),
+            Value::String(s) => Value::String(self.target.create_string(s.as_bytes())?),
+            Value::Table(t) => Value::Table(self.table(t, path)?),
+            Value::UserData(ud) => {
+                for cloner in &self.options.userdata {
+                    if let Some(ud) = cloner(&ud, self.target) {
+                        return Ok(Value::UserData(ud?));
+                    }
+                }
+                return Err(not_transferable("userdata", path));
+            }
+            Value::Error(e) => Value::Error(e),
+            v @ Value::Function(_) | v @ Value::Thread(_) => {
+                return Err(not_transferable(v.type_name(), path));
+            }
+        })
+    }
+
+    fn table<'a>(&mut self, table: Table<'a>, path: &str) -> Result<Table<'b>> {
+        let ptr = unsafe { ref_pointer(&table.0) };
+        if let Some(copy) = self.tables.get(&ptr) {
+            return Ok(copy.clone());
+        }
+
+        if self.depth == MAX_TRANSFER_DEPTH {
+            return Err(not_transferable("table", path));
+        }
+        self.depth += 1;
+        let copy = self.copy_table(ptr, table, path);
+        self.depth -= 1;
+        copy
+    }
+
+    fn copy_table<'a>(
+        &mut self,
+        ptr: *const c_void,
+        table: Table<'a>,
+        path: &str,
+    ) -> Result<Table<'b>> {
+        let copy = self.target.create_table()?;
+        self.tables.insert(ptr, copy.clone());
+
+        if self.options.metatables {
+            if let Some(metatable) = table.get_metatable() {
+                let metatable = self.table(metatable, &format!("{}(metatable)", path))?;
+                copy.set_metatable(Some(metatable));
+            }
+        }
+
+        for pair in table.pairs::<Value, Value>() {
+            let (key, value) = pair?;
+            let value_path = key_path(path, &key);
+            let key = self.value(key, &format!("{}(key)", value_path))?;
+            let value = self.value(value, &value_path)?;
+            copy.raw_set(key, value)?;
+        }
+
+        Ok(copy)
+    }
+}
+
+fn clone_userdata<'a, 'b, T>(
+    ud: &AnyUserData<'a>,
+    target: Context<'b>,
+) -> Option<Result<AnyUserData<'b>>>
+where
+    T: 'static + Send + Clone + UserData,
+{
+    match ud.borrow::<T>() {
+        Ok(data) => Some(target.create_userdata(data.clone())),
+        Err(Error::UserDataTypeMismatch) | Err(Error::ForeignUserData) => None,
+        Err(err) => Some(Err(err)),
+    }
+}
+
+fn not_transferable(type_name: &'static str, path: &str) -> Error {
+    Error::NotTransferable {
+        type_name,
+        path: path.to_owned(),
+    }
+}
+
+pub(crate) fn key_path(path: &str, key: &Value) -> StdString {
+    match key {
+        Value::String(s) => match s.to_str() {
+            Ok(s) if path.is_empty() => s.to_owned(),
+            Ok(s) => format!("{}.{}", path, s),
+            Err(_) => format!("{}[<string>]", path),
+        },
+        Value::Integer(i) => format!("{}[{}]", path, i),
+        Value::Number(n) => format!("{}[{}]", path, n),
+        Value::Boolean(b) => format!("{}[{}]", path, b),
+        key => format!("{}[<{}>]", path, key.type_name()),
+    }
+}
+
+pub(crate) unsafe fn ref_pointer(lref: &LuaRef) -> *const c_void {
+    let lua = lref.lua;
+    let _sg = StackGuard::new(lua.state);
+    assert_stack(lua.state, 1);
+    lua.push_ref(lref);
+    ffi::lua_topointer(lua.state, -1)
+}
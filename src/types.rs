@@ -1,10 +1,13 @@
+use std::cell::RefCell;
 use std::os::raw::{c_int, c_void};
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use std::{fmt, mem, ptr};
 
 use crate::context::Context;
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::ffi;
+use crate::hook::{Debug, StackFrame};
 use crate::value::MultiValue;
 
 /// Type of Lua integer numbers.
@@ -16,9 +19,46 @@ pub type Number = ffi::lua_Number;
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct LightUserData(pub *mut c_void);
 
+/// The Lua types whose values all share a single metatable, used with
+/// [`Context::metatable_of`] and [`Context::set_metatable_of`].
+///
+/// Tables and full userdata have a metatable per value instead.  Integers and floats share the
+/// metatable of `Number`.
+///
+/// [`Context::metatable_of`]: struct.Context.html#method.metatable_of
+/// [`Context::set_metatable_of`]: struct.Context.html#method.set_metatable_of
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum TypeCategory {
+    /// The `nil` value.
+    Nil,
+    /// `true` and `false`.
+    Boolean,
+    /// Light userdata, which are raw pointers.
+    LightUserData,
+    /// Integers and floats.
+    Number,
+    /// Strings.  The standard `string` library sets this metatable so that string methods can be
+    /// called with `s:method()`.
+    String,
+    /// Lua functions and Rust callbacks.
+    Function,
+    /// Threads, also known as coroutines.
+    Thread,
+}
+
 pub(crate) type Callback<'lua, 'a> =
     Box<dyn Fn(Context<'lua>, MultiValue<'lua>) -> Result<MultiValue<'lua>> + 'a>;
 
+pub(crate) type HookCallback = Rc<RefCell<dyn FnMut(Context, Debug) -> Result<()>>>;
+
+pub(crate) type FinalizerErrorHandler = Rc<dyn Fn(Error)>;
+
+pub(crate) type ErrorHook = Rc<dyn Fn(Error) -> Error>;
+
+pub(crate) type DeprecationHandler = Rc<RefCell<dyn FnMut(&str, &str, Option<StackFrame>)>>;
+
+pub(crate) type SandboxPrintHandler = Arc<dyn Fn(&str) + Send + Sync>;
+
 /// An auto generated key into the Lua registry.
 ///
 /// This is a handle to a value stored inside the Lua registry.  Unlike the `Table` or `Function`
@@ -30,6 +70,9 @@ pub(crate) type Callback<'lua, 'a> =
 /// [`Context::remove_registry_value`], and instances not manually removed can be garbage collected
 /// with [`Context::expire_registry_values`].
 ///
+/// Cloning a `RegistryKey` creates another handle to the same registry value, which remains in the
+/// registry until every clone has been dropped or removed.
+///
 /// Be warned, If you place this into Lua via a `UserData` type or a rust callback and rely on
 /// [`Context::expire_registry_values`], it is *very easy* to accidentally cause reference cycles
 /// that cannot be automatically collected.  The Lua garbage collector is not aware of the registry
@@ -46,18 +89,25 @@ pub(crate) type Callback<'lua, 'a> =
 /// [`Function::bind`]: struct.Function.html#method.bind
 /// [`UserData::set_user_value`]: struct.UserData.html#method.set_user_value
 /// [`UserData::get_user_value`]: struct.UserData.html#method.get_user_value
+#[derive(Clone)]
 pub struct RegistryKey {
-    pub(crate) registry_id: c_int,
-    pub(crate) unref_list: Arc<Mutex<Option<Vec<c_int>>>>,
+    slot: Arc<RegistrySlot>,
+}
+
+// The registry slot shared by a `RegistryKey` and its clones, which is added to the unref list
+// once all of them are dropped.
+struct RegistrySlot {
+    registry_id: c_int,
+    unref_list: Arc<Mutex<Option<Vec<c_int>>>>,
 }
 
 impl fmt::Debug for RegistryKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "RegistryKey({})", self.registry_id)
+        write!(f, "RegistryKey({})", self.slot.registry_id)
     }
 }
 
-impl Drop for RegistryKey {
+impl Drop for RegistrySlot {
     fn drop(&mut self) {
         if let Some(list) = rlua_expect!(self.unref_list.lock(), "unref_list poisoned").as_mut() {
             list.push(self.registry_id);
@@ -66,14 +116,77 @@ impl Drop for RegistryKey {
 }
 
 impl RegistryKey {
-    // Destroys the RegistryKey without adding to the drop list
-    pub(crate) fn take(self) -> c_int {
-        let registry_id = self.registry_id;
+    /// Returns true if `other` is this key or a clone of it.
+    pub fn is_same_slot(&self, other: &RegistryKey) -> bool {
+        Arc::ptr_eq(&self.slot, &other.slot)
+    }
+
+    pub(crate) fn new(registry_id: c_int, unref_list: Arc<Mutex<Option<Vec<c_int>>>>) -> Self {
+        RegistryKey {
+            slot: Arc::new(RegistrySlot {
+                registry_id,
+                unref_list,
+            }),
+        }
+    }
+
+    pub(crate) fn registry_id(&self) -> c_int {
+        self.slot.registry_id
+    }
+
+    pub(crate) fn unref_list(&self) -> &Arc<Mutex<Option<Vec<c_int>>>> {
+        &self.slot.unref_list
+    }
+
+    // Destroys the RegistryKey without adding to the drop list, if there are no other clones of it
+    pub(crate) fn take(self) -> Option<c_int> {
+        let slot = Arc::try_unwrap(self.slot).ok()?;
+        let registry_id = slot.registry_id;
         unsafe {
-            ptr::read(&self.unref_list);
-            mem::forget(self);
+            ptr::read(&slot.unref_list);
+            mem::forget(slot);
         }
-        registry_id
+        Some(registry_id)
+    }
+}
+
+/// A string key interned in a Lua state, created with [`Context::intern_key`].
+///
+/// A `LuaKey` refers to a Lua string kept in the registry, so looking it up in a table with
+/// methods such as [`Table::get_k`] does not need to hash or copy the bytes of a Rust string.  The
+/// same key can be used with any table of the state it was created in, and using it with a table
+/// of an unrelated state returns `Error::MismatchedRegistryKey`.  Like [`RegistryKey`], it is
+/// `Send + Sync + 'static`, and cloning it is cheap.
+///
+/// Interned keys are never removed from the registry, so this is meant for a fixed set of field
+/// names that are looked up over and over.
+///
+/// [`Context::intern_key`]: struct.Context.html#method.intern_key
+/// [`Table::get_k`]: struct.Table.html#method.get_k
+/// [`RegistryKey`]: struct.RegistryKey.html
+#[derive(Clone)]
+pub struct LuaKey {
+    registry_id: c_int,
+    owner: Arc<Mutex<Option<Vec<c_int>>>>,
+}
+
+impl fmt::Debug for LuaKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LuaKey({})", self.registry_id)
+    }
+}
+
+impl LuaKey {
+    pub(crate) fn new(registry_id: c_int, owner: Arc<Mutex<Option<Vec<c_int>>>>) -> Self {
+        LuaKey { registry_id, owner }
+    }
+
+    pub(crate) fn registry_id(&self) -> c_int {
+        self.registry_id
+    }
+
+    pub(crate) fn owner(&self) -> &Arc<Mutex<Option<Vec<c_int>>>> {
+        &self.owner
     }
 }
 
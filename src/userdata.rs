@@ -1,11 +1,19 @@
+use std::any::TypeId;
 use std::cell::{Ref, RefCell, RefMut};
+use std::fmt;
 
 use crate::context::Context;
 use crate::error::{Error, Result};
 use crate::ffi;
+use crate::function::Function;
+use crate::lua::extra_data;
+use crate::table::Table;
 use crate::types::LuaRef;
-use crate::util::{assert_stack, get_userdata, StackGuard};
-use crate::value::{FromLua, FromLuaMulti, ToLua, ToLuaMulti};
+use crate::util::{
+    assert_stack, get_destructed_userdata_metatable, get_userdata, is_rlua_userdata_metatable,
+    StackGuard,
+};
+use crate::value::{FromLua, FromLuaMulti, Nil, ToLua, ToLuaMulti, Value};
 
 /// Kinds of metamethods that can be overridden.
 ///
@@ -100,6 +108,31 @@ impl MetaMethod {
     }
 }
 
+// Returns an error if a type used `add_tostring_from_display` or `add_tostring_from_debug` and
+// its metamethods, listed in `metas`, contain more than one `__tostring`.  Called when the
+// metatable of the type is built, so that no userdata is created with either `__tostring`.
+pub(crate) fn check_tostring_helper<I>(
+    type_name: &'static str,
+    used_helper: bool,
+    metas: I,
+) -> Result<()>
+where
+    I: IntoIterator<Item = MetaMethod>,
+{
+    let count = metas
+        .into_iter()
+        .filter(|&meta| meta == MetaMethod::ToString)
+        .count();
+    if used_helper && count > 1 {
+        Err(Error::DuplicateMetaMethod {
+            type_name,
+            meta: MetaMethod::ToString,
+        })
+    } else {
+        Ok(())
+    }
+}
+
 /// Method registry for [`UserData`] implementors.
 ///
 /// [`UserData`]: trait.UserData.html
@@ -118,6 +151,135 @@ pub trait UserDataMethods<'lua, T: UserData> {
         R: ToLuaMulti<'lua>,
         M: 'static + Send + Fn(Context<'lua>, &T, A) -> Result<R>;
 
+    /// Add a method like [`add_method`], with a documentation string which is reported by
+    /// [`Lua::userdata_descriptor`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, UserData, UserDataMethods};
+    /// # fn main() -> Result<()> {
+    /// struct Counter(u32);
+    ///
+    /// impl UserData for Counter {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_method_with_doc("get", "Returns the current count.", |_, c, ()| Ok(c.0));
+    ///     }
+    /// }
+    ///
+    /// let descriptor = Lua::new().userdata_descriptor::<Counter>();
+    /// assert_eq!(descriptor.methods[0].name, "get");
+    /// assert_eq!(descriptor.methods[0].doc.as_deref(), Some("Returns the current count."));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`add_method`]: #method.add_method
+    /// [`Lua::userdata_descriptor`]: struct.Lua.html#method.userdata_descriptor
+    fn add_method_with_doc<S, A, R, M>(&mut self, name: &S, doc: &str, method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        M: 'static + Send + Fn(Context<'lua>, &T, A) -> Result<R>,
+    {
+        let _ = doc;
+        self.add_method(name, method)
+    }
+
+    /// Add a regular method which accepts a `&T` as the first parameter, and is called without
+    /// boxing `method`.
+    ///
+    /// `method` must be `Copy`, such as a `fn` item or a closure which captures nothing.  It is
+    /// stored in the Lua function created for it and called directly, rather than through a boxed
+    /// closure, which makes small methods which are called very often somewhat cheaper.  Otherwise
+    /// it behaves exactly like a method added with [`add_method`], including how arguments and
+    /// results are converted and which errors are raised.  With scoped non-'static userdata, this
+    /// is the same as [`add_method`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, UserData, UserDataMethods};
+    /// # fn main() -> Result<()> {
+    /// #[derive(Clone, Copy)]
+    /// struct Vec3(f64, f64, f64);
+    ///
+    /// impl UserData for Vec3 {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_method_fn("dot", |_, a, b: Vec3| Ok(a.0 * b.0 + a.1 * b.1 + a.2 * b.2));
+    ///         methods.add_method_fn("scaled", |_, a, (x, y, z): (f64, f64, f64)| {
+    ///             Ok(Vec3(a.0 * x, a.1 * y, a.2 * z))
+    ///         });
+    ///     }
+    /// }
+    ///
+    /// # Lua::new().context(|lua_context| {
+    /// lua_context.globals().set("a", Vec3(1.0, 2.0, 3.0))?;
+    /// lua_context.globals().set("b", Vec3(4.0, 5.0, 6.0))?;
+    /// assert_eq!(lua_context.load("a:dot(b)").eval::<f64>()?, 32.0);
+    /// assert_eq!(lua_context.load("a:scaled(2, 1, 0):dot(b)").eval::<f64>()?, 18.0);
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`add_method`]: #method.add_method
+    fn add_method_fn<S, A, R, M>(&mut self, name: &S, method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLuaMulti<'lua>,
+        R: ToLuaMulti<'lua>,
+        M: 'static + Send + Copy + Fn(Context<'lua>, &T, A) -> Result<R>,
+    {
+        self.add_method(name, method)
+    }
+
+    /// Add a method which returns bytes or a string borrowed from the `&T` receiver.
+    ///
+    /// The returned data is copied directly into a new Lua string, which avoids copying it into an
+    /// owned `Vec<u8>` or `String` first, as returning it from a method added with [`add_method`]
+    /// would require.  The returned reference cannot outlive the borrow of the receiver, which
+    /// ends when the method returns.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use rlua::{Lua, Result, UserData, UserDataMethods};
+    /// # fn main() -> Result<()> {
+    /// struct Blob(Vec<u8>);
+    ///
+    /// impl UserData for Blob {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_method_borrowed("bytes", |_, blob, ()| Ok(&blob.0[..]));
+    ///         methods.add_method_borrowed("prefix", |_, blob, n: usize| {
+    ///             Ok(&blob.0[..n.min(blob.0.len())])
+    ///         });
+    ///     }
+    /// }
+    ///
+    /// # Lua::new().context(|lua_context| {
+    /// lua_context.globals().set("blob", Blob(b"hello".to_vec()))?;
+    /// assert_eq!(lua_context.load("blob:bytes()").eval::<String>()?, "hello");
+    /// assert_eq!(lua_context.load("blob:prefix(2)").eval::<String>()?, "he");
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`add_method`]: #method.add_method
+    fn add_method_borrowed<S, A, R, M>(&mut self, name: &S, method: M)
+    where
+        S: ?Sized + AsRef<[u8]>,
+        A: FromLuaMulti<'lua>,
+        R: ?Sized + AsRef<[u8]>,
+        M: 'static + Send + for<'a> Fn(Context<'lua>, &'a T, A) -> Result<&'a R>,
+    {
+        self.add_method(name, move |lua, data, args| {
+            lua.create_string(method(lua, data, args)?)
+        })
+    }
+
     /// Add a regular method which accepts a `&mut T` as the first parameter.
     ///
     /// Refer to [`add_method`] for more information about the implementation.
@@ -190,7 +352,12 @@ pub trait UserDataMethods<'lua, T: UserData> {
     ///
     /// Metamethods for binary operators can be triggered if either the left or right argument to
     /// the binary operator has a metatable, so the first argument here is not necessarily a
-    /// userdata of type `T`.
+    /// userdata of type `T`.  Taking both operands as `Value`s (or `AnyUserData`) lets the function
+    /// handle either ordering, such as both `3 + v` and `v + 3`.  Any metamethod may be added this
+    /// way, including `__call`, `__index` and `__newindex`; [`add_meta_method`] remains the
+    /// convenient choice when the userdata is always the first argument.
+    ///
+    /// [`add_meta_method`]: #method.add_meta_method
     fn add_meta_function<A, R, F>(&mut self, meta: MetaMethod, function: F)
     where
         A: FromLuaMulti<'lua>,
@@ -207,6 +374,67 @@ pub trait UserDataMethods<'lua, T: UserData> {
         A: FromLuaMulti<'lua>,
         R: ToLuaMulti<'lua>,
         F: 'static + Send + FnMut(Context<'lua>, A) -> Result<R>;
+
+    /// Add a `__tostring` metamethod which formats the value with its `Display` implementation.
+    ///
+    /// Adding another `__tostring` metamethod for the same type, whether with
+    /// [`add_meta_method`] or [`add_tostring_from_debug`], is an error: neither metamethod is
+    /// used, and creating a userdata of the type fails with [`Error::DuplicateMetaMethod`].  As
+    /// `add_methods` cannot return errors, the error is only reported at that point, not when the
+    /// methods are added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::fmt;
+    /// # use rlua::{Lua, Result, UserData, UserDataMethods};
+    /// # fn main() -> Result<()> {
+    /// struct Point(i32, i32);
+    ///
+    /// impl fmt::Display for Point {
+    ///     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    ///         write!(f, "({}, {})", self.0, self.1)
+    ///     }
+    /// }
+    ///
+    /// impl UserData for Point {
+    ///     fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+    ///         methods.add_tostring_from_display();
+    ///     }
+    /// }
+    ///
+    /// # Lua::new().context(|lua_context| {
+    /// lua_context.globals().set("p", Point(1, 2))?;
+    /// assert_eq!(lua_context.load("tostring(p)").eval::<String>()?, "(1, 2)");
+    /// # Ok(())
+    /// # })
+    /// # }
+    /// ```
+    ///
+    /// [`add_meta_method`]: #method.add_meta_method
+    /// [`add_tostring_from_debug`]: #method.add_tostring_from_debug
+    /// [`Error::DuplicateMetaMethod`]: enum.Error.html#variant.DuplicateMetaMethod
+    fn add_tostring_from_display(&mut self)
+    where
+        T: fmt::Display,
+    {
+        self.add_meta_method(MetaMethod::ToString, |_, data, ()| Ok(data.to_string()));
+    }
+
+    /// Add a `__tostring` metamethod which formats the value with its `Debug` implementation.
+    ///
+    /// Like [`add_tostring_from_display`], adding another `__tostring` metamethod for the same
+    /// type is an error.
+    ///
+    /// [`add_tostring_from_display`]: #method.add_tostring_from_display
+    fn add_tostring_from_debug(&mut self)
+    where
+        T: fmt::Debug,
+    {
+        self.add_meta_method(MetaMethod::ToString, |_, data, ()| {
+            Ok(format!("{:?}", data))
+        });
+    }
 }
 
 /// Trait for custom userdata types.
@@ -280,6 +508,36 @@ pub trait UserData: Sized {
     fn add_methods<'lua, T: UserDataMethods<'lua, Self>>(_methods: &mut T) {}
 }
 
+/// The methods and metamethods of a [`UserData`] type, returned by [`Lua::userdata_descriptor`]
+/// and [`Lua::all_userdata_descriptors`].
+///
+/// [`UserData`]: trait.UserData.html
+/// [`Lua::userdata_descriptor`]: struct.Lua.html#method.userdata_descriptor
+/// [`Lua::all_userdata_descriptors`]: struct.Lua.html#method.all_userdata_descriptors
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TypeDescriptor {
+    /// The name of the Rust type, as given by `std::any::type_name`.
+    pub name: &'static str,
+    /// The regular methods and functions, in the order they were added.
+    pub methods: Vec<MethodDescriptor>,
+    /// The metamethods, in the order they were added.
+    pub metamethods: Vec<MetaMethod>,
+}
+
+/// A regular method or function of a [`UserData`] type, described by a [`TypeDescriptor`].
+///
+/// [`UserData`]: trait.UserData.html
+/// [`TypeDescriptor`]: struct.TypeDescriptor.html
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MethodDescriptor {
+    /// The name the method is called by from Lua.
+    pub name: String,
+    /// The documentation string given to [`UserDataMethods::add_method_with_doc`], if any.
+    ///
+    /// [`UserDataMethods::add_method_with_doc`]: trait.UserDataMethods.html#method.add_method_with_doc
+    pub doc: Option<String>,
+}
+
 /// Handle to an internal Lua userdata for any type that implements [`UserData`].
 ///
 /// Similar to `std::any::Any`, this provides an interface for dynamic type checking via the [`is`]
@@ -301,11 +559,33 @@ pub struct AnyUserData<'lua>(pub(crate) LuaRef<'lua>);
 
 impl<'lua> AnyUserData<'lua> {
     /// Checks whether the type of this userdata is `T`.
+    ///
+    /// This does not borrow the userdata, so it can be called even while the userdata is mutably
+    /// borrowed.
     pub fn is<T: 'static + UserData>(&self) -> bool {
-        match self.inspect(|_: &RefCell<T>| Ok(())) {
-            Ok(()) => true,
-            Err(Error::UserDataTypeMismatch) => false,
-            Err(_) => unreachable!(),
+        self.type_id() == Some(TypeId::of::<T>())
+    }
+
+    /// Returns the `TypeId` of the Rust type held by this userdata.
+    ///
+    /// Returns `None` for userdata which were not created by `rlua` from a `'static` type, such as
+    /// userdata created by C libraries or non-`'static` userdata created through `Scope`, and for
+    /// destructed userdata.
+    pub fn type_id(&self) -> Option<TypeId> {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 2);
+
+            lua.push_ref(&self.0);
+            if ffi::lua_getmetatable(lua.state, -1) == 0 {
+                return None;
+            }
+            let ptr = ffi::lua_topointer(lua.state, -1);
+            (*extra_data(lua.state))
+                .registered_userdata_types
+                .get(&ptr)
+                .cloned()
         }
     }
 
@@ -314,9 +594,10 @@ impl<'lua> AnyUserData<'lua> {
     /// # Errors
     ///
     /// Returns a `UserDataBorrowError` if the userdata is already mutably borrowed. Returns a
-    /// `UserDataTypeMismatch` if the userdata is not of type `T`.
+    /// `UserDataTypeMismatch` if the userdata is not of type `T`, or a `ForeignUserData` error if
+    /// it was not created by `rlua` at all.
     pub fn borrow<T: 'static + UserData>(&self) -> Result<Ref<T>> {
-        self.inspect(|cell| Ok(cell.try_borrow().map_err(|_| Error::UserDataBorrowError)?))
+        self.inspect(|cell| cell.try_borrow())
     }
 
     /// Borrow this userdata mutably if it is of type `T`.
@@ -324,13 +605,10 @@ impl<'lua> AnyUserData<'lua> {
     /// # Errors
     ///
     /// Returns a `UserDataBorrowMutError` if the userdata is already borrowed. Returns a
-    /// `UserDataTypeMismatch` if the userdata is not of type `T`.
+    /// `UserDataTypeMismatch` if the userdata is not of type `T`, or a `ForeignUserData` error if
+    /// it was not created by `rlua` at all.
     pub fn borrow_mut<T: 'static + UserData>(&self) -> Result<RefMut<T>> {
-        self.inspect(|cell| {
-            Ok(cell
-                .try_borrow_mut()
-                .map_err(|_| Error::UserDataBorrowMutError)?)
-        })
+        self.inspect(|cell| cell.try_borrow_mut())
     }
 
     /// Sets an associated value to this `AnyUserData`.
@@ -366,33 +644,263 @@ impl<'lua> AnyUserData<'lua> {
         V::from_lua(res, lua)
     }
 
+    /// Returns a restricted handle to the metatable of this userdata.
+    ///
+    /// The metatable of a userdata created by [`Context::create_userdata`] is shared by all
+    /// userdata of the same type created that way, so changes made through the returned handle
+    /// affect every such userdata.  Userdata of the same type created by
+    /// [`Context::create_userdata_boxed`] share a separate metatable, which is not affected, and
+    /// each userdata created through a [`Scope`] has a metatable of its own.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CallbackDestructed` error if the userdata has been destructed, and a
+    /// `ForeignUserData` error if it was not created by `rlua`.
+    ///
+    /// [`Context::create_userdata`]: struct.Context.html#method.create_userdata
+    /// [`Context::create_userdata_boxed`]: struct.Context.html#method.create_userdata_boxed
+    /// [`Scope`]: struct.Scope.html
+    pub fn get_metatable(&self) -> Result<UserDataMetatable<'lua>> {
+        let lua = self.0.lua;
+        unsafe {
+            let _sg = StackGuard::new(lua.state);
+            assert_stack(lua.state, 3);
+
+            lua.push_ref(&self.0);
+            if ffi::lua_getmetatable(lua.state, -1) == 0 {
+                return Err(Error::ForeignUserData);
+            }
+
+            get_destructed_userdata_metatable(lua.state);
+            if ffi::lua_rawequal(lua.state, -1, -2) != 0 {
+                return Err(Error::CallbackDestructed);
+            }
+            ffi::lua_pop(lua.state, 1);
+            if !is_rlua_userdata_metatable(lua.state, -1) {
+                return Err(Error::ForeignUserData);
+            }
+
+            Ok(UserDataMetatable(Table(lua.pop_ref())))
+        }
+    }
+
+    /// Adds or replaces a metamethod in the metatable of this userdata.
+    ///
+    /// This is a shortcut for setting the metamethod through [`get_metatable`], so it affects
+    /// every userdata sharing the metatable.  Only the metamethods listed in [`MetaMethod`] can be
+    /// set, so `__gc` and the other entries `rlua` relies on are never replaced.  Replacing
+    /// `MetaMethod::Index` hides the regular methods of the userdata type.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CallbackDestructed` error if the userdata has been destructed, and a
+    /// `ForeignUserData` error if it was not created by `rlua`.
+    ///
+    /// [`get_metatable`]: #method.get_metatable
+    /// [`MetaMethod`]: enum.MetaMethod.html
+    pub fn set_metamethod(&self, meta: MetaMethod, function: Function<'lua>) -> Result<()> {
+        self.get_metatable()?.set(meta, function)
+    }
+
     fn inspect<'a, T, R, F>(&'a self, func: F) -> Result<R>
     where
         T: 'static + UserData,
-        F: FnOnce(&'a RefCell<T>) -> Result<R>,
+        F: FnOnce(UserDataCell<'a, T>) -> Result<R>,
     {
         unsafe {
             let lua = self.0.lua;
             let _sg = StackGuard::new(lua.state);
-            assert_stack(lua.state, 3);
+            assert_stack(lua.state, 4);
 
             lua.push_ref(&self.0);
 
             if ffi::lua_getmetatable(lua.state, -1) == 0 {
+                return Err(Error::ForeignUserData);
+            }
+            ffi::lua_rawgeti(
+                lua.state,
+                ffi::LUA_REGISTRYINDEX,
+                lua.userdata_metatable::<T>()? as ffi::lua_Integer,
+            );
+            if ffi::lua_rawequal(lua.state, -1, -2) != 0 {
+                return func(UserDataCell::Inline(&*get_userdata::<RefCell<T>>(
+                    lua.state, -3,
+                )));
+            }
+            ffi::lua_pop(lua.state, 1);
+
+            // Userdata created by `create_userdata_boxed` have a separate metatable, which only
+            // exists once one has been created.
+            let boxed = (*extra_data(lua.state))
+                .registered_boxed_userdata
+                .get(&TypeId::of::<T>())
+                .cloned();
+            if let Some(boxed) = boxed {
+                ffi::lua_rawgeti(lua.state, ffi::LUA_REGISTRYINDEX, boxed as ffi::lua_Integer);
+                if ffi::lua_rawequal(lua.state, -1, -2) != 0 {
+                    return func(UserDataCell::Boxed(&*get_userdata::<RefCell<Box<T>>>(
+                        lua.state, -3,
+                    )));
+                }
+                ffi::lua_pop(lua.state, 1);
+            }
+            if is_rlua_userdata_metatable(lua.state, -1) {
                 Err(Error::UserDataTypeMismatch)
             } else {
-                ffi::lua_rawgeti(
-                    lua.state,
-                    ffi::LUA_REGISTRYINDEX,
-                    lua.userdata_metatable::<T>()? as ffi::lua_Integer,
-                );
-
-                if ffi::lua_rawequal(lua.state, -1, -2) == 0 {
-                    Err(Error::UserDataTypeMismatch)
-                } else {
-                    func(&*get_userdata::<RefCell<T>>(lua.state, -3))
-                }
+                Err(Error::ForeignUserData)
+            }
+        }
+    }
+}
+
+// The storage of a userdata holding a `T`, either inline in the Lua allocation, or behind a `Box`
+// for userdata created by `Context::create_userdata_boxed`.
+enum UserDataCell<'a, T> {
+    Inline(&'a RefCell<T>),
+    Boxed(&'a RefCell<Box<T>>),
+}
+
+impl<'a, T> UserDataCell<'a, T> {
+    fn try_borrow(self) -> Result<Ref<'a, T>> {
+        match self {
+            UserDataCell::Inline(cell) => cell.try_borrow(),
+            UserDataCell::Boxed(cell) => cell.try_borrow().map(|b| Ref::map(b, |b| &**b)),
+        }
+        .map_err(|_| Error::UserDataBorrowError)
+    }
+
+    fn try_borrow_mut(self) -> Result<RefMut<'a, T>> {
+        match self {
+            UserDataCell::Inline(cell) => cell.try_borrow_mut(),
+            UserDataCell::Boxed(cell) => {
+                cell.try_borrow_mut().map(|b| RefMut::map(b, |b| &mut **b))
             }
         }
+        .map_err(|_| Error::UserDataBorrowMutError)
+    }
+}
+
+/// Handle to a Lua userdata of the known type `T`, returned by [`Context::create_typed_userdata`].
+///
+/// Borrowing through this handle does not check the type of the userdata, unlike
+/// [`AnyUserData::borrow`], as it is known when the handle is created.
+///
+/// [`Context::create_typed_userdata`]: struct.Context.html#method.create_typed_userdata
+/// [`AnyUserData::borrow`]: struct.AnyUserData.html#method.borrow
+pub struct UserDataHandle<'lua, T> {
+    userdata: AnyUserData<'lua>,
+    // Userdata never move, and this one is kept alive by `userdata`.  It cannot be destructed
+    // early either, as it is not owned by a `Scope` and its `__gc` is hidden from Lua.
+    cell: *const RefCell<T>,
+}
+
+impl<'lua, T: 'static + UserData> UserDataHandle<'lua, T> {
+    // Safe as long as `userdata` was created by `Context::make_userdata` with a `T`.
+    pub(crate) unsafe fn new(userdata: AnyUserData<'lua>) -> UserDataHandle<'lua, T> {
+        let lua = userdata.0.lua;
+        let _sg = StackGuard::new(lua.state);
+        assert_stack(lua.state, 1);
+        lua.push_ref(&userdata.0);
+        let cell = get_userdata::<RefCell<T>>(lua.state, -1) as *const RefCell<T>;
+        UserDataHandle { userdata, cell }
+    }
+
+    /// Borrow this userdata immutably.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `UserDataBorrowError` if the userdata is already mutably borrowed.
+    pub fn borrow(&self) -> Result<Ref<'_, T>> {
+        unsafe { &*self.cell }
+            .try_borrow()
+            .map_err(|_| Error::UserDataBorrowError)
+    }
+
+    /// Borrow this userdata mutably.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `UserDataBorrowMutError` if the userdata is already borrowed.
+    pub fn borrow_mut(&self) -> Result<RefMut<'_, T>> {
+        unsafe { &*self.cell }
+            .try_borrow_mut()
+            .map_err(|_| Error::UserDataBorrowMutError)
+    }
+
+    /// Returns the untyped handle to this userdata.
+    pub fn as_any(&self) -> &AnyUserData<'lua> {
+        &self.userdata
+    }
+
+    /// Converts this handle into an untyped `AnyUserData`.
+    pub fn into_any(self) -> AnyUserData<'lua> {
+        self.userdata
+    }
+}
+
+impl<'lua, T> Clone for UserDataHandle<'lua, T> {
+    fn clone(&self) -> Self {
+        UserDataHandle {
+            userdata: self.userdata.clone(),
+            cell: self.cell,
+        }
+    }
+}
+
+impl<'lua, T> fmt::Debug for UserDataHandle<'lua, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("UserDataHandle")
+            .field(&self.userdata)
+            .finish()
+    }
+}
+
+impl<'lua, T> ToLua<'lua> for UserDataHandle<'lua, T> {
+    fn to_lua(self, _: Context<'lua>) -> Result<Value<'lua>> {
+        Ok(Value::UserData(self.userdata))
+    }
+}
+
+/// Handle to the metatable of a userdata, returned by [`AnyUserData::get_metatable`].
+///
+/// Unlike a plain `Table`, this only allows access to the metatable entries for the metamethods
+/// listed in [`MetaMethod`], so the entries `rlua` relies on internally (such as `__gc`) cannot be
+/// changed.
+///
+/// The metatable is not necessarily shared by every userdata of a Rust type: userdata created by
+/// [`Context::create_userdata`] and by [`Context::create_userdata_boxed`] have different
+/// metatables even when they hold the same type, see [`AnyUserData::get_metatable`].
+///
+/// [`Context::create_userdata`]: struct.Context.html#method.create_userdata
+/// [`Context::create_userdata_boxed`]: struct.Context.html#method.create_userdata_boxed
+/// [`AnyUserData::get_metatable`]: struct.AnyUserData.html#method.get_metatable
+/// [`MetaMethod`]: enum.MetaMethod.html
+#[derive(Clone, Debug)]
+pub struct UserDataMetatable<'lua>(pub(crate) Table<'lua>);
+
+impl<'lua> UserDataMetatable<'lua> {
+    /// Gets the value of the given metamethod in this metatable.
+    ///
+    /// If the userdata type has regular methods, the `__index` entry is a table of those methods
+    /// (or a function wrapping it, if an `__index` metamethod was also added).
+    pub fn get<V: FromLua<'lua>>(&self, meta: MetaMethod) -> Result<V> {
+        let lua = (self.0).0.lua;
+        self.0.raw_get(lua.create_string(meta.name())?)
+    }
+
+    /// Sets the value of the given metamethod in this metatable.
+    ///
+    /// Setting a value of `nil` removes the metamethod.
+    pub fn set<V: ToLua<'lua>>(&self, meta: MetaMethod, value: V) -> Result<()> {
+        let lua = (self.0).0.lua;
+        self.0.raw_set(lua.create_string(meta.name())?, value)
+    }
+
+    /// Checks whether the given metamethod is set in this metatable.
+    pub fn contains(&self, meta: MetaMethod) -> Result<bool> {
+        match self.get::<Value>(meta)? {
+            Nil => Ok(false),
+            _ => Ok(true),
+        }
     }
 }
@@ -1,13 +1,18 @@
-use std::any::Any;
+use std::any::{type_name, Any};
 use std::borrow::Cow;
+use std::ffi::CStr;
 use std::fmt::Write;
 use std::os::raw::{c_char, c_int, c_void};
 use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
 use std::sync::Arc;
 use std::{mem, ptr, slice};
 
+use crate::context::callback_name;
 use crate::error::{Error, Result};
 use crate::ffi;
+use crate::lua::extra_data;
+use crate::number_format::format_number;
+use crate::types::RegistryKey;
 
 // Checks that Lua has enough free stack space for future stack operations.  On failure, this will
 // panic with an internal error message.
@@ -164,9 +169,29 @@ where
 // error at the top of the stack:
 //   1) If the error is actually a WrappedPanic, this will continue the panic.
 //   2) If the error on the top of the stack is actually a WrappedError, just returns it.
-//   3) Otherwise, interprets the error as the appropriate lua error.
-// Uses 2 stack spaces, does not call lua_checkstack.
+//   3) If the error is a runtime error with a value that is not a string, number or nil, places the
+//      value in the registry and returns an Error::RuntimeErrorValue.  The registry values of any
+//      dropped `RegistryKey`s, such as those of earlier errors, are removed first.
+//   4) Otherwise, interprets the error as the appropriate lua error.
+// The error is then passed to any hook set with `Lua::set_error_hook`.
+// Uses 2 stack spaces, and calls lua_checkstack for the extra space needed to place an error value
+// in the registry.
 pub unsafe fn pop_error(state: *mut ffi::lua_State, err_code: c_int) -> Error {
+    apply_error_hook(state, pop_error_value(state, err_code))
+}
+
+// Passes `err` to any hook set with `Lua::set_error_hook`.  Code which needs to inspect an error
+// before it is returned, such as to add the position of a syntax error, takes it off the stack with
+// `pop_error_value` instead, and applies the hook once it is done.
+pub unsafe fn apply_error_hook(state: *mut ffi::lua_State, err: Error) -> Error {
+    match (*extra_data(state)).error_hook.clone() {
+        Some(hook) => hook(err),
+        None => err,
+    }
+}
+
+// Like `pop_error`, but without passing the error to the error hook.
+pub unsafe fn pop_error_value(state: *mut ffi::lua_State, err_code: c_int) -> Error {
     rlua_debug_assert!(
         err_code != ffi::LUA_OK && err_code != ffi::LUA_YIELD,
         "pop_error called with non-error return code"
@@ -182,6 +207,25 @@ pub unsafe fn pop_error(state: *mut ffi::lua_State, err_code: c_int) -> Error {
         } else {
             rlua_panic!("error during panic handling, panic was resumed twice")
         }
+    } else if err_code == ffi::LUA_ERRRUN && !is_plain_error(state, -1) {
+        let message = to_string(state, -1).into_owned();
+        if ffi::lua_checkstack(state, 3) == 0 {
+            ffi::lua_pop(state, 1);
+            return Error::RuntimeError(message);
+        }
+        expire_registry_values(state);
+        match protect_lua_closure(state, 1, 0, |state| {
+            ffi::luaL_ref(state, ffi::LUA_REGISTRYINDEX)
+        }) {
+            Ok(registry_id) => Error::RuntimeErrorValue {
+                message,
+                value: Arc::new(RegistryKey::new(
+                    registry_id,
+                    (*extra_data(state)).registry_unref_list.clone(),
+                )),
+            },
+            Err(_) => Error::RuntimeError(message),
+        }
     } else {
         let err_string = to_string(state, -1).into_owned();
         ffi::lua_pop(state, 1);
@@ -194,6 +238,10 @@ pub unsafe fn pop_error(state: *mut ffi::lua_State, err_code: c_int) -> Error {
                     // stock Lua REPL does.
                     incomplete_input: err_string.ends_with("<eof>"),
                     message: err_string,
+                    // Filled in by `Context::load_chunk`, which has the source.
+                    line: None,
+                    column: None,
+                    span: None,
                 }
             }
             ffi::LUA_ERRERR => {
@@ -265,6 +313,31 @@ pub unsafe fn init_userdata_metatable<T>(
     state: *mut ffi::lua_State,
     metatable: c_int,
     members: Option<c_int>,
+) -> Result<()> {
+    init_metatable_with_gc(state, metatable, members, userdata_destructor::<T>)
+}
+
+// Like `init_userdata_metatable`, but the __gc method also removes the userdata from the counts
+// kept by `Lua::enable_userdata_tracking`, under the type name of `T`.  The userdata must be stored
+// as an `S`.
+pub unsafe fn init_tracked_userdata_metatable<S, T: ?Sized>(
+    state: *mut ffi::lua_State,
+    metatable: c_int,
+    members: Option<c_int>,
+) -> Result<()> {
+    init_metatable_with_gc(
+        state,
+        metatable,
+        members,
+        tracked_userdata_destructor::<S, T>,
+    )
+}
+
+unsafe fn init_metatable_with_gc(
+    state: *mut ffi::lua_State,
+    metatable: c_int,
+    members: Option<c_int>,
+    gc: ffi::lua_CFunction,
 ) -> Result<()> {
     // Used if both an __index metamethod is set and regular methods, checks methods table
     // first, then __index metamethod.
@@ -312,7 +385,7 @@ pub unsafe fn init_userdata_metatable<T>(
     }
 
     push_string(state, "__gc")?;
-    ffi::lua_pushcfunction(state, userdata_destructor::<T>);
+    ffi::lua_pushcfunction(state, gc);
     protect_lua_closure(state, 3, 1, |state| {
         ffi::lua_rawset(state, -3);
     })?;
@@ -323,19 +396,96 @@ pub unsafe fn init_userdata_metatable<T>(
         ffi::lua_rawset(state, -3);
     })?;
 
+    ffi::lua_pushlightuserdata(
+        state,
+        &USERDATA_METATABLE_MARKER as *const u8 as *mut c_void,
+    );
+    ffi::lua_pushboolean(state, 1);
+    protect_lua_closure(state, 3, 1, |state| {
+        ffi::lua_rawset(state, -3);
+    })?;
+
     ffi::lua_pop(state, 1);
 
     Ok(())
 }
 
 pub unsafe extern "C" fn userdata_destructor<T>(state: *mut ffi::lua_State) -> c_int {
-    callback_error(state, |_| {
+    finalizer_callback(state, || {
         check_stack(state, 1)?;
         take_userdata::<T>(state);
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn tracked_userdata_destructor<S, T: ?Sized>(
+    state: *mut ffi::lua_State,
+) -> c_int {
+    finalizer_callback(state, || {
+        check_stack(state, 1)?;
+        // Untracked before the value is dropped, so that the count stays correct if dropping it
+        // panics.
+        untrack_userdata(state, type_name::<T>());
+        take_userdata::<S>(state);
+        Ok(())
+    })
+}
+
+// Runs the body of a __gc metamethod like `callback_error`.  If a handler is set with
+// `Lua::set_finalizer_error_handler`, an error or panic is instead passed to the handler, so that
+// it does not abort whatever operation triggered the garbage collection.
+unsafe fn finalizer_callback<F>(state: *mut ffi::lua_State, f: F) -> c_int
+where
+    F: FnOnce() -> Result<()>,
+{
+    let handler = match (*extra_data(state)).finalizer_error_handler.clone() {
+        Some(handler) => handler,
+        None => return callback_error(state, |_| f().map(|()| 0)),
+    };
+
+    let err = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return 0,
+        Ok(Err(err)) => err,
+        Err(p) => Error::GarbageCollectorError(format!(
+            "panic in __gc metamethod ({})",
+            panic_message(&*p)
+        )),
+    };
+    // A panic in the handler itself is propagated as usual.
+    callback_error(state, |_| {
+        handler(err);
         Ok(0)
     })
 }
 
+// Counts the userdata on the top of the stack under the given type name, if
+// `Lua::enable_userdata_tracking` is on.
+pub unsafe fn track_userdata(state: *mut ffi::lua_State, name: &'static str) {
+    if let Some(tracking) = (*extra_data(state)).userdata_tracking.as_mut() {
+        tracking
+            .live
+            .insert(ffi::lua_touserdata(state, -1) as *const c_void);
+        *tracking.counts.entry(name).or_insert(0) += 1;
+    }
+}
+
+// Stops counting the userdata on the top of the stack, once its value is dropped or taken.  Does
+// nothing for userdata created while tracking was off.
+pub unsafe fn untrack_userdata(state: *mut ffi::lua_State, name: &'static str) {
+    if let Some(tracking) = (*extra_data(state)).userdata_tracking.as_mut() {
+        if tracking
+            .live
+            .remove(&(ffi::lua_touserdata(state, -1) as *const c_void))
+        {
+            let count = tracking.counts.get_mut(name).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                tracking.counts.remove(name);
+            }
+        }
+    }
+}
+
 // In the context of a lua callback, this will call the given function and if the given function
 // returns an error, *or if the given function panics*, this will result in a call to lua_error (a
 // longjmp).  The error or panic is wrapped in such a way that when calling pop_error back on
@@ -378,11 +528,35 @@ where
         }
         Ok(Err(err)) => {
             ffi::lua_settop(state, 1);
+            if let Some(registry_id) = owned_error_value(state, &err) {
+                // Raise the original error value, `err` must be dropped first as `lua_error` does
+                // not return.
+                drop(err);
+                ffi::lua_rawgeti(
+                    state,
+                    ffi::LUA_REGISTRYINDEX,
+                    registry_id as ffi::lua_Integer,
+                );
+                ffi::lua_error(state)
+            }
             ptr::write(ud as *mut WrappedError, WrappedError(err));
             get_error_metatable(state);
             ffi::lua_setmetatable(state, -2);
             ffi::lua_error(state)
         }
+        Err(p) if !(*extra_data(state)).catch_rust_panics => {
+            ffi::lua_settop(state, 1);
+            // `p` must be dropped here, as `lua_error` does not return.
+            let message = format!("panic in Rust callback: {}", panic_message(&*p));
+            drop(p);
+            ptr::write(
+                ud as *mut WrappedError,
+                WrappedError(Error::RuntimeError(message)),
+            );
+            get_error_metatable(state);
+            ffi::lua_setmetatable(state, -2);
+            ffi::lua_error(state)
+        }
         Err(p) => {
             ffi::lua_settop(state, 1);
             ptr::write(ud as *mut WrappedPanic, WrappedPanic(Some(p)));
@@ -393,14 +567,26 @@ where
     }
 }
 
+// Returns the message a panic was raised with, if it was raised with a string.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_owned()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "<non-string panic payload>".to_owned()
+    }
+}
+
 // Takes an error at the top of the stack, and if it is a WrappedError, converts it to an
-// Error::CallbackError with a traceback, if it is some lua type, prints the error along with a
-// traceback, and if it is a WrappedPanic, does not modify it.  This function does its best to avoid
-// triggering another error and shadowing previous rust errors, but it may trigger Lua errors that
-// shadow rust errors under certain memory conditions.  This function ensures that such behavior
-// will *never* occur with a rust panic, however.
+// Error::CallbackError with a traceback, if it is a string, number or nil, prints the error along
+// with a traceback, and if it is a WrappedPanic or any other Lua value, does not modify it.  This
+// function does its best to avoid triggering another error and shadowing previous rust errors, but
+// it may trigger Lua errors that shadow rust errors under certain memory conditions.  This function
+// ensures that such behavior will *never* occur with a rust panic, however.
 pub unsafe extern "C" fn error_traceback(state: *mut ffi::lua_State) -> c_int {
-    // I believe luaL_traceback requires this much free stack to not error.
+    // I believe luaL_traceback requires this much free stack to not error, and `traceback` needs
+    // no more.
     const LUA_TRACEBACK_STACK: c_int = 11;
 
     if ffi::lua_checkstack(state, 2) == 0 {
@@ -411,11 +597,9 @@ pub unsafe extern "C" fn error_traceback(state: *mut ffi::lua_State) -> c_int {
         // on the rust stack at this time.
         let ud = ffi::lua_newuserdata(state, mem::size_of::<WrappedError>()) as *mut WrappedError;
         let traceback = if ffi::lua_checkstack(state, LUA_TRACEBACK_STACK) != 0 {
-            ffi::luaL_traceback(state, state, ptr::null(), 0);
-
-            let traceback = to_string(state, -1).into_owned();
-            ffi::lua_pop(state, 1);
-            traceback
+            let buf = traceback_buffer(state);
+            traceback(state, None, &mut *buf);
+            String::from_utf8_lossy(&*buf).into_owned()
         } else {
             "<not enough stack space for traceback>".to_owned()
         };
@@ -432,16 +616,246 @@ pub unsafe extern "C" fn error_traceback(state: *mut ffi::lua_State) -> c_int {
         );
         get_error_metatable(state);
         ffi::lua_setmetatable(state, -2);
-    } else if !is_wrapped_panic(state, -1) {
+    } else if !is_wrapped_panic(state, -1) && is_plain_error(state, -1) {
         if ffi::lua_checkstack(state, LUA_TRACEBACK_STACK) != 0 {
-            let s = ffi::luaL_tolstring(state, -1, ptr::null_mut());
-            ffi::luaL_traceback(state, state, s, 0);
+            let mut size = 0;
+            let s = ffi::luaL_tolstring(state, -1, &mut size);
+            let message = slice::from_raw_parts(s as *const u8, size);
+            let source_line = resident_source_line(state, message);
+            // The traceback is built in a buffer owned by Lua, so that nothing is leaked if pushing
+            // it raises a memory error.
+            let buf = traceback_buffer(state);
+            traceback(state, Some(message), &mut *buf);
+            if let Some(line) = source_line {
+                insert_source_line(&mut *buf, line);
+            }
+            ffi::lua_pushlstring(state, (*buf).as_ptr() as *const c_char, (*buf).len());
+            ffi::lua_remove(state, -2);
             ffi::lua_remove(state, -2);
         }
     }
     1
 }
 
+// If the error message starts with the location of a line in a chunk loaded with
+// `Context::load_resident`, returns the text of that line.  The chunk is found through the function
+// on the stack at that location, and looked up by its full name, since the name in the message may
+// be truncated.  The returned slice is only valid until the resident sources change.
+unsafe fn resident_source_line<'a>(state: *mut ffi::lua_State, message: &[u8]) -> Option<&'a [u8]> {
+    let extra = extra_data(state);
+    if (*extra).resident_sources.is_empty() {
+        return None;
+    }
+
+    let mut ar: ffi::lua_Debug = mem::zeroed();
+    let mut level = 0;
+    while ffi::lua_getstack(state, level, &mut ar) != 0 {
+        level += 1;
+        if ffi::lua_getinfo(state, cstr!("Sl"), &mut ar) == 0 || ar.currentline <= 0 {
+            continue;
+        }
+        let short_src = CStr::from_ptr(ar.short_src.as_ptr()).to_bytes();
+        let rest = match message.strip_prefix(short_src) {
+            Some(rest) if rest.first() == Some(&b':') => &rest[1..],
+            _ => continue,
+        };
+        let digits = rest.iter().take_while(|c| c.is_ascii_digit()).count();
+        if rest.get(digits) != Some(&b':')
+            || std::str::from_utf8(&rest[..digits])
+                .ok()
+                .and_then(|l| l.parse::<c_int>().ok())
+                != Some(ar.currentline)
+        {
+            continue;
+        }
+
+        let name = CStr::from_ptr(ar.source).to_bytes();
+        let (_, line_offset, source) = (*extra)
+            .resident_sources
+            .iter()
+            .find(|(resident, _, _)| &resident[..] == name)?;
+        return match (ar.currentline as usize).checked_sub(*line_offset) {
+            Some(line) if line > 0 => source.split(|&c| c == b'\n').nth(line - 1),
+            _ => None,
+        };
+    }
+    None
+}
+
+// Adds the text of a source line, without surrounding whitespace, after the first line of the
+// error message in `buf`.
+fn insert_source_line(buf: &mut Vec<u8>, line: &[u8]) {
+    let start = line
+        .iter()
+        .position(|c| !c.is_ascii_whitespace())
+        .unwrap_or(line.len());
+    let end = line
+        .iter()
+        .rposition(|c| !c.is_ascii_whitespace())
+        .map_or(start, |end| end + 1);
+    let at = buf.iter().position(|&c| c == b'\n').unwrap_or(buf.len());
+    let mut text = b"\n    ".to_vec();
+    text.extend_from_slice(&line[start..end]);
+    buf.splice(at..at, text);
+}
+
+// Returns the buffer kept in the registry for building tracebacks, after clearing it.  The buffer
+// is owned by Lua, so it may be filled while calling functions that raise Lua errors.
+unsafe fn traceback_buffer(state: *mut ffi::lua_State) -> *mut Vec<u8> {
+    ffi::lua_pushlightuserdata(state, &TRACEBACK_BUFFER_KEY as *const u8 as *mut c_void);
+    ffi::lua_rawget(state, ffi::LUA_REGISTRYINDEX);
+    let buf = ffi::lua_touserdata(state, -1) as *mut Vec<u8>;
+    ffi::lua_pop(state, 1);
+    (*buf).clear();
+    buf
+}
+
+// Builds a traceback in `out` in the same format as `luaL_traceback` at level 0, except that Rust
+// callbacks created with `Context::create_named_function` are reported by their name.  Uses at
+// most 7 stack spaces.
+//
+// The only call that may raise a Lua error is the lookup of the loaded modules, which happens
+// before anything is built, so `out` should not be owned by Rust code which could be skipped by
+// the error.
+unsafe fn traceback(state: *mut ffi::lua_State, msg: Option<&[u8]>, out: &mut Vec<u8>) {
+    // The number of levels shown before and after the "..." in a long traceback.
+    const LEVELS1: c_int = 10;
+    const LEVELS2: c_int = 11;
+
+    let mut ar: ffi::lua_Debug = mem::zeroed();
+
+    // Finds the last level of the stack with a binary search.
+    let (mut li, mut le) = (1, 1);
+    while ffi::lua_getstack(state, le, &mut ar) != 0 {
+        li = le;
+        le *= 2;
+    }
+    while li < le {
+        let m = (li + le) / 2;
+        if ffi::lua_getstack(state, m, &mut ar) != 0 {
+            li = m + 1;
+        } else {
+            le = m;
+        }
+    }
+    let last = le - 1;
+
+    // The loaded modules, used to find the names of library functions.
+    ffi::lua_pushstring(state, cstr!("_LOADED"));
+    ffi::lua_rawget(state, ffi::LUA_REGISTRYINDEX);
+    let loaded = ffi::lua_gettop(state);
+
+    if let Some(msg) = msg {
+        out.extend_from_slice(msg);
+        out.push(b'\n');
+    }
+    out.extend_from_slice(b"stack traceback:");
+
+    let mut skip = if last > LEVELS1 + LEVELS2 {
+        LEVELS1
+    } else {
+        -1
+    };
+    let mut level = 0;
+    while ffi::lua_getstack(state, level, &mut ar) != 0 {
+        level += 1;
+        if skip == 0 {
+            out.extend_from_slice(b"\n\t...");
+            level = last - LEVELS2 + 1;
+        } else {
+            ffi::lua_getinfo(state, cstr!("Slnt"), &mut ar);
+            out.extend_from_slice(b"\n\t");
+            out.extend_from_slice(CStr::from_ptr(ar.short_src.as_ptr()).to_bytes());
+            out.push(b':');
+            if ar.currentline > 0 {
+                out.extend_from_slice(format!("{}:", ar.currentline).as_bytes());
+            }
+            out.extend_from_slice(b" in ");
+            push_function_name(state, &mut ar, loaded, out);
+            if ar.istailcall != 0 {
+                out.extend_from_slice(b"\n\t(...tail calls...)");
+            }
+        }
+        skip -= 1;
+    }
+    ffi::lua_pop(state, 1);
+}
+
+// Describes the function of a traceback level like `pushfuncname` in lauxlib, preferring the name
+// of a named Rust callback.
+unsafe fn push_function_name(
+    state: *mut ffi::lua_State,
+    ar: &mut ffi::lua_Debug,
+    loaded: c_int,
+    out: &mut Vec<u8>,
+) {
+    ffi::lua_getinfo(state, cstr!("f"), ar);
+    let name = match callback_name(state, -1) {
+        Some(name) => Some(name),
+        None => global_function_name(state, loaded),
+    };
+    ffi::lua_pop(state, 1);
+
+    if let Some(name) = name {
+        out.extend_from_slice(b"function '");
+        out.extend_from_slice(&name);
+        out.push(b'\'');
+    } else if !ar.namewhat.is_null() && *ar.namewhat != 0 {
+        out.extend_from_slice(CStr::from_ptr(ar.namewhat).to_bytes());
+        out.extend_from_slice(b" '");
+        out.extend_from_slice(CStr::from_ptr(ar.name).to_bytes());
+        out.push(b'\'');
+    } else if *ar.what == b'm' as c_char {
+        out.extend_from_slice(b"main chunk");
+    } else if *ar.what != b'C' as c_char {
+        out.extend_from_slice(b"function <");
+        out.extend_from_slice(CStr::from_ptr(ar.short_src.as_ptr()).to_bytes());
+        out.extend_from_slice(format!(":{}>", ar.linedefined).as_bytes());
+    } else {
+        out.push(b'?');
+    }
+}
+
+// Searches the loaded modules at `loaded` for the function at the top of the stack, like
+// `pushglobalfuncname` in lauxlib, returning a name such as `string.format`.
+unsafe fn global_function_name(state: *mut ffi::lua_State, loaded: c_int) -> Option<Vec<u8>> {
+    let function = ffi::lua_gettop(state);
+    ffi::lua_pushvalue(state, loaded);
+    let name = find_field(state, function, 2);
+    ffi::lua_pop(state, 1);
+    name.map(|name| match name.strip_prefix(b"_G.") {
+        Some(global) => global.to_vec(),
+        None => name,
+    })
+}
+
+// Searches the table at the top of the stack for the value at `index`, through at most `level`
+// tables, using only string keys.  Like `findfield` in lauxlib.
+unsafe fn find_field(state: *mut ffi::lua_State, index: c_int, level: c_int) -> Option<Vec<u8>> {
+    if level == 0 || ffi::lua_istable(state, -1) == 0 {
+        return None;
+    }
+    ffi::lua_pushnil(state);
+    while ffi::lua_next(state, -2) != 0 {
+        if ffi::lua_type(state, -2) == ffi::LUA_TSTRING {
+            let mut size = 0;
+            let key = ffi::lua_tolstring(state, -2, &mut size);
+            let key = slice::from_raw_parts(key as *const u8, size);
+            let name = if ffi::lua_rawequal(state, index, -1) != 0 {
+                Some(key.to_vec())
+            } else {
+                find_field(state, index, level - 1).map(|field| [key, b".", &field].concat())
+            };
+            if name.is_some() {
+                ffi::lua_pop(state, 2);
+                return name;
+            }
+        }
+        ffi::lua_pop(state, 1);
+    }
+    None
+}
+
 // A variant of pcall that does not allow lua to catch panic errors from callback_error
 pub unsafe extern "C" fn safe_pcall(state: *mut ffi::lua_State) -> c_int {
     ffi::luaL_checkstack(state, 2, ptr::null());
@@ -685,6 +1099,21 @@ pub unsafe fn init_error_registry(state: *mut ffi::lua_State) {
     ffi::lua_setmetatable(state, -2);
 
     ffi::lua_rawset(state, ffi::LUA_REGISTRYINDEX);
+
+    // Create traceback buffer
+
+    ffi::lua_pushlightuserdata(state, &TRACEBACK_BUFFER_KEY as *const u8 as *mut c_void);
+
+    let ud = ffi::lua_newuserdata(state, mem::size_of::<Vec<u8>>()) as *mut Vec<u8>;
+    ptr::write(ud, Vec::new());
+
+    ffi::lua_newtable(state);
+    ffi::lua_pushstring(state, cstr!("__gc"));
+    ffi::lua_pushcfunction(state, userdata_destructor::<Vec<u8>>);
+    ffi::lua_rawset(state, -3);
+    ffi::lua_setmetatable(state, -2);
+
+    ffi::lua_rawset(state, ffi::LUA_REGISTRYINDEX);
 }
 
 struct WrappedError(pub Error);
@@ -692,7 +1121,7 @@ struct WrappedPanic(pub Option<Box<dyn Any + Send>>);
 
 // Converts the given lua value to a string in a reasonable format without causing a Lua error or
 // panicking.
-unsafe fn to_string<'a>(state: *mut ffi::lua_State, index: c_int) -> Cow<'a, str> {
+pub unsafe fn to_string<'a>(state: *mut ffi::lua_State, index: c_int) -> Cow<'a, str> {
     match ffi::lua_type(state, index) {
         ffi::LUA_TNONE => "<none>".into(),
         ffi::LUA_TNIL => "<nil>".into(),
@@ -704,7 +1133,11 @@ unsafe fn to_string<'a>(state: *mut ffi::lua_State, index: c_int) -> Cow<'a, str
             let mut isint = 0;
             let i = ffi::lua_tointegerx(state, -1, &mut isint);
             if isint == 0 {
-                ffi::lua_tonumber(state, index).to_string().into()
+                let n = ffi::lua_tonumber(state, index);
+                match format_number((*extra_data(state)).number_format, n) {
+                    Some(s) => s.into(),
+                    None => n.to_string().into(),
+                }
             } else {
                 i.to_string().into()
             }
@@ -722,9 +1155,45 @@ unsafe fn to_string<'a>(state: *mut ffi::lua_State, index: c_int) -> Cow<'a, str
     }
 }
 
+// Removes the registry values of all `RegistryKey`s which have been dropped.  Uses 2 stack spaces,
+// does not call lua_checkstack.
+pub unsafe fn expire_registry_values(state: *mut ffi::lua_State) {
+    let unref_list = rlua_expect!(
+        (*extra_data(state)).registry_unref_list.lock(),
+        "unref list poisoned"
+    )
+    .replace(Vec::new());
+    for id in rlua_expect!(unref_list, "unref list not set") {
+        ffi::luaL_unref(state, ffi::LUA_REGISTRYINDEX, id);
+    }
+}
+
+// Checks if the value at the given index is a string, number or nil, the error values which are
+// converted to Error::RuntimeError rather than Error::RuntimeErrorValue.  Does not use stack space.
+unsafe fn is_plain_error(state: *mut ffi::lua_State, index: c_int) -> bool {
+    let t = ffi::lua_type(state, index);
+    t == ffi::LUA_TSTRING || t == ffi::LUA_TNUMBER || t == ffi::LUA_TNIL
+}
+
+// If the error is an Error::RuntimeErrorValue whose value is held in the registry of this Lua
+// state, returns the registry id of the value.
+unsafe fn owned_error_value(state: *mut ffi::lua_State, err: &Error) -> Option<c_int> {
+    match *err {
+        Error::RuntimeErrorValue { ref value, .. }
+            if Arc::ptr_eq(
+                value.unref_list(),
+                &(*extra_data(state)).registry_unref_list,
+            ) =>
+        {
+            Some(value.registry_id())
+        }
+        _ => None,
+    }
+}
+
 // Checks if the value at the given index is a WrappedPanic.  Uses 2 stack spaces and does not call
 // lua_checkstack.
-unsafe fn is_wrapped_panic(state: *mut ffi::lua_State, index: c_int) -> bool {
+pub unsafe fn is_wrapped_panic(state: *mut ffi::lua_State, index: c_int) -> bool {
     let userdata = ffi::lua_touserdata(state, index);
     if userdata.is_null() {
         return false;
@@ -756,7 +1225,26 @@ unsafe fn get_panic_metatable(state: *mut ffi::lua_State) {
     ffi::lua_rawget(state, ffi::LUA_REGISTRYINDEX);
 }
 
-unsafe fn get_destructed_userdata_metatable(state: *mut ffi::lua_State) {
+// Returns whether the table at the given index is the metatable of a userdata created by rlua,
+// rather than by C code.  Uses 2 stack spaces, does not call checkstack.
+pub unsafe fn is_rlua_userdata_metatable(state: *mut ffi::lua_State, index: c_int) -> bool {
+    let index = ffi::lua_absindex(state, index);
+    ffi::lua_pushlightuserdata(
+        state,
+        &USERDATA_METATABLE_MARKER as *const u8 as *mut c_void,
+    );
+    let marked = ffi::lua_rawget(state, index) != ffi::LUA_TNIL;
+    ffi::lua_pop(state, 1);
+    if marked {
+        return true;
+    }
+    get_destructed_userdata_metatable(state);
+    let destructed = ffi::lua_rawequal(state, -1, index) != 0;
+    ffi::lua_pop(state, 1);
+    destructed
+}
+
+pub unsafe fn get_destructed_userdata_metatable(state: *mut ffi::lua_State) {
     ffi::lua_pushlightuserdata(
         state,
         &DESTRUCTED_USERDATA_METATABLE as *const u8 as *mut c_void,
@@ -767,4 +1255,6 @@ unsafe fn get_destructed_userdata_metatable(state: *mut ffi::lua_State) {
 static ERROR_METATABLE_REGISTRY_KEY: u8 = 0;
 static PANIC_METATABLE_REGISTRY_KEY: u8 = 0;
 static DESTRUCTED_USERDATA_METATABLE: u8 = 0;
+static USERDATA_METATABLE_MARKER: u8 = 0;
 static ERROR_PRINT_BUFFER_KEY: u8 = 0;
+static TRACEBACK_BUFFER_KEY: u8 = 0;
@@ -1,9 +1,11 @@
 use std::iter::{self, FromIterator};
-use std::{slice, str, vec};
+use std::{fmt, slice, str, vec};
 
 use crate::context::Context;
 use crate::error::{Error, Result};
 use crate::function::Function;
+use crate::inspect::{inspect, InspectOptions};
+use crate::number_format::NumberFormat;
 use crate::string::String;
 use crate::table::Table;
 use crate::thread::Thread;
@@ -14,7 +16,7 @@ use crate::userdata::AnyUserData;
 /// variants contain handle types into the internal Lua state.  It is a logic error to mix handle
 /// types between separate `Lua` instances, or between a parent `Lua` instance and one received as a
 /// parameter in a Rust callback, and doing so will result in a panic.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub enum Value<'lua> {
     /// The Lua value `nil`.
     Nil,
@@ -46,6 +48,31 @@ pub enum Value<'lua> {
 }
 pub use self::Value::Nil;
 
+impl<'lua> fmt::Debug for Value<'lua> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "Nil"),
+            Value::Boolean(b) => f.debug_tuple("Boolean").field(b).finish(),
+            Value::LightUserData(ud) => f.debug_tuple("LightUserData").field(ud).finish(),
+            Value::Integer(i) => f.debug_tuple("Integer").field(i).finish(),
+            Value::Number(n) => f.debug_tuple("Number").field(n).finish(),
+            Value::String(s) => f.debug_tuple("String").field(s).finish(),
+            // Show the immediate contents of tables, falling back to the opaque reference if they
+            // cannot be read.
+            Value::Table(t) => {
+                match inspect(self, &InspectOptions::new().max_depth(1), NumberFormat::Lua) {
+                    Ok(s) => write!(f, "Table({})", s),
+                    Err(_) => f.debug_tuple("Table").field(t).finish(),
+                }
+            }
+            Value::Function(func) => f.debug_tuple("Function").field(func).finish(),
+            Value::Thread(t) => f.debug_tuple("Thread").field(t).finish(),
+            Value::UserData(ud) => f.debug_tuple("UserData").field(ud).finish(),
+            Value::Error(e) => f.debug_tuple("Error").field(e).finish(),
+        }
+    }
+}
+
 impl<'lua> Value<'lua> {
     pub fn type_name(&self) -> &'static str {
         match *self {
@@ -62,6 +89,56 @@ impl<'lua> Value<'lua> {
             Value::Error(_) => "error",
         }
     }
+
+    /// Makes the value read-only for Lua code if it is a table, along with every table reachable
+    /// from it.  Other values are left unchanged.
+    ///
+    /// See [`Table::deep_freeze`] for details.
+    ///
+    /// [`Table::deep_freeze`]: struct.Table.html#method.deep_freeze
+    pub fn deep_freeze(&self) -> Result<()> {
+        match self {
+            Value::Table(t) => t.deep_freeze(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the length of the value as given by Lua's `#` operator, or `None` for values which
+    /// have no length.
+    ///
+    /// Strings have their length in bytes, tables their `__len` metamethod result or border, and
+    /// userdata their `__len` metamethod result if they have one.  All other values, including
+    /// userdata without a `__len` metamethod, return `None`.
+    pub fn len(&self) -> Result<Option<Integer>> {
+        match self {
+            Value::String(s) => Ok(Some(s.as_bytes().len() as Integer)),
+            Value::Table(t) => t.len().map(Some),
+            Value::UserData(ud) => {
+                // Userdata created outside of rlua may have no metatable at all.
+                match ud.0.lua.get_metafield(self, "__len")? {
+                    Some(len) => Function::from_lua(len, ud.0.lua)?
+                        .call(ud.clone())
+                        .map(Some),
+                    None => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns true if the value is a function or has a `__call` metafield.
+    ///
+    /// The `__call` metafield itself is not checked, so calling the value can still fail if the
+    /// metafield is not callable.
+    pub fn is_callable(&self, lua: Context<'lua>) -> bool {
+        match self {
+            Value::Function(_) => true,
+            v => match lua.get_metafield(v, "__call") {
+                Ok(call) => call.is_some(),
+                Err(_) => false,
+            },
+        }
+    }
 }
 
 /// Trait for types convertible to `Value`.
@@ -118,11 +195,14 @@ impl<'a, 'lua> IntoIterator for &'a MultiValue<'lua> {
 }
 
 impl<'lua> MultiValue<'lua> {
+    /// Creates a `MultiValue` from a `Vec` of values, with the first element of the `Vec` becoming
+    /// the first value.
     pub fn from_vec(mut v: Vec<Value<'lua>>) -> MultiValue<'lua> {
         v.reverse();
         MultiValue(v)
     }
 
+    /// Converts this `MultiValue` into a `Vec`, with the first value becoming the first element.
     pub fn into_vec(self) -> Vec<Value<'lua>> {
         let mut v = self.0;
         v.reverse();
@@ -154,6 +234,63 @@ impl<'lua> MultiValue<'lua> {
     }
 }
 
+/// Builds a `MultiValue` by appending values in order, converting each one with `ToLua`.
+///
+/// This is convenient for returning a varying number of values of different types from a callback.
+///
+/// # Examples
+///
+/// ```
+/// # use rlua::{Lua, MultiValueBuilder, Result};
+/// # fn main() -> Result<()> {
+/// # Lua::new().context(|lua_context| {
+/// let stats = lua_context.create_function(|lua, verbose: bool| {
+///     let mut results = MultiValueBuilder::new(lua).push(3)?.push("items")?;
+///     if verbose {
+///         results = results.push(vec![1, 2, 3])?;
+///     }
+///     Ok(results.build())
+/// })?;
+/// lua_context.globals().set("stats", stats)?;
+/// lua_context.load(r#"
+///     local count, name, list = stats(true)
+///     assert(count == 3 and name == "items" and #list == 3)
+/// "#).exec()
+/// # })
+/// # }
+/// ```
+pub struct MultiValueBuilder<'lua> {
+    lua: Context<'lua>,
+    values: Vec<Value<'lua>>,
+}
+
+impl<'lua> MultiValueBuilder<'lua> {
+    /// Creates a builder with no values.
+    pub fn new(lua: Context<'lua>) -> MultiValueBuilder<'lua> {
+        MultiValueBuilder {
+            lua,
+            values: Vec::new(),
+        }
+    }
+
+    /// Appends a single value after those already added.
+    pub fn push<T: ToLua<'lua>>(mut self, value: T) -> Result<MultiValueBuilder<'lua>> {
+        self.values.push(value.to_lua(self.lua)?);
+        Ok(self)
+    }
+
+    /// Appends any number of values after those already added.
+    pub fn push_multi<T: ToLuaMulti<'lua>>(mut self, values: T) -> Result<MultiValueBuilder<'lua>> {
+        self.values.extend(values.to_lua_multi(self.lua)?);
+        Ok(self)
+    }
+
+    /// Returns the values added so far as a `MultiValue`, in the order they were added.
+    pub fn build(self) -> MultiValue<'lua> {
+        MultiValue::from_vec(self.values)
+    }
+}
+
 /// Trait for types convertible to any number of Lua values.
 ///
 /// This is a generalization of `ToLua`, allowing any number of resulting Lua values instead of just
@@ -0,0 +1,75 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use bstr::BString;
+
+use rlua::{Lua, String, UserData, UserDataMethods};
+
+// Counts the bytes allocated by Rust on each thread.  Lua allocates its own memory with `libc`, so
+// it is not counted.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|a| a.set(a.get() + layout.size()));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocated_by<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATED.with(|a| a.get());
+    f();
+    ALLOCATED.with(|a| a.get()) - before
+}
+
+#[test]
+fn test_borrowed_method_allocations() {
+    const SIZE: usize = 1024 * 1024;
+
+    struct Blob(Vec<u8>);
+
+    impl UserData for Blob {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("bytes_owned", |_, blob, ()| {
+                Ok(BString::from(blob.0.clone()))
+            });
+            methods.add_method_borrowed("bytes", |_, blob, ()| Ok(&blob.0[..]));
+        }
+    }
+
+    Lua::new().context(|lua| {
+        lua.globals().set("blob", Blob(vec![7; SIZE])).unwrap();
+        let owned = lua
+            .load("return blob:bytes_owned()")
+            .into_function()
+            .unwrap();
+        let borrowed = lua.load("return blob:bytes()").into_function().unwrap();
+
+        // Warm up, so that both paths have already registered their metatables and callbacks.
+        owned.call::<_, String>(()).unwrap();
+        borrowed.call::<_, String>(()).unwrap();
+
+        let owned_bytes = allocated_by(|| {
+            let s = owned.call::<_, String>(()).unwrap();
+            assert_eq!(s.as_bytes().len(), SIZE);
+        });
+        let borrowed_bytes = allocated_by(|| {
+            let s = borrowed.call::<_, String>(()).unwrap();
+            assert_eq!(s.as_bytes().len(), SIZE);
+            assert!(s.as_bytes().iter().all(|&b| b == 7));
+        });
+        assert!(owned_bytes >= SIZE, "{}", owned_bytes);
+        assert!(borrowed_bytes < 4096, "{}", borrowed_bytes);
+    });
+}
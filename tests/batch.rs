@@ -0,0 +1,111 @@
+use rlua::{BatchOptions, Error, Function, Lua, LuaOptions, StdLib};
+
+fn chunks(sources: &[(&str, &str)]) -> Vec<(String, String)> {
+    sources
+        .iter()
+        .map(|&(name, source)| (name.to_owned(), source.to_owned()))
+        .collect()
+}
+
+#[test]
+fn test_eval_batch() {
+    Lua::new().context(|lua| {
+        let results = lua.eval_batch::<i64, _>(
+            chunks(&[
+                ("first", "local x = 20\nreturn x + 1"),
+                ("second", "1 +"),
+                ("third", "error('bad value')"),
+            ]),
+            BatchOptions::new(),
+        );
+
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["first", "second", "third"]);
+        match &results[0].1 {
+            Ok(21) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match &results[1].1 {
+            Err(Error::SyntaxError { message, .. }) => {
+                assert!(message.starts_with("[string \"second\"]:1:"), "{}", message)
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        match &results[2].1 {
+            Err(Error::RuntimeError(message)) => {
+                assert!(
+                    message.starts_with("[string \"third\"]:1: bad value"),
+                    "{}",
+                    message
+                )
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // Nothing is left behind by the failed chunks.
+        assert_eq!(lua.load("1 + 1").eval::<i64>().unwrap(), 2);
+    });
+}
+
+#[test]
+fn test_eval_batch_environment() {
+    Lua::new().context(|lua| {
+        lua.globals().set("base", 10).unwrap();
+        let sources = chunks(&[
+            ("a", "value = base + 1; _G.leaked = true; return value"),
+            (
+                "b",
+                "return value == nil and leaked == nil and string.upper('x')",
+            ),
+        ]);
+
+        let results = lua.eval_batch::<rlua::Value, _>(sources.clone(), BatchOptions::new());
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        assert!(lua.globals().contains_key("value").unwrap());
+        lua.globals().set("value", rlua::Nil).unwrap();
+        lua.globals().set("leaked", rlua::Nil).unwrap();
+
+        let options = BatchOptions::new().environment(lua.globals());
+        let results = lua.eval_batch::<rlua::Value, _>(sources, options);
+        match (&results[0].1, &results[1].1) {
+            (Ok(rlua::Value::Integer(11)), Ok(rlua::Value::String(s))) => {
+                assert_eq!(s.to_str().unwrap(), "X")
+            }
+            r => panic!("unexpected results {:?}", r),
+        }
+        assert!(!lua.globals().contains_key("value").unwrap());
+        assert!(!lua.globals().contains_key("leaked").unwrap());
+    });
+}
+
+#[test]
+fn test_eval_batch_callback_panic() {
+    let lua = Lua::new_with_options(
+        StdLib::ALL_NO_DEBUG,
+        LuaOptions::new().catch_rust_panics(false),
+    );
+    lua.context(|lua| {
+        let explode: Function = lua
+            .create_function(|_, ()| -> rlua::Result<()> { panic!("exploded") })
+            .unwrap();
+        lua.globals().set("explode", explode).unwrap();
+
+        let results = lua.eval_batch::<String, _>(
+            chunks(&[
+                ("before", "'ok'"),
+                ("panic", "explode()"),
+                ("after", "'still ok'"),
+            ]),
+            BatchOptions::new(),
+        );
+        assert_eq!(results[0].1.as_ref().unwrap(), "ok");
+        match &results[1].1 {
+            Err(Error::CallbackError { cause, .. }) => match cause.as_ref() {
+                Error::RuntimeError(message) => assert!(message.contains("exploded")),
+                e => panic!("unexpected cause {:?}", e),
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(results[2].1.as_ref().unwrap(), "still ok");
+    });
+}
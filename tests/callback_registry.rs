@@ -0,0 +1,126 @@
+use std::sync::{Arc, Mutex};
+
+use rlua::{CallbackRegistry, Error, Function, Lua, SubscriptionId, Table};
+
+#[test]
+fn test_emit() {
+    Lua::new().context(|lua| {
+        let events = lua.create_callback_registry().unwrap();
+        lua.load(
+            r#"
+                log = {}
+                function first(x) table.insert(log, "first " .. x) return x + 1 end
+                function second(x) error("second failed") end
+                function third(x) table.insert(log, "third " .. x) return x * 2, "extra" end
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let globals = lua.globals();
+        for name in &["first", "second", "third"] {
+            let function: Function = globals.get(*name).unwrap();
+            events.add(lua, "tick", function).unwrap();
+        }
+
+        let results = events.emit(lua, "tick", 10).unwrap();
+        assert_eq!(results.len(), 3);
+        let values = |i: usize| {
+            results[i]
+                .as_ref()
+                .unwrap()
+                .iter()
+                .map(|v| lua.coerce_string(v.clone()).unwrap().unwrap())
+                .map(|s| s.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values(0), vec!["11"]);
+        match results[1] {
+            Err(Error::RuntimeError(ref msg)) => assert!(msg.contains("second failed")),
+            ref r => panic!("wrong result {:?}", r),
+        }
+        assert_eq!(values(2), vec!["20", "extra"]);
+
+        let log: Table = globals.get("log").unwrap();
+        let log = log
+            .sequence_values::<String>()
+            .collect::<rlua::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(log, vec!["first 10", "third 10"]);
+
+        assert!(events.emit(lua, "other", ()).unwrap().is_empty());
+        events.clear(lua, "tick").unwrap();
+        assert!(events.emit(lua, "tick", 1).unwrap().is_empty());
+    });
+}
+
+#[test]
+fn test_remove_during_emit() {
+    Lua::new().context(|lua| {
+        let events = Arc::new(lua.create_callback_registry().unwrap());
+        let third_id: Arc<Mutex<Option<SubscriptionId>>> = Arc::new(Mutex::new(None));
+        lua.globals().set("calls", 0).unwrap();
+
+        let remover = {
+            let events = events.clone();
+            let third_id = third_id.clone();
+            lua.create_function(move |lua, ()| {
+                if let Some(id) = third_id.lock().unwrap().as_ref() {
+                    assert!(events.remove(lua, id)?);
+                    assert!(!events.remove(lua, id)?);
+                }
+                let adder: Function = lua.load("function() calls = calls + 100 end").eval()?;
+                events.add(lua, "tick", adder)?;
+                Ok(())
+            })
+            .unwrap()
+        };
+        let counter: Function = lua.load("function() calls = calls + 1 end").eval().unwrap();
+
+        events.add(lua, "tick", remover).unwrap();
+        events.add(lua, "tick", counter.clone()).unwrap();
+        *third_id.lock().unwrap() = Some(events.add(lua, "tick", counter).unwrap());
+
+        // Removal and addition take effect from the next emit.
+        assert_eq!(events.emit(lua, "tick", ()).unwrap().len(), 3);
+        assert_eq!(lua.globals().get::<_, i64>("calls").unwrap(), 2);
+
+        *third_id.lock().unwrap() = None;
+        assert_eq!(events.emit(lua, "tick", ()).unwrap().len(), 3);
+        assert_eq!(lua.globals().get::<_, i64>("calls").unwrap(), 103);
+    });
+}
+
+#[test]
+fn test_drop_callback_registry() {
+    let rc = Arc::new(());
+
+    Lua::new().context(|lua| {
+        let events: CallbackRegistry = lua.create_callback_registry().unwrap();
+        for event in &["a", "b"] {
+            let rc = rc.clone();
+            let function = lua
+                .create_function(move |_, ()| Ok(Arc::strong_count(&rc)))
+                .unwrap();
+            events.add(lua, event, function).unwrap();
+        }
+        assert_eq!(Arc::strong_count(&rc), 3);
+
+        drop(events);
+        lua.expire_registry_values();
+        lua.load(r#"collectgarbage("collect")"#).exec().unwrap();
+        assert_eq!(Arc::strong_count(&rc), 1);
+    });
+}
+
+#[test]
+fn test_callback_registry_mismatch() {
+    let events = Lua::new().context(|lua| lua.create_callback_registry().unwrap());
+    Lua::new().context(|lua| {
+        let function: Function = lua.load("function() end").eval().unwrap();
+        match events.add(lua, "tick", function) {
+            Err(Error::MismatchedRegistryKey) => {}
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+}
@@ -0,0 +1,115 @@
+use rlua::{ChunkCacheStats, Error, Function, Lua, Table};
+
+#[test]
+fn test_load_cached() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        let first = lua.load_cached("double", "return 2 * ...").unwrap();
+        let second = lua.load_cached("double", "return 2 * ...").unwrap();
+        assert_eq!(second.call::<_, i64>(21).unwrap(), 42);
+
+        let equal: Function = lua.load("function(a, b) return a == b end").eval().unwrap();
+        assert!(equal.call::<_, bool>((first, second)).unwrap());
+    });
+    assert_eq!(
+        lua.chunk_cache_stats(),
+        ChunkCacheStats {
+            hits: 1,
+            misses: 1,
+            evictions: 0,
+            len: 1,
+            capacity: 64,
+        }
+    );
+
+    // A changed source is compiled again, and replaces the cached function.
+    lua.context(|lua| {
+        let triple = lua.load_cached("double", "return 3 * ...").unwrap();
+        assert_eq!(triple.call::<_, i64>(2).unwrap(), 6);
+        let triple = lua.load_cached("double", "return 3 * ...").unwrap();
+        assert_eq!(triple.call::<_, i64>(2).unwrap(), 6);
+    });
+    let stats = lua.chunk_cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.len), (2, 2, 1));
+
+    lua.clear_chunk_cache();
+    lua.context(|lua| {
+        lua.load_cached("double", "return 3 * ...").unwrap();
+    });
+    let stats = lua.chunk_cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.len), (2, 3, 1));
+}
+
+#[test]
+fn test_load_cached_eviction() {
+    let lua = Lua::new();
+    lua.set_chunk_cache_capacity(2);
+    let functions = || lua.registry_report().values.get("function").cloned();
+    let before = functions();
+
+    lua.context(|lua| {
+        lua.load_cached("a", "return 'a'").unwrap();
+        lua.load_cached("b", "return 'b'").unwrap();
+        // Makes "b" the least recently used.
+        lua.load_cached("a", "return 'a'").unwrap();
+        lua.load_cached("c", "return 'c'").unwrap();
+    });
+    let stats = lua.chunk_cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.evictions), (1, 3, 1));
+    assert_eq!(stats.len, 2);
+
+    lua.context(|lua| {
+        lua.load_cached("a", "return 'a'").unwrap();
+        lua.load_cached("c", "return 'c'").unwrap();
+        lua.load_cached("b", "return 'b'").unwrap();
+    });
+    let stats = lua.chunk_cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.evictions), (3, 4, 2));
+
+    lua.set_chunk_cache_capacity(0);
+    assert_eq!(lua.chunk_cache_stats().len, 0);
+    assert_eq!(functions(), before);
+    lua.context(|lua| {
+        lua.load_cached("a", "return 'a'").unwrap();
+        lua.load_cached("a", "return 'a'").unwrap();
+    });
+    let stats = lua.chunk_cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.len), (3, 6, 0));
+}
+
+#[test]
+fn test_load_cached_environment() {
+    Lua::new().context(|lua| {
+        lua.globals().set("name", "global").unwrap();
+        let source = "return name";
+
+        let env: Table = lua.load("{ name = 'custom' }").eval().unwrap();
+        let template = lua.load_cached("template", source).unwrap();
+        template.set_environment(env).unwrap();
+        assert_eq!(template.call::<_, String>(()).unwrap(), "custom");
+
+        // The environment set on a previous use does not leak into the next one.
+        let template = lua.load_cached("template", source).unwrap();
+        assert_eq!(template.call::<_, String>(()).unwrap(), "global");
+    });
+}
+
+#[test]
+fn test_load_cached_errors() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        match lua.load_cached("=broken", "return +") {
+            Err(Error::SyntaxError { message, .. }) => assert!(message.starts_with("broken:1:")),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match lua
+            .load_cached("=failing", "error('oops')")
+            .unwrap()
+            .call::<_, ()>(())
+        {
+            Err(Error::RuntimeError(message)) => assert!(message.starts_with("failing:1: oops")),
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+    assert_eq!(lua.chunk_cache_stats().len, 1);
+}
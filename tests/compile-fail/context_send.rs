@@ -0,0 +1,14 @@
+extern crate rlua;
+
+use rlua::Lua;
+
+fn assert_send<T: Send>(_: T) {}
+
+fn main() {
+    Lua::new().context(|lua| {
+        let table = lua.create_table().unwrap();
+        assert_send(table);
+        //~^ error: cannot be sent between threads safely
+        //~| error: cannot be sent between threads safely
+    });
+}
@@ -0,0 +1,13 @@
+extern crate rlua;
+
+use std::rc::Rc;
+
+use rlua::Lua;
+
+fn main() {
+    Lua::new().context(|lua| {
+        let rc = Rc::new(1);
+        lua.create_function(move |_, ()| Ok(*rc)).unwrap();
+        //~^ error: cannot be sent between threads safely
+    });
+}
@@ -0,0 +1,20 @@
+extern crate rlua;
+
+use std::sync::{Arc, Mutex};
+
+use rlua::{UserData, UserDataMethods};
+
+struct Blob(Vec<u8>);
+
+impl UserData for Blob {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        let leaked: Arc<Mutex<Option<&'static [u8]>>> = Arc::new(Mutex::new(None));
+        methods.add_method_borrowed("bytes", move |_, blob, ()| {
+            *leaked.lock().unwrap() = Some(&blob.0[..]);
+            //~^ error: borrowed data escapes outside of closure
+            Ok(&blob.0[..])
+        });
+    }
+}
+
+fn main() {}
@@ -0,0 +1,160 @@
+use std::sync::{Arc, Mutex};
+
+use rlua::{Function, Lua, StackFrame, Table};
+
+type Reports = Arc<Mutex<Vec<(String, String, Option<StackFrame>)>>>;
+
+fn record(lua: &Lua) -> Reports {
+    let reports = Reports::default();
+    let log = reports.clone();
+    lua.set_deprecation_handler(move |path, message, location| {
+        log.lock()
+            .unwrap()
+            .push((path.to_owned(), message.to_owned(), location));
+    });
+    reports
+}
+
+#[test]
+fn test_deprecate_global() {
+    let lua = Lua::new();
+    let reports = record(&lua);
+    lua.context(|lua| {
+        lua.load(
+            r#"
+                function spawn(...)
+                    local n = select('#', ...)
+                    if n == 0 then error("nothing to spawn") end
+                    return n, ...
+                end
+                spawn_unit = spawn
+            "#,
+        )
+        .exec()
+        .unwrap();
+        lua.deprecate_global("spawn_unit", "use spawn instead")
+            .unwrap();
+
+        lua.load(
+            r#"
+                local a = spawn(1)
+
+                local n, x, y, z = spawn_unit("a", nil, 3)
+                assert(n == 3 and x == "a" and y == nil and z == 3)
+                assert(spawn_unit == spawn)
+
+                local ok, err = pcall(spawn_unit)
+                local ok2, err2 = pcall(spawn)
+                assert(not ok and not ok2 and err == err2)
+            "#,
+        )
+        .set_name("=game.lua")
+        .unwrap()
+        .exec()
+        .unwrap();
+    });
+
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 1);
+    let (path, message, location) = &reports[0];
+    assert_eq!(path, "spawn_unit");
+    assert_eq!(message, "use spawn instead");
+    let location = location.as_ref().unwrap();
+    assert_eq!(location.source, "game.lua");
+    assert_eq!(location.current_line, Some(4));
+}
+
+#[test]
+fn test_deprecate_nested() {
+    let lua = Lua::new();
+    let reports = record(&lua);
+    lua.set_report_every_deprecated_access(true);
+    lua.context(|lua| {
+        let units: Table = lua
+            .load(
+                r#"
+                    units = setmetatable({ old_spawn = 1, old_count = 2 }, {
+                        __index = function(_, k) return "default " .. k end,
+                    })
+                    return units
+                "#,
+            )
+            .eval()
+            .unwrap();
+        lua.deprecate_global("units.old_spawn", "first").unwrap();
+        lua.deprecate_global("units.old_count", "second").unwrap();
+        assert!(!units.raw_contains_key("old_spawn").unwrap());
+
+        lua.load(
+            r#"
+                assert(units.old_spawn == 1)
+                assert(units.old_spawn == 1)
+                assert(units.old_count == 2)
+                assert(units.other == "default other")
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        // Accesses from Rust are reported without a location.
+        assert_eq!(units.get::<_, i64>("old_count").unwrap(), 2);
+
+        assert!(lua.deprecate_global("missing.key", "").is_err());
+    });
+
+    let reports = reports.lock().unwrap();
+    let paths = reports
+        .iter()
+        .map(|(path, _, location)| (path.as_str(), location.is_some()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        vec![
+            ("units.old_spawn", true),
+            ("units.old_spawn", true),
+            ("units.old_count", true),
+            ("units.old_count", false),
+        ]
+    );
+}
+
+#[test]
+fn test_deprecate_without_handler() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        lua.globals().set("old", 1).unwrap();
+        lua.deprecate_global("old", "gone").unwrap();
+        assert_eq!(lua.load("old").eval::<i64>().unwrap(), 1);
+    });
+
+    // The first access after a handler is set is still reported.
+    let reports = record(&lua);
+    lua.context(|lua| {
+        let old: Function = lua.load("function() return old end").eval().unwrap();
+        assert_eq!(old.call::<_, i64>(()).unwrap(), 1);
+        assert_eq!(old.call::<_, i64>(()).unwrap(), 1);
+    });
+    assert_eq!(reports.lock().unwrap().len(), 1);
+
+    lua.remove_deprecation_handler();
+    lua.context(|lua| {
+        lua.load("assert(old == 1)").exec().unwrap();
+    });
+}
+
+#[test]
+fn test_deprecate_again() {
+    let lua = Lua::new();
+    let reports = record(&lua);
+    lua.context(|lua| {
+        lua.globals().set("old", 1).unwrap();
+        lua.deprecate_global("old", "gone").unwrap();
+        lua.load("assert(old == 1)").exec().unwrap();
+        lua.deprecate_global("old", "really gone").unwrap();
+        lua.load("assert(old == 1 and old == 1)").exec().unwrap();
+    });
+
+    let reports = reports.lock().unwrap();
+    let messages: Vec<&str> = reports.iter().map(|(_, m, _)| m.as_str()).collect();
+    assert_eq!(messages, ["gone", "really gone"]);
+}
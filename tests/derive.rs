@@ -0,0 +1,212 @@
+#![cfg(feature = "derive")]
+
+use rlua::{Error, FromLua, Lua, Table, ToLua};
+
+#[derive(Debug, PartialEq, ToLua, FromLua)]
+struct Config {
+    name: String,
+    size: u32,
+    verbose: Option<bool>,
+    mode: Mode,
+    priority: Priority,
+}
+
+#[derive(Debug, PartialEq, ToLua, FromLua)]
+enum Mode {
+    Fast,
+    Safe,
+}
+
+#[derive(Debug, PartialEq, ToLua, FromLua)]
+#[rlua(integer)]
+enum Priority {
+    Low = 1,
+    High = 10,
+}
+
+#[test]
+fn test_derive_struct() {
+    Lua::new().context(|lua| {
+        let config = Config {
+            name: "test".to_owned(),
+            size: 3,
+            verbose: None,
+            mode: Mode::Safe,
+            priority: Priority::High,
+        };
+        lua.globals().set("config", config).unwrap();
+        lua.load(
+            r#"
+                assert(config.name == "test")
+                assert(config.size == 3)
+                assert(config.verbose == nil)
+                assert(config.mode == "Safe")
+                assert(config.priority == 10)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let config: Config = lua
+            .load(r#"{ name = "other", size = 5.0, mode = "Fast", priority = "Low" }"#)
+            .eval()
+            .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                name: "other".to_owned(),
+                size: 5,
+                verbose: None,
+                mode: Mode::Fast,
+                priority: Priority::Low,
+            }
+        );
+
+        match lua
+            .load(r#"{ name = "other", mode = "Fast", priority = 1 }"#)
+            .eval::<Config>()
+        {
+            Err(Error::FromLuaConversionError {
+                from: "nil",
+                to: "Config",
+                message: Some(message),
+            }) => assert_eq!(message, "missing field `size`"),
+            r => panic!("wrong result {:?}", r),
+        }
+        match lua
+            .load(r#"{ name = "other", size = 1, mode = "Slow", priority = 1 }"#)
+            .eval::<Config>()
+        {
+            Err(Error::FromLuaConversionError {
+                from: "string",
+                to: "Config",
+                message: Some(message),
+            }) => assert!(message.starts_with("invalid field `mode`:"), "{}", message),
+            r => panic!("wrong result {:?}", r),
+        }
+        match lua.load("42").eval::<Config>() {
+            Err(Error::FromLuaConversionError {
+                from: "integer",
+                to: "Config",
+                ..
+            }) => {}
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_derive_enum() {
+    Lua::new().context(|lua| {
+        assert_eq!(lua.load("'Fast'").eval::<Mode>().unwrap(), Mode::Fast);
+        assert_eq!(
+            String::from_lua(Mode::Safe.to_lua(lua).unwrap(), lua).unwrap(),
+            "Safe"
+        );
+        match lua.load("'Slow'").eval::<Mode>() {
+            Err(Error::FromLuaConversionError {
+                message: Some(message),
+                ..
+            }) => assert_eq!(
+                message,
+                "\"Slow\" is not a valid variant, expected one of Fast, Safe"
+            ),
+            r => panic!("wrong result {:?}", r),
+        }
+        match lua.load("1").eval::<Mode>() {
+            Err(Error::FromLuaConversionError { .. }) => {}
+            r => panic!("wrong result {:?}", r),
+        }
+
+        assert_eq!(lua.load("10").eval::<Priority>().unwrap(), Priority::High);
+        assert_eq!(lua.load("10.0").eval::<Priority>().unwrap(), Priority::High);
+        assert_eq!(lua.load("'Low'").eval::<Priority>().unwrap(), Priority::Low);
+        match lua.load("5").eval::<Priority>() {
+            Err(Error::FromLuaConversionError {
+                message: Some(message),
+                ..
+            }) => assert_eq!(
+                message,
+                "5 is not a valid variant, expected one of Low (1), High (10)"
+            ),
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_derive_lifetimes() {
+    #[derive(ToLua, FromLua)]
+    struct WithTable<'lua> {
+        table: Table<'lua>,
+        count: i64,
+    }
+
+    #[derive(ToLua, FromLua)]
+    struct Generic<T> {
+        value: T,
+    }
+
+    Lua::new().context(|lua| {
+        let value: WithTable = lua.load("{ table = { 1, 2 }, count = 2 }").eval().unwrap();
+        assert_eq!(value.table.len().unwrap(), value.count);
+        lua.globals().set("value", value).unwrap();
+
+        let generic: Generic<Vec<i64>> = lua.load("{ value = value.table }").eval().unwrap();
+        assert_eq!(generic.value, vec![1, 2]);
+        let table: Table = Generic { value: "x" }
+            .to_lua(lua)
+            .and_then(|v| Table::from_lua(v, lua))
+            .unwrap();
+        assert_eq!(table.get::<_, String>("value").unwrap(), "x");
+    });
+}
+
+mod shadowed {
+    // The generated code does not rely on `format!` and `vec!` from the prelude.
+    #[allow(unused_macros)]
+    macro_rules! format {
+        ($($t:tt)*) => {
+            compile_error!("the prelude's format! was shadowed")
+        };
+    }
+    #[allow(unused_macros)]
+    macro_rules! vec {
+        ($($t:tt)*) => {
+            compile_error!("the prelude's vec! was shadowed")
+        };
+    }
+
+    #[derive(Debug, PartialEq, rlua::ToLua, rlua::FromLua)]
+    pub struct Point {
+        pub x: i32,
+    }
+
+    #[derive(Debug, PartialEq, rlua::ToLua, rlua::FromLua)]
+    pub enum Direction {
+        Up,
+    }
+
+    #[derive(Debug, PartialEq, rlua::ToLua, rlua::FromLua)]
+    #[rlua(integer)]
+    pub enum Level {
+        Low = 1,
+    }
+}
+
+#[test]
+fn test_derive_shadowed_macros() {
+    use shadowed::{Direction, Level, Point};
+
+    Lua::new().context(|lua| {
+        let point = Point { x: 1 };
+        let value = point.to_lua(lua).unwrap();
+        assert_eq!(Point::from_lua(value, lua).unwrap(), Point { x: 1 });
+        assert!(Point::from_lua(lua.create_table().unwrap().to_lua(lua).unwrap(), lua).is_err());
+        assert!(Direction::from_lua("Down".to_lua(lua).unwrap(), lua).is_err());
+        assert_eq!(
+            Level::from_lua(1.to_lua(lua).unwrap(), lua).unwrap(),
+            Level::Low
+        );
+    });
+}
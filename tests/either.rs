@@ -0,0 +1,31 @@
+#![cfg(feature = "either")]
+
+use either::Either;
+
+use rlua::{Lua, Value};
+
+#[test]
+fn test_return_either() {
+    Lua::new().context(|lua| {
+        let f = lua
+            .create_function(|lua, n: i64| {
+                Ok(if n > 0 {
+                    Either::Left(Value::Integer(n))
+                } else {
+                    Either::Right((Value::Nil, lua.create_string("not positive")?))
+                })
+            })
+            .unwrap();
+        lua.globals().set("f", f).unwrap();
+
+        lua.load(
+            r##"
+                assert(select("#", f(3)) == 1 and f(3) == 3)
+                local ok, err = f(-1)
+                assert(ok == nil and err == "not positive")
+            "##,
+        )
+        .exec()
+        .unwrap();
+    });
+}
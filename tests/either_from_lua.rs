@@ -0,0 +1,92 @@
+#![cfg(feature = "either")]
+
+use either::Either;
+
+use rlua::{Error, Function, Integer, Lua, Table, Thread, UserData, Value};
+
+#[test]
+fn test_either_callback() {
+    Lua::new().context(|lua| {
+        let describe: Function = lua
+            .create_function(|_, value: Either<String, Table>| {
+                Ok(match value {
+                    Either::Left(s) => format!("string {}", s),
+                    Either::Right(t) => format!("table {}", t.get::<_, String>("name")?),
+                })
+            })
+            .unwrap();
+
+        assert_eq!(describe.call::<_, String>("abc").unwrap(), "string abc");
+        let options = lua.create_table().unwrap();
+        options.set("name", "opts").unwrap();
+        assert_eq!(describe.call::<_, String>(options).unwrap(), "table opts");
+        // Numbers coerce to strings, so they take the first alternative.
+        assert_eq!(describe.call::<_, String>(3).unwrap(), "string 3");
+
+        match describe.call::<_, String>(true) {
+            Err(Error::CallbackError { cause, .. }) => match cause.as_ref() {
+                Error::FromLuaConversionError {
+                    from: "boolean",
+                    to: "Either",
+                    message: Some(message),
+                } => assert_eq!(message, "expected string or table, got boolean"),
+                e => panic!("unexpected cause {:?}", e),
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_either_nested() {
+    Lua::new().context(|lua| {
+        let value = lua.load("{}").eval::<Value>().unwrap();
+        match lua.unpack::<Either<Integer, Either<Function, Either<Thread, String>>>>(value) {
+            Err(Error::FromLuaConversionError {
+                message: Some(message),
+                ..
+            }) => assert_eq!(
+                message,
+                "expected number or function or thread or string, got table"
+            ),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        match lua.unpack::<Either<Thread, Either<Integer, String>>>(lua.pack(5).unwrap()) {
+            Ok(Either::Right(Either::Left(5))) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_either_userdata() {
+    #[derive(Clone, Debug)]
+    struct A;
+    impl UserData for A {}
+    #[derive(Clone, Debug)]
+    struct B(i64);
+    impl UserData for B {}
+    struct C;
+    impl UserData for C {}
+
+    Lua::new().context(|lua| {
+        let b = lua.create_userdata(B(3)).unwrap();
+        match lua.unpack::<Either<A, B>>(Value::UserData(b)) {
+            Ok(Either::Right(B(3))) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let c = lua.create_userdata(C).unwrap();
+        match lua.unpack::<Either<A, B>>(Value::UserData(c)) {
+            Err(Error::FromLuaConversionError {
+                message: Some(message),
+                ..
+            }) => assert_eq!(
+                message,
+                "expected userdata, got userdata which did not convert"
+            ),
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
@@ -1,4 +1,8 @@
-use rlua::{Function, Lua, String};
+use std::string::String as StdString;
+
+use rlua::{
+    Callable, Error, Function, Lua, MetaMethod, String, Table, UserData, UserDataMethods, Value,
+};
 
 #[test]
 fn test_function() {
@@ -19,6 +23,34 @@ fn test_function() {
     });
 }
 
+#[test]
+fn test_call1() {
+    Lua::new().context(|lua| {
+        let double: Function = lua
+            .load("function(x) return x * 2, 'ignored' end")
+            .eval()
+            .unwrap();
+        assert_eq!(double.call1::<_, i64>(21).unwrap(), 42);
+        assert!(double.call1::<_, i64>("x").is_err());
+
+        let nothing: Function = lua
+            .load("function(...) return select('#', ...) end")
+            .eval()
+            .unwrap();
+        assert_eq!(nothing.call0::<i64>().unwrap(), 0);
+        assert_eq!(nothing.call1::<_, i64>(Value::Nil).unwrap(), 1);
+
+        let empty: Function = lua.load("function() end").eval().unwrap();
+        assert_eq!(empty.call0::<Option<i64>>().unwrap(), None);
+
+        let fail: Function = lua.load("function(x) error(x) end").eval().unwrap();
+        match fail.call1::<_, Value>("boom") {
+            Err(Error::RuntimeError(msg)) => assert!(msg.contains("boom")),
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+}
+
 #[test]
 fn test_bind() {
     Lua::new().context(|lua| {
@@ -72,3 +104,196 @@ fn test_rust_function() {
         assert_eq!(lua_function.call::<_, String>(()).unwrap(), "hello");
     });
 }
+
+#[test]
+fn test_collect_yields() {
+    Lua::new().context(|lua| {
+        let generator: Function = lua
+            .load(
+                r#"
+                    function(prefix, n)
+                        for i = 1, n do
+                            coroutine.yield(prefix .. i, "ignored")
+                        end
+                        coroutine.yield()
+                        return "not collected"
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(
+            generator
+                .collect_yields::<_, Option<StdString>>(("item", 2))
+                .unwrap(),
+            vec![Some("item1".to_owned()), Some("item2".to_owned()), None]
+        );
+
+        let no_yields: Function = lua.load("function() return 1 end").eval().unwrap();
+        assert!(no_yields.collect_yields::<_, i64>(()).unwrap().is_empty());
+
+        let fails: Function = lua
+            .load("function() coroutine.yield(1) error('failed') end")
+            .eval()
+            .unwrap();
+        assert!(fails.collect_yields::<_, i64>(()).is_err());
+    });
+}
+
+#[test]
+fn test_callable() {
+    struct Adder(i64);
+
+    impl UserData for Adder {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method(MetaMethod::Call, |_, this, x: i64| Ok(this.0 + x));
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let function: Callable = lua.load("function(x) return x + 1 end").eval().unwrap();
+        assert_eq!(function.call::<_, i64>(1).unwrap(), 2);
+
+        let table: Table = lua
+            .load(
+                r#"
+                    setmetatable({ n = 10 }, {
+                        __call = function(self, x) return self.n + x, self end
+                    })
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let callable: Callable = lua.unpack(Value::Table(table.clone())).unwrap();
+        let (sum, callee): (i64, Table) = callable.call(5).unwrap();
+        assert_eq!(sum, 15);
+
+        // The callee is passed as the first argument, and the original value is kept.
+        let globals = lua.globals();
+        globals.set("table", table).unwrap();
+        globals.set("callee", callee).unwrap();
+        globals.set("value", callable.value().clone()).unwrap();
+        globals.set("callable", callable).unwrap();
+        lua.load("assert(rawequal(table, callee) and rawequal(table, value))")
+            .exec()
+            .unwrap();
+        lua.load("assert(rawequal(table, callable))")
+            .exec()
+            .unwrap();
+
+        let userdata: Callable = lua.unpack(lua.pack(Adder(100)).unwrap()).unwrap();
+        assert_eq!(userdata.call::<_, i64>(23).unwrap(), 123);
+
+        for source in &["{}", "42", "'print'"] {
+            match lua.load(*source).eval::<Callable>() {
+                Err(Error::FromLuaConversionError {
+                    to: "Callable",
+                    message: Some(message),
+                    ..
+                }) => assert_eq!(
+                    message,
+                    "expected a function, or a table or userdata with a `__call` metamethod"
+                ),
+                r => panic!("wrong result {:?}", r),
+            }
+        }
+    });
+}
+
+#[test]
+fn test_environment() {
+    Lua::new().context(|lua| {
+        lua.globals().set("name", "global").unwrap();
+        let chunk = lua
+            .load(
+                r#"
+                    function get_name() return name end
+                    return name
+                "#,
+            )
+            .into_function()
+            .unwrap();
+        let env = chunk.environment().unwrap().unwrap();
+        assert_eq!(env.get::<_, StdString>("name").unwrap(), "global");
+
+        let sandbox = lua.create_table().unwrap();
+        sandbox.set("name", "sandbox").unwrap();
+        assert!(chunk.set_environment(sandbox.clone()).unwrap());
+        assert_eq!(chunk.call::<_, StdString>(()).unwrap(), "sandbox");
+        assert_eq!(
+            chunk
+                .environment()
+                .unwrap()
+                .unwrap()
+                .get::<_, StdString>("name")
+                .unwrap(),
+            "sandbox"
+        );
+
+        // Globals set by the chunk went to the sandbox, and functions it defined share its `_ENV`.
+        assert!(!lua.globals().contains_key("get_name").unwrap());
+        let get_name: Function = sandbox.get("get_name").unwrap();
+        assert!(get_name.environment().unwrap().is_some());
+        assert_eq!(get_name.call::<_, StdString>(()).unwrap(), "sandbox");
+
+        let pure: Function = lua.load("function(a, b) return a + b end").eval().unwrap();
+        assert!(pure.environment().unwrap().is_none());
+        assert!(!pure.set_environment(sandbox.clone()).unwrap());
+        assert_eq!(pure.call::<_, i64>((1, 2)).unwrap(), 3);
+
+        let print: Function = lua.globals().get("print").unwrap();
+        assert!(print.environment().unwrap().is_none());
+        let callback = lua.create_function(|_, ()| Ok(())).unwrap();
+        assert!(!callback.set_environment(sandbox).unwrap());
+    });
+}
+
+#[test]
+fn test_named_function() {
+    Lua::new().context(|lua| {
+        let fail = |_, ()| Err::<(), _>(Error::RuntimeError("failed".to_owned()));
+        let named = lua.create_named_function("load_asset", fail).unwrap();
+        let unnamed = lua.create_function(fail).unwrap();
+
+        // The functions are passed as arguments, since global functions are already found by name.
+        let traceback =
+            |source: &str, f| match lua.load(source).into_function().unwrap().call::<_, ()>(f) {
+                Err(Error::CallbackError { traceback, .. }) => traceback,
+                r => panic!("wrong result {:?}", r),
+            };
+        let metamethod = "local _ = setmetatable({}, { __index = ... }).x";
+        assert!(
+            traceback(metamethod, named.clone()).contains("\n\t[C]: in function 'load_asset'\n")
+        );
+        assert!(
+            traceback(metamethod, unnamed.clone()).contains("\n\t[C]: in metamethod '__index'\n")
+        );
+        let local = "local f = ...; f()";
+        assert!(traceback(local, named.clone()).contains("\n\t[C]: in function 'load_asset'\n"));
+        assert!(traceback(local, unnamed.clone()).contains("\n\t[C]: in local 'f'\n"));
+
+        // Other functions are named as before.
+        match lua.load("local function f() string.rep() end f()").exec() {
+            Err(Error::RuntimeError(msg)) => assert!(
+                msg.contains(
+                    "stack traceback:\n\t[C]: in ?\n\t[C]: in function 'string.rep'\n\
+                     \t[string \"?\"]:1: in local 'f'\n\t[string \"?\"]:1: in main chunk"
+                ),
+                "{}",
+                msg
+            ),
+            r => panic!("wrong result {:?}", r),
+        }
+
+        let frame_name = lua
+            .create_named_function("frame_name", |lua, ()| Ok(lua.backtrace()[0].name.clone()))
+            .unwrap();
+        let name: Option<StdString> = lua
+            .load("local f = ...; return f()")
+            .into_function()
+            .unwrap()
+            .call(frame_name)
+            .unwrap();
+        assert_eq!(name.as_deref(), Some("frame_name"));
+    });
+}
@@ -2,8 +2,9 @@ use std::cell::RefCell;
 use std::ops::Deref;
 use std::str;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use rlua::{Error, HookTriggers, Lua, Value};
+use rlua::{Error, HookTriggers, Lua, Thread, ThreadStatus, Value};
 
 #[test]
 fn line_counts() {
@@ -141,6 +142,87 @@ fn limit_execution_instructions() {
     });
 }
 
+#[test]
+fn limit_execution_time() {
+    let lua = Lua::new();
+    let start = Instant::now();
+    let calls = Arc::new(Mutex::new(0));
+
+    lua.set_hook_every_ms(10, {
+        let calls = calls.clone();
+        move |_lua, _debug| {
+            *calls.lock().unwrap() += 1;
+            if start.elapsed() >= Duration::from_millis(100) {
+                Err(Error::RuntimeError("time's up".to_string()))
+            } else {
+                Ok(())
+            }
+        }
+    });
+
+    lua.context(|lua| match lua.load("while true do end").exec() {
+        Err(Error::CallbackError { cause, .. }) => match cause.deref() {
+            Error::RuntimeError(s) if s == "time's up" => {}
+            e => panic!("unexpected cause {:?}", e),
+        },
+        r => panic!("time limit didn't occur, got {:?}", r),
+    });
+
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(100));
+    assert!(elapsed < Duration::from_secs(5), "took {:?}", elapsed);
+    // About every 10 milliseconds for 100 milliseconds, allowing for a slow machine.
+    let calls = *calls.lock().unwrap();
+    assert!((2..=11).contains(&calls), "called {} times", calls);
+}
+
+#[test]
+fn execution_time_ignores_idle_time() {
+    let lua = Lua::new();
+    let calls = Arc::new(Mutex::new(0));
+    lua.set_hook_every_ms(200, {
+        let calls = calls.clone();
+        move |_lua, _debug| {
+            *calls.lock().unwrap() += 1;
+            Ok(())
+        }
+    });
+
+    // Time passing before the code runs does not count towards the interval.
+    std::thread::sleep(Duration::from_millis(300));
+    lua.context(|lua| {
+        lua.load("for i = 1, 100000 do end").exec().unwrap();
+    });
+    assert_eq!(*calls.lock().unwrap(), 0);
+}
+
+#[test]
+fn hooks_without_debug_library() {
+    let lua = Lua::new();
+    lua.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(100),
+            ..Default::default()
+        },
+        |_lua, _debug| Err(Error::RuntimeError("time's up".to_string())),
+    );
+
+    lua.context(|lua| {
+        assert!(lua
+            .globals()
+            .get::<_, Option<Value>>("debug")
+            .unwrap()
+            .is_none());
+        match lua.load("while true do end").exec() {
+            Err(err) => match err.chain().last() {
+                Some(Error::RuntimeError(msg)) => assert_eq!(msg, "time's up"),
+                other => panic!("wrong cause {:?}", other),
+            },
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+}
+
 #[test]
 fn hook_removal() {
     let lua = Lua::new();
@@ -230,3 +312,122 @@ fn hook_swap_within_hook() {
         });
     });
 }
+
+#[test]
+fn thread_hooks() {
+    let lua = Lua::new();
+    let global_calls = Arc::new(Mutex::new(0));
+    let hook_global_calls = global_calls.clone();
+
+    lua.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(1),
+            ..Default::default()
+        },
+        move |_lua, _debug| {
+            *hook_global_calls.lock().unwrap() += 1;
+            Ok(())
+        },
+    );
+
+    lua.context(|lua| {
+        let source = r#"
+            coroutine.create(function()
+                local sum = 0
+                for i = 1, 1000 do
+                    sum = sum + i
+                end
+                return sum
+            end)
+        "#;
+        let budgeted: Thread = lua.load(source).eval().unwrap();
+        let unhindered: Thread = lua.load(source).eval().unwrap();
+
+        let budget = Arc::new(Mutex::new(100));
+        let hook_budget = budget.clone();
+        budgeted
+            .set_hook(
+                HookTriggers {
+                    every_nth_instruction: Some(1),
+                    ..Default::default()
+                },
+                move |_lua, _debug| {
+                    let mut budget = hook_budget.lock().unwrap();
+                    if *budget == 0 {
+                        Err(Error::RuntimeError("budget exhausted".to_owned()))
+                    } else {
+                        *budget -= 1;
+                        Ok(())
+                    }
+                },
+            )
+            .unwrap();
+
+        *global_calls.lock().unwrap() = 0;
+        match budgeted.resume::<_, i64>(()) {
+            Err(Error::CallbackError { .. }) => {}
+            r => panic!("budgeted thread was not aborted: {:?}", r),
+        }
+        assert_eq!(budgeted.status(), ThreadStatus::Error);
+        assert_eq!(*budget.lock().unwrap(), 0);
+        assert_eq!(*global_calls.lock().unwrap(), 0);
+
+        assert_eq!(unhindered.resume::<_, i64>(()).unwrap(), 500500);
+        assert!(*global_calls.lock().unwrap() > 0);
+
+        budgeted.remove_hook();
+    });
+}
+
+#[test]
+fn backtrace() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        assert!(lua.backtrace().is_empty());
+
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let captured = frames.clone();
+        let capture = lua
+            .create_function(move |lua, ()| {
+                *captured.lock().unwrap() = lua.backtrace();
+                Ok(())
+            })
+            .unwrap();
+        lua.globals().set("capture", capture).unwrap();
+
+        lua.load(
+            r#"
+                local function inner()
+                    capture()
+                end
+                function outer()
+                    inner()
+                end
+                outer()
+            "#,
+        )
+        .set_name("backtrace")
+        .unwrap()
+        .exec()
+        .unwrap();
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 4);
+
+        assert_eq!(frames[0].what, "C");
+        assert_eq!(frames[0].source, "[C]");
+        assert_eq!(frames[0].current_line, None);
+        assert_eq!(frames[0].name.as_deref(), Some("capture"));
+
+        assert_eq!(frames[1].what, "Lua");
+        assert_eq!(frames[1].source, "[string \"backtrace\"]");
+        assert_eq!(frames[1].current_line, Some(3));
+        assert_eq!(frames[1].name.as_deref(), Some("inner"));
+
+        assert_eq!(frames[2].current_line, Some(6));
+        assert_eq!(frames[2].name.as_deref(), Some("outer"));
+
+        assert_eq!(frames[3].what, "main");
+        assert_eq!(frames[3].current_line, Some(8));
+    });
+}
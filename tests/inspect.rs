@@ -0,0 +1,90 @@
+use rlua::{InspectOptions, Lua, StdLib, Value};
+
+#[test]
+fn inspect_nested() {
+    Lua::new().context(|lua| {
+        let value: Value = lua
+            .load(
+                r#"
+                    local t = { 10, 20, b = true, a = { x = 1.5 }, [3.5] = "float", ["not ident"] = 1 }
+                    t.self = t
+                    return t
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(
+            lua.inspect(&value, &InspectOptions::new()).unwrap(),
+            r#"<1>{ 10, 20, [3.5] = "float", a = { x = 1.5 }, b = true, ["not ident"] = 1, self = <table 1> }"#
+        );
+        assert_eq!(
+            lua.inspect(&value, &InspectOptions::new().max_depth(1))
+                .unwrap(),
+            r#"<1>{ 10, 20, [3.5] = "float", a = {...}, b = true, ["not ident"] = 1, self = <table 1> }"#
+        );
+
+        let sequence: Value = lua.load("{ 1, 2, 3, 4, 5 }").eval().unwrap();
+        assert_eq!(
+            lua.inspect(&sequence, &InspectOptions::new().max_entries(3))
+                .unwrap(),
+            "{ 1, 2, 3, ... }"
+        );
+
+        let shared: Value = lua
+            .load("local s = {} return { s, s }")
+            .eval()
+            .unwrap();
+        assert_eq!(
+            lua.inspect(&shared, &InspectOptions::new()).unwrap(),
+            "{ <1>{}, <table 1> }"
+        );
+    });
+}
+
+#[test]
+fn inspect_strings() {
+    Lua::new().context(|lua| {
+        let value: Value = lua.load(r#"string.rep("ab", 50)"#).eval().unwrap();
+        let options = InspectOptions::new().max_string_len(5);
+        assert_eq!(lua.inspect(&value, &options).unwrap(), r#""ababa...""#);
+
+        let value: Value = lua.load(r#""quote \" and\nnewline""#).eval().unwrap();
+        assert_eq!(
+            lua.inspect(&value, &InspectOptions::new()).unwrap(),
+            r#""quote \" and\nnewline""#
+        );
+    });
+}
+
+#[test]
+fn inspect_userdata() {
+    struct Point;
+    impl rlua::UserData for Point {}
+
+    let lua = unsafe { Lua::unsafe_new_with(StdLib::ALL) };
+    lua.context(|lua| {
+        lua.globals().set("point", Point).unwrap();
+        let value: Value = lua
+            .load(
+                r#"
+                    debug.getmetatable(point).__name = "Point"
+                    return { point, print }
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(
+            lua.inspect(&value, &InspectOptions::new()).unwrap(),
+            "{ <userdata Point>, <function> }"
+        );
+    });
+}
+
+#[test]
+fn debug_value() {
+    Lua::new().context(|lua| {
+        let value: Value = lua.load("{ 1, { 2 } }").eval().unwrap();
+        assert_eq!(format!("{:?}", value), "Table({ 1, {...} })");
+        assert_eq!(format!("{:?}", Value::Integer(3)), "Integer(3)");
+    });
+}
@@ -0,0 +1,258 @@
+#![cfg(feature = "json")]
+
+use serde_json::{json, Value as JsonValue};
+
+use rlua::{Error, JsonOptions, Lua, Value};
+
+#[test]
+fn test_to_json() {
+    Lua::new().context(|lua| {
+        let value: Value = lua
+            .load(
+                r#"
+                    {
+                        name = "widget",
+                        count = 3,
+                        ratio = 0.5,
+                        whole = 2.0,
+                        enabled = true,
+                        tags = { "a", "b" },
+                        sizes = { [1] = "small", [10] = "large" },
+                        empty = {},
+                    }
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(
+            lua.to_json(value).unwrap(),
+            json!({
+                "name": "widget",
+                "count": 3,
+                "ratio": 0.5,
+                "whole": 2.0,
+                "enabled": true,
+                "tags": ["a", "b"],
+                "sizes": { "1": "small", "10": "large" },
+                "empty": {},
+            })
+        );
+
+        let json = lua.to_json(lua.load("2.0").eval().unwrap()).unwrap();
+        assert!(json.is_f64());
+        let json = lua.to_json(lua.load("2").eval().unwrap()).unwrap();
+        assert_eq!(json.as_i64(), Some(2));
+        assert_eq!(lua.to_json(Value::Nil).unwrap(), JsonValue::Null);
+    });
+}
+
+#[test]
+fn test_to_json_errors() {
+    Lua::new().context(|lua| {
+        let error_message = |source: &str, options: &JsonOptions| {
+            let value: Value = lua.load(source).eval().unwrap();
+            match lua.to_json_with(value, options) {
+                Err(Error::FromLuaConversionError {
+                    to: "JSON",
+                    message: Some(message),
+                    ..
+                }) => message,
+                r => panic!("wrong result {:?}", r),
+            }
+        };
+        let default = JsonOptions::new();
+
+        assert_eq!(
+            error_message("{ items = { 1, { f = print } } }", &default),
+            "only nil, booleans, numbers, strings and tables can be converted (at `items[2].f`)"
+        );
+        assert_eq!(
+            error_message("{ x = 0/0 }", &default),
+            "NaN and infinity are not allowed (at `x`)"
+        );
+        assert_eq!(
+            error_message("{ [true] = 1 }", &default),
+            "keys must be strings or finite numbers (at `[true]`)"
+        );
+        assert!(error_message("{ [2] = 'a', ['2'] = 'b' }", &default)
+            .starts_with("two keys convert to the same JSON key"));
+        assert_eq!(
+            error_message("{ s = '\\xff' }", &default),
+            "strings must be valid UTF-8 (at `s`)"
+        );
+        assert_eq!(
+            error_message(
+                "(function() local t = { inner = {} } t.inner.outer = t return t end)()",
+                &default
+            ),
+            "table contains a cycle (at `inner.outer`)"
+        );
+
+        let mixed = "{ 'a', 'b', name = 'c' }";
+        assert_eq!(
+            lua.to_json(lua.load(mixed).eval().unwrap()).unwrap(),
+            json!({ "1": "a", "2": "b", "name": "c" })
+        );
+        assert_eq!(
+            error_message(mixed, &JsonOptions::new().allow_mixed_tables(false)),
+            "table has both a sequence part and other keys"
+        );
+
+        // Tables reached more than once without a cycle are converted each time.
+        let shared: Value = lua
+            .load("(function() local s = { 1 } return { a = s, b = s } end)()")
+            .eval()
+            .unwrap();
+        assert_eq!(lua.to_json(shared).unwrap(), json!({ "a": [1], "b": [1] }));
+
+        let nested = "{ a = { b = { 1 } } }";
+        assert_eq!(
+            lua.to_json_with(
+                lua.load(nested).eval().unwrap(),
+                &JsonOptions::new().max_depth(3)
+            )
+            .unwrap(),
+            json!({ "a": { "b": [1] } })
+        );
+        assert_eq!(
+            error_message(nested, &JsonOptions::new().max_depth(2)),
+            "table is nested too deeply (at `a.b`)"
+        );
+        let deep = "(function() local t = {} for i = 1, 100000 do t = { t } end return t end)()";
+        assert!(error_message(deep, &default).starts_with("table is nested too deeply"));
+    });
+}
+
+#[test]
+fn test_from_json() {
+    Lua::new().context(|lua| {
+        let value = lua
+            .from_json(&json!({
+                "name": "widget",
+                "count": 3,
+                "big": 18446744073709551615u64,
+                "ratio": 0.5,
+                "tags": ["a", null, "c"],
+                "missing": null,
+            }))
+            .unwrap();
+        lua.globals().set("value", value).unwrap();
+
+        lua.load(
+            r#"
+                assert(value.name == "widget")
+                assert(math.type(value.count) == "integer" and value.count == 3)
+                assert(math.type(value.big) == "float")
+                assert(value.ratio == 0.5)
+                assert(value.tags[1] == "a" and value.tags[2] == nil and value.tags[3] == "c")
+                assert(value.missing == nil)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let nested = json!({ "a": { "b": [1] } });
+        assert!(lua
+            .from_json_with(&nested, &JsonOptions::new().max_depth(3))
+            .is_ok());
+        match lua.from_json_with(&nested, &JsonOptions::new().max_depth(2)) {
+            Err(Error::ToLuaConversionError {
+                from: "JSON",
+                to: "table",
+                ..
+            }) => {}
+            r => panic!("wrong result {:?}", r),
+        }
+        let mut deep = json!([]);
+        for _ in 0..200 {
+            deep = json!([deep]);
+        }
+        assert!(lua.from_json(&deep).is_err());
+    });
+}
+
+#[test]
+fn test_create_table_from_json() {
+    Lua::new().context(|lua| {
+        let value = lua
+            .create_table_from_json(r#"{ "items": [1, null, "three"], "nested": { "ok": true } }"#)
+            .unwrap();
+        lua.globals().set("value", value).unwrap();
+        lua.load(
+            r#"
+                assert(value.items[1] == 1 and value.items[2] == nil)
+                assert(value.items[3] == "three" and value.nested.ok == true)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        match lua.create_table_from_json("{ \"unterminated\": ") {
+            Err(Error::ToLuaConversionError {
+                from: "JSON",
+                message: Some(_),
+                ..
+            }) => {}
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+}
+
+// A small linear congruential generator, so that the generated documents are the same on every
+// run.
+struct Generator(u64);
+
+impl Generator {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) % bound
+    }
+
+    // Generates JSON which survives a round trip through Lua: no nulls, and no empty arrays
+    // (which become empty objects).
+    fn json(&mut self, depth: u32) -> JsonValue {
+        let kinds = if depth == 0 { 5 } else { 7 };
+        match self.next(kinds) {
+            0 => JsonValue::Bool(self.next(2) == 0),
+            1 => json!(self.next(1 << 40) as i64 - (1 << 39)),
+            2 => json!(self.next(1 << 20) as f64 / 64.0 + 0.5),
+            3 => json!(self.string()),
+            4 => json!(i64::MIN + self.next(3) as i64),
+            5 => {
+                let len = self.next(4) + 1;
+                JsonValue::Array((0..len).map(|_| self.json(depth - 1)).collect())
+            }
+            _ => {
+                let len = self.next(4);
+                JsonValue::Object(
+                    (0..len)
+                        .map(|_| (self.string(), self.json(depth - 1)))
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    fn string(&mut self) -> String {
+        const CHARS: &[char] = &['a', 'b', '1', ' ', '"', '\\', 'é', '\u{1f600}', '\0'];
+        (0..self.next(6))
+            .map(|_| CHARS[self.next(CHARS.len() as u64) as usize])
+            .collect()
+    }
+}
+
+#[test]
+fn test_json_round_trip() {
+    Lua::new().context(|lua| {
+        let mut generator = Generator(7);
+        for _ in 0..500 {
+            let json = generator.json(4);
+            let value = lua.from_json(&json).unwrap();
+            assert_eq!(lua.to_json(value).unwrap(), json);
+        }
+    });
+}
@@ -0,0 +1,112 @@
+use rlua::{Context, Error, FromLua, Integer, Lua, LuaEnum, Result, Table, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Key {
+    Up = 1,
+    Down = 2,
+    Escape = 27,
+}
+
+impl LuaEnum for Key {
+    fn variants() -> &'static [(&'static str, Integer)] {
+        &[("UP", 1), ("DOWN", 2), ("ESCAPE", 27)]
+    }
+
+    fn from_integer(value: Integer) -> Option<Key> {
+        match value {
+            1 => Some(Key::Up),
+            2 => Some(Key::Down),
+            27 => Some(Key::Escape),
+            _ => None,
+        }
+    }
+}
+
+impl<'lua> FromLua<'lua> for Key {
+    fn from_lua(value: Value<'lua>, lua: Context<'lua>) -> Result<Key> {
+        Key::from_lua_enum(value, lua)
+    }
+}
+
+#[test]
+fn test_register_enum() {
+    Lua::new().context(|lua| {
+        lua.register_enum::<Key>("Key").unwrap();
+
+        lua.load(
+            r#"
+                assert(Key.UP == 1 and Key.DOWN == 2 and Key.ESCAPE == 27)
+                assert(Key.names[27] == "ESCAPE")
+                assert(Key.LEFT == nil)
+
+                local count = 0
+                for name, value in pairs(Key) do
+                    assert(Key[name] == value)
+                    count = count + 1
+                end
+                assert(count == 3)
+
+                assert(not pcall(function() Key.UP = 5 end))
+                assert(not pcall(function() Key.LEFT = 3 end))
+                assert(not pcall(function() Key.names[1] = "LEFT" end))
+                assert(getmetatable(Key) == false)
+                assert(Key.UP == 1 and rawget(Key, "LEFT") == nil)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
+
+#[test]
+fn test_enum_from_lua() {
+    Lua::new().context(|lua| {
+        lua.register_enum::<Key>("Key").unwrap();
+
+        assert_eq!(lua.load("Key.ESCAPE").eval::<Key>().unwrap(), Key::Escape);
+        assert_eq!(lua.load("2").eval::<Key>().unwrap(), Key::Down);
+        assert_eq!(lua.load("1.0").eval::<Key>().unwrap(), Key::Up);
+        assert_eq!(lua.load("'UP'").eval::<Key>().unwrap(), Key::Up);
+
+        for invalid in &["3", "'LEFT'", "'up'", "1.5", "{}"] {
+            match lua.load(*invalid).eval::<Key>() {
+                Err(Error::FromLuaConversionError {
+                    message: Some(message),
+                    ..
+                }) => assert!(
+                    message.ends_with("expected one of UP (1), DOWN (2), ESCAPE (27)"),
+                    "unexpected message {:?}",
+                    message
+                ),
+                r => panic!("wrong result for {}: {:?}", invalid, r),
+            }
+        }
+    });
+}
+
+#[test]
+fn test_enum_variant_called_names() {
+    struct Reserved;
+
+    impl LuaEnum for Reserved {
+        fn variants() -> &'static [(&'static str, Integer)] {
+            &[("names", 1)]
+        }
+
+        fn from_integer(_: Integer) -> Option<Reserved> {
+            Some(Reserved)
+        }
+    }
+
+    Lua::new().context(|lua| {
+        match lua.register_enum::<Reserved>("Reserved") {
+            Err(Error::RuntimeError(message)) => assert!(message.contains("`names`")),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        assert!(lua
+            .globals()
+            .get::<_, Option<Table>>("Reserved")
+            .unwrap()
+            .is_none());
+    });
+}
@@ -1,6 +1,8 @@
-use std::sync::Arc;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use rlua::{Error, Lua, Nil, UserData};
+use rlua::{Error, GcStepOutcome, Lua, Nil, UserData};
 
 #[test]
 fn test_memory_limit() {
@@ -83,3 +85,182 @@ fn test_gc_error() {
         }
     });
 }
+
+#[test]
+fn test_gc_step_for() {
+    let lua = Lua::new();
+    // Effectively stop automatic collection, so that only `gc_step_for` collects garbage.
+    lua.gc_set_pause(1_000_000);
+    lua.gc_collect().unwrap();
+
+    let mut max_memory = 0;
+    let mut finished = false;
+    lua.context(|ctx| {
+        let make_garbage = ctx
+            .load("local t = {} for i = 1, 1000 do t[i] = { i } end")
+            .into_function()
+            .unwrap();
+        for _ in 0..500 {
+            make_garbage.call::<_, ()>(()).unwrap();
+            if lua.gc_step_for(Duration::from_millis(1)).unwrap() == GcStepOutcome::Finished {
+                finished = true;
+            }
+            max_memory = max_memory.max(lua.used_memory());
+        }
+    });
+    assert!(finished);
+    // Around 50 MB of garbage is created in total.
+    assert!(max_memory < 16 * 1024 * 1024, "used {} bytes", max_memory);
+}
+
+#[test]
+fn test_gc_step_for_disabled() {
+    let lua = Lua::new();
+    lua.gc_stop();
+    assert_eq!(
+        lua.gc_step_for(Duration::from_millis(1)).unwrap(),
+        GcStepOutcome::Disabled
+    );
+    assert!(!lua.gc_is_running());
+
+    lua.gc_restart();
+    assert_ne!(
+        lua.gc_step_for(Duration::from_millis(1)).unwrap(),
+        GcStepOutcome::Disabled
+    );
+
+    // Stepping from within a callback.
+    lua.context(|ctx| {
+        ctx.scope(|scope| {
+            let step = scope
+                .create_function(|_, ()| {
+                    lua.gc_step_for(Duration::from_millis(1))?;
+                    Ok(())
+                })
+                .unwrap();
+            ctx.globals().set("step", step).unwrap();
+            ctx.load("for i = 1, 10 do local t = { i } step() end")
+                .exec()
+                .unwrap();
+        });
+    });
+}
+
+#[test]
+fn test_userdata_counts() {
+    struct Foo;
+    impl UserData for Foo {}
+
+    struct Bar<'a>(PhantomData<&'a i32>);
+    impl<'a> UserData for Bar<'a> {}
+
+    struct Panicking;
+    impl UserData for Panicking {}
+    impl Drop for Panicking {
+        fn drop(&mut self) {
+            panic!("drop panic");
+        }
+    }
+
+    // Strips the module path and any generic parameters from the type names.
+    fn counts(lua: &Lua) -> Vec<(String, usize)> {
+        lua.userdata_counts()
+            .into_iter()
+            .map(|(name, count)| {
+                let name = name.split('<').next().unwrap();
+                (name.rsplit("::").next().unwrap().to_owned(), count)
+            })
+            .collect()
+    }
+
+    let lua = Lua::new();
+    lua.context(|lua| {
+        lua.create_userdata(Foo).unwrap();
+    });
+    assert!(lua.userdata_counts().is_empty());
+
+    lua.enable_userdata_tracking(true);
+    lua.context(|ctx| {
+        let foo = ctx.create_userdata(Foo).unwrap();
+        ctx.create_userdata_boxed(Box::new(Foo)).unwrap();
+        ctx.scope(|scope| {
+            scope.create_static_userdata(Foo).unwrap();
+            scope.create_nonstatic_userdata(Bar(PhantomData)).unwrap();
+            scope.create_nonstatic_userdata(Bar(PhantomData)).unwrap();
+            assert_eq!(
+                counts(&lua),
+                vec![("Bar".to_owned(), 2), ("Foo".to_owned(), 3)]
+            );
+        });
+        assert_eq!(counts(&lua), vec![("Foo".to_owned(), 2)]);
+
+        // Collecting the scoped userdata again does not affect the counts, and neither does
+        // collecting the userdata created before tracking was enabled.
+        lua.gc_collect().unwrap();
+        lua.gc_collect().unwrap();
+        assert_eq!(counts(&lua), vec![("Foo".to_owned(), 1)]);
+        drop(foo);
+    });
+    lua.gc_collect().unwrap();
+    assert!(lua.userdata_counts().is_empty());
+
+    lua.context(|ctx| {
+        ctx.create_userdata(Panicking).unwrap();
+        assert_eq!(counts(&lua), vec![("Panicking".to_owned(), 1)]);
+    });
+    // The panic becomes an error in the finalizer.
+    match lua.gc_collect() {
+        Err(Error::GarbageCollectorError(_)) => {}
+        r => panic!("wrong result {:?}", r),
+    }
+    assert!(lua.userdata_counts().is_empty());
+
+    lua.context(|ctx| {
+        ctx.create_userdata(Foo).unwrap();
+    });
+    lua.enable_userdata_tracking(false);
+    assert!(lua.userdata_counts().is_empty());
+    lua.gc_collect().unwrap();
+}
+
+#[test]
+fn test_finalizer_error_handler() {
+    struct Panicking;
+    impl UserData for Panicking {}
+    impl Drop for Panicking {
+        fn drop(&mut self) {
+            panic!("drop panic");
+        }
+    }
+
+    let lua = Lua::new();
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    lua.set_finalizer_error_handler({
+        let errors = errors.clone();
+        move |err| errors.lock().unwrap().push(err.to_string())
+    });
+
+    lua.context(|lua| {
+        lua.create_userdata(Panicking).unwrap();
+        assert_eq!(
+            lua.load(r#"collectgarbage("collect"); return 1 + 1"#)
+                .eval::<i64>()
+                .unwrap(),
+            2
+        );
+    });
+    assert_eq!(
+        *errors.lock().unwrap(),
+        vec!["garbage collector error: panic in __gc metamethod (drop panic)".to_owned()]
+    );
+
+    lua.remove_finalizer_error_handler();
+    lua.context(|lua| {
+        lua.create_userdata(Panicking).unwrap();
+        match lua.load(r#"collectgarbage("collect")"#).exec() {
+            Err(Error::GarbageCollectorError(_)) => {}
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+    assert_eq!(errors.lock().unwrap().len(), 1);
+}
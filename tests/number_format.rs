@@ -0,0 +1,160 @@
+use rlua::{Error, InspectOptions, Lua, NumberFormat, SandboxOptions, StdLib, Value};
+
+fn tostring(lua: &Lua, expr: &str) -> String {
+    lua.context(|lua| {
+        lua.load(&format!("tostring({})", expr))
+            .eval::<String>()
+            .unwrap()
+    })
+}
+
+#[test]
+fn test_number_format_modes() {
+    let lua = Lua::new();
+    let values = [
+        "0.1", "1e300", "-0.0", "0/0", "1/0", "2^53", "1e15", "1e-5", "10",
+    ];
+
+    let default: Vec<String> = values.iter().map(|v| tostring(&lua, v)).collect();
+    assert_eq!(default[..3], ["0.1", "1e+300", "-0.0"]);
+    // The sign of NaN depends on the platform.
+    assert!(
+        default[3] == "nan" || default[3] == "-nan",
+        "{}",
+        default[3]
+    );
+
+    lua.set_number_format(NumberFormat::Precision(14)).unwrap();
+    let precision: Vec<String> = values.iter().map(|v| tostring(&lua, v)).collect();
+    assert_eq!(precision[3], "nan");
+    for (i, (p, d)) in precision.iter().zip(&default).enumerate() {
+        if i != 3 {
+            assert_eq!(p, d);
+        }
+    }
+
+    lua.set_number_format(NumberFormat::Shortest).unwrap();
+    let shortest: Vec<String> = values.iter().map(|v| tostring(&lua, v)).collect();
+    assert_eq!(
+        shortest,
+        vec![
+            "0.1",
+            "1e+300",
+            "-0.0",
+            "nan",
+            "inf",
+            "9007199254740992.0",
+            "1000000000000000.0",
+            "1e-05",
+            "10"
+        ]
+    );
+    assert_eq!(tostring(&lua, "0.1 + 0.2"), "0.30000000000000004");
+    assert_eq!(tostring(&lua, "1e16"), "1e+16");
+
+    lua.set_number_format(NumberFormat::Precision(17)).unwrap();
+    let precision: Vec<String> = values.iter().map(|v| tostring(&lua, v)).collect();
+    assert_eq!(
+        precision[..5],
+        [
+            "0.10000000000000001",
+            "1.0000000000000001e+300",
+            "-0.0",
+            "nan",
+            "inf"
+        ]
+    );
+    lua.set_number_format(NumberFormat::Precision(0)).unwrap();
+    assert_eq!(tostring(&lua, "0.26"), "0.3");
+    assert_eq!(tostring(&lua, "-2.6e10"), "-3e+10");
+
+    lua.set_number_format(NumberFormat::Lua).unwrap();
+    assert_eq!(tostring(&lua, "0.1 + 0.2"), "0.3");
+    lua.context(|lua| {
+        lua.load("assert(getmetatable(1) == nil)").exec().unwrap();
+    });
+}
+
+#[test]
+fn test_number_format_paths() {
+    let lua = unsafe { Lua::unsafe_new_with(StdLib::ALL) };
+    lua.set_number_format(NumberFormat::Precision(3)).unwrap();
+    lua.context(|lua| {
+        let third: Value = lua.load("1 / 3").eval().unwrap();
+        assert_eq!(
+            lua.coerce_string(third.clone())
+                .unwrap()
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "0.333"
+        );
+        assert_eq!(lua.load("1 / 3").eval::<String>().unwrap(), "0.333");
+        assert_eq!(
+            lua.inspect(
+                &lua.load("{ 1 / 3, 2 }").eval().unwrap(),
+                &InspectOptions::new()
+            )
+            .unwrap(),
+            "{ 0.333, 2 }"
+        );
+        assert_eq!(
+            lua.load("string.format('%s %s', 1 / 3, 7)")
+                .eval::<String>()
+                .unwrap(),
+            "0.333 7"
+        );
+        match lua.load("error(1 / 3)").exec() {
+            Err(Error::RuntimeError(msg)) => assert!(msg.starts_with("0.333\n"), "{}", msg),
+            r => panic!("wrong result {:?}", r),
+        }
+
+        // Concatenation and numeric format specifiers are handled by Lua.
+        assert_eq!(
+            lua.load("'x' .. 1 / 3 .. string.format(' %g', 1 / 3)")
+                .eval::<String>()
+                .unwrap(),
+            "x0.33333333333333 0.333333"
+        );
+
+        // A metatable set by scripts is kept when the format is reset.
+        lua.load("debug.setmetatable(1, { __index = math })")
+            .exec()
+            .unwrap();
+    });
+    lua.set_number_format(NumberFormat::Lua).unwrap();
+    lua.context(|lua| {
+        assert_eq!(lua.load("(4):sqrt()").eval::<f64>().unwrap(), 2.0);
+        assert_eq!(
+            lua.load("tostring(1 / 3)").eval::<String>().unwrap(),
+            "0.33333333333333"
+        );
+    });
+}
+
+#[test]
+fn test_number_metatable_hidden() {
+    let lua = Lua::new();
+    lua.set_number_format(NumberFormat::Shortest).unwrap();
+    lua.context(|lua| {
+        lua.load("assert(getmetatable(1) == false)").exec().unwrap();
+    });
+    lua.set_number_format(NumberFormat::Lua).unwrap();
+    lua.context(|lua| {
+        lua.load("assert(getmetatable(1) == nil)").exec().unwrap();
+    });
+
+    let lua = unsafe { Lua::unsafe_new_with(StdLib::ALL) };
+    lua.context(|lua| {
+        lua.load("debug.setmetatable(1, { __index = math })")
+            .exec()
+            .unwrap();
+        let report = lua.sandbox(SandboxOptions::new()).unwrap();
+        assert!(report.replaced.contains(&"number metatable".to_owned()));
+        lua.load("assert(getmetatable(1) == false and (4):sqrt() == 2)")
+            .exec()
+            .unwrap();
+        let report = lua.sandbox(SandboxOptions::new()).unwrap();
+        assert!(!report.replaced.contains(&"number metatable".to_owned()));
+    });
+}
@@ -0,0 +1,544 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rlua::{
+    Error, ExternalError, Function, HookTriggers, Limits, Lua, SandboxOptions, SandboxReport,
+    StdLib, StringLimits, Table, Thread,
+};
+
+fn strings(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn test_sandbox() {
+    let bytecode = Lua::new().context(|lua| {
+        lua.load("string.dump(function() return 'escaped' end)")
+            .eval::<rlua::String>()
+            .unwrap()
+            .as_bytes()
+            .to_vec()
+    });
+
+    let lua = unsafe { Lua::unsafe_new_with(StdLib::ALL) };
+    lua.context(|lua| {
+        let report = lua.sandbox(SandboxOptions::new()).unwrap();
+        assert_eq!(
+            report,
+            SandboxReport {
+                removed: strings(&[
+                    "dofile",
+                    "loadfile",
+                    "collectgarbage",
+                    "load",
+                    "io",
+                    "os",
+                    "debug",
+                    "string.dump",
+                    "package.loadlib",
+                ]),
+                replaced: strings(&[
+                    "string.rep",
+                    "package.searchers",
+                    "print",
+                    "string metatable"
+                ]),
+            }
+        );
+        lua.globals()
+            .set("bytecode", lua.create_string(&bytecode).unwrap())
+            .unwrap();
+
+        for escape in &[
+            "load(bytecode)",
+            "load('return 1')",
+            "dofile('/etc/passwd')",
+            "loadfile('/etc/passwd')",
+            "io.open('/etc/passwd')",
+            "os.execute('true')",
+            "debug.getregistry()",
+            "require('io')",
+            "require('os').exit()",
+            "require('debug')",
+            "package.loaded.io.open('/etc/passwd')",
+            "package.loadlib('libc.so.6', 'system')",
+            "getmetatable('').__index.dump(print)",
+            "string.dump(print)",
+            "('').dump(print)",
+            "collectgarbage('count')",
+            "string.rep('x', 1 << 30)",
+            "('x'):rep(1 << 21)",
+            "string.rep('x', 2, ('y'):rep(1 << 20))",
+        ] {
+            match lua.load(*escape).exec() {
+                Err(Error::RuntimeError(_)) | Err(Error::CallbackError { .. }) => {}
+                r => panic!("escape {} was not prevented: {:?}", escape, r),
+            }
+        }
+
+        lua.load(
+            r#"
+                local t = { 5, 3, 1, 4 }
+                table.sort(t)
+                assert(table.concat(t, ",") == "1,3,4,5")
+                assert(string.format("%d-%s", 3, "x") == "3-x")
+                assert(math.max(1, 7, 3) == 7 and math.floor(2.5) == 2)
+                assert(("ab"):rep(3, ",") == "ab,ab,ab" and string.rep("x", 0) == "")
+                assert(("abc"):upper() == "ABC")
+                assert(not pcall(error, "caught"))
+                local co = coroutine.wrap(function(a) coroutine.yield(a + 1) end)
+                assert(co(1) == 2)
+                print("nowhere")
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        // Calling it again changes nothing.
+        assert_eq!(
+            lua.sandbox(SandboxOptions::new()).unwrap(),
+            SandboxReport::default()
+        );
+    });
+}
+
+#[test]
+fn test_sandbox_options() {
+    let printed = Arc::new(Mutex::new(Vec::new()));
+    let lua = Lua::new();
+    lua.context(|lua| {
+        let output = printed.clone();
+        let options = SandboxOptions::new()
+            .keep("collectgarbage")
+            .keep("os.time")
+            .keep("os.clock")
+            .keep("coroutine")
+            .allow_load_text_chunks(true)
+            .max_string_rep_len(Some(10))
+            .print_handler(move |line| output.lock().unwrap().push(line.to_owned()));
+        let report = lua.sandbox(options.clone()).unwrap();
+        assert_eq!(
+            report.removed,
+            strings(&["dofile", "loadfile", "io", "string.dump", "package.loadlib"])
+        );
+        assert_eq!(
+            report.replaced,
+            strings(&[
+                "load",
+                "os",
+                "string.rep",
+                "package.searchers",
+                "print",
+                "string metatable"
+            ])
+        );
+        assert_eq!(lua.sandbox(options).unwrap(), SandboxReport::default());
+
+        lua.load(
+            r#"
+                assert(collectgarbage("count") > 0)
+                assert(os.time() > 0 and os.clock() >= 0 and os.execute == nil)
+                assert(require("os") == os and coroutine.running ~= nil)
+
+                assert(load("return x", "chunk", "t", { x = 5 })() == 5)
+                local parts = { "return ", "1 + ", "1" }
+                assert(load(function() return table.remove(parts, 1) end)() == 2)
+                local f, err = load("return +", "=broken")
+                assert(f == nil and err:find("^broken:1:"))
+                f, err = load("return 1", "chunk", "b")
+                assert(f == nil and err:find("mode is 'b'"))
+
+                assert(string.rep("ab", 5) == "ababababab")
+                assert(not pcall(string.rep, "ab", 6))
+                print(1, "two", nil)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let bytecode: rlua::String = Lua::new().context(|source| {
+            let bytes = source
+                .load("string.dump(function() end)")
+                .eval::<rlua::String>()
+                .unwrap()
+                .as_bytes()
+                .to_vec();
+            lua.create_string(&bytes).unwrap()
+        });
+        let (f, err): (Option<rlua::Function>, String) = lua
+            .load("return load(...)")
+            .into_function()
+            .unwrap()
+            .call(bytecode)
+            .unwrap();
+        assert!(f.is_none());
+        assert!(err.contains("attempt to load a binary chunk"), "{}", err);
+
+        // Sandboxing again can lower the limit of `string.rep`, but not raise it.
+        lua.sandbox(SandboxOptions::new().max_string_rep_len(Some(20)))
+            .unwrap();
+        lua.load("assert(not pcall(string.rep, 'ab', 6))")
+            .exec()
+            .unwrap();
+        lua.sandbox(SandboxOptions::new().max_string_rep_len(Some(6)))
+            .unwrap();
+        lua.load("assert(#string.rep('ab', 3) == 6 and not pcall(string.rep, 'ab', 4))")
+            .exec()
+            .unwrap();
+    });
+    assert_eq!(*printed.lock().unwrap(), strings(&["1\ttwo\tnil"]));
+}
+
+#[test]
+fn test_sandbox_keeps_lower_rep_limit() {
+    let lua = Lua::new();
+    lua.set_string_limits(StringLimits {
+        max_rep_output: Some(10),
+        ..StringLimits::default()
+    })
+    .unwrap();
+    lua.context(|lua| {
+        lua.sandbox(SandboxOptions::new()).unwrap();
+        lua.load("assert(#string.rep('ab', 5) == 10 and not pcall(string.rep, 'ab', 6))")
+            .exec()
+            .unwrap();
+    });
+}
+
+#[test]
+fn test_sandbox_load_with_error_hook() {
+    let lua = Lua::new();
+    lua.set_error_hook(|err| match err {
+        Error::SyntaxError { .. } => err.to_string().to_lua_err(),
+        err => err,
+    });
+    lua.context(|lua| {
+        lua.sandbox(SandboxOptions::new().allow_load_text_chunks(true))
+            .unwrap();
+        lua.load(
+            r#"
+                local f, err = load('x = = 1')
+                assert(f == nil and err:find("unexpected symbol"))
+                assert(load('return 1')() == 1)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        // The hook still applies to syntax errors returned to Rust.
+        match lua.load("x = = 1").exec() {
+            Err(Error::ExternalError(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_sandbox_load_chunk_size() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        lua.sandbox(SandboxOptions::new().allow_load_text_chunks(true))
+            .unwrap();
+        lua.load(
+            r#"
+                function check()
+                    local f, err = load('return 1 + 2 + 3')
+                    assert(f == nil and err:find("chunk too large"))
+                    assert(load('return 1')() == 1)
+
+                    local calls = 0
+                    f, err = load(function()
+                        calls = calls + 1
+                        return "--"
+                    end)
+                    assert(f == nil and err:find("chunk too large") and calls == 6)
+                end
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+    lua.set_max_chunk_size(Some(10));
+    lua.context(|lua| {
+        let check: Function = lua.globals().get("check").unwrap();
+        check.call::<_, ()>(()).unwrap();
+    });
+}
+
+#[test]
+fn test_sandbox_restricted_std_lib() {
+    Lua::new_with(StdLib::BASE | StdLib::STRING).context(|lua| {
+        let report = lua.sandbox(SandboxOptions::new()).unwrap();
+        assert_eq!(
+            report.removed,
+            strings(&[
+                "dofile",
+                "loadfile",
+                "collectgarbage",
+                "load",
+                "string.dump"
+            ])
+        );
+        assert_eq!(
+            report.replaced,
+            strings(&["string.rep", "print", "string metatable"])
+        );
+        assert_eq!(lua.load("('x'):rep(3)").eval::<String>().unwrap(), "xxx");
+    });
+}
+
+#[test]
+fn test_run_sandboxed() {
+    let lua = Lua::new();
+    let hook_calls = Arc::new(AtomicUsize::new(0));
+    let counter = hook_calls.clone();
+    lua.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(10),
+            ..Default::default()
+        },
+        move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        },
+    );
+    lua.set_memory_limit(Some(64 * 1024 * 1024));
+
+    lua.context(|lua_context| {
+        let globals = lua_context.globals();
+        lua_context
+            .load(
+                r#"
+                    function spin() while true do end end
+                    function recurse(n) if n > 0 then return 1 + recurse(n - 1) end return 0 end
+                    function allocate()
+                        local t = {}
+                        for i = 1, 1000000 do t[i] = ("x"):rep(100) .. i end
+                        return #t
+                    end
+                "#,
+            )
+            .exec()
+            .unwrap();
+        let function = |name: &str| globals.get::<_, Function>(name).unwrap();
+
+        let limits = Limits {
+            deadline: Some(Instant::now() + Duration::from_millis(20)),
+            max_memory: Some(lua.used_memory() + 1024 * 1024),
+            max_stack_depth: Some(50),
+        };
+        match lua.run_sandboxed::<()>(function("spin"), limits) {
+            Err(Error::DeadlineExceeded) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        let limits = Limits {
+            deadline: Some(Instant::now() + Duration::from_secs(60)),
+            ..limits
+        };
+        match lua.run_sandboxed::<i64>(function("allocate"), limits) {
+            Err(Error::MemoryLimitExceeded { limit }) => {
+                assert_eq!(Some(limit), limits.max_memory)
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        let recurse = function("recurse");
+        match lua.run_sandboxed::<i64>(recurse.bind(100).unwrap(), limits) {
+            Err(Error::StackDepthExceeded { limit: 50 }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(
+            lua.run_sandboxed::<i64>(recurse.bind(40).unwrap(), limits)
+                .unwrap(),
+            40
+        );
+
+        // The deadline interrupts code that catches the error and keeps running.
+        let stubborn: Function = lua_context
+            .load("function() while true do pcall(spin) end end")
+            .eval()
+            .unwrap();
+        let limits = Limits {
+            deadline: Some(Instant::now() + Duration::from_millis(20)),
+            ..Limits::default()
+        };
+        match lua.run_sandboxed::<()>(stubborn, limits) {
+            Err(Error::DeadlineExceeded) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+
+    // The previous hook and memory limit are back in place.
+    let before = hook_calls.load(Ordering::SeqCst);
+    lua.context(|lua_context| {
+        let table: Table = lua_context.load("return { recurse(10) }").eval().unwrap();
+        assert_eq!(table.raw_get::<_, i64>(1).unwrap(), 10);
+    });
+    assert!(hook_calls.load(Ordering::SeqCst) > before);
+    lua.context(|lua_context| {
+        match lua_context
+            .load("local t = {} for i = 1, 1e7 do t[i] = i end")
+            .exec()
+        {
+            Err(Error::MemoryError(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+
+    // Without a previous hook, none is left behind.
+    lua.remove_hook();
+    let limits = Limits {
+        deadline: Some(Instant::now() + Duration::from_secs(60)),
+        ..Limits::default()
+    };
+    lua.context(|lua_context| {
+        let f: Function = lua_context.load("return 1").into_function().unwrap();
+        assert_eq!(lua.run_sandboxed::<i64>(f, limits).unwrap(), 1);
+    });
+    let before = hook_calls.load(Ordering::SeqCst);
+    lua.context(|lua_context| lua_context.load("recurse(10)").exec().unwrap());
+    assert_eq!(hook_calls.load(Ordering::SeqCst), before);
+
+    // A function from another state is not called.
+    let other = Lua::new();
+    other.context(|other_context| {
+        let f: Function = other_context.load("ran = true").into_function().unwrap();
+        match lua.run_sandboxed::<()>(f, limits) {
+            Err(Error::RuntimeError(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(
+            other_context
+                .globals()
+                .get::<_, Option<bool>>("ran")
+                .unwrap(),
+            None
+        );
+    });
+}
+
+#[test]
+fn test_run_sandboxed_coroutine_hooks() {
+    let lua = Lua::new();
+    let hook_calls = Arc::new(AtomicUsize::new(0));
+    let counter = hook_calls.clone();
+    lua.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(1_000_000),
+            ..Default::default()
+        },
+        move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        },
+    );
+
+    lua.context(|lua_context| {
+        let run: Function = lua_context
+            .load(
+                r#"
+                    function()
+                        co = coroutine.create(function()
+                            while true do
+                                for i = 1, 1000 do end
+                                coroutine.yield()
+                            end
+                        end)
+                        coroutine.resume(co)
+                        while true do end
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        let limits = Limits {
+            deadline: Some(Instant::now() + Duration::from_millis(20)),
+            ..Limits::default()
+        };
+        match lua.run_sandboxed::<()>(run, limits) {
+            Err(Error::DeadlineExceeded) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(hook_calls.load(Ordering::SeqCst), 0);
+
+        // The coroutine created during the call goes back to the previous hook, rather than
+        // calling it with the triggers of the sandbox.
+        let co: Thread = lua_context.globals().get("co").unwrap();
+        for _ in 0..100 {
+            co.resume::<_, ()>(()).unwrap();
+        }
+        assert!(hook_calls.load(Ordering::SeqCst) <= 1);
+    });
+}
+
+#[test]
+fn test_run_sandboxed_lower_memory_limit() {
+    let lua = Lua::new();
+    lua.set_memory_limit(Some(lua.used_memory() + 1024 * 1024));
+    let limits = Limits {
+        max_memory: Some(64 * 1024 * 1024),
+        ..Limits::default()
+    };
+    lua.context(|lua_context| {
+        let allocate: Function = lua_context
+            .load("function() local t = {} for i = 1, 1e7 do t[i] = i end end")
+            .eval()
+            .unwrap();
+        // The memory limit which was already set is the one exceeded, not `max_memory`.
+        match lua.run_sandboxed::<()>(allocate, limits) {
+            Err(Error::MemoryError(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_run_sandboxed_panic() {
+    let lua = Lua::new();
+    let hook_calls = Arc::new(AtomicUsize::new(0));
+    let counter = hook_calls.clone();
+    lua.set_hook(
+        HookTriggers {
+            every_nth_instruction: Some(10),
+            ..Default::default()
+        },
+        move |_, _| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        },
+    );
+    lua.set_memory_limit(Some(64 * 1024 * 1024));
+
+    let limits = Limits {
+        deadline: Some(Instant::now() + Duration::from_secs(60)),
+        max_memory: Some(lua.used_memory() + 1024 * 1024),
+        ..Limits::default()
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        lua.context(|lua_context| {
+            let fail = lua_context
+                .create_function(|_, ()| -> Result<(), Error> { panic!("sandboxed panic") })
+                .unwrap();
+            let _ = lua.run_sandboxed::<()>(fail, limits);
+        })
+    }));
+    assert!(result.is_err());
+
+    // The previous hook and memory limit are back in place.
+    let before = hook_calls.load(Ordering::SeqCst);
+    lua.context(|lua_context| {
+        lua_context
+            .load("local t = {} for i = 1, 1e5 do t[i] = ('x'):rep(10) .. i end")
+            .exec()
+            .unwrap();
+        match lua_context
+            .load("local t = {} for i = 1, 1e7 do t[i] = i end")
+            .exec()
+        {
+            Err(Error::MemoryError(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+    assert!(hook_calls.load(Ordering::SeqCst) > before);
+}
@@ -0,0 +1,222 @@
+use rlua::{Error, GlobalsDiff, Lua, Table};
+
+#[test]
+fn restore_globals() {
+    let lua = Lua::new();
+
+    let snapshot = lua.context(|lua| lua.snapshot_globals(false).unwrap());
+
+    lua.context(|lua| {
+        lua.load(
+            r#"
+                defined = "new global"
+                string.upper = function() return "patched" end
+                print = nil
+                setmetatable(_G, { __index = function() return 1 end })
+                assert(("abc"):upper() == "patched")
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        lua.restore_globals(&snapshot).unwrap();
+
+        lua.load(
+            r#"
+                assert(rawget(_G, "defined") == nil)
+                assert(getmetatable(_G) == nil)
+                assert(print ~= nil)
+                assert(string.upper("abc") == "ABC")
+                assert(("abc"):upper() == "ABC")
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
+
+#[test]
+fn restore_package_loaded() {
+    let lua = Lua::new();
+
+    lua.context(|lua| {
+        let with_loaded = lua.snapshot_globals(true).unwrap();
+        let without_loaded = lua.snapshot_globals(false).unwrap();
+
+        let set_loaded = lua.load(r#"package.loaded.cached = "module""#);
+        set_loaded.exec().unwrap();
+
+        lua.restore_globals(&without_loaded).unwrap();
+        lua.load(r#"assert(package.loaded.cached == "module")"#)
+            .exec()
+            .unwrap();
+
+        lua.restore_globals(&with_loaded).unwrap();
+        lua.load(r#"assert(package.loaded.cached == nil)"#)
+            .exec()
+            .unwrap();
+    });
+
+    let other = Lua::new();
+    let snapshot = other.context(|other| other.snapshot_globals(false).unwrap());
+    lua.context(|lua| match lua.restore_globals(&snapshot) {
+        Err(Error::MismatchedRegistryKey) => {}
+        r => panic!("expected MismatchedRegistryKey, got {:?}", r),
+    });
+}
+
+#[test]
+fn diff_globals() {
+    let lua = Lua::new();
+    lua.context(|lua| {
+        lua.load(
+            r#"
+                config = { window = { width = 800 }, name = "app" }
+                doomed = true
+                copied = { 1, 2, { 3 } }
+                untouched = { 4 }
+            "#,
+        )
+        .exec()
+        .unwrap();
+        let snapshot = lua.snapshot_globals(false).unwrap();
+
+        lua.load(
+            r#"
+                created = 1
+                config.window.width = 1024
+                doomed = nil
+                copied = { 1, 2, { 3 } }
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let strings = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            lua.diff_globals(&snapshot, false).unwrap(),
+            GlobalsDiff {
+                added: strings(&["created"]),
+                modified: strings(&["copied"]),
+                removed: strings(&["doomed"]),
+                replaced_with_equal: Vec::new(),
+            }
+        );
+        assert_eq!(
+            lua.diff_globals(&snapshot, true).unwrap(),
+            GlobalsDiff {
+                added: strings(&["created"]),
+                modified: strings(&["config"]),
+                removed: strings(&["doomed"]),
+                replaced_with_equal: strings(&["copied"]),
+            }
+        );
+
+        lua.load("copied[3][1] = 4; setmetatable(untouched, {})")
+            .exec()
+            .unwrap();
+        let diff = lua.diff_globals(&snapshot, true).unwrap();
+        assert_eq!(diff.modified, strings(&["config", "copied", "untouched"]));
+        assert!(diff.replaced_with_equal.is_empty());
+
+        lua.restore_globals(&snapshot).unwrap();
+        assert_eq!(
+            lua.diff_globals(&snapshot, true).unwrap(),
+            GlobalsDiff::default()
+        );
+
+        lua.load(
+            r#"
+                copied = setmetatable({ 1, 2, { 3 } }, { __index = {} })
+                _G["\xff"] = true
+            "#,
+        )
+        .exec()
+        .unwrap();
+        let diff = lua.diff_globals(&snapshot, true).unwrap();
+        assert_eq!(diff.added, strings(&["\u{fffd}"]));
+        assert_eq!(diff.modified, strings(&["copied"]));
+        assert!(diff.replaced_with_equal.is_empty());
+
+        let other = Lua::new().context(|lua| lua.snapshot_globals(false).unwrap());
+        match lua.diff_globals(&other, false) {
+            Err(Error::MismatchedRegistryKey) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn snapshot_deeply_nested() {
+    Lua::new().context(|lua| {
+        lua.load(
+            r#"
+                nested = {}
+                local t = nested
+                for i = 1, 100000 do
+                    t.next = {}
+                    t = t.next
+                end
+                t.value = "deep"
+            "#,
+        )
+        .exec()
+        .unwrap();
+        let snapshot = lua.snapshot_globals(false).unwrap();
+
+        lua.load(
+            r#"
+                local t = nested
+                while t.next do t = t.next end
+                t.value = "changed"
+            "#,
+        )
+        .exec()
+        .unwrap();
+        lua.restore_globals(&snapshot).unwrap();
+        lua.load(
+            r#"
+                local t = nested
+                while t.next do t = t.next end
+                assert(t.value == "deep")
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
+
+#[test]
+fn diff_deeply_nested() {
+    Lua::new().context(|lua| {
+        let create = r#"
+            local root = {}
+            local t = root
+            for i = 1, 100000 do
+                t.next = {}
+                t = t.next
+            end
+            t.value = ...
+            return root
+        "#;
+        lua.globals()
+            .set("nested", lua.load(create).call::<_, Table>("deep").unwrap())
+            .unwrap();
+        let snapshot = lua.snapshot_globals(false).unwrap();
+
+        lua.globals()
+            .set("nested", lua.load(create).call::<_, Table>("deep").unwrap())
+            .unwrap();
+        let diff = lua.diff_globals(&snapshot, true).unwrap();
+        assert_eq!(diff.replaced_with_equal, vec!["nested".to_owned()]);
+
+        lua.globals()
+            .set(
+                "nested",
+                lua.load(create).call::<_, Table>("other").unwrap(),
+            )
+            .unwrap();
+        let diff = lua.diff_globals(&snapshot, true).unwrap();
+        assert_eq!(diff.modified, vec!["nested".to_owned()]);
+    });
+}
@@ -1,6 +1,6 @@
 use std::borrow::Cow;
 
-use rlua::{Lua, String};
+use rlua::{Lua, String, Table, TypeCategory, Value};
 
 fn with_str<F>(s: &str, f: F)
 where
@@ -58,7 +58,7 @@ fn string_views() {
 
         assert_eq!(empty.to_str().unwrap(), "");
         assert_eq!(empty.as_bytes_with_nul(), &[0]);
-        assert_eq!(empty.as_bytes(), &[]);
+        assert_eq!(empty.as_bytes(), b"");
     });
 }
 
@@ -69,3 +69,105 @@ fn raw_string() {
         assert_eq!(rs.as_bytes(), &[0, 1, 2, 3, 0, 1, 2, 3]);
     });
 }
+
+#[test]
+fn string_from_chunks() {
+    Lua::new().context(|lua| {
+        let contiguous = (0..10 * 1024 * 1024)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<u8>>();
+        let chunked = lua
+            .create_string_from_chunks(contiguous.chunks(4096))
+            .unwrap();
+        assert_eq!(chunked, lua.create_string(&contiguous).unwrap());
+        assert_eq!(chunked.as_bytes().len(), 10 * 1024 * 1024);
+
+        let small = lua
+            .create_string_from_chunks(vec![&b"a\0b"[..], b"", b"\xff\0"])
+            .unwrap();
+        assert_eq!(small.as_bytes(), b"a\0b\xff\0");
+
+        let empty = lua.create_string_from_chunks(Vec::<&[u8]>::new()).unwrap();
+        assert_eq!(empty.as_bytes(), b"");
+    });
+}
+
+#[test]
+fn intern_string() {
+    let lua = Lua::new();
+    let before = lua.registry_report().values.get("string").copied();
+    lua.context(|lua| {
+        let a = lua.intern_string("field").unwrap();
+        let b = lua.intern_string(&b"field"[..]).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(b, "field");
+        drop((a, b));
+
+        let binary = lua.intern_string(&b"\0\xff"[..]).unwrap();
+        assert_eq!(binary.as_bytes(), b"\0\xff");
+        lua.intern_string("field").unwrap();
+
+        let globals = lua.globals();
+        globals.set(lua.intern_string("key").unwrap(), 1).unwrap();
+        assert_eq!(globals.get::<_, i64>("key").unwrap(), 1);
+    });
+    let after = lua.registry_report().values["string"];
+    assert_eq!(after, before.unwrap_or(0) + 3);
+}
+
+#[test]
+fn test_string_metatable() {
+    Lua::new().context(|lua| {
+        let metatable = lua.metatable_of(TypeCategory::String).unwrap().unwrap();
+        let index: Table = metatable.get("__index").unwrap();
+        index
+            .set(
+                "shout",
+                lua.create_function(|_, s: String| Ok(format!("{}!", s.to_str()?.to_uppercase())))
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(
+            lua.load(r#"("abc"):shout()"#).eval::<String>().unwrap(),
+            "ABC!"
+        );
+        assert!(lua.metatable_of(TypeCategory::Boolean).unwrap().is_none());
+
+        lua.seal_string_metatable(true).unwrap();
+        assert_eq!(
+            lua.load(r#"("abc"):shout()"#).eval::<String>().unwrap(),
+            "ABC!"
+        );
+        assert!(matches!(
+            lua.load(r#"getmetatable("")"#).eval::<Value>().unwrap(),
+            Value::Boolean(false)
+        ));
+        assert!(lua
+            .load(r#"return getmetatable("").__index"#)
+            .exec()
+            .is_err());
+
+        // The methods seen by strings are a copy of the `string` table.
+        lua.load("string.upper = nil").exec().unwrap();
+        assert_eq!(
+            lua.load(r#"("abc"):upper()"#).eval::<String>().unwrap(),
+            "ABC"
+        );
+
+        lua.set_metatable_of(TypeCategory::String, None).unwrap();
+        assert!(lua.metatable_of(TypeCategory::String).unwrap().is_none());
+        assert!(lua.load(r#"("abc"):upper()"#).exec().is_err());
+    });
+}
+
+#[test]
+fn test_seal_missing_string_metatable() {
+    Lua::new_with(rlua::StdLib::BASE).context(|lua| {
+        assert!(lua.metatable_of(TypeCategory::String).unwrap().is_none());
+        lua.seal_string_metatable(false).unwrap();
+        assert!(matches!(
+            lua.load(r#"getmetatable("")"#).eval::<Value>().unwrap(),
+            Value::Boolean(false)
+        ));
+    });
+}
@@ -0,0 +1,145 @@
+use rlua::{Error, Lua, StringLimits};
+
+fn assert_limit_error(lua: &Lua, source: &str, limit: &str) {
+    lua.context(|lua| match lua.load(source).exec() {
+        Err(Error::RuntimeError(message)) => {
+            assert!(message.contains(limit), "{}: {}", source, message)
+        }
+        r => panic!("{} did not exceed {}: {:?}", source, limit, r),
+    });
+}
+
+fn assert_ok(lua: &Lua, source: &str) {
+    lua.context(|lua| lua.load(source).exec().unwrap());
+}
+
+#[test]
+fn test_string_limits_rep() {
+    let lua = Lua::new();
+    lua.set_string_limits(StringLimits {
+        max_rep_output: Some(10),
+        ..StringLimits::default()
+    })
+    .unwrap();
+
+    assert_ok(
+        &lua,
+        r#"
+            assert(string.rep("ab", 5) == "ababababab")
+            assert(("abc"):rep(2, "x") == "abcxabc")
+            assert(string.rep("ab", 0) == "" and string.rep("ab", -1) == "")
+            assert(string.rep(12, 5) == "1212121212")
+        "#,
+    );
+    for source in &[
+        "string.rep('ab', 6)",
+        "('abc'):rep(3, 'xy')",
+        "string.rep('x', math.maxinteger, 'y')",
+    ] {
+        assert_limit_error(&lua, source, "max_rep_output");
+    }
+    // Invalid arguments are still reported by `string.rep` itself.
+    assert_limit_error(&lua, "string.rep('x', 'y')", "bad argument #2");
+}
+
+#[test]
+fn test_string_limits_subject() {
+    let lua = Lua::new();
+    lua.set_string_limits(StringLimits {
+        max_pattern_subject: Some(8),
+        ..StringLimits::default()
+    })
+    .unwrap();
+
+    assert_ok(
+        &lua,
+        r#"
+            local s = "abcdefgh"
+            assert(s:find("d") == 4 and string.find(s, "e", 1, true) == 5)
+            assert(s:match("c(.)e") == "d")
+            local count = 0
+            for c in s:gmatch(".") do count = count + 1 end
+            assert(count == 8)
+            assert(s:gsub("a", "A") == "Abcdefgh")
+        "#,
+    );
+    for source in &[
+        "('abcdefghi'):find('a')",
+        "string.find('abcdefghi', 'a', 1, true)",
+        "string.match('abcdefghi', '.')",
+        "string.gmatch('abcdefghi', '.')",
+        "string.gsub('abcdefghi', '.', 'x')",
+    ] {
+        assert_limit_error(&lua, source, "max_pattern_subject");
+    }
+}
+
+#[test]
+fn test_string_limits_match_depth() {
+    let lua = Lua::new();
+    lua.set_string_limits(StringLimits {
+        max_match_depth: Some(4),
+        ..StringLimits::default()
+    })
+    .unwrap();
+
+    assert_ok(
+        &lua,
+        r#"
+            assert(string.find("a.b", "a%.b") == 1)
+            assert(("key = value"):match("(%w+)%s*=") == "key")
+            assert(("a(b)c"):match("%b()") == "(b)")
+            assert((" ab"):find("%f[%a]%a*") == 2)
+            assert(("[]]"):match("[]]+") == "]]")
+            assert(string.find("a*b*c*d*e*", "a*b*c*d*e*", 1, true) == 1)
+            for w in ("one two"):gmatch("%a+") do end
+            assert(("aaa"):gsub("a?a?a?a?", "") == "")
+        "#,
+    );
+    for source in &[
+        "string.find('aaaa', 'a*a*a*a*a*')",
+        "('key = value'):match('((%w+)%s*=%s*(%w+))')",
+        "string.gmatch('abc', '[a-z]*[]]-.?.+.*')",
+        "string.gsub('abc', '(%a)(%a)(%a)', '%3')",
+    ] {
+        assert_limit_error(&lua, source, "max_match_depth");
+    }
+}
+
+#[test]
+fn test_string_limits_default() {
+    let lua = Lua::new();
+    assert_eq!(lua.string_limits(), StringLimits::default());
+    let functions = "{ string.rep, string.find, string.match, string.gmatch, string.gsub }";
+    lua.context(|lua| {
+        lua.load(&format!("original = {}", functions))
+            .exec()
+            .unwrap();
+    });
+
+    // Unlimited defaults leave the library untouched.
+    lua.set_string_limits(StringLimits::default()).unwrap();
+    assert_ok(
+        &lua,
+        &format!(
+            "for i, f in ipairs({}) do assert(original[i] == f) end",
+            functions
+        ),
+    );
+    let source = r#"
+        assert(#string.rep("ab", 1000) == 2000)
+        assert(("x"):rep(100):find(("x*"):rep(10)) == 1)
+    "#;
+    assert_ok(&lua, source);
+
+    // Lifting the limits makes the wrappers transparent.
+    lua.set_string_limits(StringLimits {
+        max_rep_output: Some(1),
+        max_pattern_subject: Some(1),
+        max_match_depth: Some(1),
+    })
+    .unwrap();
+    assert_limit_error(&lua, "string.rep('ab', 2)", "max_rep_output");
+    lua.set_string_limits(StringLimits::default()).unwrap();
+    assert_ok(&lua, source);
+}
@@ -1,4 +1,6 @@
-use rlua::{Lua, Nil, Result, Table, Value};
+use std::collections::HashMap;
+
+use rlua::{Context, Error, FromLua, Lua, Nil, Result, Table, ToLua, Value};
 
 #[test]
 fn test_set_get() {
@@ -72,7 +74,7 @@ fn test_table() {
                 .sequence_values()
                 .collect::<Result<Vec<i64>>>()
                 .unwrap(),
-            vec![]
+            Vec::<i64>::new()
         );
 
         // sequence_values should only iterate until the first border
@@ -150,6 +152,96 @@ fn test_metatable() {
     });
 }
 
+#[test]
+fn test_raw_remove() {
+    Lua::new().context(|lua| {
+        let table: Table = lua
+            .load(
+                r#"
+                    setmetatable({ 1, 2, 3, 4, key = "value" }, {
+                        __index = function() error("index") end,
+                        __newindex = function() error("newindex") end,
+                        __len = function() error("len") end,
+                    })
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        table.raw_remove(2).unwrap();
+        assert_eq!(table.raw_len(), 3);
+        let values = (1..=3)
+            .map(|i| table.raw_get(i))
+            .collect::<Result<Vec<i64>>>()
+            .unwrap();
+        assert_eq!(values, vec![1, 3, 4]);
+
+        table.raw_remove(3).unwrap();
+        assert_eq!(table.raw_len(), 2);
+
+        table.raw_remove(10).unwrap();
+        table.raw_remove("key").unwrap();
+        assert!(table.raw_get::<_, Option<String>>("key").unwrap().is_none());
+        assert_eq!(table.raw_len(), 2);
+    });
+}
+
+#[test]
+fn test_sort_by() {
+    Lua::new().context(|lua| {
+        let table: Table = lua
+            .load(
+                r#"
+                    setmetatable({ 5, 3, 4, 1, 2, key = "value" }, {
+                        __index = function() error("index") end,
+                        __newindex = function() error("newindex") end,
+                        __len = function() error("len") end,
+                    })
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        let mut calls = 0;
+        table
+            .sort_by(|a, b| {
+                calls += 1;
+                match (a, b) {
+                    (Value::Integer(a), Value::Integer(b)) => b.cmp(a),
+                    _ => panic!("unexpected values"),
+                }
+            })
+            .unwrap();
+        assert!(calls > 0);
+        let values = (1..=5)
+            .map(|i| table.raw_get(i))
+            .collect::<Result<Vec<i64>>>()
+            .unwrap();
+        assert_eq!(values, vec![5, 4, 3, 2, 1]);
+        assert_eq!(table.raw_get::<_, String>("key").unwrap(), "value");
+
+        // The sort is stable.
+        let pairs: Table = lua
+            .load("{ {1, 'a'}, {0, 'b'}, {1, 'c'}, {0, 'd'} }")
+            .eval()
+            .unwrap();
+        let key = |v: &Value| match v {
+            Value::Table(t) => t.raw_get::<_, i64>(1).unwrap(),
+            _ => panic!("expected a table"),
+        };
+        pairs.sort_by(|a, b| key(a).cmp(&key(b))).unwrap();
+        let order = pairs
+            .sequence_values::<Table>()
+            .map(|t| t.and_then(|t| t.raw_get(2)))
+            .collect::<Result<Vec<String>>>()
+            .unwrap();
+        assert_eq!(order, vec!["b", "d", "a", "c"]);
+
+        let empty = lua.create_table().unwrap();
+        empty.sort_by(|_, _| panic!("no comparisons")).unwrap();
+    });
+}
+
 #[test]
 fn test_table_error() {
     Lua::new().context(|lua| {
@@ -182,3 +274,500 @@ fn test_table_error() {
         assert_eq!(bad_table.raw_len(), 1);
     });
 }
+
+#[test]
+fn test_len_metamethod() {
+    Lua::new().context(|lua| {
+        let table: Table = lua
+            .load("setmetatable({ 1, 2, 3 }, { __len = function() return 42 end })")
+            .eval()
+            .unwrap();
+        assert_eq!(table.len().unwrap(), 42);
+        assert_eq!(table.raw_len(), 3);
+
+        let table: Table = lua
+            .load("setmetatable({}, { __len = function() return 'long' end })")
+            .eval()
+            .unwrap();
+        assert!(table.len().is_err());
+    });
+}
+
+#[test]
+fn test_contains_key() {
+    Lua::new().context(|lua| {
+        let table: Table = lua
+            .load(
+                r#"
+                    setmetatable({ a = 1, b = false }, {
+                        __index = function(_, k) if k == "c" then return 3 end end,
+                    })
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert!(table.contains_key("a").unwrap());
+        assert!(table.contains_key("b").unwrap());
+        assert!(table.contains_key("c").unwrap());
+        assert!(!table.contains_key("d").unwrap());
+
+        assert!(table.raw_contains_key("a").unwrap());
+        assert!(table.raw_contains_key("b").unwrap());
+        assert!(!table.raw_contains_key("c").unwrap());
+
+        let bad_table: Table = lua
+            .load(r#"setmetatable({}, { __index = function() error("index") end })"#)
+            .eval()
+            .unwrap();
+        assert!(bad_table.contains_key(1).is_err());
+        assert!(!bad_table.raw_contains_key(1).unwrap());
+    });
+}
+
+#[test]
+fn test_create_table_with_capacity() {
+    let lua = Lua::new();
+    lua.context(|ctx| {
+        let before = lua.used_memory();
+        let table = ctx.create_table_with_capacity(100, 1000).unwrap();
+        let allocated = lua.used_memory() - before;
+        assert!(allocated > 100 * 16 + 1000 * 16, "{}", allocated);
+
+        // Filling the preallocated parts does not grow the table.
+        let keys: Vec<rlua::String> = (0..1000)
+            .map(|i| ctx.create_string(&format!("key{}", i)).unwrap())
+            .collect();
+        let before = lua.used_memory();
+        for i in 1..=100 {
+            table.raw_set(i, i).unwrap();
+        }
+        for key in keys {
+            table.raw_set(key, true).unwrap();
+        }
+        assert_eq!(lua.used_memory(), before);
+        assert_eq!(table.raw_len(), 100);
+
+        let map: HashMap<String, i64> = (0..1000).map(|i| (format!("k{}", i), i)).collect();
+        let table = ctx.create_table_from(map.clone()).unwrap();
+        assert_eq!(table.clone().pairs::<String, i64>().count(), 1000);
+        for (k, v) in map {
+            assert_eq!(table.raw_get::<_, i64>(k).unwrap(), v);
+        }
+
+        let sequence = ctx.create_sequence_from(0..1000).unwrap();
+        assert_eq!(sequence.raw_len(), 1000);
+        assert_eq!(sequence.raw_get::<_, i64>(1000).unwrap(), 999);
+
+        // Iterators without a size hint are still collected in full.
+        let filtered = ctx
+            .create_sequence_from((0..10).filter(|i| i % 2 == 0))
+            .unwrap();
+        assert_eq!(filtered.sequence_values::<i64>().count(), 5);
+    });
+}
+
+#[test]
+fn test_table_builder() {
+    struct Unconvertible;
+
+    impl<'lua> ToLua<'lua> for Unconvertible {
+        fn to_lua(self, _: Context<'lua>) -> Result<Value<'lua>> {
+            Err(Error::ToLuaConversionError {
+                from: "Unconvertible",
+                to: "value",
+                message: None,
+            })
+        }
+    }
+
+    Lua::new().context(|lua| {
+        lua.register_global_module("geometry", |b| {
+            b.value("version", "1.0")
+                .function("area", |_, (w, h): (f64, f64)| Ok(w * h))
+                .table("vec2", |b| {
+                    b.value("dims", 2)
+                        .function("length", |_, (x, y): (f64, f64)| Ok((x * x + y * y).sqrt()))
+                        .metatable(|b| b.function("__index", |_, _: (Table, String)| Ok("missing")))
+                })
+        })
+        .unwrap();
+
+        lua.load(
+            r#"
+                assert(geometry.version == "1.0")
+                assert(geometry.area(2, 3) == 6)
+                assert(geometry.vec2.dims == 2)
+                assert(geometry.vec2.length(3, 4) == 5)
+                assert(geometry.vec2.other == "missing")
+                assert(require("geometry") == geometry)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        match lua
+            .table_builder()
+            .table("outer", |b| {
+                b.value("ok", 1)
+                    .table("inner", |b| b.value("huge", Unconvertible))
+            })
+            .build()
+        {
+            Err(Error::TableBuildError { path, cause }) => {
+                assert_eq!(path, "outer.inner.huge");
+                match *cause {
+                    Error::ToLuaConversionError { .. } => {}
+                    ref err => panic!("wrong cause {:?}", err),
+                }
+            }
+            r => panic!("wrong result {:?}", r),
+        }
+
+        match lua.register_global_module("broken", |b| b.value("huge", Unconvertible)) {
+            Err(Error::TableBuildError { path, .. }) => assert_eq!(path, "broken.huge"),
+            r => panic!("wrong result {:?}", r),
+        }
+        assert!(lua
+            .globals()
+            .get::<_, Option<Table>>("broken")
+            .unwrap()
+            .is_none());
+    });
+}
+
+#[test]
+fn test_set_readonly() {
+    Lua::new().context(|lua| {
+        let config: Table = lua
+            .load("{ 'a', 'b', name = 'config', nested = { x = 1 } }")
+            .eval()
+            .unwrap();
+        config.set_readonly().unwrap();
+        config.set_readonly().unwrap();
+        lua.globals().set("config", config.clone()).unwrap();
+
+        lua.load(
+            r#"
+                assert(config.name == "config" and config[2] == "b" and #config == 2)
+                local count = 0
+                for k, v in pairs(config) do count = count + 1 end
+                assert(count == 4)
+                for i, v in ipairs(config) do assert(v == ({ "a", "b" })[i]) end
+                assert(getmetatable(config) == false)
+
+                -- Nested tables are not affected.
+                config.nested.x = 2
+            "#,
+        )
+        .exec()
+        .unwrap();
+        assert_eq!(config.get::<_, String>("name").unwrap(), "config");
+
+        for source in &[
+            "config.name = 'other'",
+            "config.new = true",
+            "config[1] = nil",
+            "table.insert(config, 'c')",
+            "rawset(config, 'x', 1); config.name = 1",
+        ] {
+            match lua.load(*source).exec() {
+                Err(Error::RuntimeError(msg)) => {
+                    assert!(msg.contains("attempt to modify readonly table"), "{}", msg)
+                }
+                r => panic!("wrong result for {}: {:?}", source, r),
+            }
+        }
+        match lua.load("setmetatable(config, nil)").exec() {
+            Err(Error::RuntimeError(msg)) => assert!(msg.contains("protected metatable")),
+            r => panic!("wrong result {:?}", r),
+        }
+
+        // An existing metatable keeps working.
+        let point: Table = lua
+            .load(
+                r#"
+                    setmetatable({ x = 1 }, {
+                        __index = function(_, k) return k .. "?" end,
+                        __tostring = function() return "point" end,
+                    })
+                "#,
+            )
+            .eval()
+            .unwrap();
+        point.set_readonly().unwrap();
+        lua.globals().set("point", point).unwrap();
+        lua.load(r#"assert(point.x == 1 and point.y == "y?" and tostring(point) == "point")"#)
+            .exec()
+            .unwrap();
+
+        // An `__index` function is passed the read-only table, not the hidden one.
+        let object: Table = lua
+            .load(
+                r#"
+                    local object = { name = "object" }
+                    setmetatable(object, {
+                        __index = function(t, k) return rawequal(t, object) and k end,
+                    })
+                    return object
+                "#,
+            )
+            .eval()
+            .unwrap();
+        object.set_readonly().unwrap();
+        assert_eq!(object.get::<_, String>("name").unwrap(), "object");
+        assert_eq!(object.get::<_, String>("missing").unwrap(), "missing");
+
+        // Rust code is blocked by the same metamethods, except for raw access.
+        match object.set("name", "other") {
+            Err(Error::RuntimeError(msg)) => {
+                assert!(msg.contains("attempt to modify readonly table"), "{}", msg)
+            }
+            r => panic!("wrong result {:?}", r),
+        }
+        object.raw_set("name", "raw").unwrap();
+        assert_eq!(object.get::<_, String>("name").unwrap(), "raw");
+    });
+}
+
+#[test]
+fn test_globals_readonly() {
+    Lua::new().context(|lua| {
+        lua.globals().set("level", 3).unwrap();
+        let view = lua.globals_readonly().unwrap();
+        assert_eq!(view.get::<_, i64>("level").unwrap(), 3);
+        assert!(!view.raw_contains_key("level").unwrap());
+
+        // The view reflects later changes to the globals.
+        lua.globals().set("added", "yes").unwrap();
+        assert_eq!(view.get::<_, String>("added").unwrap(), "yes");
+
+        let browse = lua
+            .load(
+                r#"
+                    function(g)
+                        local count = 0
+                        for k, v in pairs(g) do
+                            assert(_G[k] == v)
+                            count = count + 1
+                        end
+                        assert(g.string.format == string.format)
+                        assert(getmetatable(g) == false)
+                        return count
+                    end
+                "#,
+            )
+            .eval::<rlua::Function>()
+            .unwrap();
+        let count = lua.globals().clone().pairs::<Value, Value>().count();
+        assert_eq!(browse.call::<_, usize>(view.clone()).unwrap(), count);
+
+        let modify = lua
+            .load("function(g, source) load(source, nil, 't', { g = g })() end")
+            .eval::<rlua::Function>()
+            .unwrap();
+        for source in &[
+            "g.level = 4",
+            "g.new = true",
+            "g.level = nil",
+            "setmetatable(g, nil)",
+        ] {
+            assert!(
+                modify.call::<_, ()>((view.clone(), *source)).is_err(),
+                "{}",
+                source
+            );
+        }
+        assert!(view.set("level", 4).is_err());
+        assert_eq!(lua.globals().get::<_, i64>("level").unwrap(), 3);
+        assert!(!lua.globals().contains_key("new").unwrap());
+
+        // Only the top level is read-only, so the tables held by the globals can be modified.
+        modify
+            .call::<_, ()>((view.clone(), "g.string.answer = 42"))
+            .unwrap();
+        let string: Table = lua.globals().get("string").unwrap();
+        assert_eq!(string.get::<_, i64>("answer").unwrap(), 42);
+
+        string.set_readonly().unwrap();
+        assert!(modify
+            .call::<_, ()>((view.clone(), "g.string.format = nil"))
+            .is_err());
+        assert!(lua.load("string.format('%d', 1)").exec().is_ok());
+    });
+}
+
+#[test]
+fn test_deep_freeze() {
+    Lua::new().context(|lua| {
+        let config: Table = lua
+            .load(
+                r#"
+                    local shared = { level = 1 }
+                    local config = {
+                        server = { ports = { 80, 443 } },
+                        shared = shared,
+                        again = shared,
+                        [{ key = true }] = "table key",
+                    }
+                    config.self = config
+                    shared.parent = config
+                    return config
+                "#,
+            )
+            .eval()
+            .unwrap();
+        // A table which is already read-only still has its contents frozen.
+        let server: Table = config.get("server").unwrap();
+        server.set_readonly().unwrap();
+
+        Value::Table(config.clone()).deep_freeze().unwrap();
+        Value::Integer(1).deep_freeze().unwrap();
+        lua.globals().set("config", config).unwrap();
+
+        lua.load(
+            r#"
+                assert(config.server.ports[2] == 443)
+                assert(config.self.shared.parent.again.level == 1)
+            "#,
+        )
+        .exec()
+        .unwrap();
+        for source in &[
+            "config.name = 'other'",
+            "config.server.ports[1] = 8080",
+            "config.shared.level = 2",
+            "config.self.again.parent.x = 1",
+            "for k in pairs(config) do if type(k) == 'table' then k.key = false end end",
+        ] {
+            match lua.load(*source).exec() {
+                Err(Error::RuntimeError(msg)) => {
+                    assert!(msg.contains("attempt to modify readonly table"), "{}", msg)
+                }
+                r => panic!("wrong result for {}: {:?}", source, r),
+            }
+        }
+
+        // `rawset` is not blocked, and hides the frozen value.
+        lua.load("rawset(config.server, 'ports', 8080)")
+            .exec()
+            .unwrap();
+        lua.load("assert(config.server.ports == 8080)")
+            .exec()
+            .unwrap();
+
+        // A read-only view is frozen without freezing the table it reads from.
+        let holder = lua.create_table().unwrap();
+        holder.set("env", lua.globals_readonly().unwrap()).unwrap();
+        holder.deep_freeze().unwrap();
+        lua.globals().set("holder", holder).unwrap();
+        lua.load(
+            r#"
+                assert(holder.env.string == string)
+                assert(not pcall(function() holder.env.x = 1 end))
+                x = 1
+                string.custom = 1
+                assert(holder.env.x == 1)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
+
+#[test]
+fn test_interned_keys() {
+    let lua = Lua::new();
+    lua.context(|ctx| {
+        let position = ctx.intern_key("position").unwrap();
+        let velocity = ctx.intern_key(b"velocity").unwrap();
+        assert_eq!(
+            format!("{:?}", ctx.intern_key("position").unwrap()),
+            format!("{:?}", position)
+        );
+
+        // Keys and plain strings refer to the same fields, in any table.
+        let a = ctx.create_table().unwrap();
+        let b = ctx.create_table().unwrap();
+        a.set_k(&position, 1).unwrap();
+        a.set("velocity", 2).unwrap();
+        b.raw_set_k(&velocity, 3).unwrap();
+        assert_eq!(a.get::<_, i64>("position").unwrap(), 1);
+        assert_eq!(a.get_k::<i64>(&velocity).unwrap(), 2);
+        assert_eq!(a.raw_get_k::<i64>(&position.clone()).unwrap(), 1);
+        assert_eq!(b.raw_get::<_, i64>("velocity").unwrap(), 3);
+        assert_eq!(b.get_k::<Option<i64>>(&position).unwrap(), None);
+
+        // Repeated lookups keep hitting the same slots.
+        let mut total = 0;
+        for i in 0..10_000 {
+            a.raw_set_k(&position, i).unwrap();
+            total += a.get_k::<i64>(&position).unwrap() - a.raw_get::<_, i64>("position").unwrap();
+        }
+        assert_eq!(total, 0);
+        assert_eq!(a.clone().pairs::<String, i64>().count(), 2);
+
+        // The non-raw methods invoke metamethods.
+        let c: Table = ctx
+            .load(
+                r#"
+                    setmetatable({}, {
+                        __index = function(t, k) return k .. "!" end,
+                        __newindex = function(t, k, v) rawset(t, "last", k) end,
+                    })
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(c.get_k::<String>(&position).unwrap(), "position!");
+        assert_eq!(c.raw_get_k::<Option<String>>(&position).unwrap(), None);
+        c.set_k(&velocity, 1).unwrap();
+        assert_eq!(c.raw_get::<_, String>("last").unwrap(), "velocity");
+
+        Lua::new().context(|other| {
+            let t = other.create_table().unwrap();
+            match t.get_k::<Value>(&position) {
+                Err(Error::MismatchedRegistryKey) => {}
+                r => panic!("unexpected result {:?}", r),
+            }
+            match t.raw_set_k(&position, 1) {
+                Err(Error::MismatchedRegistryKey) => {}
+                r => panic!("unexpected result {:?}", r),
+            }
+        });
+    });
+}
+
+#[test]
+fn test_raw_iter() {
+    Lua::new().context(|lua| {
+        let table: Table = lua
+            .load(
+                r#"
+                    setmetatable({ "a", "b", "c", nil, "e", key = "value" }, {
+                        __index = function(t, i) return "missing" end,
+                    })
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        let items: Vec<(i64, String)> = table
+            .raw_iter()
+            .map(|(i, v)| (i, String::from_lua(v, lua).unwrap()))
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                (1, "a".to_owned()),
+                (2, "b".to_owned()),
+                (3, "c".to_owned())
+            ]
+        );
+
+        let empty = lua.create_table().unwrap();
+        assert_eq!(empty.raw_iter().count(), 0);
+        let sequence = lua.create_sequence_from(1..=1000).unwrap();
+        assert_eq!(sequence.raw_iter().map(|(i, _)| i).sum::<i64>(), 500 * 1001);
+    });
+}
@@ -1,11 +1,15 @@
 use std::iter::FromIterator;
-use std::panic::catch_unwind;
-use std::sync::Arc;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::{NonZeroI8, NonZeroU32, NonZeroU64, NonZeroU8, Wrapping};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::result::Result as StdResult;
+use std::sync::{Arc, Mutex};
 use std::{error, f32, f64, fmt};
 
 use rlua::{
-    Error, ExternalError, Function, Lua, Nil, Result, StdLib, String, Table, UserData, Value,
-    Variadic,
+    Error, ExternalError, Function, HookTriggers, Limits, Lua, LuaOptions, MetaMethod,
+    MultiValueBuilder, Nil, RegistryReport, Result, StdLib, String, Table, Thread, ThreadStatus,
+    UserData, UserDataMethods, Value, Variadic,
 };
 
 #[test]
@@ -86,6 +90,47 @@ fn test_eval() {
         assert_eq!(lua.load("1 + 1").eval::<i32>().unwrap(), 2);
         assert_eq!(lua.load("false == false").eval::<bool>().unwrap(), true);
         assert_eq!(lua.load("return 1 + 2").eval::<i32>().unwrap(), 3);
+        assert_eq!(
+            lua.load("1, 'two', 3.5")
+                .eval::<(i32, std::string::String, f64)>()
+                .unwrap(),
+            (1, "two".to_owned(), 3.5)
+        );
+        assert_eq!(
+            lua.load("string.find('hello', 'l+')")
+                .eval::<(i32, i32)>()
+                .unwrap(),
+            (3, 4)
+        );
+        assert_eq!(
+            lua.load("local x = 2\nreturn x, x * 2")
+                .eval::<(i32, i32)>()
+                .unwrap(),
+            (2, 4)
+        );
+        lua.load("y = 5").eval::<()>().unwrap();
+        match lua.load("y = 6").eval::<Value>() {
+            Ok(Value::Nil) => {}
+            r => panic!("expected nil, got {:?}", r),
+        }
+        assert_eq!(lua.load("y -- a comment").eval::<i32>().unwrap(), 6);
+        // Errors from the statement fallback keep the chunk name and line numbers.
+        match lua
+            .load("local x = 1\nx = = 2")
+            .set_name("snippet")
+            .unwrap()
+            .eval::<()>()
+        {
+            Err(Error::SyntaxError { message, .. }) => {
+                assert!(
+                    message.starts_with("[string \"snippet\"]:2:"),
+                    "{}",
+                    message
+                );
+                assert!(!message.contains("return"), "{}", message);
+            }
+            r => panic!("expected SyntaxError, got {:?}", r),
+        }
         match lua.load("if true then").eval::<()>() {
             Err(Error::SyntaxError {
                 incomplete_input: true,
@@ -126,6 +171,20 @@ fn test_lua_multi() {
         let (a, b, v) = mreturn.call::<_, (u64, u64, Variadic<u64>)>(()).unwrap();
         assert_eq!((a, b), (1, 2));
         assert_eq!(v[..], [3, 4, 5, 6]);
+
+        let built = MultiValueBuilder::new(lua)
+            .push(1)
+            .unwrap()
+            .push_multi(("two", 3.5))
+            .unwrap()
+            .push(Nil)
+            .unwrap()
+            .build();
+        assert_eq!(built.len(), 4);
+        let (a, b, c, d) = lua
+            .unpack_multi::<(i64, String, f64, Option<i64>)>(built)
+            .unwrap();
+        assert_eq!((a, b.to_str().unwrap(), c, d), (1, "two", 3.5, None));
     });
 }
 
@@ -149,6 +208,140 @@ fn test_coercion() {
     });
 }
 
+#[test]
+fn test_value_len() {
+    struct Bag(i64);
+
+    impl UserData for Bag {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method(MetaMethod::Len, |_, this, ()| Ok(this.0));
+        }
+    }
+
+    struct Plain;
+
+    impl UserData for Plain {}
+
+    Lua::new().context(|lua| {
+        let len = |v: Value| v.len().unwrap();
+
+        assert_eq!(len(lua.load("'a\\0b'").eval().unwrap()), Some(3));
+        assert_eq!(len(lua.load("{ 1, 2, 3 }").eval().unwrap()), Some(3));
+        assert_eq!(
+            len(lua
+                .load("setmetatable({}, { __len = function() return 7 end })")
+                .eval()
+                .unwrap()),
+            Some(7)
+        );
+        assert_eq!(len(lua.pack(Bag(12)).unwrap()), Some(12));
+        assert_eq!(len(lua.pack(Plain).unwrap()), None);
+        assert_eq!(len(Value::Integer(5)), None);
+        assert_eq!(len(Value::Nil), None);
+
+        let bad: Value = lua
+            .load("setmetatable({}, { __len = function() error('no length') end })")
+            .eval()
+            .unwrap();
+        assert!(bad.len().is_err());
+    });
+
+    // Userdata without a metatable have no length either.
+    let lua = unsafe { Lua::unsafe_new_with(StdLib::ALL) };
+    lua.context(|lua| {
+        let plain = lua.pack(Plain).unwrap();
+        lua.load("debug.setmetatable(...)")
+            .call::<_, ()>((plain.clone(), Value::Nil))
+            .unwrap();
+        assert_eq!(plain.len().unwrap(), None);
+    });
+}
+
+#[test]
+fn test_get_metafield() {
+    Lua::new().context(|lua| {
+        let protected: Value = lua
+            .load(
+                r#"
+                    setmetatable({}, {
+                        __tostring = function() return "protected" end,
+                        __call = function() end,
+                        __metatable = "locked",
+                    })
+                "#,
+            )
+            .eval()
+            .unwrap();
+        match lua.get_metafield(&protected, "__tostring").unwrap() {
+            Some(Value::Function(f)) => assert_eq!(f.call::<_, String>(()).unwrap(), "protected"),
+            r => panic!("wrong result {:?}", r),
+        }
+        assert!(lua.get_metafield(&protected, "__index").unwrap().is_none());
+        assert!(protected.is_callable(lua));
+
+        let plain: Value = lua.load("{}").eval().unwrap();
+        assert!(lua.get_metafield(&plain, "__tostring").unwrap().is_none());
+        assert!(!plain.is_callable(lua));
+
+        assert!(lua.get_metafield(&Value::Nil, "__index").unwrap().is_none());
+        let string: Value = lua.load("'abc'").eval().unwrap();
+        assert!(lua.get_metafield(&string, "__index").unwrap().is_some());
+        assert!(!string.is_callable(lua));
+
+        let function: Value = lua.load("print").eval().unwrap();
+        assert!(function.is_callable(lua));
+    });
+}
+
+#[test]
+fn test_syntax_error_position() {
+    fn position(
+        lua: &Lua,
+        source: &str,
+        line_offset: usize,
+    ) -> (Option<usize>, Option<usize>, Option<std::ops::Range<usize>>) {
+        lua.context(
+            |lua| match lua.load(source).set_line_offset(line_offset).exec() {
+                Err(Error::SyntaxError {
+                    line, column, span, ..
+                }) => (line, column, span),
+                r => panic!("expected SyntaxError, got {:?}", r),
+            },
+        )
+    }
+
+    let lua = Lua::new();
+    assert_eq!(position(&lua, "x = = 1", 0), (Some(1), Some(5), Some(4..5)));
+    assert_eq!(
+        position(&lua, "local t = {1, 2 3}", 0),
+        (Some(1), Some(17), Some(16..17))
+    );
+    assert_eq!(
+        position(&lua, "x = 1\r\ny = 2 ==", 0),
+        (Some(2), Some(9), Some(15..15))
+    );
+    assert_eq!(
+        position(&lua, "x = 1\r\ny = = 2", 0),
+        (Some(2), Some(5), Some(11..12))
+    );
+    assert_eq!(
+        position(&lua, "local s = [[\nlong]] .. 12ab", 0),
+        (Some(2), Some(11), Some(23..27))
+    );
+    assert_eq!(
+        position(&lua, "x = = 1", 10),
+        (Some(11), Some(5), Some(4..5))
+    );
+
+    // The text of a string with escapes differs from its source, so only the line is known.
+    assert_eq!(
+        position(&lua, "x = 1\ns = 'a\\tb\n", 0),
+        (Some(2), None, None)
+    );
+    // Errors which do not name a token.
+    assert_eq!(position(&lua, "\ngoto nowhere", 0), (Some(2), None, None));
+}
+
 #[test]
 fn test_error() {
     #[derive(Debug)]
@@ -340,6 +533,142 @@ fn test_error() {
     };
 }
 
+#[test]
+fn test_error_value() {
+    Lua::new().context(|lua| {
+        let fail: Function = lua
+            .load("function(code) error({ code = code }) end")
+            .eval()
+            .unwrap();
+        match fail.call::<_, ()>(7) {
+            Err(Error::RuntimeErrorValue { value, .. }) => {
+                let value: Table = lua.registry_value(&value).unwrap();
+                assert_eq!(value.get::<_, i64>("code").unwrap(), 7);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let raise = lua
+            .create_function(|lua, code: i64| -> Result<()> {
+                let err = lua.create_table()?;
+                err.set("code", code)?;
+                Err(lua.create_error(Value::Table(err))?)
+            })
+            .unwrap();
+        let rethrow = lua
+            .create_function(|lua, ()| {
+                let fail: Function = lua.globals().get("fail")?;
+                fail.call::<_, ()>(8)
+            })
+            .unwrap();
+        let globals = lua.globals();
+        globals.set("fail", fail).unwrap();
+        globals.set("raise", raise).unwrap();
+        globals.set("rethrow", rethrow).unwrap();
+        lua.load(
+            r#"
+                local ok, err = pcall(raise, 5)
+                assert(not ok and type(err) == "table" and err.code == 5)
+
+                local ok, err = pcall(rethrow)
+                assert(not ok and type(err) == "table" and err.code == 8)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let thread = lua
+            .create_thread(
+                lua.load("function() error({ code = 9 }) end")
+                    .eval()
+                    .unwrap(),
+            )
+            .unwrap();
+        match thread.resume::<_, ()>(()) {
+            Err(Error::RuntimeErrorValue { value, .. }) => {
+                let value: Table = lua.registry_value(&value).unwrap();
+                assert_eq!(value.get::<_, i64>("code").unwrap(), 9);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        match lua.load("error('message')").exec() {
+            Err(Error::RuntimeError(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_error_value_released() {
+    let lua = Lua::new();
+    let tables = || lua.registry_report().values.get("table").cloned();
+    let before = tables();
+    lua.context(|lua_ctx| {
+        let fail: Function = lua_ctx.load("function() error({}) end").eval().unwrap();
+        for _ in 0..100 {
+            assert!(fail.call::<_, ()>(()).is_err());
+        }
+        let held = fail.call::<_, ()>(()).unwrap_err();
+
+        // The slots of the dropped errors are reused, and the held error keeps its value.
+        assert_eq!(tables(), Some(before.unwrap_or(0) + 1));
+        match held {
+            Error::RuntimeErrorValue { value, .. } => {
+                assert!(lua_ctx.registry_value::<Table>(&value).is_ok())
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    });
+}
+
+#[test]
+fn test_call_with_handler() {
+    Lua::new().context(|lua| {
+        let fail: Function = lua.load("function(msg) error(msg, 0) end").eval().unwrap();
+        let decorate: Function = lua
+            .load("function(err) return 'decorated: ' .. err end")
+            .eval()
+            .unwrap();
+        match fail.call_with_handler::<_, ()>("oops", decorate) {
+            Err(Error::RuntimeError(msg)) => assert_eq!(msg, "decorated: oops"),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let throw: Function = lua
+            .load("function(err) error('handler failed') end")
+            .eval()
+            .unwrap();
+        match fail.call_with_handler::<_, ()>("oops", throw) {
+            Err(Error::RuntimeError(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let add: Function = lua.load("function(a, b) return a + b end").eval().unwrap();
+        let decorate: Function = lua.load("function(err) return err end").eval().unwrap();
+        assert_eq!(
+            add.call_with_handler::<_, i64>((1, 2), decorate).unwrap(),
+            3
+        );
+    });
+
+    match catch_unwind(|| -> Result<()> {
+        Lua::new().context(|lua| {
+            let rust_panic = lua
+                .create_function(|_, ()| -> Result<()> { panic!("test_panic") })
+                .unwrap();
+            let handler = lua
+                .create_function(|_, ()| -> Result<()> { panic!("handler called") })
+                .unwrap();
+            rust_panic.call_with_handler::<_, ()>((), handler)
+        })
+    }) {
+        Ok(Ok(_)) => panic!("no panic was detected"),
+        Ok(Err(e)) => panic!("error during panic test {:?}", e),
+        Err(p) => assert!(*p.downcast::<&str>().unwrap() == "test_panic"),
+    };
+}
+
 #[test]
 fn test_result_conversions() {
     Lua::new().context(|lua| {
@@ -372,6 +701,64 @@ fn test_result_conversions() {
         )
         .exec()
         .unwrap();
+
+        // Domain errors as table values, in both directions.
+        let parse = lua
+            .create_function(|lua, s: std::string::String| {
+                Ok(match s.parse::<i64>() {
+                    Ok(n) => Ok(n),
+                    Err(_) => {
+                        let err = lua.create_table()?;
+                        err.set("code", "invalid_number")?;
+                        err.set("input", s)?;
+                        Err(err)
+                    }
+                })
+            })
+            .unwrap();
+        globals.set("parse", parse).unwrap();
+        lua.load(
+            r#"
+                assert(parse("12") == 12)
+                local r, e = parse("x")
+                assert(r == nil and e.code == "invalid_number" and e.input == "x")
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let check: Function = lua
+            .load(
+                r#"
+                    function(n)
+                        if n > 0 then
+                            return n * 2
+                        else
+                            return nil, { code = "not_positive" }
+                        end
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        match check.call::<_, StdResult<i64, Table>>(21).unwrap() {
+            Ok(n) => assert_eq!(n, 42),
+            Err(_) => panic!("expected a value"),
+        }
+        match check.call::<_, StdResult<i64, Table>>(0).unwrap() {
+            Err(e) => assert_eq!(e.get::<_, String>("code").unwrap(), "not_positive"),
+            Ok(n) => panic!("expected an error, got {}", n),
+        }
+        match check.call::<_, StdResult<i64, Table>>("x") {
+            Err(Error::CallbackError { .. }) | Err(Error::RuntimeError(_)) => {}
+            r => panic!("expected a Lua error, got {:?}", r.map(|r| r.is_ok())),
+        }
+
+        let none: Function = lua.load("function() end").eval().unwrap();
+        match none.call::<_, StdResult<Option<i64>, Table>>(()).unwrap() {
+            Ok(None) => {}
+            _ => panic!("expected Ok(None)"),
+        }
     });
 }
 
@@ -437,6 +824,100 @@ fn test_num_conversion() {
     });
 }
 
+#[test]
+fn test_nonzero_conversion() {
+    Lua::new().context(|lua| {
+        let id: NonZeroU32 = lua.load("42").eval().unwrap();
+        assert_eq!(id.get(), 42);
+        let id: NonZeroI8 = lua.load("'-3'").eval().unwrap();
+        assert_eq!(id.get(), -3);
+        match lua.pack(NonZeroU64::new(7).unwrap()).unwrap() {
+            Value::Integer(7) => {}
+            v => panic!("wrong value {:?}", v),
+        }
+
+        let error_message = |source: &str| match lua.load(source).eval::<NonZeroU8>() {
+            Err(Error::FromLuaConversionError {
+                to: "NonZeroU8",
+                message: Some(message),
+                ..
+            }) => message,
+            r => panic!("wrong result {:?}", r),
+        };
+        assert_eq!(error_message("0"), "expected a non-zero integer");
+        assert_eq!(error_message("256"), "out of range");
+        assert_eq!(error_message("-1"), "out of range");
+        assert_eq!(
+            error_message("{}"),
+            "expected number or string coercible to number"
+        );
+    });
+}
+
+#[test]
+fn test_char_conversion() {
+    Lua::new().context(|lua| {
+        assert_eq!(lua.load("'x'").eval::<char>().unwrap(), 'x');
+        assert_eq!(lua.load("'é'").eval::<char>().unwrap(), 'é');
+        assert_eq!(lua.load("7").eval::<char>().unwrap(), '7');
+        let s: std::string::String = lua.unpack(lua.pack('ß').unwrap()).unwrap();
+        assert_eq!(s, "ß");
+
+        for source in &["''", "'xy'"] {
+            match lua.load(*source).eval::<char>() {
+                Err(Error::FromLuaConversionError {
+                    to: "char",
+                    message: Some(message),
+                    ..
+                }) => assert_eq!(message, "expected a string of exactly one character"),
+                r => panic!("wrong result {:?}", r),
+            }
+        }
+    });
+}
+
+#[test]
+fn test_wrapping_conversion() {
+    Lua::new().context(|lua| {
+        let Wrapping(n) = lua.load("255").eval::<Wrapping<u8>>().unwrap();
+        assert_eq!(n, 255);
+        let n: i64 = lua
+            .unpack(lua.pack(Wrapping(254u8) + Wrapping(3)).unwrap())
+            .unwrap();
+        assert_eq!(n, 1);
+        assert!(lua.load("256").eval::<Wrapping<u8>>().is_err());
+    });
+}
+
+#[test]
+fn test_addr_conversion() {
+    Lua::new().context(|lua| {
+        let ip: IpAddr = lua.load("'127.0.0.1'").eval().unwrap();
+        assert_eq!(ip, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let addr: SocketAddr = lua.load("'[::1]:8080'").eval().unwrap();
+        assert_eq!(addr, SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 8080));
+        let s: std::string::String = lua.unpack(lua.pack(addr).unwrap()).unwrap();
+        assert_eq!(s, "[::1]:8080");
+
+        match lua.load("'localhost'").eval::<IpAddr>() {
+            Err(Error::FromLuaConversionError {
+                to: "IpAddr",
+                message: Some(message),
+                ..
+            }) => assert_eq!(message, "invalid IP address syntax: \"localhost\""),
+            r => panic!("wrong result {:?}", r),
+        }
+        match lua.load("'127.0.0.1'").eval::<SocketAddr>() {
+            Err(Error::FromLuaConversionError {
+                to: "SocketAddr",
+                message: Some(message),
+                ..
+            }) => assert_eq!(message, "invalid socket address syntax: \"127.0.0.1\""),
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+}
+
 #[test]
 fn test_pcall_xpcall() {
     Lua::new().context(|lua| {
@@ -507,6 +988,147 @@ fn test_pcall_xpcall() {
     });
 }
 
+// Calls a panicking callback inside `pcall`, returning what `pcall` returned as a string.
+fn pcall_panicking_callback(lua: &Lua) -> std::thread::Result<Result<std::string::String>> {
+    catch_unwind(AssertUnwindSafe(|| {
+        lua.context(|lua| {
+            let panicking = lua.create_function(|_, ()| -> Result<()> { panic!("test_panic") })?;
+            lua.globals().set("panicking", panicking)?;
+            lua.load(
+                "local ok, err = pcall(panicking) return tostring(ok) .. ': ' .. tostring(err)",
+            )
+            .eval()
+        })
+    }))
+}
+
+#[test]
+fn test_lua_options() {
+    // By default the panic propagates through `pcall`.
+    let lua = Lua::new_with_options(StdLib::BASE, LuaOptions::new());
+    let p = pcall_panicking_callback(&lua).unwrap_err();
+    assert_eq!(*p.downcast::<&str>().unwrap(), "test_panic");
+
+    // The stock `pcall` catches the wrapped panic.
+    let lua = Lua::new_with_options(StdLib::BASE, LuaOptions::new().override_pcall(false));
+    assert!(pcall_panicking_callback(&lua)
+        .unwrap()
+        .unwrap()
+        .starts_with("false: userdata"));
+
+    // Once reinstalled, `pcall` no longer catches it.
+    lua.reinstall_safe_pcall().unwrap();
+    assert!(pcall_panicking_callback(&lua).is_err());
+
+    // Panics converted to Lua errors are caught by either `pcall`.
+    for &override_pcall in &[true, false] {
+        let lua = Lua::new_with_options(
+            StdLib::BASE,
+            LuaOptions::new()
+                .catch_rust_panics(false)
+                .override_pcall(override_pcall),
+        );
+        assert!(pcall_panicking_callback(&lua)
+            .unwrap()
+            .unwrap()
+            .starts_with("false: runtime error: panic in Rust callback: test_panic"));
+
+        lua.context(|lua| {
+            let panicking: Function = lua.globals().get("panicking").unwrap();
+            match panicking.call::<_, ()>(()) {
+                Err(Error::CallbackError { cause, .. }) => match *cause {
+                    Error::RuntimeError(ref msg) => {
+                        assert_eq!(msg, "panic in Rust callback: test_panic")
+                    }
+                    ref e => panic!("unexpected cause {:?}", e),
+                },
+                r => panic!("unexpected result {:?}", r),
+            }
+        });
+    }
+}
+
+#[test]
+fn test_reinstall_safe_pcall() {
+    // Loading the base library after creation installs the stock `pcall`.
+    let lua = Lua::new_with(StdLib::empty());
+    lua.load_from_std_lib(StdLib::BASE).unwrap();
+    assert!(pcall_panicking_callback(&lua)
+        .unwrap()
+        .unwrap()
+        .starts_with("false: userdata"));
+
+    lua.reinstall_safe_pcall().unwrap();
+    assert!(pcall_panicking_callback(&lua).is_err());
+}
+
+#[test]
+fn test_lua_builder() {
+    let lua = Lua::builder()
+        .std_libs(StdLib::BASE | StdLib::MATH)
+        .memory_limit(1024 * 1024)
+        .multivalue_limit(3)
+        .catch_rust_panics(false)
+        .build()
+        .unwrap();
+    lua.context(|lua| {
+        assert_eq!(lua.load("math.max(1, 2)").eval::<i64>().unwrap(), 2);
+        assert!(lua
+            .load("string")
+            .eval::<Option<Table>>()
+            .unwrap()
+            .is_none());
+
+        match lua
+            .load("local t = {} for i = 1, 1e6 do t[i] = i end")
+            .exec()
+        {
+            Err(Error::MemoryError(_)) => {}
+            r => panic!("expected a memory error, got {:?}", r),
+        }
+        match lua.load("return 1, 2, 3, 4").eval::<Variadic<i64>>() {
+            Err(Error::TooManyValues { limit: 3, got: 4 }) => {}
+            r => panic!("expected too many values, got {:?}", r),
+        }
+
+        let panicking = lua
+            .create_function(|_, ()| -> Result<()> { panic!("test_panic") })
+            .unwrap();
+        assert!(panicking.call::<_, ()>(()).is_err());
+    });
+
+    // Safe `pcall` is still installed after loading the base library.
+    let lua = Lua::builder().std_libs(StdLib::BASE).build().unwrap();
+    assert!(pcall_panicking_callback(&lua).is_err());
+
+    let lines = Arc::new(Mutex::new(0));
+    let lua = Lua::builder()
+        .hook(
+            HookTriggers {
+                every_line: true,
+                ..Default::default()
+            },
+            {
+                let lines = lines.clone();
+                move |_, _| {
+                    *lines.lock().unwrap() += 1;
+                    Ok(())
+                }
+            },
+        )
+        .build()
+        .unwrap();
+    assert_eq!(*lines.lock().unwrap(), 0);
+    lua.context(|lua| lua.load("local x = 1\nlocal y = 2").exec().unwrap());
+    assert_eq!(*lines.lock().unwrap(), 2);
+
+    // The memory limit already applies to loading the standard libraries.
+    match Lua::builder().memory_limit(1).build() {
+        Err(Error::MemoryError(_)) => {}
+        r => panic!("expected a memory error, got {:?}", r.map(|_| ())),
+    }
+}
+
 #[test]
 fn test_recursive_mut_callback_error() {
     Lua::new().context(|lua| {
@@ -544,6 +1166,20 @@ fn test_recursive_mut_callback_error() {
             },
             other => panic!("incorrect result: {:?}", other),
         };
+
+        let err = lua
+            .globals()
+            .get::<_, Function>("f")
+            .unwrap()
+            .call::<_, ()>(false)
+            .unwrap_err();
+        let chain = err.chain().collect::<Vec<_>>();
+        assert_eq!(chain.len(), 3);
+        match chain[..] {
+            [Error::CallbackError { .. }, Error::CallbackError { .. }, Error::RecursiveMutCallback] => {
+            }
+            ref other => panic!("incorrect chain: {:?}", other),
+        }
     });
 }
 
@@ -582,6 +1218,44 @@ fn test_named_registry_value() {
     });
 }
 
+#[test]
+fn test_with_temporary_globals() {
+    Lua::new().context(|lua| {
+        lua.globals().set("x", "global").unwrap();
+        let outer: Function = lua.load("function() return x end").eval().unwrap();
+
+        let env = lua.create_table().unwrap();
+        env.set("x", "sandbox").unwrap();
+        let inner = lua
+            .with_temporary_globals(env.clone(), || {
+                assert_eq!(lua.globals().get::<_, String>("x").unwrap(), "sandbox");
+                // Functions loaded earlier keep their environment.
+                assert_eq!(outer.call::<_, String>(()).unwrap(), "global");
+                lua.load("y = 1; return function() return x end")
+                    .eval::<Function>()
+            })
+            .unwrap();
+        assert_eq!(inner.call::<_, String>(()).unwrap(), "sandbox");
+        assert_eq!(env.get::<_, i64>("y").unwrap(), 1);
+        assert_eq!(lua.globals().get::<_, String>("x").unwrap(), "global");
+        assert_eq!(lua.globals().get::<_, Option<i64>>("y").unwrap(), None);
+
+        // The globals are restored after an error or a panic.
+        let empty = lua.create_table().unwrap();
+        match lua.with_temporary_globals(empty.clone(), || lua.load("print('x')").exec()) {
+            Err(Error::RuntimeError(_)) => {}
+            r => panic!("wrong result {:?}", r),
+        }
+        assert_eq!(lua.load("x").eval::<String>().unwrap(), "global");
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            lua.with_temporary_globals(empty, || -> Result<()> { panic!("inside") })
+        }));
+        assert!(result.is_err());
+        assert_eq!(lua.load("x").eval::<String>().unwrap(), "global");
+    });
+}
+
 #[test]
 fn test_registry_value() {
     Lua::new().context(|lua| {
@@ -623,6 +1297,116 @@ fn test_drop_registry_value() {
     });
 }
 
+#[test]
+fn test_registry_key_clone() {
+    let lua = Lua::new();
+    lua.context(|ctx| {
+        let key = ctx.create_registry_value("shared").unwrap();
+        let clone = key.clone();
+        let other = ctx.create_registry_value("shared").unwrap();
+        assert!(key.is_same_slot(&clone));
+        assert!(!key.is_same_slot(&other));
+
+        drop(key);
+        ctx.expire_registry_values();
+        assert_eq!(lua.registry_report().expired_keys, 0);
+        assert_eq!(ctx.registry_value::<String>(&clone).unwrap(), "shared");
+
+        let slot = format!("{:?}", clone);
+        drop(clone);
+        assert_eq!(lua.registry_report().expired_keys, 1);
+        ctx.expire_registry_values();
+        assert_eq!(format!("{:?}", ctx.create_registry_value(1).unwrap()), slot);
+
+        ctx.expire_registry_values();
+
+        // Removing one clone leaves the value for the others.
+        let key = ctx.create_registry_value("removed").unwrap();
+        let clone = key.clone();
+        ctx.remove_registry_value(key).unwrap();
+        assert_eq!(ctx.registry_value::<String>(&clone).unwrap(), "removed");
+        ctx.remove_registry_value(clone).unwrap();
+        assert_eq!(lua.registry_report().expired_keys, 0);
+
+        Lua::new().context(|ctx2| {
+            let other = ctx2.create_registry_value(1).unwrap();
+            match ctx.registry_value::<i64>(&other.clone()) {
+                Err(Error::MismatchedRegistryKey) => {}
+                r => panic!("wrong result {:?}", r),
+            }
+        });
+    });
+}
+
+#[test]
+fn test_registry_expiry_interval() {
+    struct MyUserdata {
+        _rc: Arc<()>,
+    }
+
+    impl UserData for MyUserdata {}
+
+    let lua = Lua::new();
+    lua.set_registry_expiry_interval(Some(3));
+    lua.context(|lua| {
+        let rc = Arc::new(());
+
+        drop(
+            lua.create_registry_value(MyUserdata { _rc: rc.clone() })
+                .unwrap(),
+        );
+        let _a = lua.create_registry_value(1).unwrap();
+        lua.load(r#"collectgarbage("collect")"#).exec().unwrap();
+        assert_eq!(Arc::strong_count(&rc), 2);
+
+        // The third value created expires the dropped key.
+        let _b = lua.create_registry_value(2).unwrap();
+        lua.load(r#"collectgarbage("collect")"#).exec().unwrap();
+        assert_eq!(Arc::strong_count(&rc), 1);
+    });
+}
+
+#[test]
+fn test_registry_report() {
+    let lua = Lua::new();
+    let initial = lua.registry_report();
+    assert_eq!(initial.expired_keys, 0);
+    assert!(initial
+        .named
+        .iter()
+        .any(|(name, type_name)| name == "_LOADED" && *type_name == "table"));
+    let count =
+        |report: &RegistryReport, type_name| report.values.get(type_name).cloned().unwrap_or(0);
+
+    lua.context(|ctx| {
+        let a = ctx
+            .create_registry_value(ctx.create_table().unwrap())
+            .unwrap();
+        let b = ctx
+            .create_registry_value(ctx.create_table().unwrap())
+            .unwrap();
+        let _c = ctx.create_registry_value("value").unwrap();
+        ctx.set_named_registry_value("my_value", 1).unwrap();
+
+        let report = lua.registry_report();
+        assert_eq!(count(&report, "table"), count(&initial, "table") + 2);
+        assert_eq!(count(&report, "string"), count(&initial, "string") + 1);
+        assert!(report.named.contains(&("my_value".to_owned(), "number")));
+
+        ctx.remove_registry_value(a).unwrap();
+        drop(b);
+        let report = lua.registry_report();
+        assert_eq!(report.expired_keys, 1);
+        assert_eq!(count(&report, "table"), count(&initial, "table") + 1);
+
+        ctx.expire_registry_values();
+        let report = lua.registry_report();
+        assert_eq!(report.expired_keys, 0);
+        assert_eq!(count(&report, "table"), count(&initial, "table"));
+        assert_eq!(count(&report, "string"), count(&initial, "string") + 1);
+    });
+}
+
 #[test]
 fn test_lua_registry_ownership() {
     Lua::new().context(|lua1| {
@@ -675,6 +1459,41 @@ fn too_many_arguments() {
     });
 }
 
+#[test]
+fn multivalue_limit() {
+    let lua = Lua::new();
+    lua.set_multivalue_limit(Some(3));
+    lua.context(|lua| {
+        let count = lua
+            .create_function(|_, args: Variadic<i64>| Ok(args.len()))
+            .unwrap();
+        let repeat = lua
+            .create_function(|_, n: usize| Ok(Variadic::from_iter(0..n)))
+            .unwrap();
+        let globals = lua.globals();
+        globals.set("count", count).unwrap();
+        globals.set("rep", repeat).unwrap();
+
+        let check = |err: Error| match err.chain().last().unwrap() {
+            Error::TooManyValues { limit: 3, got: 4 } => {}
+            other => panic!("incorrect result: {:?}", other),
+        };
+
+        assert_eq!(lua.load("count(1, 2, 3)").eval::<i64>().unwrap(), 3);
+        check(lua.load("count(1, 2, 3, 4)").exec().unwrap_err());
+
+        lua.load("rep(3)").exec().unwrap();
+        check(lua.load("rep(4)").exec().unwrap_err());
+
+        let f: Function = lua
+            .load("return function(n) return table.unpack({ 1, 2, 3, 4 }, 1, n) end")
+            .eval()
+            .unwrap();
+        assert_eq!(f.call::<_, Variadic<i64>>(3).unwrap().len(), 3);
+        check(f.call::<_, Variadic<i64>>(4).unwrap_err());
+    });
+}
+
 #[test]
 fn too_many_recursions() {
     Lua::new().context(|lua| {
@@ -771,6 +1590,195 @@ fn large_args_ref() {
     });
 }
 
+#[test]
+fn chunk_line_offset() {
+    Lua::new().context(|lua| {
+        match lua
+            .load("local x = 1\nerror('oops')")
+            .set_name("config.toml")
+            .unwrap()
+            .set_line_offset(40)
+            .exec()
+        {
+            Err(Error::RuntimeError(msg)) => {
+                assert!(
+                    msg.starts_with("[string \"config.toml\"]:42: oops"),
+                    "{}",
+                    msg
+                )
+            }
+            r => panic!("wrong result {:?}", r),
+        }
+
+        match lua.load("\nlocal = 1").set_line_offset(9).exec() {
+            Err(Error::SyntaxError {
+                message,
+                line,
+                column,
+                span,
+                ..
+            }) => {
+                assert!(message.contains(":11:"), "{}", message);
+                assert_eq!((line, column, span), (Some(11), Some(7), Some(7..8)));
+            }
+            r => panic!("wrong result {:?}", r),
+        }
+
+        match lua.load("error('far')").set_line_offset(5_000_000).exec() {
+            Err(Error::RuntimeError(msg)) => assert!(msg.contains(":5000001:"), "{}", msg),
+            r => panic!("wrong result {:?}", r),
+        }
+
+        match lua.load("nil + 1").set_line_offset(4).eval::<()>() {
+            Err(Error::RuntimeError(msg)) => assert!(msg.contains(":5:"), "{}", msg),
+            r => panic!("wrong result {:?}", r),
+        }
+        assert_eq!(
+            lua.load("1 + 2").set_line_offset(3).eval::<i64>().unwrap(),
+            3
+        );
+    });
+}
+
+#[test]
+fn chunk_load_resident() {
+    fn runtime_error<T: std::fmt::Debug>(r: rlua::Result<T>) -> Vec<std::string::String> {
+        match r {
+            Err(Error::RuntimeError(msg)) => msg.lines().take(2).map(str::to_owned).collect(),
+            r => panic!("wrong result {:?}", r),
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let source = "local t = {}\nfunction fail(x)\n  return t.x.y\nend\nerror('oops')";
+        assert_eq!(
+            runtime_error(
+                lua.load_resident(source)
+                    .set_name("=script")
+                    .unwrap()
+                    .exec()
+            ),
+            vec!["script:5: oops", "    error('oops')"]
+        );
+
+        // The source is kept after the chunk has run.
+        let fail: Function = lua.globals().get("fail").unwrap();
+        assert_eq!(
+            runtime_error(fail.call::<_, ()>(1)),
+            vec![
+                "script:3: attempt to index a nil value (field 'x')",
+                "    return t.x.y"
+            ]
+        );
+
+        assert_eq!(
+            runtime_error(
+                lua.load_resident("\nnil + 1")
+                    .set_line_offset(10)
+                    .eval::<()>()
+            ),
+            vec![
+                "[string \"?\"]:12: attempt to perform arithmetic on a nil value",
+                "    nil + 1"
+            ]
+        );
+
+        // Chunks loaded with `load` are not affected, even with the same name.
+        assert_eq!(
+            runtime_error(lua.load("error('oops')").set_name("=other").unwrap().exec())[1],
+            "stack traceback:"
+        );
+        // Loading another chunk with the same name forgets the resident source.
+        assert_eq!(
+            runtime_error(
+                lua.load("error('oops')")
+                    .set_name("=script")
+                    .unwrap()
+                    .exec()
+            )[1],
+            "stack traceback:"
+        );
+        assert_eq!(runtime_error(fail.call::<_, ()>(1))[1], "stack traceback:");
+
+        // Chunks whose names are only told apart after the part shown in error messages keep
+        // their own sources.
+        let prefix = "x".repeat(80);
+        let load_long = |name: &str, source: &str| -> Function {
+            lua.load_resident(source)
+                .set_name(&format!("={}{}", prefix, name))
+                .unwrap()
+                .eval()
+                .unwrap()
+        };
+        let a = load_long("a", "return function()\n  error('in a')\nend");
+        let b = load_long("b", "return function()\n\n  error('in b')\nend");
+        assert_eq!(runtime_error(a.call::<_, ()>(()))[1], "    error('in a')");
+        assert_eq!(runtime_error(b.call::<_, ()>(()))[1], "    error('in b')");
+
+        // Only the sources of the most recently loaded resident chunks are kept.
+        let load_raise = |name: &str| -> Function {
+            lua.load_resident("return function() error('oops') end")
+                .set_name(&format!("={}", name))
+                .unwrap()
+                .eval()
+                .unwrap()
+        };
+        let first = load_raise("first");
+        let mut last = None;
+        for i in 0..64 {
+            last = Some(load_raise(&format!("chunk{}", i)));
+        }
+        assert_eq!(
+            runtime_error(last.unwrap().call::<_, ()>(()))[1],
+            "    return function() error('oops') end"
+        );
+        assert_eq!(
+            runtime_error(first.call::<_, ()>(()))[1],
+            "stack traceback:"
+        );
+    });
+}
+
+#[test]
+fn chunk_max_size() {
+    let lua = Lua::new();
+    lua.set_max_chunk_size(Some(10));
+    lua.context(|lua| {
+        assert_eq!(lua.load("return 1+2").eval::<i64>().unwrap(), 3);
+        match lua.load("return 1+23").eval::<i64>() {
+            Err(Error::ChunkTooLarge {
+                limit: 10,
+                size: 11,
+            }) => {}
+            r => panic!("wrong result {:?}", r),
+        }
+        match lua.load("x = 1 + 23").exec() {
+            Err(Error::ChunkTooLarge {
+                limit: 10,
+                size: 10,
+            }) => panic!("limit is inclusive"),
+            Ok(()) => {}
+            r => panic!("wrong result {:?}", r),
+        }
+        // The line offset is not part of the chunk's size.
+        lua.load("x = 1").set_line_offset(100).exec().unwrap();
+        let err = lua.load("x = x + 100").into_function().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "chunk too large (11 bytes, the limit is 10 bytes)"
+        );
+    });
+
+    lua.set_max_chunk_size(None);
+    lua.context(|lua| lua.load("x = x + 100").exec().unwrap());
+
+    let lua = Lua::builder().max_chunk_size(4).build().unwrap();
+    lua.context(|lua| match lua.load("x = 1").exec() {
+        Err(Error::ChunkTooLarge { limit: 4, size: 5 }) => {}
+        r => panic!("wrong result {:?}", r),
+    });
+}
+
 #[test]
 fn chunk_env() {
     Lua::new().context(|lua| {
@@ -836,5 +1844,149 @@ fn context_thread() {
             .into_function()
             .unwrap();
         f.call::<_, ()>(lua_ctx.current_thread()).unwrap();
+        assert!(lua_ctx.is_main_thread());
+
+        let stashed = Arc::new(Mutex::new(None));
+        let stash = stashed.clone();
+        let check = lua_ctx
+            .create_function(move |lua_ctx, ()| {
+                if lua_ctx.is_main_thread() {
+                    return Ok(false);
+                }
+                let thread = lua_ctx.create_registry_value(lua_ctx.current_thread())?;
+                *stash.lock().unwrap() = Some(thread);
+                Ok(true)
+            })
+            .unwrap();
+        lua_ctx.globals().set("check", check).unwrap();
+        lua_ctx
+            .load(
+                r#"
+                    assert(check() == false)
+                    resumed = false
+                    coroutine.wrap(function()
+                        assert(check() == true)
+                        coroutine.yield()
+                        resumed = true
+                    end)()
+                "#,
+            )
+            .exec()
+            .unwrap();
+
+        let thread = stashed.lock().unwrap().take().unwrap();
+        let thread: Thread = lua_ctx.registry_value(&thread).unwrap();
+        assert_eq!(thread.status(), ThreadStatus::Resumable);
+        thread.resume::<_, ()>(()).unwrap();
+        assert!(lua_ctx.globals().get::<_, bool>("resumed").unwrap());
+    });
+}
+
+#[test]
+fn test_error_hook() {
+    #[derive(Debug)]
+    struct NotFound(std::string::String);
+
+    impl fmt::Display for NotFound {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "not found: {}", self.0)
+        }
+    }
+
+    impl error::Error for NotFound {}
+
+    let lua = Lua::new();
+    lua.set_error_hook(|err| match err {
+        Error::RuntimeError(ref message) if message.contains("missing ") => {
+            let line = message.lines().next().unwrap();
+            let name = line.rsplit("missing ").next().unwrap();
+            NotFound(name.to_owned()).to_lua_err()
+        }
+        err => err,
+    });
+
+    lua.context(|lua_ctx| {
+        match lua_ctx.load("error('missing thing', 0)").exec() {
+            Err(Error::ExternalError(err)) => assert_eq!(err.to_string(), "not found: thing"),
+            r => panic!("error was not converted: {:?}", r),
+        }
+        match lua_ctx.load("error('other', 0)").exec() {
+            Err(Error::RuntimeError(message)) => assert!(message.starts_with("other\n")),
+            r => panic!("unrelated error was converted: {:?}", r),
+        }
+
+        // An error which already passed through the hook is handed to it again when it crosses
+        // back out of Lua, and is left alone.
+        let inner = lua_ctx
+            .create_function(|lua_ctx, ()| lua_ctx.load("error('missing inner', 0)").exec())
+            .unwrap();
+        match inner.call::<_, ()>(()) {
+            Err(Error::CallbackError { cause, .. }) => match *cause {
+                Error::ExternalError(ref err) => assert_eq!(err.to_string(), "not found: inner"),
+                ref e => panic!("unexpected cause: {:?}", e),
+            },
+            r => panic!("unexpected result: {:?}", r),
+        }
+    });
+
+    lua.remove_error_hook();
+    lua.context(
+        |lua_ctx| match lua_ctx.load("error('missing thing', 0)").exec() {
+            Err(Error::RuntimeError(message)) => assert!(message.starts_with("missing thing\n")),
+            r => panic!("error was converted after removing the hook: {:?}", r),
+        },
+    );
+}
+
+#[test]
+fn test_error_hook_sees_final_errors() {
+    let lua = Lua::new();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let hook_seen = seen.clone();
+    lua.set_error_hook(move |err| {
+        hook_seen.lock().unwrap().push(err.clone());
+        match err {
+            Error::SyntaxError { .. } | Error::MemoryLimitExceeded { .. } => {
+                err.to_string().to_lua_err()
+            }
+            err => err,
+        }
+    });
+
+    lua.context(|lua_ctx| {
+        // Syntax errors reach the hook with their position.
+        assert!(lua_ctx.load("x = = 1").exec().is_err());
+        match seen.lock().unwrap().pop() {
+            Some(Error::SyntaxError {
+                line, column, span, ..
+            }) => assert_eq!((line, column, span), (Some(1), Some(5), Some(4..5))),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // A block is still evaluated after failing to parse as an expression, without the hook
+        // seeing that syntax error.
+        assert_eq!(
+            lua_ctx.load("local x = 2 return x").eval::<i64>().unwrap(),
+            2
+        );
+        assert!(seen.lock().unwrap().is_empty());
+
+        // Exceeded limits are recognized before the hook transforms the error.
+        let allocate: Function = lua_ctx
+            .load("function() local t = {} for i = 1, 1e7 do t[i] = i end end")
+            .eval()
+            .unwrap();
+        let limits = Limits {
+            max_memory: Some(lua.used_memory() + 1024 * 1024),
+            ..Limits::default()
+        };
+        match lua.run_sandboxed::<()>(allocate, limits) {
+            Err(Error::ExternalError(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match seen.lock().unwrap().pop() {
+            Some(Error::MemoryLimitExceeded { .. }) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
     });
 }
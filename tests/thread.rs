@@ -1,6 +1,8 @@
 use std::panic::catch_unwind;
 
-use rlua::{Error, Function, Lua, Result, Thread, ThreadStatus};
+use rlua::{
+    CoroutineConfig, Error, Function, Lua, Nil, Result, StdLib, Thread, ThreadStatus, Value,
+};
 
 #[test]
 fn test_thread() {
@@ -126,3 +128,221 @@ fn coroutine_panic() {
         Err(p) => assert!(*p.downcast::<&str>().unwrap() == "test_panic"),
     }
 }
+
+#[test]
+fn managed_coroutines() {
+    Lua::new_with(StdLib::BASE | StdLib::STRING).context(|lua| {
+        lua.install_managed_coroutines(CoroutineConfig::new())
+            .unwrap();
+        lua.load(
+            r#"
+                local co = coroutine.create(function(a)
+                    assert(coroutine.isyieldable())
+                    assert(coroutine.status(coroutine.running()) == "running")
+                    local b = coroutine.yield(a + 1)
+                    error({ code = b })
+                end)
+                local ok, v = coroutine.resume(co, 1)
+                assert(ok and v == 2 and coroutine.status(co) == "suspended")
+                local ok, err = coroutine.resume(co, 7)
+                assert(not ok and err.code == 7 and coroutine.status(co) == "dead")
+                assert(not coroutine.resume(co))
+
+                local gen = coroutine.wrap(function() for i = 1, 3 do coroutine.yield(i) end end)
+                assert(gen() == 1 and gen() == 2 and gen() == 3)
+                local failing = coroutine.wrap(function() error("failed") end)
+                local ok, err = pcall(failing)
+                assert(not ok and err:find("failed"))
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+
+    Lua::new_with(StdLib::BASE | StdLib::STRING).context(|lua| {
+        lua.install_managed_coroutines(CoroutineConfig::new().expose_introspection(false))
+            .unwrap();
+        lua.load("assert(coroutine.status == nil and coroutine.running == nil)")
+            .exec()
+            .unwrap();
+    });
+}
+
+#[test]
+fn managed_coroutine_budget() {
+    Lua::new_with(StdLib::BASE | StdLib::STRING).context(|lua| {
+        lua.install_managed_coroutines(CoroutineConfig::new().budget(10_000))
+            .unwrap();
+        lua.load(
+            r#"
+                -- The budget covers every resume of a coroutine.
+                local step = coroutine.wrap(function()
+                    while true do
+                        for i = 1, 100 do end
+                        coroutine.yield()
+                    end
+                end)
+                for i = 1, 10 do step() end
+                local ok, err = pcall(function() for i = 1, 1000 do step() end end)
+                assert(not ok and tostring(err):find("coroutine instruction budget exhausted"))
+
+                -- Each coroutine has its own budget.
+                local other = coroutine.wrap(function() for i = 1, 100 do end return "done" end)
+                assert(other() == "done")
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
+
+#[test]
+fn managed_coroutine_max_live() {
+    Lua::new_with(StdLib::BASE | StdLib::STRING).context(|lua| {
+        lua.install_managed_coroutines(CoroutineConfig::new().max_live(2).budget(10_000))
+            .unwrap();
+        lua.load(
+            r#"
+                local function suspended() return coroutine.create(coroutine.yield) end
+                local a = suspended()
+                local b = coroutine.wrap(function() coroutine.yield() end)
+                local ok, err = pcall(suspended)
+                assert(not ok and tostring(err):find("too many live coroutines %(the limit is 2%)"))
+
+                -- Finishing a coroutine makes room for another.
+                assert(coroutine.resume(a))
+                assert(coroutine.resume(a))
+                assert(coroutine.status(a) == "dead")
+                local c = suspended()
+
+                -- An abandoned coroutine stops counting once it is collected.
+                a, b, c = nil, nil, nil
+                collectgarbage()
+                collectgarbage()
+                local d, e = suspended(), suspended()
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
+
+#[test]
+fn managed_coroutine_panic() {
+    match catch_unwind(|| -> Result<()> {
+        Lua::new_with(StdLib::BASE | StdLib::STRING).context(|lua| {
+            lua.install_managed_coroutines(CoroutineConfig::new())?;
+            let panicking = lua.create_function(|_, ()| -> Result<()> {
+                panic!("managed_panic");
+            })?;
+            lua.globals().set("panicking", panicking)?;
+            lua.load(
+                r#"
+                    local co = coroutine.create(panicking)
+                    local ok = coroutine.resume(co)
+                    error("panic was caught by coroutine.resume")
+                "#,
+            )
+            .exec()
+        })
+    }) {
+        Ok(r) => panic!("coroutine panic not propagated, instead returned {:?}", r),
+        Err(p) => assert!(*p.downcast::<&str>().unwrap() == "managed_panic"),
+    }
+}
+
+#[test]
+fn test_resume1() {
+    Lua::new().context(|lua| {
+        let thread: Thread = lua
+            .load(
+                r#"
+                    coroutine.create(function(a)
+                        local b, c = coroutine.yield(a, "extra")
+                        assert(b == "b" and c == nil)
+                        local d = coroutine.yield()
+                        error(d)
+                    end)
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(thread.resume1::<_, i64>(7).unwrap(), 7);
+        assert_eq!(thread.resume1::<_, Option<i64>>("b").unwrap(), None);
+        match thread.resume1::<_, Value>("boom") {
+            Err(Error::RuntimeError(msg)) => assert!(msg.contains("boom")),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(thread.status(), ThreadStatus::Error);
+
+        let finished = lua
+            .create_thread(lua.load("function(...) return ... end").eval().unwrap())
+            .unwrap();
+        assert_eq!(finished.resume1::<_, Option<i64>>(Nil).unwrap(), None);
+        match finished.resume1::<_, Value>(Nil) {
+            Err(Error::CoroutineInactive) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_body_matches() {
+    Lua::new().context(|lua| {
+        let task: Function = lua
+            .load("function(n) for i = 1, n do coroutine.yield(i) end end")
+            .eval()
+            .unwrap();
+        let other: Function = lua.load("function() end").eval().unwrap();
+
+        let thread = task.clone().into_thread().unwrap();
+        assert!(thread.body_matches(&task));
+        assert!(!thread.body_matches(&other));
+        thread.resume::<_, i64>(2).unwrap();
+        thread.resume::<_, i64>(()).unwrap();
+        thread.resume::<_, ()>(()).unwrap();
+        assert_eq!(thread.status(), ThreadStatus::Unresumable);
+        assert!(thread.body_matches(&task));
+        assert!(lua
+            .create_thread(other.clone())
+            .unwrap()
+            .body_matches(&other));
+
+        // Threads created in Lua are matched until they finish.
+        lua.globals().set("task", task.clone()).unwrap();
+        let thread: Thread = lua.load("coroutine.create(task)").eval().unwrap();
+        assert!(thread.body_matches(&task));
+        thread.resume::<_, i64>(1).unwrap();
+        assert!(thread.body_matches(&task));
+        assert!(!thread.body_matches(&other));
+        thread.resume::<_, ()>(()).unwrap();
+        assert!(!thread.body_matches(&task));
+
+        let failing: Function = lua.load("function() error('oops') end").eval().unwrap();
+        lua.globals().set("failing", failing.clone()).unwrap();
+        let thread: Thread = lua.load("coroutine.create(failing)").eval().unwrap();
+        assert!(thread.resume::<_, ()>(()).is_err());
+        assert!(thread.body_matches(&failing));
+
+        // A running thread is matched from inside itself.
+        let check = lua
+            .create_function(|lua, f: Function| Ok(lua.current_thread().body_matches(&f)))
+            .unwrap();
+        lua.globals().set("check", check).unwrap();
+        let outer: Function = lua
+            .load(
+                r#"
+                    function(self)
+                        local inner = coroutine.wrap(function() return check(self) end)
+                        return check(self) and not inner()
+                    end
+                "#,
+            )
+            .eval()
+            .unwrap();
+        lua.globals().set("outer", outer.clone()).unwrap();
+        let thread: Thread = lua.load("coroutine.create(outer)").eval().unwrap();
+        assert!(thread.resume::<_, bool>(outer).unwrap());
+    });
+}
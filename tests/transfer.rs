@@ -0,0 +1,173 @@
+use rlua::{Error, Lua, Table, TransferOptions, UserData, Value};
+
+#[test]
+fn transfer_plain_data() {
+    let source = Lua::new();
+    let target = Lua::new();
+
+    source.context(|src| {
+        target.context(|dst| {
+            let value = src
+                .load(
+                    r#"
+                        local t = { name = "test", list = { 1, 2.5, true }, [10] = "ten" }
+                        t.list[4] = t
+                        t.alias = t.list
+                        return t
+                    "#,
+                )
+                .eval::<Value>()
+                .unwrap();
+
+            let copy = src.transfer(value, dst).unwrap();
+            dst.globals().set("copy", copy).unwrap();
+            dst.load(
+                r#"
+                    assert(copy.name == "test")
+                    assert(copy[10] == "ten")
+                    assert(copy.list[1] == 1 and copy.list[2] == 2.5 and copy.list[3] == true)
+                    assert(copy.list[4] == copy)
+                    assert(copy.alias == copy.list)
+                "#,
+            )
+            .exec()
+            .unwrap();
+        });
+    });
+}
+
+#[test]
+fn transfer_errors() {
+    let source = Lua::new();
+    let target = Lua::new();
+
+    source.context(|src| {
+        target.context(|dst| {
+            let value = src
+                .load(r#"{ items = { 1, { callback = function() end } } }"#)
+                .eval::<Value>()
+                .unwrap();
+            match src.transfer(value, dst) {
+                Err(Error::NotTransferable { type_name, path }) => {
+                    assert_eq!(type_name, "function");
+                    assert_eq!(path, "items[2].callback");
+                }
+                r => panic!("expected NotTransferable, got {:?}", r),
+            }
+
+            let thread = src.load("coroutine.create(print)").eval::<Value>().unwrap();
+            match src.transfer(thread, dst) {
+                Err(Error::NotTransferable { type_name, path }) => {
+                    assert_eq!(type_name, "thread");
+                    assert_eq!(path, "");
+                }
+                r => panic!("expected NotTransferable, got {:?}", r),
+            }
+        });
+    });
+}
+
+#[test]
+fn transfer_userdata() {
+    #[derive(Clone)]
+    struct Point(i64, i64);
+    impl UserData for Point {}
+
+    struct Opaque;
+    impl UserData for Opaque {}
+
+    let source = Lua::new();
+    let target = Lua::new();
+
+    source.context(|src| {
+        target.context(|dst| {
+            let points = src
+                .create_sequence_from(vec![Point(1, 2), Point(3, 4)])
+                .unwrap();
+
+            assert!(src.transfer(Value::Table(points.clone()), dst).is_err());
+
+            let options = TransferOptions::new().allow_userdata::<Point>();
+            let copy: Table = dst
+                .unpack(
+                    src.transfer_with(Value::Table(points), dst, &options)
+                        .unwrap(),
+                )
+                .unwrap();
+            let second: Point = copy.get(2).unwrap();
+            assert_eq!((second.0, second.1), (3, 4));
+
+            let opaque = Value::UserData(src.create_userdata(Opaque).unwrap());
+            match src.transfer_with(opaque, dst, &options) {
+                Err(Error::NotTransferable { type_name, .. }) => assert_eq!(type_name, "userdata"),
+                r => panic!("expected NotTransferable, got {:?}", r),
+            }
+        });
+    });
+}
+
+#[test]
+fn transfer_metatables() {
+    let source = Lua::new();
+    let target = Lua::new();
+
+    source.context(|src| {
+        target.context(|dst| {
+            let value = src
+                .load(r#"setmetatable({}, { kind = "point" })"#)
+                .eval::<Value>()
+                .unwrap();
+
+            let copy: Table = dst
+                .unpack(src.transfer(value.clone(), dst).unwrap())
+                .unwrap();
+            assert!(copy.get_metatable().is_none());
+
+            let options = TransferOptions::new().transfer_metatables(true);
+            let copy: Table = dst
+                .unpack(src.transfer_with(value, dst, &options).unwrap())
+                .unwrap();
+            let metatable = copy.get_metatable().unwrap();
+            assert_eq!(metatable.get::<_, String>("kind").unwrap(), "point");
+
+            let value = src
+                .load(r#"setmetatable({}, { __index = function() end })"#)
+                .eval::<Value>()
+                .unwrap();
+            match src.transfer_with(value, dst, &options) {
+                Err(Error::NotTransferable { type_name, path }) => {
+                    assert_eq!(type_name, "function");
+                    assert_eq!(path, "(metatable).__index");
+                }
+                r => panic!("expected NotTransferable, got {:?}", r),
+            }
+        });
+    });
+}
+
+#[test]
+fn transfer_deep_tables() {
+    let source = Lua::new();
+    let target = Lua::new();
+
+    source.context(|src| {
+        target.context(|dst| {
+            let nested = |levels: i64| {
+                src.load("local t = {} for i = 2, ... do t = { t } end return t")
+                    .call::<_, Value>(levels)
+                    .unwrap()
+            };
+
+            assert!(src.transfer(nested(200), dst).is_ok());
+            for &levels in &[201, 100_000] {
+                match src.transfer(nested(levels), dst) {
+                    Err(Error::NotTransferable { type_name, path }) => {
+                        assert_eq!(type_name, "table");
+                        assert_eq!(path, "[1]".repeat(200));
+                    }
+                    r => panic!("unexpected result {:?}", r),
+                }
+            }
+        });
+    });
+}
@@ -1,7 +1,10 @@
+use std::any::TypeId;
+use std::fmt;
 use std::sync::Arc;
 
 use rlua::{
-    AnyUserData, ExternalError, Function, Lua, MetaMethod, String, UserData, UserDataMethods,
+    AnyUserData, BinaryOperands, Error, ExternalError, Function, Lua, MetaMethod, MethodDescriptor,
+    MultiValue, String, Table, TypeDescriptor, UserData, UserDataMethods, Value, Variadic,
 };
 
 #[test]
@@ -26,6 +29,113 @@ fn test_user_data() {
     });
 }
 
+#[test]
+fn test_userdata_type_id() {
+    struct UserData1;
+    struct UserData2;
+    struct UserData3;
+    struct Borrowed<'a>(&'a i64);
+
+    impl UserData for UserData1 {}
+    impl UserData for UserData2 {}
+    impl UserData for UserData3 {}
+    impl<'a> UserData for Borrowed<'a> {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, data, ()| Ok(*data.0));
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let userdata1 = lua.create_userdata(UserData1).unwrap();
+        let userdata2 = lua.create_userdata(UserData2).unwrap();
+        let userdata3 = lua.create_userdata(UserData3).unwrap();
+
+        assert_eq!(userdata1.type_id(), Some(TypeId::of::<UserData1>()));
+        assert_eq!(userdata2.type_id(), Some(TypeId::of::<UserData2>()));
+        assert_eq!(userdata3.type_id(), Some(TypeId::of::<UserData3>()));
+        assert!(userdata3.is::<UserData3>());
+        assert!(!userdata3.is::<UserData1>());
+
+        let _borrow = userdata1.borrow_mut::<UserData1>().unwrap();
+        assert!(userdata1.is::<UserData1>());
+
+        let value = 1;
+        lua.scope(|scope| {
+            let scoped = scope.create_nonstatic_userdata(Borrowed(&value)).unwrap();
+            assert_eq!(scoped.type_id(), None);
+            assert!(!scoped.is::<UserData1>());
+
+            let scoped = scope.create_static_userdata(UserData2).unwrap();
+            assert!(scoped.is::<UserData2>());
+        });
+
+        let file: AnyUserData = lua.load("io.stdout").eval().unwrap();
+        assert_eq!(file.type_id(), None);
+        assert!(!file.is::<UserData1>());
+    });
+}
+
+#[test]
+fn test_foreign_userdata() {
+    #[derive(Clone)]
+    struct MyUserData(i64);
+
+    impl UserData for MyUserData {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, data, ()| Ok(data.0));
+        }
+    }
+
+    struct Other;
+
+    impl UserData for Other {}
+
+    Lua::new().context(|lua| {
+        let globals = lua.globals();
+        globals.set("ud", MyUserData(1)).unwrap();
+
+        // `io.stdout` is a full userdata created by C code, with its own metatable.
+        let file: AnyUserData = lua.load("io.stdout").eval().unwrap();
+        match file.borrow::<MyUserData>() {
+            Err(Error::ForeignUserData) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        match file.borrow_mut::<MyUserData>() {
+            Err(Error::ForeignUserData) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        match lua.unpack::<MyUserData>(Value::UserData(file.clone())) {
+            Err(Error::ForeignUserData) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        match file.get_metatable() {
+            Err(Error::ForeignUserData) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        // Userdata created by rlua for another type are still only a type mismatch.
+        let ud: AnyUserData = globals.get("ud").unwrap();
+        match ud.borrow::<Other>() {
+            Err(Error::UserDataTypeMismatch) => {}
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        let table = lua.create_table().unwrap();
+        table.set("file", file).unwrap();
+        let file: AnyUserData = table.get("file").unwrap();
+        assert!(!file.is::<MyUserData>());
+
+        match lua.load("ud.get(io.stdout)").exec() {
+            Err(Error::CallbackError { cause, .. }) => match *cause {
+                Error::ForeignUserData => {}
+                ref other => panic!("unexpected error {:?}", other),
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
 #[test]
 fn test_methods() {
     struct MyUserData(i64);
@@ -117,6 +227,172 @@ fn test_metamethods() {
     });
 }
 
+#[test]
+fn test_meta_function_operands() {
+    #[derive(Copy, Clone)]
+    struct MyUserData(i64);
+
+    impl UserData for MyUserData {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_function(MetaMethod::Add, |_, (lhs, rhs): (Value, Value)| {
+                let operand = |v: Value| match v {
+                    Value::Integer(i) => Ok(i),
+                    Value::UserData(ud) => Ok(ud.borrow::<MyUserData>()?.0),
+                    v => Err(format!("cannot add {} to MyUserData", v.type_name()).to_lua_err()),
+                };
+                Ok(MyUserData(operand(lhs)? + operand(rhs)?))
+            });
+        }
+    }
+
+    Lua::new().context(|lua| {
+        lua.globals().set("ud", MyUserData(7)).unwrap();
+        assert_eq!(lua.load("ud + 3").eval::<MyUserData>().unwrap().0, 10);
+        assert_eq!(lua.load("3 + ud").eval::<MyUserData>().unwrap().0, 10);
+        assert_eq!(lua.load("ud + ud").eval::<MyUserData>().unwrap().0, 14);
+
+        let err = match lua.load("ud + 'text'").eval::<MyUserData>() {
+            Err(err) => err,
+            Ok(_) => panic!("adding a string should fail"),
+        };
+        assert_eq!(
+            err.chain().last().unwrap().to_string(),
+            "cannot add string to MyUserData"
+        );
+    });
+}
+
+#[test]
+fn test_operator_metamethods() {
+    #[derive(Copy, Clone)]
+    struct Fixed(i64);
+
+    fn operand(v: Value) -> rlua::Result<i64> {
+        match v {
+            Value::Integer(i) => Ok(i),
+            Value::UserData(ud) => Ok(ud.borrow::<Fixed>()?.0),
+            v => Err(format!("unsupported operand {}", v.type_name()).to_lua_err()),
+        }
+    }
+
+    impl UserData for Fixed {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_function(MetaMethod::IDiv, |_, (a, b): (Value, Value)| {
+                Ok(Fixed(operand(a)? / operand(b)?))
+            });
+            methods.add_meta_function(MetaMethod::BAnd, |_, (a, b): (Value, Value)| {
+                Ok(Fixed(operand(a)? & operand(b)?))
+            });
+            methods.add_meta_function(MetaMethod::BOr, |_, (a, b): (Value, Value)| {
+                Ok(Fixed(operand(a)? | operand(b)?))
+            });
+            methods.add_meta_function(MetaMethod::BXor, |_, (a, b): (Value, Value)| {
+                Ok(Fixed(operand(a)? ^ operand(b)?))
+            });
+            methods.add_meta_method(MetaMethod::BNot, |_, data, ()| Ok(Fixed(!data.0)));
+            methods.add_meta_function(MetaMethod::Shl, |_, (a, b): (Value, Value)| {
+                Ok(Fixed(operand(a)? << operand(b)?))
+            });
+            methods.add_meta_function(MetaMethod::Shr, |_, (a, b): (Value, Value)| {
+                Ok(Fixed(operand(a)? >> operand(b)?))
+            });
+            methods.add_meta_function(MetaMethod::Concat, |_, (a, b): (Value, Value)| {
+                let part = |v: Value| match v {
+                    Value::String(s) => Ok(s.to_str()?.to_owned()),
+                    v => Ok(format!("<{}>", operand(v)?)),
+                };
+                Ok(format!("{}{}", part(a)?, part(b)?))
+            });
+            methods.add_meta_method_mut(MetaMethod::Len, |_, data, ()| Ok(data.0));
+        }
+    }
+
+    Lua::new().context(|lua| {
+        lua.globals().set("x", Fixed(12)).unwrap();
+        let eval = |expr: &str| lua.load(expr).eval::<Fixed>().unwrap().0;
+
+        assert_eq!(eval("x // 5"), 2);
+        assert_eq!(eval("25 // x"), 2);
+        assert_eq!(eval("x & 10"), 8);
+        assert_eq!(eval("10 & x"), 8);
+        assert_eq!(eval("x | 1"), 13);
+        assert_eq!(eval("1 | x"), 13);
+        assert_eq!(eval("x ~ 4"), 8);
+        assert_eq!(eval("4 ~ x"), 8);
+        assert_eq!(eval("~x"), !12);
+        assert_eq!(eval("x << 2"), 48);
+        assert_eq!(eval("1 << x"), 4096);
+        assert_eq!(eval("x >> 2"), 3);
+        assert_eq!(eval("4096 >> x"), 1);
+
+        assert_eq!(lua.load(r#"x .. "!""#).eval::<String>().unwrap(), "<12>!");
+        assert_eq!(
+            lua.load(r#""x = " .. x"#).eval::<String>().unwrap(),
+            "x = <12>"
+        );
+        assert_eq!(lua.load("#x").eval::<i64>().unwrap(), 12);
+    });
+}
+
+#[test]
+fn test_meta_method_mut() {
+    use std::collections::HashMap;
+
+    // Computes fields on first access and caches them.
+    struct Lazy {
+        cache: HashMap<std::string::String, i64>,
+        computed: usize,
+    }
+
+    impl UserData for Lazy {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method_mut(
+                MetaMethod::Index,
+                |lua, this, key: std::string::String| {
+                    if key == "reentrant" {
+                        return lua.load("lazy.apple").eval();
+                    }
+                    if let Some(value) = this.cache.get(&key) {
+                        return Ok(*value);
+                    }
+                    this.computed += 1;
+                    let value = key.len() as i64;
+                    this.cache.insert(key, value);
+                    Ok(value)
+                },
+            );
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let lazy = Lazy {
+            cache: HashMap::new(),
+            computed: 0,
+        };
+        lua.globals().set("lazy", lazy).unwrap();
+        lua.load(
+            r#"
+                assert(lazy.apple == 5)
+                assert(lazy.apple == 5)
+                assert(lazy.kiwi == 4)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let lazy: AnyUserData = lua.globals().get("lazy").unwrap();
+        assert_eq!(lazy.borrow::<Lazy>().unwrap().computed, 2);
+
+        match lua.load("local _ = lazy.reentrant").exec() {
+            Err(err) => match err.chain().last() {
+                Some(Error::UserDataBorrowMutError) => {}
+                other => panic!("wrong cause {:?}", other),
+            },
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+}
+
 #[test]
 fn test_gc_userdata() {
     struct MyUserdata {
@@ -240,3 +516,639 @@ fn test_functions() {
         assert_eq!(get_constant.call::<_, i64>(()).unwrap(), 7);
     });
 }
+
+#[test]
+fn test_metatable() {
+    struct MyUserData(i64);
+
+    impl UserData for MyUserData {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, data, ()| Ok(data.0));
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let ud = lua.create_userdata(MyUserData(3)).unwrap();
+        let metatable = ud.get_metatable().unwrap();
+        assert!(metatable.contains(MetaMethod::Index).unwrap());
+        assert!(!metatable.contains(MetaMethod::Add).unwrap());
+
+        let add = lua
+            .create_function(|_, (a, b): (AnyUserData, i64)| Ok(a.borrow::<MyUserData>()?.0 + b))
+            .unwrap();
+        metatable.set(MetaMethod::Add, add).unwrap();
+        assert!(metatable.contains(MetaMethod::Add).unwrap());
+
+        let methods: Table = metatable.get(MetaMethod::Index).unwrap();
+        methods
+            .set(
+                "double",
+                lua.create_function(|_, ud: AnyUserData| Ok(ud.borrow::<MyUserData>()?.0 * 2))
+                    .unwrap(),
+            )
+            .unwrap();
+
+        // Changes are shared by every userdata of the same type.
+        let globals = lua.globals();
+        globals.set("ud", ud).unwrap();
+        globals.set("other", MyUserData(5)).unwrap();
+        lua.load(
+            r#"
+                assert(ud:get() == 3)
+                assert(ud + 4 == 7)
+                assert(other + 1 == 6)
+                assert(other:double() == 10)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}
+
+#[test]
+fn test_set_metamethod() {
+    struct MyUserData(i64);
+
+    impl UserData for MyUserData {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, data, ()| Ok(data.0));
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let ud = lua.create_userdata(MyUserData(3)).unwrap();
+        let to_string = lua
+            .create_function(|_, ud: AnyUserData| {
+                Ok(format!("value {}", ud.borrow::<MyUserData>()?.0))
+            })
+            .unwrap();
+        ud.set_metamethod(MetaMethod::ToString, to_string).unwrap();
+        lua.globals().set("ud", ud.clone()).unwrap();
+        lua.load("assert(tostring(ud) == 'value 3' and ud:get() == 3)")
+            .exec()
+            .unwrap();
+
+        let replaced = lua
+            .create_function(|_, _: AnyUserData| Ok("replaced"))
+            .unwrap();
+        ud.set_metamethod(MetaMethod::ToString, replaced).unwrap();
+        lua.load("assert(tostring(ud) == 'replaced')")
+            .exec()
+            .unwrap();
+
+        // Destructed userdata are rejected.
+        let ud = lua.scope(|scope| scope.create_static_userdata(MyUserData(4)).unwrap());
+        let f = lua.create_function(|_, ()| Ok(())).unwrap();
+        match ud.set_metamethod(MetaMethod::Call, f) {
+            Err(Error::CallbackDestructed) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_tostring_helpers() {
+    #[derive(Debug)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    impl fmt::Display for Point {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "({}, {})", self.x, self.y)
+        }
+    }
+
+    struct Displayed(Point);
+    struct Debugged(Point);
+    struct Duplicated(Point);
+
+    impl UserData for Displayed {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method(MetaMethod::Add, |_, _, ()| Ok(()));
+            methods.add_tostring_from_display();
+        }
+    }
+
+    impl fmt::Display for Displayed {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    impl UserData for Debugged {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_tostring_from_debug();
+        }
+    }
+
+    impl fmt::Debug for Debugged {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    impl UserData for Duplicated {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method(MetaMethod::ToString, |_, _, ()| Ok("explicit"));
+            methods.add_tostring_from_debug();
+        }
+    }
+
+    impl fmt::Debug for Duplicated {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let point = Point { x: 1, y: -2 };
+        let globals = lua.globals();
+        globals
+            .set("displayed", Displayed(Point { x: 1, y: -2 }))
+            .unwrap();
+        globals
+            .set("debugged", Debugged(Point { x: 1, y: -2 }))
+            .unwrap();
+        assert_eq!(
+            lua.load("tostring(displayed)").eval::<String>().unwrap(),
+            format!("{}", point)
+        );
+        assert_eq!(
+            lua.load("tostring(debugged)").eval::<String>().unwrap(),
+            format!("{:?}", point)
+        );
+
+        match lua.create_userdata(Duplicated(point)) {
+            Err(Error::DuplicateMetaMethod {
+                meta: MetaMethod::ToString,
+                type_name,
+            }) => assert!(type_name.ends_with("Duplicated")),
+            r => panic!("unexpected result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_boxed_userdata() {
+    use std::alloc::{alloc_zeroed, Layout};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static GRID_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    // Far larger than the stack of a test thread, so creating the userdata must not move it.
+    const SIZE: usize = 16 << 20;
+
+    struct Grid {
+        cells: [u8; SIZE],
+    }
+
+    impl Drop for Grid {
+        fn drop(&mut self) {
+            GRID_DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl UserData for Grid {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get", |_, grid, i: usize| Ok(grid.cells[i]));
+            methods.add_method_mut("set", |_, grid, (i, v): (usize, u8)| {
+                grid.cells[i] = v;
+                Ok(())
+            });
+        }
+    }
+
+    fn new_grid() -> Box<Grid> {
+        // A zeroed `Grid` is valid, and allocating it directly keeps it off the stack.
+        unsafe { Box::from_raw(alloc_zeroed(Layout::new::<Grid>()) as *mut Grid) }
+    }
+
+    let lua = Lua::new();
+    lua.context(|lua| {
+        let grid = lua.create_userdata_boxed(new_grid()).unwrap();
+        assert!(grid.is::<Grid>());
+        assert_eq!(grid.type_id(), Some(TypeId::of::<Grid>()));
+
+        grid.borrow_mut::<Grid>().unwrap().cells[SIZE - 1] = 7;
+        lua.globals().set("grid", grid.clone()).unwrap();
+        lua.load(
+            r#"
+                assert(grid:get(16 * 1024 * 1024 - 1) == 7)
+                grid:set(3, 42)
+            "#,
+        )
+        .exec()
+        .unwrap();
+        assert_eq!(grid.borrow::<Grid>().unwrap().cells[3], 42);
+
+        {
+            let _borrow = grid.borrow::<Grid>().unwrap();
+            match grid.borrow_mut::<Grid>() {
+                Err(Error::UserDataBorrowMutError) => {}
+                r => panic!("wrong result {:?}", r.map(|_| ())),
+            }
+        }
+
+        lua.globals().raw_remove("grid").unwrap();
+    });
+    lua.gc_collect().unwrap();
+    assert_eq!(GRID_DROPS.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_boxed_and_inline_userdata() {
+    struct Counter(i64);
+    struct Other;
+
+    impl UserData for Counter {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method_mut("inc", |_, counter, ()| {
+                counter.0 += 1;
+                Ok(counter.0)
+            });
+        }
+    }
+
+    impl UserData for Other {}
+
+    Lua::new().context(|lua| {
+        let inline = lua.create_userdata(Counter(10)).unwrap();
+        let boxed = lua.create_userdata_boxed(Box::new(Counter(20))).unwrap();
+        let inc: Function = lua.load("function(c) return c:inc() end").eval().unwrap();
+        assert_eq!(inc.call::<_, i64>(inline.clone()).unwrap(), 11);
+        assert_eq!(inc.call::<_, i64>(boxed.clone()).unwrap(), 21);
+        assert_eq!(inline.borrow::<Counter>().unwrap().0, 11);
+        assert_eq!(boxed.borrow::<Counter>().unwrap().0, 21);
+        assert!(boxed.borrow::<Other>().is_err());
+    });
+}
+
+#[test]
+fn test_typed_userdata() {
+    struct Counter(i64);
+
+    impl UserData for Counter {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method_mut("increment", |_, counter, ()| {
+                counter.0 += 1;
+                Ok(counter.0)
+            });
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let counter = lua.create_typed_userdata(Counter(0)).unwrap();
+        counter.borrow_mut().unwrap().0 = 10;
+        lua.globals().set("counter", counter.clone()).unwrap();
+        lua.load("counter:increment()").exec().unwrap();
+        assert_eq!(counter.borrow().unwrap().0, 11);
+
+        {
+            let _borrow = counter.borrow().unwrap();
+            match counter.borrow_mut() {
+                Err(Error::UserDataBorrowMutError) => {}
+                r => panic!("expected UserDataBorrowMutError, got {:?}", r.map(|_| ())),
+            }
+            match lua.load("counter:increment()").exec() {
+                Err(Error::CallbackError { ref cause, .. }) => match *cause.as_ref() {
+                    Error::UserDataBorrowMutError => {}
+                    ref e => panic!("unexpected cause {:?}", e),
+                },
+                r => panic!("expected CallbackError, got {:?}", r),
+            }
+        }
+
+        let any: AnyUserData = lua.globals().get("counter").unwrap();
+        assert!(any.is::<Counter>());
+        assert_eq!(any.borrow::<Counter>().unwrap().0, 11);
+        assert_eq!(counter.into_any().borrow::<Counter>().unwrap().0, 11);
+    });
+}
+
+#[test]
+fn test_binary_operands() {
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Vec2(f64, f64);
+
+    impl UserData for Vec2 {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_function(MetaMethod::Mul, |_, ops: BinaryOperands<Vec2, f64>| {
+                Ok(match ops {
+                    BinaryOperands::SelfLhs(v, n) | BinaryOperands::SelfRhs(n, v) => {
+                        let v = v.borrow()?;
+                        Vec2(v.0 * n, v.1 * n)
+                    }
+                })
+            });
+            methods.add_meta_function(MetaMethod::Sub, |_, ops: BinaryOperands<Vec2, Vec2>| {
+                Ok(match ops {
+                    BinaryOperands::SelfLhs(a, b) => {
+                        let a = a.borrow()?;
+                        Vec2(a.0 - b.0, a.1 - b.1)
+                    }
+                    BinaryOperands::SelfRhs(_, _) => unreachable!(),
+                })
+            });
+            methods.add_meta_function(MetaMethod::Div, |_, ops: BinaryOperands<Vec2, f64>| {
+                Ok(match ops {
+                    BinaryOperands::SelfLhs(v, n) => {
+                        let v = v.borrow()?;
+                        Vec2(v.0 / n, v.1 / n)
+                    }
+                    BinaryOperands::SelfRhs(n, v) => {
+                        let v = v.borrow()?;
+                        Vec2(n / v.0, n / v.1)
+                    }
+                })
+            });
+        }
+    }
+
+    // Not `Clone`, so it can only be borrowed.
+    struct Counter(i64);
+
+    impl UserData for Counter {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_function(MetaMethod::Add, |_, ops: BinaryOperands<Counter, i64>| {
+                let (counter, n) = match ops {
+                    BinaryOperands::SelfLhs(c, n) | BinaryOperands::SelfRhs(n, c) => (c, n),
+                };
+                counter.borrow_mut()?.0 += n;
+                Ok(counter.as_any().clone())
+            });
+            methods.add_method("get", |_, counter, ()| Ok(counter.0));
+        }
+    }
+
+    Lua::new().context(|lua| {
+        lua.globals().set("v", Vec2(1.0, 2.0)).unwrap();
+        lua.globals().set("w", Vec2(0.5, 0.5)).unwrap();
+        lua.globals().set("c", Counter(1)).unwrap();
+
+        assert_eq!(lua.load("v * 3").eval::<Vec2>().unwrap(), Vec2(3.0, 6.0));
+        assert_eq!(lua.load("3 * v").eval::<Vec2>().unwrap(), Vec2(3.0, 6.0));
+        assert_eq!(lua.load("v / 2").eval::<Vec2>().unwrap(), Vec2(0.5, 1.0));
+        assert_eq!(lua.load("2 / v").eval::<Vec2>().unwrap(), Vec2(2.0, 1.0));
+        assert_eq!(lua.load("v - w").eval::<Vec2>().unwrap(), Vec2(0.5, 1.5));
+        assert_eq!(lua.load("(c + 2 + 3):get()").eval::<i64>().unwrap(), 6);
+
+        match lua.load("return v * 'abc'").exec() {
+            Err(Error::CallbackError { ref cause, .. }) => match *cause.as_ref() {
+                Error::BinaryOperandsError {
+                    lhs: "userdata",
+                    rhs: "string",
+                    cause: Some(ref cause),
+                } => match **cause {
+                    Error::FromLuaConversionError { from: "string", .. } => {}
+                    ref e => panic!("unexpected operand error {:?}", e),
+                },
+                ref e => panic!("unexpected cause {:?}", e),
+            },
+            r => panic!("expected an operands error, got {:?}", r),
+        }
+        match lua.load("return v - 1").exec() {
+            Err(Error::CallbackError { ref cause, .. }) => {
+                assert!(
+                    cause
+                        .to_string()
+                        .starts_with("unsupported operand types userdata and integer: "),
+                    "{}",
+                    cause
+                )
+            }
+            r => panic!("expected an operands error, got {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_userdata_iterator() {
+    struct Range(i64, i64);
+
+    impl UserData for Range {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_meta_method(MetaMethod::Pairs, |lua, range, ()| {
+                lua.create_iterator((range.0..range.1).map(|i| Ok((i, i * i))))
+            });
+            methods.add_method("checked", |lua, range, ()| {
+                lua.create_iterator((range.0..range.1).map(|i| {
+                    if i != 3 {
+                        Ok(i)
+                    } else {
+                        Err(Error::RuntimeError("too big".to_owned()))
+                    }
+                }))
+            });
+        }
+    }
+
+    Lua::new().context(|lua| {
+        lua.globals().set("range", Range(1, 5)).unwrap();
+        lua.load(
+            r#"
+                local sum = 0
+                for i, sq in pairs(range) do
+                    assert(sq == i * i)
+                    sum = sum + sq
+                end
+                assert(sum == 30)
+
+                local next_value = range:checked()
+                assert(next_value() == 1 and next_value() == 2)
+                assert(not pcall(next_value))
+                assert(next_value() == 4)
+                -- An exhausted iterator stays exhausted.
+                assert(select('#', next_value()) == 0)
+                assert(select('#', next_value()) == 0)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        match lua.load("for i in range:checked() do end").exec() {
+            Err(Error::CallbackError { ref cause, .. }) => match *cause.as_ref() {
+                Error::RuntimeError(ref msg) => assert_eq!(msg, "too big"),
+                ref e => panic!("unexpected cause {:?}", e),
+            },
+            r => panic!("wrong result {:?}", r),
+        }
+    });
+}
+
+#[test]
+fn test_userdata_descriptor() {
+    struct Account(i64);
+
+    impl UserData for Account {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method_with_doc("balance", "Returns the balance.", |_, a, ()| Ok(a.0));
+            methods.add_method_mut("deposit", |_, a, n: i64| {
+                a.0 += n;
+                Ok(())
+            });
+            methods.add_function("new", |_, n: i64| Ok(Account(n)));
+            methods.add_method("fits", |_, a, n: i64| Ok(n <= a.0));
+            methods.add_meta_method(MetaMethod::ToString, |_, a, ()| Ok(a.0.to_string()));
+            methods.add_meta_function(MetaMethod::Eq, |_, (a, b): (AnyUserData, AnyUserData)| {
+                Ok(a.borrow::<Account>()?.0 == b.borrow::<Account>()?.0)
+            });
+            // Replaces the undocumented `deposit`.
+            methods.add_method_with_doc("deposit", "Adds to the balance.", |_, _, ()| Ok(()));
+        }
+    }
+
+    struct Plain;
+    impl UserData for Plain {}
+
+    fn method(name: &str, doc: Option<&str>) -> MethodDescriptor {
+        MethodDescriptor {
+            name: name.to_owned(),
+            doc: doc.map(str::to_owned),
+        }
+    }
+
+    let lua = Lua::new();
+    let account = lua.userdata_descriptor::<Account>();
+    assert_eq!(
+        account,
+        TypeDescriptor {
+            name: std::any::type_name::<Account>(),
+            methods: vec![
+                method("balance", Some("Returns the balance.")),
+                method("new", None),
+                method("fits", None),
+                method("deposit", Some("Adds to the balance.")),
+            ],
+            metamethods: vec![MetaMethod::ToString, MetaMethod::Eq],
+        }
+    );
+    let plain = lua.userdata_descriptor::<Plain>();
+    assert!(plain.methods.is_empty() && plain.metamethods.is_empty());
+
+    // Only types passed to Lua are listed.
+    assert!(lua.all_userdata_descriptors().is_empty());
+    lua.context(|lua| {
+        lua.globals().set("a", Account(10)).unwrap();
+        lua.globals().set("b", Account(20)).unwrap();
+        lua.globals().set("p", Plain).unwrap();
+    });
+    let mut expected = vec![account, plain];
+    expected.sort_by_key(|descriptor| descriptor.name);
+    assert_eq!(lua.all_userdata_descriptors(), expected);
+}
+
+#[test]
+fn test_add_method_fn() {
+    #[derive(Clone, Copy)]
+    struct MyUserData(i64);
+
+    // Each method is added both with `add_method` and, with an `_fn` suffix, with `add_method_fn`.
+    impl UserData for MyUserData {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            fn add(_: rlua::Context, data: &MyUserData, n: i64) -> rlua::Result<i64> {
+                Ok(data.0 + n)
+            }
+            fn get(_: rlua::Context, data: &MyUserData, (): ()) -> rlua::Result<i64> {
+                Ok(data.0)
+            }
+            fn sum(
+                _: rlua::Context,
+                data: &MyUserData,
+                (a, b, rest): (i64, Option<i64>, Variadic<i64>),
+            ) -> rlua::Result<(i64, usize)> {
+                Ok((
+                    data.0 + a + b.unwrap_or(0) + rest.iter().sum::<i64>(),
+                    rest.len(),
+                ))
+            }
+            fn nothing(_: rlua::Context, _: &MyUserData, _: Value) -> rlua::Result<()> {
+                Ok(())
+            }
+
+            methods.add_method("add", add);
+            methods.add_method_fn("add_fn", add);
+            methods.add_method("get", get);
+            methods.add_method_fn("get_fn", get);
+            methods.add_method("sum", sum);
+            methods.add_method_fn("sum_fn", sum);
+            methods.add_method("nothing", nothing);
+            methods.add_method_fn("nothing_fn", nothing);
+        }
+    }
+
+    struct Other;
+    impl UserData for Other {}
+
+    Lua::new().context(|lua| {
+        let globals = lua.globals();
+        globals.set("ud", MyUserData(7)).unwrap();
+        globals.set("other", Other).unwrap();
+
+        // Both paths give the same results or error for each call.
+        for call in &[
+            "ud:add{}(3)",
+            "ud:add{}(3, 4, 5)",
+            "ud:add{}()",
+            "ud:add{}('x')",
+            "ud:add{}('5')",
+            "ud.add{}(other, 3)",
+            "ud.add{}(1, 3)",
+            "ud.add{}()",
+            "ud:get{}()",
+            "ud:get{}(1)",
+            "ud:sum{}(1)",
+            "ud:sum{}(1, 2)",
+            "ud:sum{}(1, nil, 3, 4)",
+            "ud:sum{}(1, 2, 'x')",
+            "ud:sum{}()",
+            "select('#', ud:nothing{}())",
+            "ud.nothing{}(other)",
+        ] {
+            let run = |suffix| {
+                lua.load(&format!("return {}", call.replace("{}", suffix)))
+                    .eval::<MultiValue>()
+                    .map(|values| {
+                        values
+                            .into_iter()
+                            .map(|v| format!("{:?}", v))
+                            .collect::<Vec<_>>()
+                    })
+                    .map_err(|e| match e {
+                        Error::CallbackError { cause, .. } => cause.to_string(),
+                        e => e.to_string(),
+                    })
+            };
+            assert_eq!(run(""), run("_fn"), "{}", call);
+        }
+        assert_eq!(lua.load("ud:add_fn(3)").eval::<i64>().unwrap(), 10);
+        assert_eq!(
+            lua.load("ud:sum_fn(1, 2, 3, 4)")
+                .eval::<(i64, usize)>()
+                .unwrap(),
+            (17, 2)
+        );
+        match lua.load("ud.add_fn(other, 3)").exec() {
+            Err(Error::CallbackError { ref cause, .. }) => match *cause.as_ref() {
+                Error::UserDataTypeMismatch => {}
+                ref other => panic!("wrong error type {:?}", other),
+            },
+            r => panic!("wrong result {:?}", r),
+        }
+
+        // A mutable borrow is held while the method is called.
+        let ud = globals.get::<_, AnyUserData>("ud").unwrap();
+        let mut data = ud.borrow_mut::<MyUserData>().unwrap();
+        match lua.load("ud:add_fn(3)").exec() {
+            Err(Error::CallbackError { ref cause, .. }) => match *cause.as_ref() {
+                Error::UserDataBorrowError => {}
+                ref other => panic!("wrong error type {:?}", other),
+            },
+            r => panic!("wrong result {:?}", r),
+        }
+        data.0 = 1;
+        drop(data);
+        assert_eq!(lua.load("ud:add_fn(3)").eval::<i64>().unwrap(), 4);
+    });
+}